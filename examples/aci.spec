@@ -0,0 +1,12 @@
+# examples/aci.hex: MVI A,55H / ACI 74H / HLT at 0100H
+#
+#     sbc8micro batch --cpu 8080 examples/aci.hex --spec examples/aci.spec
+A == C9
+F == 86
+flags.S == 1
+flags.Z == 0
+flags.AC == 0
+flags.P == 1
+flags.C == 0
+PC == 0104      ; stopped before the HLT
+mem[0100..0105] == 3E 55 CE 74 76
@@ -0,0 +1,237 @@
+//////////////////////////////////////////////////////////
+/// Operand expressions of the assemblers.
+///
+/// Operands:
+///   numbers     ACME:  $FF, %1010, &17 (octal), 0xFF, 255
+///               Intel: 0FFH, 1010B, 17O, 17Q, 255, 255D
+///               both:  'A' (character code)
+///   symbols     labels and constants
+///   location    * in ACME, $ in Intel syntax
+///
+/// Operators from the lowest priority, keywords are not case sensitive:
+///   | OR   XOR   & AND   = == != <> < > <= >=   << >> SHL SHR   + -
+///   * / % MOD DIV   unary: - ! NOT, < LOW (low byte), > HIGH (high byte)
+///
+/// Comparisons give 1 or 0.
+//////////////////////////////////////////////////////////
+use crate::expression::{self, Level, Node, Op, Operands, Parser, Syntax, Token, Unary};
+use crate::machine::config::parse_number;
+
+pub use crate::expression::ExprError;
+
+///
+/// Assembler syntax, it decides how numbers and the location are written
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dialect {
+    Acme,
+    Intel,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expression {
+    root: Node,
+}
+
+///
+/// Binary operators grouped by priority, the lowest first
+///
+const LEVELS: [Level; 7] = [
+    &[("|", Op::Or), ("OR", Op::Or)],
+    &[("XOR", Op::Xor)],
+    &[("&", Op::And), ("AND", Op::And)],
+    &[
+        ("=", Op::Eq),
+        ("==", Op::Eq),
+        ("!=", Op::Ne),
+        ("<>", Op::Ne),
+        ("<=", Op::Le),
+        (">=", Op::Ge),
+        ("<", Op::Lt),
+        (">", Op::Gt),
+    ],
+    &[
+        ("<<", Op::Shl),
+        (">>", Op::Shr),
+        ("SHL", Op::Shl),
+        ("SHR", Op::Shr),
+    ],
+    &[("+", Op::Add), ("-", Op::Sub)],
+    &[
+        ("*", Op::Mul),
+        ("/", Op::Div),
+        ("%", Op::Mod),
+        ("MOD", Op::Mod),
+        ("DIV", Op::Div),
+    ],
+];
+
+///
+/// Intel number: digits with an optional radix suffix H, B, O, Q or D
+///
+fn intel_number(word: &str) -> Option<i64> {
+    let upper = word.to_ascii_uppercase();
+    let (digits, radix) = match upper.as_bytes().last()? {
+        b'H' => (&upper[..upper.len() - 1], 16),
+        b'B' => (&upper[..upper.len() - 1], 2),
+        b'O' | b'Q' => (&upper[..upper.len() - 1], 8),
+        b'D' => (&upper[..upper.len() - 1], 10),
+        _ => (upper.as_str(), 10),
+    };
+    i64::from_str_radix(digits, radix).ok()
+}
+
+fn acme_number(word: &str) -> Option<i64> {
+    if let Some(binary) = word.strip_prefix('%') {
+        i64::from_str_radix(binary, 2).ok()
+    } else if let Some(octal) = word.strip_prefix('&') {
+        i64::from_str_radix(octal, 8).ok()
+    } else {
+        parse_number(word).map(|value| value as i64)
+    }
+}
+
+impl Syntax for Dialect {
+    fn symbols(&self) -> &'static [&'static str] {
+        &[
+            "<<", ">>", "==", "!=", "<>", "<=", ">=", "<", ">", "=", "+", "-", "*", "/", "%", "&",
+            "|", "!", "(", ")",
+        ]
+    }
+    fn levels(&self) -> &'static [Level] {
+        &LEVELS
+    }
+    ///
+    /// After an operand * and % are operators, otherwise location and
+    /// binary number
+    ///
+    fn scan(&self, rest: &str, operand_before: bool) -> Result<Option<(Token, usize)>, ExprError> {
+        let mut chars = rest.chars();
+        let Some(c) = chars.next() else {
+            return Ok(None);
+        };
+        let next = chars.next();
+        let prefixed_number = match (self, c) {
+            (Dialect::Acme, '$') => true,
+            (Dialect::Acme, '%' | '&') => {
+                !operand_before && next.is_some_and(|n| n.is_ascii_digit())
+            }
+            _ => false,
+        };
+        if c.is_ascii_digit() || prefixed_number {
+            let length = expression::number_length(rest);
+            let word = &rest[..length];
+            let value = match self {
+                Dialect::Acme => acme_number(word),
+                Dialect::Intel => intel_number(word),
+            }
+            .ok_or_else(|| ExprError(format!("invalid number '{}'", word)))?;
+            Ok(Some((Token::Number(value), length)))
+        } else if c == '\'' || (c == '"' && *self == Dialect::Acme) {
+            let (Some(value), Some(close)) = (next, chars.next()) else {
+                return Err(ExprError("unterminated character".to_string()));
+            };
+            if close != c {
+                return Err(ExprError(
+                    "character constant with more than one character".to_string(),
+                ));
+            }
+            Ok(Some((Token::Number(value as i64), 2 + value.len_utf8())))
+        } else if (c == '*' && *self == Dialect::Acme && !operand_before)
+            || (c == '$' && *self == Dialect::Intel)
+        {
+            Ok(Some((Token::Location, 1)))
+        } else {
+            Ok(None)
+        }
+    }
+    fn is_name_start(&self, c: char) -> bool {
+        c.is_ascii_alphabetic() || c == '_' || c == '.' || c == '@'
+    }
+    fn is_name_char(&self, c: char) -> bool {
+        c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == '@'
+    }
+    fn unary(&self, text: &str) -> Option<Unary> {
+        match text {
+            "-" => Some(Unary::Negate),
+            "!" | "NOT" => Some(Unary::Not),
+            "<" | "LOW" => Some(Unary::Low),
+            ">" | "HIGH" => Some(Unary::High),
+            _ => None,
+        }
+    }
+}
+
+///
+/// Symbols of the assembler and the location counter
+///
+struct Symbols<'a> {
+    lookup: &'a dyn Fn(&str) -> Option<i64>,
+    location: u16,
+}
+
+impl Operands for Symbols<'_> {
+    fn name(&self, name: &str) -> Result<Option<i64>, ExprError> {
+        Ok((self.lookup)(name))
+    }
+    fn location(&self) -> Result<Option<i64>, ExprError> {
+        Ok(Some(self.location as i64))
+    }
+}
+
+impl Expression {
+    pub fn parse(text: &str, dialect: Dialect) -> Result<Self, ExprError> {
+        Ok(Self {
+            root: Parser::parse(text, &dialect)?,
+        })
+    }
+    ///
+    /// Value of the expression, None if it uses a symbol that lookup does
+    /// not know (yet)
+    ///
+    pub fn evaluate(
+        &self,
+        lookup: &dyn Fn(&str) -> Option<i64>,
+        location: u16,
+    ) -> Result<Option<i64>, ExprError> {
+        expression::evaluate(&self.root, &Symbols { lookup, location })
+    }
+    ///
+    /// Names of the symbols used by the expression
+    ///
+    pub fn symbols(&self) -> Vec<&str> {
+        self.root.names()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn value(text: &str, dialect: Dialect) -> Option<i64> {
+        let lookup = |name: &str| (name == "table").then_some(0x1234);
+        Expression::parse(text, dialect)
+            .unwrap()
+            .evaluate(&lookup, 0x0600)
+            .unwrap()
+    }
+
+    #[test]
+    ///
+    /// Numbers, location and operators of both dialects
+    ///
+    fn evaluate_dialects() {
+        assert_eq!(value("$FF + %101 + &10", Dialect::Acme), Some(0xFF + 5 + 8));
+        assert_eq!(value("<table", Dialect::Acme), Some(0x34));
+        assert_eq!(value(">table+1", Dialect::Acme), Some(0x13));
+        assert_eq!(value("* + 2 * 3", Dialect::Acme), Some(0x0606));
+        assert_eq!(value("7 % 4 + 'A'", Dialect::Acme), Some(3 + 65));
+        assert_eq!(value("(1 + 2) << 4 | 1", Dialect::Acme), Some(0x31));
+        assert_eq!(value("0FFH AND 1010B", Dialect::Intel), Some(0x0A));
+        assert_eq!(value("HIGH table + LOW($)", Dialect::Intel), Some(0x12));
+        assert_eq!(value("17Q SHL 1 = 30", Dialect::Intel), Some(1));
+        assert_eq!(value("undefined + 1", Dialect::Acme), None);
+        assert!(Expression::parse("1 +", Dialect::Acme).is_err());
+        assert!(Expression::parse("$XY", Dialect::Acme).is_err());
+    }
+}
@@ -0,0 +1,293 @@
+//////////////////////////////////////////////////////////
+/// 8080 assembler with Intel syntax. The instruction set comes from the
+/// opcode table of the disassembler.
+///
+///     BDOS    EQU     0005H
+///             ORG     0100H
+///     START:  LXI     D,TEXT
+///             MVI     C,9
+///             CALL    BDOS
+///             JMP     $
+///     TEXT:   DB      'HELLO$',0DH,0AH
+///     BUFFER: DS      16
+///             END     START
+///
+/// Labels end with ':' or start in the first column. Numbers are decimal
+/// or have a suffix H, B, O/Q, D. Hex numbers start with a digit (0FFH,
+/// FFH is a name), the disassembler writes them the same way.
+///
+/// Directives: ORG, EQU, DB (bytes and 'strings'), DW, DS (reserves bytes
+/// without output) and END with an optional start address.
+//////////////////////////////////////////////////////////
+use std::collections::{HashMap, HashSet};
+
+use crate::assembler::expression::Dialect;
+use crate::assembler::{AsmError, Assembly, Context, byte, run, split_list, string_literal, word};
+use crate::disassembler::i8080::{OpcodeDef, opcodes};
+
+const REGISTERS: [&str; 10] = ["A", "B", "C", "D", "E", "H", "L", "M", "SP", "PSW"];
+
+///
+/// Placeholders of the opcode table for a value operand
+///
+const PLACEHOLDERS: [&str; 3] = ["data", "address", "port"];
+
+///
+/// Opcode and length by the mnemonic of the table ("MVI B,data")
+///
+struct Instructions {
+    forms: HashMap<String, (u8, u8)>,
+    mnemonics: HashSet<String>,
+}
+
+fn instructions(opcodes: &HashMap<u8, OpcodeDef>) -> Instructions {
+    let forms: HashMap<String, (u8, u8)> = opcodes
+        .iter()
+        .map(|(&opcode, def)| (def.mnemonic().to_string(), (opcode, def.bytes())))
+        .collect();
+    let mnemonics = forms
+        .keys()
+        .map(|form| form.split(' ').next().unwrap_or(form).to_string())
+        .collect();
+    Instructions { forms, mnemonics }
+}
+
+pub fn assemble(source: &str) -> Result<Assembly, AsmError> {
+    let instructions = instructions(opcodes());
+    run(source, Dialect::Intel, |context, line| {
+        statement(context, &instructions, line)
+    })
+}
+
+fn is_name(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_alphabetic() || matches!(c, '_' | '?' | '@' | '.'))
+        && name[1..]
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '?' | '@' | '.'))
+}
+
+///
+/// Splits the first word from the rest of the line
+///
+fn first_word(text: &str) -> (&str, &str) {
+    let end = text
+        .find(|c: char| c.is_whitespace() || c == ':')
+        .unwrap_or(text.len());
+    (&text[..end], text[end..].trim_start())
+}
+
+fn statement(context: &mut Context, instructions: &Instructions, text: &str) -> Result<(), String> {
+    if text.trim().is_empty() {
+        return Ok(());
+    }
+    let first_column = !text.starts_with(char::is_whitespace);
+    let (word, rest) = first_word(text.trim());
+    let mnemonic = word.to_ascii_uppercase();
+    if directive(context, &mnemonic, rest)? {
+        return Ok(());
+    }
+    if instructions.mnemonics.contains(&mnemonic) {
+        return instruction(context, instructions, &mnemonic, rest);
+    }
+
+    if !is_name(word) {
+        return Err(format!("unknown statement '{}'", text.trim()));
+    }
+    let colon = rest.starts_with(':');
+    let rest = rest.strip_prefix(':').unwrap_or(rest).trim_start();
+    let (next, operand) = first_word(rest);
+    if next.eq_ignore_ascii_case("EQU") {
+        if let Some(value) = context.evaluate(operand)? {
+            context.define(word, value)?;
+        }
+        return Ok(());
+    }
+    if !colon && !first_column {
+        return Err(format!("unknown instruction '{}'", word));
+    }
+    context.label(word)?;
+    statement(context, instructions, &format!(" {}", rest))
+}
+
+///
+/// Handles ORG, DB, DW, DS and END, false for other statements
+///
+fn directive(context: &mut Context, name: &str, operands: &str) -> Result<bool, String> {
+    match name {
+        "ORG" => {
+            let origin = context.evaluate_now(operands)?;
+            context.set_pc(origin)?;
+        }
+        "DB" => {
+            for item in split_list(operands) {
+                if let Some(text) = string_literal(item) {
+                    context.emit(text.as_bytes())?;
+                } else {
+                    let value = context.evaluate(item)?.unwrap_or(0);
+                    context.emit(&[byte(value)?])?;
+                }
+            }
+        }
+        "DW" => {
+            for item in split_list(operands) {
+                let value = context.evaluate(item)?.unwrap_or(0);
+                context.emit(&word(value)?.to_le_bytes())?;
+            }
+        }
+        "DS" => {
+            let count = context.evaluate_now(operands)?;
+            context.reserve(count)?;
+        }
+        "END" => {
+            if !operands.trim().is_empty()
+                && let Some(start) = context.evaluate(operands)?
+            {
+                context.image.start = Some(word(start)?);
+            }
+            context.done = true;
+        }
+        "EQU" => return Err("EQU without a name".to_string()),
+        _ => return Ok(false),
+    }
+    Ok(true)
+}
+
+fn instruction(
+    context: &mut Context,
+    instructions: &Instructions,
+    mnemonic: &str,
+    operands: &str,
+) -> Result<(), String> {
+    let operands = match operands.trim() {
+        "" => Vec::new(),
+        operands => split_list(operands),
+    };
+    let mut form = Vec::new();
+    let mut expression = None;
+    for operand in &operands {
+        let register = operand.to_ascii_uppercase();
+        if mnemonic == "RST" {
+            form.push(context.evaluate_now(operand)?.to_string());
+        } else if REGISTERS.contains(&register.as_str()) {
+            form.push(register);
+        } else {
+            form.push("{}".to_string());
+            expression = Some(*operand);
+        }
+    }
+    let form = match form.join(",") {
+        operands if operands.is_empty() => mnemonic.to_string(),
+        operands => format!("{} {}", mnemonic, operands),
+    };
+    let found = PLACEHOLDERS
+        .iter()
+        .find_map(|placeholder| instructions.forms.get(&form.replace("{}", placeholder)));
+    let Some(&(opcode, length)) = found else {
+        return Err(format!("invalid operands '{}'", operands.join(",")));
+    };
+    let value = match expression {
+        Some(expression) => context.evaluate(expression)?.unwrap_or(0),
+        None => 0,
+    };
+    match length {
+        1 => context.emit_instruction(&[opcode]),
+        2 => context.emit_instruction(&[opcode, byte(value)?]),
+        _ => {
+            let [low, high] = word(value)?.to_le_bytes();
+            context.emit_instruction(&[opcode, low, high])
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::i8080::Cpu;
+    use crate::disassembler::i8080::disassemble;
+    use crate::disassembler::i8080_opcodes_const::*;
+    use crate::memory::Memory;
+
+    #[test]
+    ///
+    /// Same bytes as the opcode constants used by the CPU tests
+    ///
+    fn assemble_program() {
+        let assembly = assemble("\tMVI A,55H\n\tACI 74H\n\tHLT").unwrap();
+        assert_eq!(assembly.bytes(), vec![MVI_A, 0x55, ACI, 0x74, HLT]);
+        let mut cpu = Cpu::new();
+        cpu.load_program(&assembly.bytes(), 0x0000);
+        cpu.step();
+        cpu.step();
+        assert_eq!(cpu.a, 0xC9);
+    }
+
+    #[test]
+    ///
+    /// Directives, labels, location counter, HEX output and listing
+    ///
+    fn directives() {
+        let source = "BDOS\tEQU\t0005H\n\
+                      \tORG\t0100H\n\
+                      START:\tLXI\tD,TEXT\n\
+                      \tMVI\tC,9\n\
+                      \tCALL\tBDOS\n\
+                      \tJMP\t$\n\
+                      TEXT:\tDB\t'HI$',0DH,0AH\n\
+                      BUFFER:\tDS\t2\n\
+                      \tDW\tSTART, BUFFER\n\
+                      \tEND\tSTART\n\
+                      \tNOP\n";
+        let assembly = assemble(source).unwrap();
+        assert_eq!(
+            assembly.bytes(),
+            [
+                0x11, 0x0B, 0x01, 0x0E, 0x09, 0xCD, 0x05, 0x00, 0xC3, 0x08, 0x01, 0x48, 0x49, 0x24,
+                0x0D, 0x0A, 0x00, 0x00, 0x00, 0x01, 0x10, 0x01,
+            ]
+        );
+        assert_eq!(assembly.image.segments.len(), 2);
+        assert_eq!(assembly.image.start, Some(0x0100));
+        assert_eq!(assembly.symbols.address_of("BUFFER"), Some(0x0110));
+        let hex = assembly.to_intel_hex();
+        assert!(hex.starts_with(":10010000110B010E09CD0500C308014849240D0A51\n"));
+        assert!(hex.ends_with(":0400000500000100F6\n:00000001FF\n"));
+        let listing = assembly.listing();
+        let listing: Vec<&str> = listing.lines().collect();
+        assert_eq!(listing[2], "0100  11 0B 01    START:\tLXI\tD,TEXT");
+        assert_eq!(listing[6], "010B  48 49 24 0D TEXT:\tDB\t'HI$',0DH,0AH");
+        assert_eq!(listing[7], "010F  0A");
+
+        let error = assemble("\tMVI A,FFH").unwrap_err();
+        assert!(error.message.contains("0FFH"), "{}", error);
+        assert!(assemble("\tMOV A,SP").is_err());
+    }
+
+    #[test]
+    ///
+    /// Every instruction of the table, written as the disassembler does,
+    /// assembles to bytes that disassemble to the same text
+    ///
+    fn round_trip() {
+        let mut defs: Vec<(&u8, &OpcodeDef)> = opcodes().iter().collect();
+        defs.sort_by_key(|(opcode, _)| **opcode);
+        let texts: Vec<String> = defs
+            .iter()
+            .map(|(_, def)| {
+                def.mnemonic()
+                    .replace("data", "12H")
+                    .replace("port", "0FEH")
+                    .replace("address", "0ABCDH")
+            })
+            .collect();
+        let source: String = texts.iter().map(|text| format!("\t{}\n", text)).collect();
+        let assembly = assemble(&format!("\tORG 4000H\n{}", source)).unwrap();
+        let mut memory = Memory::new();
+        memory.load_image(&assembly.image);
+        let end = 0x4000 + assembly.bytes().len() as u32;
+        let decoded: Vec<String> = disassemble(&memory, 0x4000, end, opcodes())
+            .iter()
+            .map(|instruction| instruction.text())
+            .collect();
+        assert_eq!(decoded, texts);
+    }
+}
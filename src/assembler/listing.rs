@@ -0,0 +1,365 @@
+//////////////////////////////////////////////////////////
+/// Listing with address, bytes, cycle counts and source of every line.
+/// Cycles come from the opcode tables: states for the 8080, clock cycles
+/// for the 6502. Where the time depends on the execution two numbers are
+/// shown, e.g. 2/3 for a 6502 branch (not taken/taken, one more if it
+/// crosses a page), 4/5 for an indexed read that may cross a page and
+/// 5/11 for a conditional return of the 8080.
+///
+/// Comments mark blocks whose cycles are added up, blocks can be nested.
+/// Every instruction is counted once, multiply loops yourself:
+///
+///     ;@cycles delay
+///             ldx #$10
+///     -       dex
+///             bne -
+///     ;@end
+///
+/// The total is shown on the ;@end line and in a summary at the end.
+///
+/// ```
+/// let assembly = assembler::assemble(CpuKind::I8080, &source)?;
+/// print!("{}", listing::generate(&assembly.lines, Some(CpuKind::I8080)));
+/// ```
+//////////////////////////////////////////////////////////
+use std::fmt;
+
+use crate::assembler::{AsmError, Line, assemble, strip_comment};
+use crate::disassembler::{i8080, mos6502};
+use crate::machine::config::CpuKind;
+
+///
+/// Bytes per line of the listing, longer data continues on the next lines
+///
+const BYTES_PER_LINE: usize = 4;
+
+const BLOCK_START: &str = "@cycles";
+const BLOCK_END: &str = "@end";
+
+///
+/// 6502 instructions that take one more cycle when the indexed address
+/// crosses a page (stores and read-modify-write always take the longer time)
+///
+const PAGE_CROSSING_READS: [&str; 9] = [
+    "ADC", "AND", "CMP", "EOR", "LDA", "LDX", "LDY", "ORA", "SBC",
+];
+
+///
+/// Shortest and longest execution time
+///
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Cycles {
+    pub min: u32,
+    pub max: u32,
+}
+
+impl Cycles {
+    fn add(&mut self, other: Cycles) {
+        self.min += other.min;
+        self.max += other.max;
+    }
+}
+
+impl fmt::Display for Cycles {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.min == self.max {
+            write!(f, "{}", self.min)
+        } else {
+            write!(f, "{}/{}", self.min, self.max)
+        }
+    }
+}
+
+///
+/// Block of lines between ;@cycles name and ;@end
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Block {
+    pub name: String,
+    pub first: usize,
+    pub last: usize,
+    pub cycles: Cycles,
+}
+
+///
+/// Execution time of the instruction at address
+///
+pub fn cycles(kind: CpuKind, address: u16, bytes: &[u8]) -> Option<Cycles> {
+    let opcode = *bytes.first()?;
+    match kind {
+        CpuKind::I8080 | CpuKind::I8085 => {
+            let def = i8080::opcodes().get(&opcode)?;
+            Some(Cycles {
+                min: def.cycles() as u32,
+                max: def.max_cycles() as u32,
+            })
+        }
+        CpuKind::Mos6502 | CpuKind::Wdc65C02 => {
+            let def = mos6502::opcodes().get(&opcode)?;
+            let min = def.cycles() as u32;
+            let extra = match def.mode() {
+                "relative" => {
+                    let next = address.wrapping_add(2);
+                    let offset = *bytes.get(1)? as i8;
+                    let target = next.wrapping_add(offset as u16);
+                    1 + (target & 0xFF00 != next & 0xFF00) as u32
+                }
+                "absolute,X" | "absolute,Y" | "(indirect),Y"
+                    if PAGE_CROSSING_READS.contains(&&def.mnemonic()[..3]) =>
+                {
+                    1
+                }
+                _ => 0,
+            };
+            Some(Cycles {
+                min,
+                max: min + extra,
+            })
+        }
+    }
+}
+
+///
+/// Block marker in the comment of the line
+///
+enum Marker<'a> {
+    Start(&'a str),
+    End,
+}
+
+fn marker(source: &str) -> Option<Marker<'_>> {
+    let comment = source[strip_comment(source).len()..]
+        .strip_prefix(';')?
+        .trim();
+    if let Some(name) = comment.strip_prefix(BLOCK_START) {
+        Some(Marker::Start(name.trim()))
+    } else if comment.starts_with(BLOCK_END) {
+        Some(Marker::End)
+    } else {
+        None
+    }
+}
+
+fn line_cycles(kind: CpuKind, line: &Line) -> Option<Cycles> {
+    if line.instruction {
+        cycles(kind, line.address, &line.bytes)
+    } else {
+        None
+    }
+}
+
+///
+/// Cycle totals of the marked blocks in the order they end. A block
+/// without ;@end ends with the last line, an ;@end without block is ignored.
+///
+pub fn blocks(lines: &[Line], kind: CpuKind) -> Vec<Block> {
+    let mut open: Vec<Block> = Vec::new();
+    let mut done = Vec::new();
+    for line in lines {
+        if let Some(cycles) = line_cycles(kind, line) {
+            for block in &mut open {
+                block.cycles.add(cycles);
+            }
+        }
+        match marker(&line.source) {
+            Some(Marker::Start(name)) => open.push(Block {
+                name: name.to_string(),
+                first: line.line,
+                last: line.line,
+                cycles: Cycles::default(),
+            }),
+            Some(Marker::End) => {
+                if let Some(mut block) = open.pop() {
+                    block.last = line.line;
+                    done.push(block);
+                }
+            }
+            None => {}
+        }
+    }
+    let last = lines.last().map_or(0, |line| line.line);
+    while let Some(mut block) = open.pop() {
+        block.last = last;
+        done.push(block);
+    }
+    done
+}
+
+fn hex_bytes(bytes: &[u8]) -> String {
+    let hex: Vec<String> = bytes.iter().map(|b| format!("{:02X}", b)).collect();
+    hex.join(" ")
+}
+
+///
+/// Address, bytes and source of every line in the layout of the
+/// disassembler. With a CPU kind the cycles are added after the bytes and
+/// the block totals at the end. Empty and comment lines have no address.
+///
+pub fn generate(lines: &[Line], kind: Option<CpuKind>) -> String {
+    let blocks = kind.map(|kind| blocks(lines, kind)).unwrap_or_default();
+    let mut listing = String::new();
+    for line in lines {
+        let address = if strip_comment(&line.source).trim().is_empty() {
+            "    ".to_string()
+        } else {
+            format!("{:04X}", line.address)
+        };
+        let mut chunks = line.bytes.chunks(BYTES_PER_LINE);
+        let bytes = hex_bytes(chunks.next().unwrap_or(&[]));
+        match kind {
+            None => listing += &format!("{}  {:<11} {}\n", address, bytes, line.source),
+            Some(kind) => {
+                let cycles = match marker(&line.source) {
+                    Some(Marker::End) => blocks
+                        .iter()
+                        .find(|block| block.last == line.line)
+                        .map(|block| format!("={}", block.cycles)),
+                    _ => line_cycles(kind, line).map(|cycles| cycles.to_string()),
+                };
+                listing += &format!(
+                    "{}  {:<11} {:>7}  {}\n",
+                    address,
+                    bytes,
+                    cycles.unwrap_or_default(),
+                    line.source
+                );
+            }
+        }
+        for (i, chunk) in chunks.enumerate() {
+            let address = line.address.wrapping_add(((i + 1) * BYTES_PER_LINE) as u16);
+            listing += &format!("{:04X}  {}\n", address, hex_bytes(chunk));
+        }
+    }
+    if !blocks.is_empty() {
+        listing += "\nCycles of the marked blocks:\n";
+        for block in &blocks {
+            listing += &format!(
+                "  {}: {} (lines {}-{})\n",
+                block.name, block.cycles, block.first, block.last
+            );
+        }
+    }
+    listing
+}
+
+///
+/// Listing lines of a program built by an external assembler (ACME): the
+/// source is assembled here to find the address of every line, the bytes
+/// are taken from the binary loaded at address. A line that assembles to
+/// other bytes than the binary has is reported as error.
+///
+pub fn from_binary(
+    source: &str,
+    kind: CpuKind,
+    binary: &[u8],
+    address: u16,
+) -> Result<Vec<Line>, AsmError> {
+    let lines = assemble(kind, source)?.lines;
+    for line in lines.iter().filter(|line| !line.bytes.is_empty()) {
+        let offset = line.address.wrapping_sub(address) as usize;
+        let built = binary.get(offset..offset + line.bytes.len());
+        if built != Some(line.bytes.as_slice()) {
+            return Err(AsmError {
+                line: line.line,
+                message: format!("bytes at {:04X} differ from the binary", line.address),
+            });
+        }
+    }
+    Ok(lines)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::split_acme_header;
+
+    #[test]
+    ///
+    /// 6502 branch and indexed read times, block total of a timing loop
+    ///
+    fn cycles_6502() {
+        let source = "\t* = $02F8\n\
+                      ;@cycles delay\n\
+                      \tldx #$10\n\
+                      -\tdex\n\
+                      \tbne -\n\
+                      \tbne +\n\
+                      \t!fill 2\n\
+                      +\n\
+                      ;@end\n\
+                      \tlda $1000,x\n\
+                      \tsta $1000,x\n";
+        let assembly = assemble(CpuKind::Mos6502, source).unwrap();
+        let lines = &assembly.lines;
+        assert_eq!(
+            cycles(CpuKind::Mos6502, lines[4].address, &lines[4].bytes).map(|c| c.to_string()),
+            Some("2/3".to_string())
+        );
+        // bne + at $02FD jumps from $02FF to $0301
+        assert_eq!(
+            cycles(CpuKind::Mos6502, lines[5].address, &lines[5].bytes),
+            Some(Cycles { min: 2, max: 4 })
+        );
+        assert_eq!(
+            blocks(lines, CpuKind::Mos6502),
+            [Block {
+                name: "delay".to_string(),
+                first: 2,
+                last: 9,
+                cycles: Cycles { min: 8, max: 11 },
+            }]
+        );
+        let listing = generate(lines, Some(CpuKind::Mos6502));
+        let listing: Vec<&str> = listing.lines().collect();
+        assert_eq!(listing[2], "02F8  A2 10             2  \tldx #$10");
+        assert_eq!(listing[8], "                    =8/11  ;@end");
+        assert_eq!(listing[9], "0301  BD 00 10        4/5  \tlda $1000,x");
+        assert_eq!(listing[10], "0304  9D 00 10          5  \tsta $1000,x");
+        assert_eq!(listing[13], "  delay: 8/11 (lines 2-9)");
+    }
+
+    #[test]
+    ///
+    /// 8080 states, conditional returns count both ways, nested blocks
+    ///
+    fn cycles_8080() {
+        let source = "\tORG 0\n\
+                      ;@cycles outer\n\
+                      \tMVI B,10\n\
+                      ;@cycles inner\n\
+                      LOOP:\tDCR B\n\
+                      \tJNZ LOOP\n\
+                      ;@end\n\
+                      \tRNZ\n";
+        let assembly = assemble(CpuKind::I8080, source).unwrap();
+        let totals: Vec<(String, Cycles)> = blocks(&assembly.lines, CpuKind::I8080)
+            .into_iter()
+            .map(|block| (block.name, block.cycles))
+            .collect();
+        assert_eq!(
+            totals,
+            [
+                ("inner".to_string(), Cycles { min: 15, max: 15 }),
+                ("outer".to_string(), Cycles { min: 27, max: 33 }),
+            ]
+        );
+    }
+
+    #[test]
+    ///
+    /// Listing of the ACME build of examples/test.a, a changed byte is found
+    ///
+    fn external_build() {
+        let source = std::fs::read_to_string("examples/test.a").unwrap();
+        let file = std::fs::read("examples/test.o").unwrap();
+        let (address, binary) = split_acme_header(&file).unwrap();
+        let lines = from_binary(&source, CpuKind::Mos6502, binary, address).unwrap();
+        assert_eq!(lines.len(), source.lines().count());
+
+        let mut changed = binary.to_vec();
+        let line = lines.iter().find(|line| line.instruction).unwrap();
+        changed[(line.address - address) as usize] ^= 0xFF;
+        let error = from_binary(&source, CpuKind::Mos6502, &changed, address).unwrap_err();
+        assert_eq!(error.line, line.line);
+    }
+}
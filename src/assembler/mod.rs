@@ -0,0 +1,461 @@
+//////////////////////////////////////////////////////////
+/// Two pass assemblers for the emulated CPUs. The first pass collects the
+/// symbols and the size of every statement, the second one evaluates the
+/// operands and emits the bytes. Sizes chosen in the first pass are kept:
+/// an operand that is not known yet (forward reference) gets the long form.
+///
+/// The syntax specific parts are in mos6502.rs (ACME) and i8080.rs (Intel),
+/// this module has the pass driver and the state shared by both.
+///
+/// ```
+/// let assembly = mos6502::assemble("*= $0600\nLDA #$42\nADC #$41\nBRK")?;
+/// cpu.memory.load_image(&assembly.image);
+/// ```
+//////////////////////////////////////////////////////////
+pub mod expression;
+pub mod i8080;
+pub mod listing;
+pub mod mos6502;
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use crate::assembler::expression::{Dialect, Expression};
+use crate::machine::config::CpuKind;
+use crate::memory::{Image, ihex};
+use crate::symbols::SymbolTable;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+///
+/// Source line with the address and the bytes it produced
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Line {
+    pub line: usize,
+    pub address: u16,
+    pub bytes: Vec<u8>,
+    pub source: String,
+    ///
+    /// True if the bytes are an instruction, false for data
+    ///
+    pub instruction: bool,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Assembly {
+    pub image: Image,
+    ///
+    /// Labels and constants that fit into 16 bits, local labels with the
+    /// name of their global label in front
+    ///
+    pub symbols: SymbolTable,
+    pub lines: Vec<Line>,
+}
+
+impl Assembly {
+    ///
+    /// Lowest address with code or data
+    ///
+    pub fn origin(&self) -> Option<u16> {
+        self.image.segments.iter().map(|s| s.address).min()
+    }
+    ///
+    /// Contiguous image from the lowest to the highest address, gaps are
+    /// filled with zeros (like ACME's plain and cbm output)
+    ///
+    pub fn bytes(&self) -> Vec<u8> {
+        let Some(origin) = self.origin() else {
+            return Vec::new();
+        };
+        let end = self
+            .image
+            .segments
+            .iter()
+            .map(|s| s.address as usize + s.data.len())
+            .max()
+            .unwrap_or(0);
+        let mut bytes = vec![0; end - origin as usize];
+        for segment in &self.image.segments {
+            let offset = (segment.address - origin) as usize;
+            bytes[offset..offset + segment.data.len()].copy_from_slice(&segment.data);
+        }
+        bytes
+    }
+    ///
+    /// Segments and the start address (END directive) as Intel HEX
+    ///
+    pub fn to_intel_hex(&self) -> String {
+        ihex::write_image(&self.image)
+    }
+    ///
+    /// Address, bytes and source of every line, see listing.rs
+    ///
+    pub fn listing(&self) -> String {
+        listing::generate(&self.lines, None)
+    }
+}
+
+///
+/// Assembles with the syntax of the CPU: Intel for 8080 and 8085, ACME for
+/// the 6502 family
+///
+pub fn assemble(kind: CpuKind, source: &str) -> Result<Assembly, AsmError> {
+    match kind {
+        CpuKind::I8080 | CpuKind::I8085 => i8080::assemble(source),
+        CpuKind::Mos6502 | CpuKind::Wdc65C02 => mos6502::assemble(source),
+    }
+}
+
+///
+/// State of a pass, handed to the syntax specific statement function
+///
+pub(crate) struct Context {
+    pub pass: u8,
+    pub pc: u32,
+    dialect: Dialect,
+    symbols: HashMap<String, i64>,
+    defined: HashSet<String>,
+    ///
+    /// Global label in front of local labels
+    ///
+    scope: String,
+    ///
+    /// Decisions of the first pass, see remember()
+    ///
+    decisions: Vec<bool>,
+    decision: usize,
+    ///
+    /// Anonymous labels (ACME - and +) by name, found in the first pass,
+    /// and how many of them were passed in the current pass
+    ///
+    anonymous: HashMap<String, Vec<i64>>,
+    passed: HashMap<String, usize>,
+    pub image: Image,
+    bytes: Vec<u8>,
+    instruction: bool,
+    ///
+    /// Set by the end directive, the rest of the source is ignored
+    ///
+    pub done: bool,
+}
+
+impl Context {
+    fn new(dialect: Dialect) -> Self {
+        Self {
+            pass: 0,
+            pc: 0,
+            dialect,
+            symbols: HashMap::new(),
+            defined: HashSet::new(),
+            scope: String::new(),
+            decisions: Vec::new(),
+            decision: 0,
+            anonymous: HashMap::new(),
+            passed: HashMap::new(),
+            image: Image::default(),
+            bytes: Vec::new(),
+            instruction: false,
+            done: false,
+        }
+    }
+    fn start_pass(&mut self, pass: u8) {
+        self.pass = pass;
+        self.pc = 0;
+        self.defined.clear();
+        self.scope.clear();
+        self.decision = 0;
+        self.passed.clear();
+        self.done = false;
+    }
+    fn qualify(&self, name: &str) -> String {
+        if name.starts_with('.') || name.starts_with('@') {
+            format!("{}{}", self.scope, name)
+        } else {
+            name.to_string()
+        }
+    }
+    ///
+    /// Starts a new scope for local labels (.name and @name)
+    ///
+    pub fn set_scope(&mut self, name: &str) {
+        self.scope = name.to_string();
+    }
+    ///
+    /// Defines a symbol, every symbol can be defined once
+    ///
+    pub fn define(&mut self, name: &str, value: i64) -> Result<(), String> {
+        let name = self.qualify(name);
+        if !self.defined.insert(name.clone()) {
+            return Err(format!("symbol '{}' is already defined", name));
+        }
+        self.symbols.insert(name, value);
+        Ok(())
+    }
+    ///
+    /// Defines a label at the current address, global labels open a new scope
+    ///
+    pub fn label(&mut self, name: &str) -> Result<(), String> {
+        self.define(name, self.pc as i64)?;
+        if !name.starts_with('.') && !name.starts_with('@') {
+            self.set_scope(name);
+        }
+        Ok(())
+    }
+    ///
+    /// Defines the next anonymous label called name ("-", "++", ...)
+    ///
+    pub fn anonymous_label(&mut self, name: &str) {
+        let passed = self.passed.entry(name.to_string()).or_default();
+        *passed += 1;
+        if self.pass == 1 {
+            self.anonymous
+                .entry(name.to_string())
+                .or_default()
+                .push(self.pc as i64);
+        }
+    }
+    ///
+    /// Address of the anonymous label: "-" is the last one defined before,
+    /// "+" the next one after the current line
+    ///
+    pub fn anonymous_reference(&self, name: &str) -> Result<Option<i64>, String> {
+        let passed = self.passed.get(name).copied().unwrap_or(0);
+        let index = if name.starts_with('-') {
+            passed.checked_sub(1)
+        } else {
+            Some(passed)
+        };
+        let value = index.and_then(|i| self.anonymous.get(name)?.get(i).copied());
+        match value {
+            None if self.pass == 2 || name.starts_with('-') => {
+                Err(format!("anonymous label '{}' not found", name))
+            }
+            value => Ok(value),
+        }
+    }
+    ///
+    /// Returns decision in the first pass and the value it had then in
+    /// the second pass, keeps the statement sizes of both passes equal
+    ///
+    pub fn remember(&mut self, decision: bool) -> bool {
+        let index = self.decision;
+        self.decision += 1;
+        if self.pass == 1 {
+            self.decisions.push(decision);
+            decision
+        } else {
+            self.decisions[index]
+        }
+    }
+    ///
+    /// Value of an expression, None for undefined symbols in the first pass
+    ///
+    pub fn evaluate(&self, text: &str) -> Result<Option<i64>, String> {
+        let expression = Expression::parse(text, self.dialect).map_err(|err| err.to_string())?;
+        let lookup = |name: &str| self.symbols.get(&self.qualify(name)).copied();
+        let value = expression
+            .evaluate(&lookup, self.pc as u16)
+            .map_err(|err| err.to_string())?;
+        if value.is_none() && self.pass == 2 {
+            let names = expression.symbols();
+            let name = names
+                .iter()
+                .find(|name| lookup(name).is_none())
+                .unwrap_or(&"");
+            if self.dialect == Dialect::Intel && is_intel_hex(name) {
+                return Err(format!(
+                    "undefined symbol '{}', hex numbers start with a digit: 0{}",
+                    name, name
+                ));
+            }
+            return Err(format!("undefined symbol '{}'", name));
+        }
+        Ok(value)
+    }
+    ///
+    /// Value that has to be known in the first pass (origin, sizes)
+    ///
+    pub fn evaluate_now(&self, text: &str) -> Result<i64, String> {
+        self.evaluate(text)?
+            .ok_or_else(|| format!("'{}' must be defined before it is used here", text))
+    }
+    ///
+    /// Puts bytes at the current address
+    ///
+    pub fn emit(&mut self, bytes: &[u8]) -> Result<(), String> {
+        if self.pc as usize + bytes.len() > 0x10000 {
+            return Err("program counter beyond $FFFF".to_string());
+        }
+        if self.pass == 2 && !bytes.is_empty() {
+            self.image.push(self.pc as u16, bytes);
+            self.bytes.extend_from_slice(bytes);
+        }
+        self.pc += bytes.len() as u32;
+        Ok(())
+    }
+    ///
+    /// Skips count bytes without putting anything there
+    ///
+    pub fn reserve(&mut self, count: i64) -> Result<(), String> {
+        let count = usize::try_from(count).map_err(|_| format!("invalid count {}", count))?;
+        if self.pc as usize + count > 0x10000 {
+            return Err("program counter beyond $FFFF".to_string());
+        }
+        self.pc += count as u32;
+        Ok(())
+    }
+    ///
+    /// Puts the bytes of an instruction at the current address
+    ///
+    pub fn emit_instruction(&mut self, bytes: &[u8]) -> Result<(), String> {
+        self.instruction = true;
+        self.emit(bytes)
+    }
+    pub fn set_pc(&mut self, value: i64) -> Result<(), String> {
+        self.pc =
+            u16::try_from(value).map_err(|_| format!("address {} out of range", value))? as u32;
+        Ok(())
+    }
+}
+
+///
+/// Name that is meant as hex number, like FFH instead of 0FFH
+///
+fn is_intel_hex(name: &str) -> bool {
+    name.len() > 1
+        && name.ends_with(['H', 'h'])
+        && name[..name.len() - 1]
+            .chars()
+            .all(|c| c.is_ascii_hexdigit())
+}
+
+///
+/// Byte operand, negative values down to -128 are stored as two's complement
+///
+pub(crate) fn byte(value: i64) -> Result<u8, String> {
+    if (-128..=255).contains(&value) {
+        Ok(value as u8)
+    } else {
+        Err(format!("value {} does not fit into a byte", value))
+    }
+}
+
+pub(crate) fn word(value: i64) -> Result<u16, String> {
+    if (-32768..=65535).contains(&value) {
+        Ok(value as u16)
+    } else {
+        Err(format!("value {} does not fit into a word", value))
+    }
+}
+
+///
+/// Text in front of the comment starting with ';'
+///
+pub(crate) fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    for (i, c) in line.char_indices() {
+        match (quote, c) {
+            (None, ';') => return &line[..i],
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(q), _) if q == c => quote = None,
+            _ => {}
+        }
+    }
+    line
+}
+
+///
+/// Splits at commas outside of quotes and parentheses
+///
+pub(crate) fn split_list(text: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut quote = None;
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, c) in text.char_indices() {
+        match (quote, c) {
+            (Some(q), _) if q == c => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '(') => depth += 1,
+            (None, ')') => depth -= 1,
+            (None, ',') if depth == 0 => {
+                parts.push(text[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(text[start..].trim());
+    parts
+}
+
+///
+/// Contents of a string in double or single quotes with more than one
+/// character (single characters are numbers)
+///
+pub(crate) fn string_literal(text: &str) -> Option<&str> {
+    let quote = text.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+    let inner = text[1..].strip_suffix(quote)?;
+    (inner.chars().count() != 1 || quote == '"').then_some(inner)
+}
+
+///
+/// Runs both passes over the source. statement handles one line without
+/// the comment.
+///
+pub(crate) fn run(
+    source: &str,
+    dialect: Dialect,
+    mut statement: impl FnMut(&mut Context, &str) -> Result<(), String>,
+) -> Result<Assembly, AsmError> {
+    let mut context = Context::new(dialect);
+    let mut lines = Vec::new();
+    for pass in 1..=2 {
+        context.start_pass(pass);
+        for (index, text) in source.lines().enumerate() {
+            let address = context.pc as u16;
+            statement(&mut context, strip_comment(text)).map_err(|message| AsmError {
+                line: index + 1,
+                message,
+            })?;
+            if pass == 2 {
+                lines.push(Line {
+                    line: index + 1,
+                    address,
+                    bytes: std::mem::take(&mut context.bytes),
+                    source: text.to_string(),
+                    instruction: context.instruction,
+                });
+            }
+            context.instruction = false;
+            if context.done {
+                break;
+            }
+        }
+    }
+    let mut symbols = SymbolTable::new();
+    for (name, &value) in &context.symbols {
+        if let Ok(value) = u16::try_from(value) {
+            symbols.insert(name, value);
+        }
+    }
+    Ok(Assembly {
+        image: context.image,
+        symbols,
+        lines,
+    })
+}
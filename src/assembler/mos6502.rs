@@ -0,0 +1,381 @@
+//////////////////////////////////////////////////////////
+/// 6502 assembler with the syntax of ACME. The instruction set comes from
+/// the opcode table of the disassembler.
+///
+///             !cpu 6502
+///     CHROUT  = $FFD2
+///             * = $0600
+///     start   ldx #0
+///     .loop   lda text,x          ; local label, belongs to start
+///             beq +               ; anonymous label, the next '+'
+///             jsr CHROUT
+///             inx
+///             bne .loop
+///     +       rts
+///     text    !text "HELLO", 13, 0
+///
+/// Labels can end with ':'. Local labels start with '.' or '@' and belong
+/// to the global label before them (or the !zone). '-' and '+' labels are
+/// anonymous, references find the previous '-' or the next '+' of the same
+/// length.
+///
+/// Zero page addressing is used when the operand is known in the first pass
+/// and below $100, a postfix forces the size: lda+1 (zero page) or lda+2
+/// (absolute).
+///
+/// Pseudo opcodes: !byte (!by, !08), !word (!wo, !16), !text (!tx, !raw),
+/// !fill (!fi), !zone (!zn) and !cpu 6502. !to and !symbollist (!sl) are
+/// ignored, the caller decides about the output.
+//////////////////////////////////////////////////////////
+use std::collections::HashMap;
+
+use crate::assembler::expression::Dialect;
+use crate::assembler::{AsmError, Assembly, Context, byte, run, split_list, string_literal, word};
+use crate::disassembler::mos6502::{OpcodeDef, opcodes};
+
+///
+/// Opcodes by mnemonic and addressing mode
+///
+type Instructions = HashMap<(String, String), (u8, u8)>;
+
+fn instructions(opcodes: &HashMap<u8, OpcodeDef>) -> Instructions {
+    opcodes
+        .iter()
+        .map(|(&opcode, def)| {
+            let mnemonic = def.mnemonic()[..3].to_string();
+            ((mnemonic, def.mode().to_string()), (opcode, def.bytes()))
+        })
+        .collect()
+}
+
+pub fn assemble(source: &str) -> Result<Assembly, AsmError> {
+    let instructions = instructions(opcodes());
+    run(source, Dialect::Acme, |context, line| {
+        statement(context, &instructions, line)
+    })
+}
+
+fn is_anonymous(name: &str) -> bool {
+    !name.is_empty() && (name.chars().all(|c| c == '-') || name.chars().all(|c| c == '+'))
+}
+
+fn is_name(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_alphabetic() || matches!(c, '_' | '.' | '@'))
+        && name[1..]
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.'))
+}
+
+///
+/// Operand value, anonymous label references included
+///
+fn value(context: &Context, text: &str) -> Result<Option<i64>, String> {
+    if is_anonymous(text) {
+        context.anonymous_reference(text)
+    } else {
+        context.evaluate(text)
+    }
+}
+
+///
+/// Splits the first word from the rest of the line
+///
+fn first_word(text: &str) -> (&str, &str) {
+    let end = text
+        .find(|c: char| c.is_whitespace() || c == ':' || c == '=')
+        .unwrap_or(text.len());
+    (&text[..end], text[end..].trim_start())
+}
+
+fn statement(context: &mut Context, instructions: &Instructions, text: &str) -> Result<(), String> {
+    let text = text.trim();
+    if text.is_empty() {
+        return Ok(());
+    }
+    if let Some(rest) = text.strip_prefix('*') {
+        let rest = rest.trim_start();
+        let Some(expression) = rest.strip_prefix('=') else {
+            return Err("'=' expected after '*'".to_string());
+        };
+        let origin = context.evaluate_now(expression)?;
+        return context.set_pc(origin);
+    }
+    let (word, rest) = first_word(text);
+    if let Some(pseudo) = word.strip_prefix('!') {
+        return pseudo_opcode(context, &pseudo.to_ascii_lowercase(), rest);
+    }
+    let (mnemonic, size) = match word.split_once('+') {
+        Some((mnemonic, size)) if size == "1" || size == "2" => (mnemonic, size.parse().ok()),
+        _ => (word, None),
+    };
+    let mnemonic = mnemonic.to_ascii_uppercase();
+    if mnemonic.len() == 3 && instructions.keys().any(|(m, _)| *m == mnemonic) {
+        return instruction(context, instructions, &mnemonic, size, rest);
+    }
+
+    if is_anonymous(word) {
+        context.anonymous_label(word);
+    } else if !is_name(word) {
+        return Err(format!("unknown statement '{}'", text));
+    } else if let Some(expression) = rest.strip_prefix('=') {
+        if let Some(value) = context.evaluate(expression)? {
+            context.define(word, value)?;
+        }
+        return Ok(());
+    } else {
+        context.label(word)?;
+    }
+    let rest = rest.strip_prefix(':').unwrap_or(rest);
+    statement(context, instructions, rest)
+}
+
+fn pseudo_opcode(context: &mut Context, name: &str, operands: &str) -> Result<(), String> {
+    match name {
+        "byte" | "by" | "08" | "8" => {
+            for item in split_list(operands) {
+                let value = value(context, item)?.unwrap_or(0);
+                context.emit(&[byte(value)?])?;
+            }
+        }
+        "word" | "wo" | "16" => {
+            for item in split_list(operands) {
+                let value = value(context, item)?.unwrap_or(0);
+                context.emit(&word(value)?.to_le_bytes())?;
+            }
+        }
+        "text" | "tx" | "raw" => {
+            for item in split_list(operands) {
+                if let Some(text) = string_literal(item) {
+                    context.emit(text.as_bytes())?;
+                } else {
+                    let value = value(context, item)?.unwrap_or(0);
+                    context.emit(&[byte(value)?])?;
+                }
+            }
+        }
+        "fill" | "fi" => {
+            let items = split_list(operands);
+            let count = context.evaluate_now(items[0])?;
+            let count = usize::try_from(count).map_err(|_| format!("invalid count {}", count))?;
+            let fill = match items.get(1) {
+                Some(item) => byte(value(context, item)?.unwrap_or(0))?,
+                None => 0,
+            };
+            context.emit(&vec![fill; count])?;
+        }
+        "zone" | "zn" => {
+            let name = match operands.trim() {
+                "" => format!("zone{:04X}", context.pc),
+                name => name.to_string(),
+            };
+            context.set_scope(&name);
+        }
+        "cpu" => {
+            if operands.trim() != "6502" {
+                return Err(format!("unsupported cpu '{}'", operands.trim()));
+            }
+        }
+        "to" | "symbollist" | "sl" => {}
+        _ => return Err(format!("unknown pseudo opcode '!{}'", name)),
+    }
+    Ok(())
+}
+
+///
+/// Index of the parenthesis closing the one at the start of text
+///
+fn closing_parenthesis(text: &str) -> Option<usize> {
+    let mut depth = 0;
+    for (i, c) in text.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+///
+/// Addressing mode names of the opcode table and the operand expression
+///
+fn addressing<'a>(operand: &'a str, has: &dyn Fn(&str) -> bool) -> (&'static str, &'a str) {
+    if operand.is_empty() {
+        return (
+            if has("implied") {
+                "implied"
+            } else {
+                "accumulator"
+            },
+            "",
+        );
+    }
+    if operand.eq_ignore_ascii_case("a") && has("accumulator") {
+        return ("accumulator", "");
+    }
+    if let Some(value) = operand.strip_prefix('#') {
+        return ("immediate", value);
+    }
+    if operand.starts_with('(')
+        && let Some(close) = closing_parenthesis(operand)
+    {
+        let inner = &operand[1..close];
+        let after = operand[close + 1..].trim();
+        let parts = split_list(inner);
+        if after.is_empty() && parts.len() == 2 && parts[1].eq_ignore_ascii_case("x") {
+            return ("(indirect,X)", parts[0]);
+        }
+        if let Some(index) = after.strip_prefix(',')
+            && index.trim().eq_ignore_ascii_case("y")
+        {
+            return ("(indirect),Y", inner);
+        }
+        if after.is_empty() && has("indirect") {
+            return ("indirect", inner);
+        }
+    }
+    if has("relative") {
+        return ("relative", operand);
+    }
+    match split_list(operand)[..] {
+        [value, index] if index.eq_ignore_ascii_case("x") => ("absolute,X", value),
+        [value, index] if index.eq_ignore_ascii_case("y") => ("absolute,Y", value),
+        _ => ("absolute", operand),
+    }
+}
+
+fn instruction(
+    context: &mut Context,
+    instructions: &Instructions,
+    mnemonic: &str,
+    size: Option<u8>,
+    operand: &str,
+) -> Result<(), String> {
+    let lookup = |mode: &str| instructions.get(&(mnemonic.to_string(), mode.to_string()));
+    let has = |mode: &str| lookup(mode).is_some();
+    let (mut mode, expression) = addressing(operand.trim(), &has);
+    let mut value = match expression {
+        "" => None,
+        expression => value(context, expression)?,
+    };
+    if let Some(absolute) = mode.strip_prefix("absolute") {
+        let zero_page = format!("zeropage{}", absolute);
+        let fits = value.is_some_and(|value| (0..0x100).contains(&value));
+        let short = match size {
+            Some(1) => true,
+            Some(_) => false,
+            None => !has(mode) || (has(&zero_page) && fits),
+        };
+        if context.remember(short) {
+            mode = match absolute {
+                ",X" => "zeropage,X",
+                ",Y" => "zeropage,Y",
+                _ => "zeropage",
+            };
+        }
+    }
+    let Some(&(opcode, length)) = lookup(mode) else {
+        return Err(format!("{} does not support {} addressing", mnemonic, mode));
+    };
+    if mode == "relative"
+        && let Some(target) = value
+    {
+        let offset = target - (context.pc as i64 + 2);
+        if context.pass == 2 && !(-128..=127).contains(&offset) {
+            return Err(format!("branch target {} bytes away, out of range", offset));
+        }
+        value = Some(offset & 0xFF);
+    }
+    let value = value.unwrap_or(0);
+    match length {
+        1 => context.emit_instruction(&[opcode]),
+        2 => context.emit_instruction(&[opcode, byte(value)?]),
+        _ => {
+            let [low, high] = word(value)?.to_le_bytes();
+            context.emit_instruction(&[opcode, low, high])
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disassembler::mos6502::{code_map, to_source};
+    use crate::memory::{Memory, split_acme_header};
+    use crate::symbols::SymbolTable;
+
+    #[test]
+    ///
+    /// Immediate, implied and branch instructions with labels
+    ///
+    fn assemble_program() {
+        let assembly = assemble("LDA #$42\nADC #$41\nBRK").unwrap();
+        assert_eq!(assembly.bytes(), [0xA9, 0x42, 0x69, 0x41, 0x00]);
+
+        let source = "\t* = $0600\n\
+                      start\tldx #0\n\
+                      .loop\tlda text,x\n\
+                      \tbeq +\n\
+                      \tsta $fb\n\
+                      \tsta+2 $fb\n\
+                      \tinx\n\
+                      \tbne .loop\n\
+                      +\tjmp (vector)\n\
+                      text\t!text \"HI\", 0\n\
+                      vector\t!word start\n";
+        let assembly = assemble(source).unwrap();
+        assert_eq!(assembly.origin(), Some(0x0600));
+        assert_eq!(
+            assembly.bytes(),
+            [
+                0xA2, 0x00, 0xBD, 0x12, 0x06, 0xF0, 0x08, 0x85, 0xFB, 0x8D, 0xFB, 0x00, 0xE8, 0xD0,
+                0xF3, 0x6C, 0x15, 0x06, 0x48, 0x49, 0x00, 0x00, 0x06,
+            ]
+        );
+        assert_eq!(assembly.symbols.address_of("start.loop"), Some(0x0602));
+        assert_eq!(assembly.lines[2].bytes, [0xBD, 0x12, 0x06]);
+
+        let error = assemble("\tnop\n\tbne far\n\t!fill 200\nfar\trts").unwrap_err();
+        assert_eq!(error.line, 2);
+        assert!(assemble("\tlda undefined").is_err());
+    }
+
+    #[test]
+    ///
+    /// examples/test.a gives the same bytes as ACME (examples/test.o)
+    ///
+    fn assemble_example() {
+        let source = std::fs::read_to_string("examples/test.a").unwrap();
+        let expected = std::fs::read("examples/test.o").unwrap();
+        let (address, payload) = split_acme_header(&expected).unwrap();
+        let assembly = assemble(&source).unwrap();
+        assert_eq!(assembly.origin(), Some(address));
+        assert_eq!(assembly.bytes(), payload);
+    }
+
+    #[test]
+    ///
+    /// Source written by the disassembler assembles to the same bytes
+    ///
+    fn round_trip() {
+        let mut memory = Memory::new();
+        let program = [0xA5, 0x10, 0x8D, 0x34, 0x00, 0xD0, 0xFA, 0x60, 0x01, 0x02];
+        memory.load_program(&program, 0xC000);
+        let map = code_map(&memory, 0xC000, 0xC00A, Some(&[0xC000]), opcodes());
+        let source = to_source(
+            &memory,
+            0xC000,
+            0xC00A,
+            &map,
+            opcodes(),
+            &SymbolTable::new(),
+        );
+        let assembly = assemble(&source).unwrap();
+        assert_eq!(assembly.bytes(), program);
+    }
+}
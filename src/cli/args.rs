@@ -0,0 +1,499 @@
+//////////////////////////////////////////////////////////
+/// Command line parser of the front end. Options are "--name value" or
+/// "--name=value", the only positional argument is the input file.
+/// Addresses and bytes are hex ($0200, 0x0200, 0200H or just 0200),
+/// counts are decimal.
+//////////////////////////////////////////////////////////
+use std::path::{Path, PathBuf};
+
+use super::CliError;
+use crate::machine::config::{CpuKind, ImageFormat};
+
+///
+/// Instructions executed by run when no limit is given, a program that
+/// never stops ends instead of hanging the script
+///
+pub const DEFAULT_MAX_STEPS: u64 = 10_000_000;
+
+///
+/// Options followed by a value
+///
+const VALUE_OPTIONS: [&str; 27] = [
+    "cpu",
+    "machine",
+    "format",
+    "load",
+    "ld65-config",
+    "start",
+    "stop-opcode",
+    "stop-at",
+    "max-steps",
+    "max-cycles",
+    "dump",
+    "trace-format",
+    "symbols",
+    "from",
+    "to",
+    "entry",
+    "gdb",
+    "vectors",
+    "tables",
+    "cpm",
+    "spec",
+    "snapshot",
+    "save-snapshot",
+    "output",
+    "export",
+    "binary",
+    "save-symbols",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    Run,
+    Asm,
+    Disasm,
+    Opcodes,
+    Debug,
+    Test,
+    Batch,
+    Help,
+}
+
+///
+/// Where --trace writes the executed instructions
+///
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TraceFormat {
+    ///
+    /// One line per instruction on stdout
+    ///
+    #[default]
+    Text,
+    ///
+    /// One JSON object per instruction on stdout
+    ///
+    Json,
+    ///
+    /// Only the last instructions, printed after the run
+    ///
+    Ring,
+}
+
+///
+/// When run stops, checked before every instruction
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StopConditions {
+    ///
+    /// HLT on the 8080, BRK on the 6502
+    ///
+    pub halt: bool,
+    pub opcodes: Vec<u8>,
+    pub addresses: Vec<u16>,
+    pub max_steps: u64,
+    pub max_cycles: Option<u64>,
+}
+
+impl Default for StopConditions {
+    fn default() -> Self {
+        Self {
+            halt: true,
+            opcodes: Vec::new(),
+            addresses: Vec::new(),
+            max_steps: DEFAULT_MAX_STEPS,
+            max_cycles: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Options {
+    pub cpu: Option<CpuKind>,
+    pub machine: Option<PathBuf>,
+    pub input: Option<PathBuf>,
+    ///
+    /// None takes the format from the extension of the input
+    ///
+    pub format: Option<ImageFormat>,
+    pub load: Option<u16>,
+    ///
+    /// ld65 config whose MEMORY areas place a raw input
+    ///
+    pub ld65_config: Option<PathBuf>,
+    pub start: Option<u16>,
+    pub stop: StopConditions,
+    ///
+    /// Inclusive address ranges printed after run
+    ///
+    pub dumps: Vec<(u16, u16)>,
+    pub trace: bool,
+    pub trace_format: TraceFormat,
+    pub quiet: bool,
+    ///
+    /// Symbol files of disasm and debug, merged in the given order
+    ///
+    pub symbols: Vec<PathBuf>,
+    ///
+    /// disasm range, to is exclusive
+    ///
+    pub from: Option<u16>,
+    pub to: Option<u16>,
+    ///
+    /// disasm --trace: entry points besides the start address and the vectors
+    ///
+    pub entries: Vec<u16>,
+    ///
+    /// disasm: source for the assembler instead of the listing
+    ///
+    pub source: bool,
+    ///
+    /// disasm: neither symbols nor generated labels
+    ///
+    pub no_labels: bool,
+    pub gdb: Option<u16>,
+    pub vectors: Option<PathBuf>,
+    pub tables: Option<PathBuf>,
+    pub cpm: Option<PathBuf>,
+    ///
+    /// Assertions checked by batch after the run
+    ///
+    pub spec: Option<PathBuf>,
+    ///
+    /// State restored before the input is loaded
+    ///
+    pub snapshot: Option<PathBuf>,
+    ///
+    /// State saved by run and batch after the run
+    ///
+    pub save_snapshot: Option<PathBuf>,
+    ///
+    /// Image written by asm, run and batch, the format comes from --format
+    /// or the extension
+    ///
+    pub output: Option<PathBuf>,
+    ///
+    /// Inclusive address range run and batch write to --output
+    ///
+    pub export: Option<(u16, u16)>,
+    ///
+    /// asm: program built by an external assembler, the listing shows its bytes
+    ///
+    pub binary: Option<PathBuf>,
+    ///
+    /// asm: symbol list of the program, readable by --symbols
+    ///
+    pub save_symbols: Option<PathBuf>,
+    ///
+    /// asm: cycles of every line and of the marked blocks in the listing
+    ///
+    pub cycles: bool,
+    ///
+    /// An input past $FFFF continues at $0000 instead of an error
+    ///
+    pub wrap: bool,
+}
+
+impl Options {
+    ///
+    /// CPU of --cpu, an error if there is none
+    ///
+    pub fn cpu_kind(&self) -> Result<CpuKind, CliError> {
+        self.cpu
+            .ok_or_else(|| CliError::Usage("--cpu is required".to_string()))
+    }
+}
+
+fn usage(message: String) -> CliError {
+    CliError::Usage(message)
+}
+
+///
+/// Hex value with an optional $ or 0x prefix or H suffix
+///
+pub fn hex(text: &str) -> Option<u32> {
+    let digits = text
+        .strip_prefix('$')
+        .or_else(|| text.strip_prefix("0x"))
+        .or_else(|| text.strip_prefix("0X"))
+        .or_else(|| text.strip_suffix('H'))
+        .or_else(|| text.strip_suffix('h'))
+        .unwrap_or(text);
+    u32::from_str_radix(digits, 16).ok()
+}
+
+pub fn address(option: &str, text: &str) -> Result<u16, CliError> {
+    hex(text)
+        .and_then(|value| u16::try_from(value).ok())
+        .ok_or_else(|| usage(format!("{}: '{}' is not an address", option, text)))
+}
+
+pub fn byte(option: &str, text: &str) -> Result<u8, CliError> {
+    hex(text)
+        .and_then(|value| u8::try_from(value).ok())
+        .ok_or_else(|| usage(format!("{}: '{}' is not a byte", option, text)))
+}
+
+fn count(option: &str, text: &str) -> Result<u64, CliError> {
+    text.replace('_', "")
+        .parse()
+        .map_err(|_| usage(format!("{}: '{}' is not a number", option, text)))
+}
+
+///
+/// Inclusive range written as START-END
+///
+pub fn range(option: &str, text: &str) -> Result<(u16, u16), CliError> {
+    let (start, end) = text
+        .split_once('-')
+        .ok_or_else(|| usage(format!("{}: '{}' is not a range START-END", option, text)))?;
+    let (start, end) = (address(option, start)?, address(option, end)?);
+    if start > end {
+        return Err(usage(format!(
+            "{}: range '{}' ends before it starts",
+            option, text
+        )));
+    }
+    Ok((start, end))
+}
+
+fn cpu(text: &str) -> Result<CpuKind, CliError> {
+    match text.to_ascii_lowercase().as_str() {
+        "8080" | "i8080" => Ok(CpuKind::I8080),
+        "8085" | "i8085" => Ok(CpuKind::I8085),
+        "6502" | "mos6502" => Ok(CpuKind::Mos6502),
+        "65c02" => Ok(CpuKind::Wdc65C02),
+        _ => Err(usage(format!("--cpu: unknown CPU '{}'", text))),
+    }
+}
+
+fn format(text: &str) -> Result<ImageFormat, CliError> {
+    match text.to_ascii_lowercase().as_str() {
+        "raw" | "bin" => Ok(ImageFormat::Raw),
+        "acme" | "prg" => Ok(ImageFormat::Acme),
+        "ihex" | "hex" => Ok(ImageFormat::Ihex),
+        "srec" => Ok(ImageFormat::Srec),
+        _ => Err(usage(format!("--format: unknown format '{}'", text))),
+    }
+}
+
+///
+/// Format of an input file by its extension, raw if the extension is unknown
+///
+pub fn format_of(path: &Path) -> ImageFormat {
+    let extension = path
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_ascii_lowercase());
+    match extension.as_deref() {
+        Some("hex" | "ihx" | "ihex") => ImageFormat::Ihex,
+        Some("s19" | "s28" | "s37" | "srec" | "mot") => ImageFormat::Srec,
+        Some("o" | "prg") => ImageFormat::Acme,
+        _ => ImageFormat::Raw,
+    }
+}
+
+fn trace_format(text: &str) -> Result<TraceFormat, CliError> {
+    match text.to_ascii_lowercase().as_str() {
+        "text" => Ok(TraceFormat::Text),
+        "json" => Ok(TraceFormat::Json),
+        "ring" => Ok(TraceFormat::Ring),
+        _ => Err(usage(format!("--trace-format: unknown format '{}'", text))),
+    }
+}
+
+fn command(name: &str) -> Result<Command, CliError> {
+    match name {
+        "run" => Ok(Command::Run),
+        "asm" => Ok(Command::Asm),
+        "disasm" => Ok(Command::Disasm),
+        "opcodes" => Ok(Command::Opcodes),
+        "debug" => Ok(Command::Debug),
+        "test" => Ok(Command::Test),
+        "batch" => Ok(Command::Batch),
+        "help" | "-h" | "--help" => Ok(Command::Help),
+        _ => Err(usage(format!("unknown command '{}'", name))),
+    }
+}
+
+///
+/// Command and options of the arguments (without the program name)
+///
+pub fn parse(args: &[String]) -> Result<(Command, Options), CliError> {
+    let Some((name, args)) = args.split_first() else {
+        return Ok((Command::Help, Options::default()));
+    };
+    let command = command(name)?;
+    let mut options = Options::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let Some(option) = arg.strip_prefix("--") else {
+            if options.input.is_some() {
+                return Err(usage(format!("more than one input file: '{}'", arg)));
+            }
+            options.input = Some(PathBuf::from(arg));
+            continue;
+        };
+        // switches without a value
+        match option {
+            "no-halt" => {
+                options.stop.halt = false;
+                continue;
+            }
+            "trace" => {
+                options.trace = true;
+                continue;
+            }
+            "quiet" => {
+                options.quiet = true;
+                continue;
+            }
+            "cycles" => {
+                options.cycles = true;
+                continue;
+            }
+            "source" => {
+                options.source = true;
+                continue;
+            }
+            "no-labels" => {
+                options.no_labels = true;
+                continue;
+            }
+            "wrap" => {
+                options.wrap = true;
+                continue;
+            }
+            "help" => return Ok((Command::Help, options)),
+            _ => {}
+        }
+        let (option, value) = match option.split_once('=') {
+            Some((option, value)) => (option, value.to_string()),
+            None if !VALUE_OPTIONS.contains(&option) => {
+                return Err(usage(format!("unknown option '{}'", arg)));
+            }
+            None => {
+                let value = args
+                    .next()
+                    .ok_or_else(|| usage(format!("--{} needs a value", option)))?;
+                (option, value.clone())
+            }
+        };
+        let name = format!("--{}", option);
+        let value = value.as_str();
+        match option {
+            "cpu" => options.cpu = Some(cpu(value)?),
+            "machine" => options.machine = Some(PathBuf::from(value)),
+            "format" => options.format = Some(format(value)?),
+            "load" => options.load = Some(address(&name, value)?),
+            "ld65-config" => options.ld65_config = Some(PathBuf::from(value)),
+            "start" => options.start = Some(address(&name, value)?),
+            "stop-opcode" => options.stop.opcodes.push(byte(&name, value)?),
+            "stop-at" => options.stop.addresses.push(address(&name, value)?),
+            "max-steps" => options.stop.max_steps = count(&name, value)?,
+            "max-cycles" => options.stop.max_cycles = Some(count(&name, value)?),
+            "dump" => options.dumps.push(range(&name, value)?),
+            "trace-format" => {
+                options.trace_format = trace_format(value)?;
+                options.trace = true;
+            }
+            "symbols" => options.symbols.push(PathBuf::from(value)),
+            "from" => options.from = Some(address(&name, value)?),
+            "to" => options.to = Some(address(&name, value)?),
+            "entry" => options.entries.push(address(&name, value)?),
+            "gdb" => {
+                let port = count(&name, value)?;
+                let port = u16::try_from(port)
+                    .map_err(|_| usage(format!("{}: '{}' is not a port", name, value)))?;
+                options.gdb = Some(port);
+            }
+            "vectors" => options.vectors = Some(PathBuf::from(value)),
+            "tables" => options.tables = Some(PathBuf::from(value)),
+            "cpm" => options.cpm = Some(PathBuf::from(value)),
+            "spec" => options.spec = Some(PathBuf::from(value)),
+            "snapshot" => options.snapshot = Some(PathBuf::from(value)),
+            "save-snapshot" => options.save_snapshot = Some(PathBuf::from(value)),
+            "output" => options.output = Some(PathBuf::from(value)),
+            "export" => options.export = Some(range(&name, value)?),
+            "binary" => options.binary = Some(PathBuf::from(value)),
+            "save-symbols" => options.save_symbols = Some(PathBuf::from(value)),
+            _ => return Err(usage(format!("unknown option '{}'", name))),
+        }
+    }
+    Ok((command, options))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(text: &str) -> Vec<String> {
+        text.split_whitespace().map(str::to_string).collect()
+    }
+
+    #[test]
+    ///
+    /// All options of run, hex addresses in every notation
+    ///
+    fn parse_run() {
+        let (command, options) = parse(&args(
+            "run --cpu 6502 test.bin --load $0200 --start=0x0210 --stop-opcode FF \
+             --stop-at 0300H --stop-at 0400 --max-cycles 1_000 --dump 0200-020F --no-halt",
+        ))
+        .unwrap();
+        assert_eq!(command, Command::Run);
+        assert_eq!(options.cpu, Some(CpuKind::Mos6502));
+        assert_eq!(options.input, Some(PathBuf::from("test.bin")));
+        assert_eq!((options.load, options.start), (Some(0x0200), Some(0x0210)));
+        assert_eq!(
+            options.stop,
+            StopConditions {
+                halt: false,
+                opcodes: vec![0xFF],
+                addresses: vec![0x0300, 0x0400],
+                max_steps: DEFAULT_MAX_STEPS,
+                max_cycles: Some(1000),
+            }
+        );
+        assert_eq!(options.dumps, [(0x0200, 0x020F)]);
+    }
+
+    #[test]
+    ///
+    /// Help without arguments, formats from the extension
+    ///
+    fn defaults() {
+        assert_eq!(parse(&[]).unwrap().0, Command::Help);
+        let (_, options) = parse(&args("disasm --cpu 8080 x.hex")).unwrap();
+        assert!(options.stop.halt);
+        assert_eq!(options.format, None);
+        assert_eq!(format_of(Path::new("x.hex")), ImageFormat::Ihex);
+        assert_eq!(format_of(Path::new("x.S19")), ImageFormat::Srec);
+        assert_eq!(format_of(Path::new("test.o")), ImageFormat::Acme);
+        assert_eq!(format_of(Path::new("x.com")), ImageFormat::Raw);
+    }
+
+    #[test]
+    ///
+    /// Errors name the option and the value
+    ///
+    fn errors() {
+        let error = |text: &str| parse(&args(text)).unwrap_err().to_string();
+        assert_eq!(error("go"), "unknown command 'go'");
+        assert_eq!(error("run --cpu z80"), "--cpu: unknown CPU 'z80'");
+        assert_eq!(error("run --load"), "--load needs a value");
+        assert_eq!(
+            error("run --load 10000"),
+            "--load: '10000' is not an address"
+        );
+        assert_eq!(
+            error("run --stop-opcode 100"),
+            "--stop-opcode: '100' is not a byte"
+        );
+        assert_eq!(
+            error("run --dump 0300-0200"),
+            "--dump: range '0300-0200' ends before it starts"
+        );
+        assert_eq!(error("run a b"), "more than one input file: 'b'");
+        assert_eq!(error("run --fast"), "unknown option '--fast'");
+    }
+}
@@ -0,0 +1,862 @@
+//////////////////////////////////////////////////////////
+/// Command line front end, one binary for all tools:
+///
+///     sbc8micro run --cpu 6502 examples/test.o --start 0200 --no-halt --stop-opcode FF
+///     sbc8micro asm --cpu 8080 monitor.asm --output monitor.hex --cycles
+///     sbc8micro disasm --cpu 8080 monitor.hex --from 0000 --to 0400
+///     sbc8micro opcodes --cpu 6502
+///     sbc8micro debug --machine pmi80.json --gdb 1234
+///     sbc8micro test --cpu 8080 --cpm tests/8080/TST8080.COM
+///     sbc8micro batch --cpu 8080 examples/aci.hex --spec examples/aci.spec
+///
+/// Exit status: 0 if the program reached a stop condition (or all tests or
+/// assertions passed), 1 if it ran into a limit (or a test or assertion
+/// failed), 2 for errors in the arguments or the input files. See USAGE for all options.
+//////////////////////////////////////////////////////////
+pub mod args;
+pub mod run;
+pub mod spec;
+
+use std::fmt;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use crate::assembler::{self, listing};
+use crate::cpu::{i8080_tables, single_step};
+use crate::debugger;
+use crate::disassembler::{self, opcode_viewer};
+use crate::machine::config::{CpuKind, ImageFormat};
+use crate::machine::cpm::{self, Cpm};
+use crate::memory::{Memory, Overflow, Segment};
+use crate::symbols::{self, SymbolTable};
+use args::{Command, Options};
+
+pub const EXIT_SUCCESS: i32 = 0;
+pub const EXIT_FAILURE: i32 = 1;
+pub const EXIT_ERROR: i32 = 2;
+
+pub const USAGE: &str = "\
+usage: sbc8micro <command> [options] [input file]
+
+commands:
+  run       run the program until a stop condition, print registers and dumps
+  asm       assemble the input (ACME syntax for the 6502, Intel for the
+            8080), print the listing and write --output
+  disasm    disassemble the input (every loaded block, or --from/--to)
+  opcodes   browse the opcode table of the CPU
+  debug     start the debugger on the loaded program (--gdb for a GDB stub)
+  test      run test vectors (--vectors), flag tables (--tables) or a CP/M
+            exerciser (--cpm)
+  batch     run the program until a stop condition, then check the
+            assertions of --spec, print a diff of the failed ones
+  help      show this text
+
+options:
+  --cpu 8080|6502        CPU core
+  --machine FILE         machine description (CPU, memory map, images),
+                         run and batch print its summary
+  --format FORMAT        raw, acme, ihex or srec, default from the file
+                         extension (.hex .ihx: ihex, .s19 .srec .mot: srec,
+                         .o .prg: acme, others: raw)
+  --load ADDR            load address of raw and acme images
+                         (default 0100 on the 8080, 0200 on the 6502)
+  --wrap                 an input past FFFF continues at 0000 instead of
+                         an error
+  --ld65-config FILE     place a raw input built by ld65 by the MEMORY
+                         areas of its config
+  --start ADDR           start address (default: start record of the file
+                         or the load address)
+  --no-halt              do not stop on HLT (8080) or BRK (6502)
+  --stop-opcode XX       stop before an instruction with this opcode
+  --stop-at ADDR         stop when PC reaches the address
+  --max-steps N          stop after N instructions (default 10000000)
+  --max-cycles N         stop after N cycles
+  --dump START-END       print the memory range after run or when debug
+                         ends (repeatable)
+  --trace                run: print every instruction while running
+  --trace-format FORMAT  text (default), json (one object per line) or ring
+                         (only the last instructions, printed after the run)
+  --quiet                print only the dumps after run
+  --symbols FILE         symbols for disasm and debug (repeatable), debug
+                         commands take their names as addresses
+  --output FILE          asm: image to write, run, batch: file of --export,
+                         the format comes from --format or the extension
+                         like for the input
+  --export START-END     run, batch: write the memory range to --output
+                         after the run (--start is the start address)
+  --cycles               asm: cycles of every line and of the blocks between
+                         ;@cycles NAME and ;@end in the listing
+  --binary FILE          asm: listing of a program built by another
+                         assembler, its bytes have to match the source
+  --save-symbols FILE    asm: write the labels as ACME symbol list
+  --from ADDR --to ADDR  disasm range, --to is exclusive
+  --trace                disasm: follow the control flow from the start
+                         address, the vectors and --entry, bytes not
+                         reached are data
+  --entry ADDR           disasm: entry point for --trace (repeatable)
+  --source               disasm: source for the assembler instead of the
+                         listing (ACME or Intel syntax)
+  --no-labels            disasm: no symbols and no generated labels
+  --gdb PORT             debug: wait for GDB on localhost:PORT
+  --vectors DIR          test: SingleStepTests JSON files of the CPU
+  --tables FILE          test: 8080 flag tables like documents/test_notes_i8080.txt
+  --cpm FILE             test: 8080 CP/M program using BDOS console output
+  --snapshot FILE        restore a saved state, the input is loaded on top
+                         (--cpu is taken from the snapshot if not given)
+  --save-snapshot FILE   run, batch: save the state after the run
+  --spec FILE            batch: assertions, one per line:
+                           A == $83
+                           mem[$0200..$0203] == 01 02 03
+                           flags.C == 1
+
+Addresses and bytes are hex ($0200, 0x0200, 0200H or 0200), counts decimal.
+Exit status: 0 stop condition reached or tests passed, 1 limit reached or
+tests or assertions failed, 2 error in the arguments, the input or the spec.
+";
+
+#[derive(Debug)]
+pub enum CliError {
+    Usage(String),
+    Io(PathBuf, io::Error),
+    Load(String),
+    Frontend(String),
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CliError::Usage(message) => write!(f, "{}", message),
+            CliError::Io(path, err) => write!(f, "{}: {}", path.display(), err),
+            CliError::Load(message) => write!(f, "{}", message),
+            CliError::Frontend(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for CliError {}
+
+impl From<io::Error> for CliError {
+    fn from(err: io::Error) -> Self {
+        CliError::Io(PathBuf::from("<stdout>"), err)
+    }
+}
+
+///
+/// Runs the command of the arguments (without the program name) and
+/// returns the exit status
+///
+pub fn main(args: &[String]) -> i32 {
+    let result = args::parse(args)
+        .and_then(|(command, options)| execute(command, &options, &mut io::stdout()));
+    match result {
+        Ok(status) => status,
+        Err(err) => {
+            eprintln!("sbc8micro: {}", err);
+            if matches!(err, CliError::Usage(_)) {
+                eprintln!("try 'sbc8micro help'");
+            }
+            EXIT_ERROR
+        }
+    }
+}
+
+pub fn execute(command: Command, options: &Options, out: &mut dyn Write) -> Result<i32, CliError> {
+    match command {
+        Command::Run => run(options, out),
+        Command::Asm => asm(options, out),
+        Command::Disasm => disasm(options, out),
+        Command::Opcodes => opcodes(options),
+        Command::Debug => debug(options),
+        Command::Test => test(options, out),
+        Command::Batch => batch(options, out),
+        Command::Help => {
+            write!(out, "{}", USAGE)?;
+            Ok(EXIT_SUCCESS)
+        }
+    }
+}
+
+fn require_input(options: &Options) -> Result<(), CliError> {
+    if options.input.is_none() && options.machine.is_none() && options.snapshot.is_none() {
+        return Err(CliError::Usage("no input file".to_string()));
+    }
+    Ok(())
+}
+
+fn run(options: &Options, out: &mut dyn Write) -> Result<i32, CliError> {
+    require_input(options)?;
+    let mut program = run::load(options)?;
+    let cpu = &mut program.cpu;
+    let outcome = run::run(cpu.as_mut(), &options.stop);
+    if !options.quiet {
+        if let Some(info) = &program.machine_info {
+            write!(out, "{}", info)?;
+        }
+        writeln!(out, "{}", outcome)?;
+        write!(out, "{}", cpu.print_registers())?;
+    }
+    for &range in &options.dumps {
+        for line in run::dump(cpu.as_ref(), range) {
+            writeln!(out, "{}", line)?;
+        }
+    }
+    run::print_ring(&program, out)?;
+    run::save_snapshot(&program, options)?;
+    run::export(&program, options)?;
+    if outcome.stop.is_condition() {
+        Ok(EXIT_SUCCESS)
+    } else {
+        eprintln!("sbc8micro: {}", outcome);
+        Ok(EXIT_FAILURE)
+    }
+}
+
+fn batch(options: &Options, out: &mut dyn Write) -> Result<i32, CliError> {
+    require_input(options)?;
+    let path = options
+        .spec
+        .as_ref()
+        .ok_or_else(|| CliError::Usage("batch needs --spec".to_string()))?;
+    let text = std::fs::read_to_string(path).map_err(|err| CliError::Io(path.clone(), err))?;
+    let assertions = spec::parse(&text).map_err(|err| load_error(path, err))?;
+    let mut program = run::load(options)?;
+    let cpu = &mut program.cpu;
+    let outcome = run::run(cpu.as_mut(), &options.stop);
+    if !options.quiet {
+        if let Some(info) = &program.machine_info {
+            write!(out, "{}", info)?;
+        }
+        writeln!(out, "{}", outcome)?;
+    }
+    for &range in &options.dumps {
+        for line in run::dump(cpu.as_ref(), range) {
+            writeln!(out, "{}", line)?;
+        }
+    }
+    run::print_ring(&program, out)?;
+    run::save_snapshot(&program, options)?;
+    run::export(&program, options)?;
+    if !outcome.stop.is_condition() {
+        eprintln!("sbc8micro: {}", outcome);
+    }
+    let failures =
+        spec::check(program.cpu.as_ref(), &assertions).map_err(|err| load_error(path, err))?;
+    for failure in &failures {
+        writeln!(out, "{}", failure)?;
+    }
+    if failures.is_empty() {
+        if !options.quiet {
+            writeln!(out, "{} assertions passed", assertions.len())?;
+        }
+    } else {
+        writeln!(
+            out,
+            "{} of {} assertions failed",
+            failures.len(),
+            assertions.len()
+        )?;
+    }
+    Ok(status(outcome.stop.is_condition() && failures.is_empty()))
+}
+
+fn asm(options: &Options, out: &mut dyn Write) -> Result<i32, CliError> {
+    let kind = options.cpu_kind()?;
+    let path = options
+        .input
+        .as_ref()
+        .ok_or_else(|| CliError::Usage("no input file".to_string()))?;
+    let source = std::fs::read_to_string(path).map_err(|err| CliError::Io(path.clone(), err))?;
+    let assembly = assembler::assemble(kind, &source).map_err(|err| load_error(path, err))?;
+    let lines = match &options.binary {
+        // the bytes of the binary are compared at their load address
+        Some(binary) => {
+            let mut memory = Memory::new();
+            run::read_image(
+                &mut memory,
+                binary,
+                args::format_of(binary),
+                options.load,
+                kind,
+                Overflow::Error,
+            )?;
+            listing::from_binary(&source, kind, memory.data(), 0)
+                .map_err(|err| load_error(binary, err))?
+        }
+        None => assembly.lines.clone(),
+    };
+    if !options.quiet {
+        if options.cycles {
+            write!(out, "{}", listing::generate(&lines, Some(kind)))?;
+        } else if options.binary.is_some() {
+            write!(out, "{}", listing::generate(&lines, None))?;
+        } else {
+            write!(out, "{}", assembly.listing())?;
+        }
+    }
+    if let Some(output) = &options.output {
+        let format = options.format.unwrap_or_else(|| args::format_of(output));
+        let data = match (format, assembly.origin()) {
+            (_, None) => {
+                return Err(CliError::Load(format!(
+                    "{}: no code or data to write",
+                    path.display()
+                )));
+            }
+            (ImageFormat::Raw, Some(_)) => assembly.bytes(),
+            (ImageFormat::Acme, Some(origin)) => {
+                let mut data = origin.to_le_bytes().to_vec();
+                data.extend(assembly.bytes());
+                data
+            }
+            (ImageFormat::Ihex, Some(_)) => assembly.to_intel_hex().into_bytes(),
+            (ImageFormat::Srec, Some(origin)) => {
+                let mut memory = Memory::new();
+                memory.load_image(&assembly.image);
+                let end = origin as usize + assembly.bytes().len() - 1;
+                memory
+                    .to_srecord(origin, end as u16, assembly.image.start)
+                    .into_bytes()
+            }
+        };
+        std::fs::write(output, data).map_err(|err| CliError::Io(output.clone(), err))?;
+    }
+    if let Some(path) = &options.save_symbols {
+        std::fs::write(path, symbols::acme::write_symbol_list(&assembly.symbols))
+            .map_err(|err| CliError::Io(path.clone(), err))?;
+    }
+    Ok(EXIT_SUCCESS)
+}
+
+fn disasm(options: &Options, out: &mut dyn Write) -> Result<i32, CliError> {
+    require_input(options)?;
+    let run::Program { cpu, image, .. } = run::load(options)?;
+    // every loaded block, or one range from --from / --to
+    let segments = image.map(|image| image.segments).unwrap_or_default();
+    // end is exclusive, 0x10000 for a segment that ends at $FFFF
+    let segment_end = |segment: &Segment| segment.address as u32 + segment.data.len() as u32;
+    let image_end = segments.iter().map(segment_end).max();
+    let ranges: Vec<(u16, u32)> = match (options.from, options.to) {
+        (None, None) if !segments.is_empty() => segments
+            .iter()
+            .map(|segment| (segment.address, segment_end(segment)))
+            .collect(),
+        (from, to) => {
+            let to = to.map(u32::from).or(image_end).ok_or_else(|| {
+                CliError::Usage("--to is required without input file".to_string())
+            })?;
+            vec![(from.unwrap_or(cpu.pc()), to)]
+        }
+    };
+    let symbols = load_symbols(options)?;
+    if options.source && ranges.len() > 1 {
+        return Err(CliError::Usage(
+            "--source needs one block, choose it with --from/--to".to_string(),
+        ));
+    }
+    let memory = cpu.memory();
+    // --trace follows the control flow from the start address, the
+    // vectors of the CPU and --entry
+    let mut entries = options.entries.clone();
+    entries.push(cpu.pc());
+    match cpu.kind() {
+        CpuKind::I8080 | CpuKind::I8085 => entries.extend(disassembler::i8080::vectors()),
+        CpuKind::Mos6502 | CpuKind::Wdc65C02 => {
+            entries.extend(disassembler::mos6502::vectors(memory))
+        }
+    }
+    let traced = options.trace.then_some(entries.as_slice());
+    for (start, end) in ranges {
+        let text = match cpu.kind() {
+            CpuKind::I8080 | CpuKind::I8085 => {
+                use disassembler::i8080::{
+                    auto_labels, code_map, disassemble, disassemble_traced,
+                    disassemble_with_symbols, opcodes, to_source,
+                };
+                if options.source {
+                    let map = code_map(memory, start, end, traced, opcodes());
+                    to_source(memory, start, end, &map, opcodes(), &symbols)
+                } else if options.trace {
+                    lines(disassemble_traced(
+                        memory,
+                        start,
+                        end,
+                        &entries,
+                        opcodes(),
+                        &symbols,
+                    ))
+                } else if options.no_labels {
+                    lines(disassemble(memory, start, end, opcodes()))
+                } else {
+                    let labels = auto_labels(memory, start, end, opcodes(), &symbols);
+                    lines(disassemble_with_symbols(
+                        memory,
+                        start,
+                        end,
+                        opcodes(),
+                        &labels,
+                    ))
+                }
+            }
+            CpuKind::Mos6502 | CpuKind::Wdc65C02 => {
+                use disassembler::mos6502::{
+                    auto_labels, code_map, disassemble, disassemble_traced,
+                    disassemble_with_symbols, opcodes, to_source,
+                };
+                if options.source {
+                    let map = code_map(memory, start, end, traced, opcodes());
+                    to_source(memory, start, end, &map, opcodes(), &symbols)
+                } else if options.trace {
+                    lines(disassemble_traced(
+                        memory,
+                        start,
+                        end,
+                        &entries,
+                        opcodes(),
+                        &symbols,
+                    ))
+                } else if options.no_labels {
+                    lines(disassemble(memory, start, end, opcodes()))
+                } else {
+                    let labels = auto_labels(memory, start, end, opcodes(), &symbols);
+                    lines(disassemble_with_symbols(
+                        memory,
+                        start,
+                        end,
+                        opcodes(),
+                        &labels,
+                    ))
+                }
+            }
+        };
+        write!(out, "{}", text)?;
+    }
+    Ok(EXIT_SUCCESS)
+}
+
+///
+/// One line per item
+///
+fn lines<T: fmt::Display>(items: Vec<T>) -> String {
+    items.iter().map(|item| format!("{}\n", item)).collect()
+}
+
+///
+/// All --symbols files in one table, a later file wins on conflicts
+///
+fn load_symbols(options: &Options) -> Result<SymbolTable, CliError> {
+    let mut table = SymbolTable::new();
+    for path in &options.symbols {
+        let symbols = symbols::load(path).map_err(|err| load_error(path, err))?;
+        table.merge(&symbols);
+    }
+    Ok(table)
+}
+
+fn frontend(err: Box<dyn std::error::Error>) -> CliError {
+    CliError::Frontend(err.to_string())
+}
+
+fn opcodes(options: &Options) -> Result<i32, CliError> {
+    match options.cpu_kind()? {
+        CpuKind::I8080 | CpuKind::I8085 => {
+            opcode_viewer::view(&disassembler::i8080_opcodes::OpcodeView::new())
+        }
+        CpuKind::Mos6502 | CpuKind::Wdc65C02 => {
+            opcode_viewer::view(&disassembler::mos6502_opcodes::OpcodeView::new())
+        }
+    }
+    .map_err(frontend)?;
+    Ok(EXIT_SUCCESS)
+}
+
+fn debug(options: &Options) -> Result<i32, CliError> {
+    require_input(options)?;
+    let mut cpu = run::load(options)?.cpu;
+    match options.gdb {
+        Some(port) => debugger::gdb::serve(cpu.as_mut(), port)
+            .map_err(|err| CliError::Frontend(format!("GDB stub: {}", err)))?,
+        None => debugger::tui::debug(cpu.as_mut(), load_symbols(options)?).map_err(frontend)?,
+    }
+    // the screen is gone, the dumps go to the terminal
+    for &(start, end) in &options.dumps {
+        cpu.memory_mut().hex_dump(start as usize, end as usize);
+    }
+    Ok(EXIT_SUCCESS)
+}
+
+fn status(passed: bool) -> i32 {
+    if passed { EXIT_SUCCESS } else { EXIT_FAILURE }
+}
+
+fn load_error(path: &Path, err: impl fmt::Display) -> CliError {
+    CliError::Load(format!("{}: {}", path.display(), err))
+}
+
+fn test(options: &Options, out: &mut dyn Write) -> Result<i32, CliError> {
+    match (&options.vectors, &options.tables, &options.cpm) {
+        (Some(dir), None, None) => {
+            let reports = single_step::run_directory(options.cpu_kind()?, dir)
+                .map_err(|err| CliError::Load(err.to_string()))?;
+            let failed: Vec<_> = reports.iter().filter(|report| !report.passed()).collect();
+            for report in &failed {
+                writeln!(out, "{}", report)?;
+            }
+            writeln!(
+                out,
+                "{} of {} opcodes passed",
+                reports.len() - failed.len(),
+                reports.len()
+            )?;
+            Ok(status(failed.is_empty()))
+        }
+        (None, Some(path), None) => {
+            let text =
+                std::fs::read_to_string(path).map_err(|err| CliError::Io(path.clone(), err))?;
+            let tables = i8080_tables::parse(&text).map_err(|err| load_error(path, err))?;
+            let mut rows = 0;
+            let mut mismatches = 0;
+            for table in &tables {
+                rows += table.rows.len();
+                for mismatch in i8080_tables::run(table) {
+                    writeln!(out, "{}", mismatch)?;
+                    mismatches += 1;
+                }
+            }
+            writeln!(
+                out,
+                "{} tables, {} rows, {} mismatches",
+                tables.len(),
+                rows,
+                mismatches
+            )?;
+            Ok(status(mismatches == 0))
+        }
+        (None, None, Some(path)) => {
+            let mut machine = Cpm::load(path).map_err(|err| load_error(path, err))?;
+            let stop = machine.run(options.stop.max_steps);
+            write!(out, "{}", machine.output())?;
+            writeln!(out, "\n{:?} after {} steps", stop, machine.steps())?;
+            Ok(status(stop == cpm::Stop::WarmBoot))
+        }
+        _ => Err(CliError::Usage(
+            "test needs one of --vectors, --tables or --cpm".to_string(),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn execute_args(text: &str) -> (Result<i32, CliError>, String) {
+        let args: Vec<String> = text.split_whitespace().map(str::to_string).collect();
+        let mut out = Vec::new();
+        let result =
+            args::parse(&args).and_then(|(command, options)| execute(command, &options, &mut out));
+        (result, String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    ///
+    /// Disassembly of the ACME example, the first instruction at the load
+    /// address
+    ///
+    fn disasm_example() {
+        let (result, output) = execute_args("disasm --cpu 6502 examples/test.o");
+        assert_eq!(result.unwrap(), EXIT_SUCCESS);
+        let data = std::fs::read("examples/test.o").unwrap();
+        let start = format!("{:04X}", u16::from_le_bytes([data[0], data[1]]));
+        assert!(
+            output.lines().next().unwrap().contains(&start),
+            "{}",
+            output
+        );
+        assert!(output.lines().count() > 100);
+    }
+
+    #[test]
+    ///
+    /// An image that ends at $FFFF is disassembled up to its last byte
+    ///
+    fn disasm_top_of_memory() {
+        let path = std::env::temp_dir().join(format!("top_{}.bin", std::process::id()));
+        std::fs::write(&path, [0xEA, 0xEA]).unwrap();
+        let (result, output) =
+            execute_args(&format!("disasm --cpu 6502 --load FFFE {}", path.display()));
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(result.unwrap(), EXIT_SUCCESS);
+        assert_eq!(output, "FFFE  EA          NOP\nFFFF  EA          NOP\n");
+    }
+
+    #[test]
+    ///
+    /// An image past $FFFF is an error, with --wrap it continues at $0000
+    ///
+    fn wrap_input() {
+        let path = std::env::temp_dir().join(format!("wrap_{}.bin", std::process::id()));
+        std::fs::write(&path, [0xEA, 0xEA, 0xEA]).unwrap();
+        let (overflow, _) =
+            execute_args(&format!("disasm --cpu 6502 --load FFFE {}", path.display()));
+        let (result, output) = execute_args(&format!(
+            "disasm --cpu 6502 --load FFFE {} --wrap --from 0000 --to 0001",
+            path.display()
+        ));
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(overflow, Err(CliError::Load(_))));
+        assert_eq!(result.unwrap(), EXIT_SUCCESS);
+        assert_eq!(output, "0000  EA          NOP\n");
+    }
+
+    #[test]
+    ///
+    /// The ACME example assembles to the checked in binary, the listing of
+    /// the binary has its bytes and cycles, the labels read back as symbols
+    ///
+    fn asm_example() {
+        let dir = std::env::temp_dir();
+        let output = dir.join(format!("asm_{}.o", std::process::id()));
+        let symbols = dir.join(format!("asm_{}.sym", std::process::id()));
+        let (result, listing) = execute_args(&format!(
+            "asm --cpu 6502 examples/test.a --output {} --save-symbols {}",
+            output.display(),
+            symbols.display()
+        ));
+        assert_eq!(result.unwrap(), EXIT_SUCCESS);
+        assert_eq!(
+            std::fs::read(&output).unwrap(),
+            std::fs::read("examples/test.o").unwrap()
+        );
+        let table = symbols::load(&symbols).unwrap();
+        std::fs::remove_file(&output).unwrap();
+        std::fs::remove_file(&symbols).unwrap();
+        assert!(!table.is_empty());
+
+        let (result, cycles) =
+            execute_args("asm --cpu 6502 examples/test.a --binary examples/test.o --cycles");
+        assert_eq!(result.unwrap(), EXIT_SUCCESS);
+        assert_eq!(cycles.lines().count(), listing.lines().count());
+        assert!(
+            cycles.contains("0200  A2 FF             2  \t\tldx\t#$FF\n"),
+            "{}",
+            cycles
+        );
+
+        let (result, _) = execute_args("asm --cpu 8080 examples/test.a");
+        assert!(matches!(result, Err(CliError::Load(_))));
+    }
+
+    #[test]
+    ///
+    /// Source of the traced example assembles to the example again, plain
+    /// disassembly has no labels
+    ///
+    fn disasm_source() {
+        let (result, source) =
+            execute_args("disasm --cpu 6502 examples/test.o --trace --entry 0200 --source");
+        assert_eq!(result.unwrap(), EXIT_SUCCESS);
+        let assembly = assembler::assemble(CpuKind::Mos6502, &source).unwrap();
+        let data = std::fs::read("examples/test.o").unwrap();
+        assert_eq!(assembly.bytes(), data[2..]);
+
+        let (result, traced) = execute_args("disasm --cpu 8080 examples/aci.hex --trace");
+        assert_eq!(result.unwrap(), EXIT_SUCCESS);
+        assert_eq!(
+            traced,
+            "0100  3E 55       MVI A,55H\n0102  CE 74       ACI 74H\n0104  76          HLT\n"
+        );
+        let (_, plain) = execute_args("disasm --cpu 6502 examples/test.o --no-labels");
+        assert!(!plain.lines().any(|line| line.ends_with(':')), "{}", plain);
+    }
+
+    #[test]
+    ///
+    /// Stop on the end marker of the example, the limit gives status 1
+    ///
+    fn run_example() {
+        let (result, output) = execute_args(
+            "run --cpu 6502 examples/test.o --start 0200 --no-halt --stop-opcode FF --dump 0000-000F",
+        );
+        assert_eq!(result.unwrap(), EXIT_SUCCESS);
+        assert!(output.starts_with("stopped on opcode FF"), "{}", output);
+        assert!(output.contains("00000000: "));
+
+        let (result, output) =
+            execute_args("run --cpu 6502 examples/test.o --start 0200 --max-steps 3 --quiet");
+        assert_eq!(result.unwrap(), EXIT_FAILURE);
+        assert!(output.is_empty());
+    }
+
+    #[test]
+    ///
+    /// Memory range written after the run reads back as Intel HEX and
+    /// S-records
+    ///
+    fn export_run() {
+        for extension in ["hex", "s19"] {
+            let path =
+                std::env::temp_dir().join(format!("export_{}.{}", std::process::id(), extension));
+            let (result, _) = execute_args(&format!(
+                "run --cpu 8080 examples/aci.hex --quiet --export 0100-0104 --start 0100 --output {}",
+                path.display()
+            ));
+            assert_eq!(result.unwrap(), EXIT_SUCCESS);
+            let image = run::read_image(
+                &mut Memory::new(),
+                &path,
+                args::format_of(&path),
+                None,
+                CpuKind::I8080,
+                Overflow::Error,
+            );
+            std::fs::remove_file(&path).unwrap();
+            let image = image.unwrap();
+            assert_eq!(image.segments[0].data, [0x3E, 0x55, 0xCE, 0x74, 0x76]);
+            assert_eq!(image.start, Some(0x0100));
+        }
+        assert!(matches!(
+            execute_args("run --cpu 8080 examples/aci.hex --export 0100-0104").0,
+            Err(CliError::Usage(_))
+        ));
+    }
+
+    #[test]
+    ///
+    /// run prints the summary of the --machine description first
+    ///
+    fn machine_info() {
+        let (_, output) = execute_args("run --machine examples/test_6502.json --max-steps 2");
+        assert!(
+            output.starts_with(
+                "6502 test bench for test.a: Mos6502 at 1000000 Hz, start 0200\n\
+                 RAM 0000-FFFF test.o\n\
+                 stopped on step limit"
+            ),
+            "{}",
+            output
+        );
+    }
+
+    #[test]
+    ///
+    /// The ring trace prints the last instructions after the run
+    ///
+    fn ring_trace() {
+        let (result, output) =
+            execute_args("run --cpu 8080 examples/aci.hex --quiet --trace-format ring");
+        assert_eq!(result.unwrap(), EXIT_SUCCESS);
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines[0], "last 2 instructions:");
+        assert!(
+            lines[1].starts_with("0100  3E 55     MVI A,55H"),
+            "{}",
+            output
+        );
+        assert!(lines[2].ends_with("CYC:7"), "{}", output);
+        assert!(matches!(
+            execute_args("run --cpu 8080 x.hex --trace-format xml").0,
+            Err(CliError::Usage(_))
+        ));
+    }
+
+    #[test]
+    ///
+    /// Raw ld65 output is placed by the MEMORY areas of the config
+    ///
+    fn ld65_binary() {
+        let dir = std::env::temp_dir();
+        let cfg = dir.join(format!("ld65_{}.cfg", std::process::id()));
+        let binary = dir.join(format!("ld65_{}.bin", std::process::id()));
+        std::fs::write(
+            &cfg,
+            "MEMORY {\n  RAM: start = $0800, size = $0010, fill = yes;\n  ROM: start = $E000, size = $2000;\n}\n",
+        )
+        .unwrap();
+        let mut data = vec![0xEA; 0x10];
+        data.extend_from_slice(&[0xA9, 0x01, 0x00]); // LDA #1; BRK
+        std::fs::write(&binary, data).unwrap();
+        let (result, output) = execute_args(&format!(
+            "run --cpu 6502 {} --ld65-config {} --start E000 --quiet --dump E000-E002",
+            binary.display(),
+            cfg.display()
+        ));
+        std::fs::remove_file(&cfg).unwrap();
+        std::fs::remove_file(&binary).unwrap();
+        assert_eq!(result.unwrap(), EXIT_SUCCESS);
+        assert!(output.starts_with("0000E000: A9 01 00 "), "{}", output);
+    }
+
+    #[test]
+    ///
+    /// State saved after a run continues from the snapshot, the CPU comes
+    /// from the snapshot
+    ///
+    fn snapshot_run() {
+        let path = std::env::temp_dir().join(format!("run_{}.json", std::process::id()));
+        let (result, _) = execute_args(&format!(
+            "run --cpu 8080 examples/aci.hex --stop-at 0102 --quiet --save-snapshot {}",
+            path.display()
+        ));
+        assert_eq!(result.unwrap(), EXIT_SUCCESS);
+        let (result, output) = execute_args(&format!("run --snapshot {}", path.display()));
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(result.unwrap(), EXIT_SUCCESS);
+        assert!(
+            output.starts_with("stopped on halt at 0104 after 1 steps"),
+            "{}",
+            output
+        );
+        assert!(output.contains("| C9H |"), "{}", output);
+    }
+
+    #[test]
+    ///
+    /// Flag tables of the notes through the test command, usage errors
+    ///
+    fn test_command() {
+        let (result, output) = execute_args("test --tables documents/test_notes_i8080.txt");
+        assert_eq!(result.unwrap(), EXIT_SUCCESS);
+        assert_eq!(output, "4 tables, 45 rows, 0 mismatches\n");
+
+        assert!(matches!(execute_args("test").0, Err(CliError::Usage(_))));
+        assert!(matches!(
+            execute_args("run --cpu 8080").0,
+            Err(CliError::Usage(_))
+        ));
+        assert!(matches!(
+            execute_args("run --cpu 8080 missing.bin").0,
+            Err(CliError::Load(message)) if message == "file missing.bin not found"
+        ));
+    }
+
+    #[test]
+    ///
+    /// Spec of the examples passes, a failing spec gives a diff and status
+    /// 1, a spec error status 2
+    ///
+    fn batch_example() {
+        let (result, output) =
+            execute_args("batch --cpu 8080 examples/aci.hex --spec examples/aci.spec");
+        assert_eq!(result.unwrap(), EXIT_SUCCESS);
+        assert!(output.ends_with("9 assertions passed\n"), "{}", output);
+
+        let path = std::env::temp_dir().join(format!("aci_{}.spec", std::process::id()));
+        std::fs::write(&path, "A == C8\nflags.CY == 0\n").unwrap();
+        let (result, output) = execute_args(&format!(
+            "batch --cpu 8080 examples/aci.hex --quiet --spec {}",
+            path.display()
+        ));
+        assert_eq!(result.unwrap(), EXIT_FAILURE);
+        assert_eq!(
+            output,
+            "line 1: A == C8\n  - C8\n  + C9\n1 of 2 assertions failed\n"
+        );
+
+        std::fs::write(&path, "Q == 1\n").unwrap();
+        let (result, _) = execute_args(&format!(
+            "batch --cpu 8080 examples/aci.hex --spec {}",
+            path.display()
+        ));
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(result, Err(CliError::Load(_))));
+    }
+}
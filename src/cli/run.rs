@@ -0,0 +1,495 @@
+//////////////////////////////////////////////////////////
+/// Loading and running of a program for the front end: the CPU comes from
+/// --cpu, a machine description or a snapshot, the input file is read as
+/// raw binary, ACME output, Intel HEX or S-records. run() executes until one
+/// of the stop conditions is met.
+//////////////////////////////////////////////////////////
+use std::fmt;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+use super::CliError;
+use super::args::{Options, StopConditions, TraceFormat, format_of};
+use crate::cpu::{self, Processor};
+use crate::machine::config::{CpuKind, ImageFormat};
+use crate::machine::snapshot::Snapshot;
+use crate::machine::{Machine, MachineCpu};
+use crate::memory::{Image, LoadError, Memory, Overflow, read_file, split_acme_header};
+use crate::symbols::cc65;
+use crate::trace::json::JsonSink;
+use crate::trace::ring::RingBuffer;
+use crate::trace::text;
+
+///
+/// Bytes of trace records kept by --trace-format ring
+///
+const TRACE_RING_CAPACITY: usize = 64 * 1024;
+
+///
+/// Address of a raw image without --load: 0100H is the TPA of CP/M on the
+/// 8080, $0200 the first page after zero page and stack on the 6502
+///
+pub fn default_load_address(kind: CpuKind) -> u16 {
+    match kind {
+        CpuKind::I8080 | CpuKind::I8085 => 0x0100,
+        CpuKind::Mos6502 | CpuKind::Wdc65C02 => 0x0200,
+    }
+}
+
+///
+/// Loads the image file through the loaders of the memory, --load places
+/// raw and ACME images. Returns the loaded image.
+///
+pub fn read_image(
+    memory: &mut Memory,
+    path: &Path,
+    format: ImageFormat,
+    load: Option<u16>,
+    kind: CpuKind,
+    overflow: Overflow,
+) -> Result<Image, CliError> {
+    let invalid = |err: LoadError| match err {
+        LoadError::FileNotFound(_) => CliError::Load(err.to_string()),
+        err => CliError::Load(format!("{}: {}", path.display(), err)),
+    };
+    let loaded = match (format, load) {
+        (ImageFormat::Ihex | ImageFormat::Srec, Some(_)) => {
+            return Err(CliError::Usage(
+                "--load has no effect, Intel HEX and S-records hold their addresses".to_string(),
+            ));
+        }
+        (ImageFormat::Ihex, None) => return memory.load_intel_hex_file(path).map_err(invalid),
+        (ImageFormat::Srec, None) => return memory.load_srecord_file(path).map_err(invalid),
+        (ImageFormat::Acme, None) => memory
+            .load_program_from_acme_file(path, overflow)
+            .map_err(invalid)?,
+        (ImageFormat::Acme, Some(address)) => {
+            let data = read_file(path).map_err(invalid)?;
+            let (_, payload) = split_acme_header(&data).map_err(invalid)?;
+            memory
+                .load_program_checked(payload, address, overflow)
+                .map_err(invalid)?
+        }
+        (ImageFormat::Raw, load) => {
+            let data = read_file(path).map_err(invalid)?;
+            let address = load.unwrap_or(default_load_address(kind));
+            memory
+                .load_program_checked(&data, address, overflow)
+                .map_err(invalid)?
+        }
+    };
+    // the bytes are read back as they may have wrapped around to $0000
+    let data: Vec<u8> = (0..loaded.length)
+        .map(|i| memory.data()[(loaded.address as usize + i) % memory.data().len()])
+        .collect();
+    let mut image = Image::default();
+    image.push(loaded.address, &data);
+    Ok(image)
+}
+
+fn processor(cpu: MachineCpu) -> Box<dyn Processor> {
+    match cpu {
+        MachineCpu::I8080(cpu) => cpu,
+        MachineCpu::Mos6502(cpu) => cpu,
+    }
+}
+
+///
+/// CPU ready to run and what it was loaded from
+///
+pub struct Program {
+    pub cpu: Box<dyn Processor>,
+    ///
+    /// None if there is no input file
+    ///
+    pub image: Option<Image>,
+    ///
+    /// Name of the --machine description
+    ///
+    pub machine: Option<String>,
+    ///
+    /// Memory map and devices of the --machine description
+    ///
+    pub machine_info: Option<String>,
+    ///
+    /// Last instructions of --trace-format ring
+    ///
+    pub ring: Option<RingBuffer>,
+}
+
+impl Program {
+    ///
+    /// Snapshot of the CPU, named after the machine
+    ///
+    pub fn snapshot(&self) -> Snapshot {
+        let mut snapshot = Snapshot::capture(self.cpu.as_ref());
+        snapshot.machine = self.machine.clone();
+        snapshot
+    }
+}
+
+///
+/// CPU with the --snapshot restored, the input loaded on top and PC at the
+/// start address: --start, the start address of the image or the address
+/// of its first byte. With a snapshot and without --start PC stays where
+/// the snapshot left it.
+///
+pub fn load(options: &Options) -> Result<Program, CliError> {
+    let snapshot = match &options.snapshot {
+        Some(path) => Some(
+            Snapshot::load(path)
+                .map_err(|err| CliError::Load(format!("{}: {}", path.display(), err)))?,
+        ),
+        None => None,
+    };
+    let mut machine_name = None;
+    let mut machine_info = None;
+    let mut cpu = match &options.machine {
+        Some(path) => {
+            let machine = Machine::from_file(path)
+                .map_err(|err| CliError::Load(format!("{}: {}", path.display(), err)))?;
+            if options.cpu.is_some_and(|kind| kind != machine.cpu_kind) {
+                return Err(CliError::Usage(format!(
+                    "--cpu does not match the {:?} of the machine",
+                    machine.cpu_kind
+                )));
+            }
+            machine_info = Some(machine.to_string());
+            machine_name = Some(machine.name);
+            processor(machine.cpu)
+        }
+        None => {
+            let kind = match (options.cpu, &snapshot) {
+                (None, Some(snapshot)) => snapshot.cpu,
+                _ => options.cpu_kind()?,
+            };
+            cpu::new(kind).ok_or_else(|| {
+                CliError::Load(format!("CPU {:?} has no emulation core yet", kind))
+            })?
+        }
+    };
+    if let (Some(snapshot), Some(path)) = (&snapshot, &options.snapshot) {
+        snapshot
+            .restore(cpu.as_mut())
+            .map_err(|err| CliError::Load(format!("{}: {}", path.display(), err)))?;
+    }
+    let image = match (&options.input, &options.ld65_config) {
+        // raw ld65 output, placed by the MEMORY areas of the linker config
+        (Some(path), Some(cfg)) => {
+            let binary = fs::read(path).map_err(|err| CliError::Io(path.clone(), err))?;
+            let cfg_text = fs::read_to_string(cfg).map_err(|err| CliError::Io(cfg.clone(), err))?;
+            let image = cc65::load_binary(cpu.memory_mut(), &binary, &cfg_text)
+                .map_err(|err| CliError::Load(format!("{}: {}", path.display(), err)))?;
+            Some(image)
+        }
+        (Some(path), None) => {
+            let format = options.format.unwrap_or_else(|| format_of(path));
+            let overflow = if options.wrap {
+                Overflow::Wrap
+            } else {
+                Overflow::Error
+            };
+            let kind = cpu.kind();
+            Some(read_image(
+                cpu.memory_mut(),
+                path,
+                format,
+                options.load,
+                kind,
+                overflow,
+            )?)
+        }
+        (None, _) => None,
+    };
+    let start = options.start.or_else(|| {
+        if snapshot.is_some() {
+            return None;
+        }
+        let image = image.as_ref()?;
+        image
+            .start
+            .or_else(|| image.segments.first().map(|segment| segment.address))
+    });
+    if let Some(start) = start {
+        cpu.set_pc(start);
+    }
+    let mut ring = None;
+    match (options.trace, options.trace_format) {
+        (false, _) => cpu.set_debug(false),
+        (true, TraceFormat::Text) => cpu.set_debug(true),
+        (true, TraceFormat::Json) => cpu.set_trace(Some(Box::new(JsonSink::new(io::stdout())))),
+        (true, TraceFormat::Ring) => {
+            let buffer = RingBuffer::new(TRACE_RING_CAPACITY);
+            cpu.set_trace(Some(Box::new(buffer.clone())));
+            ring = Some(buffer);
+        }
+    }
+    Ok(Program {
+        cpu,
+        image,
+        machine: machine_name,
+        machine_info,
+        ring,
+    })
+}
+
+///
+/// Instructions kept by --trace-format ring, the oldest first. The ring
+/// does not store the disassembly, it is taken from the memory after the
+/// run. CYC counts from the first kept instruction.
+///
+pub fn print_ring(program: &Program, out: &mut dyn Write) -> io::Result<()> {
+    let Some(ring) = program.ring.as_ref().filter(|ring| !ring.is_empty()) else {
+        return Ok(());
+    };
+    writeln!(out, "last {} instructions:", ring.len())?;
+    let mut cycles = 0;
+    for mut record in ring.records() {
+        record.disassembly = program
+            .cpu
+            .disassemble(record.pc, 1)
+            .first()
+            .map(|instruction| instruction.text())
+            .unwrap_or_default();
+        writeln!(out, "{}", text::format(&record, cycles))?;
+        cycles += record.cycles as u64;
+    }
+    Ok(())
+}
+
+///
+/// Saves the state after the run if --save-snapshot is given
+///
+pub fn save_snapshot(program: &Program, options: &Options) -> Result<(), CliError> {
+    if let Some(path) = &options.save_snapshot {
+        program
+            .snapshot()
+            .save(path)
+            .map_err(|err| CliError::Load(err.to_string()))?;
+    }
+    Ok(())
+}
+
+///
+/// Writes the --export range to --output after the run, the format comes
+/// from --format or the extension of the file
+///
+pub fn export(program: &Program, options: &Options) -> Result<(), CliError> {
+    let Some((start, end)) = options.export else {
+        return Ok(());
+    };
+    let path = options
+        .output
+        .as_ref()
+        .ok_or_else(|| CliError::Usage("--export needs --output".to_string()))?;
+    let memory = program.cpu.memory();
+    let start_address = options.start;
+    let data = match options.format.unwrap_or_else(|| format_of(path)) {
+        ImageFormat::Raw => memory.data()[start as usize..=end as usize].to_vec(),
+        ImageFormat::Acme => {
+            let mut data = start.to_le_bytes().to_vec();
+            data.extend_from_slice(&memory.data()[start as usize..=end as usize]);
+            data
+        }
+        ImageFormat::Ihex => memory.to_intel_hex(start, end, start_address).into_bytes(),
+        ImageFormat::Srec => memory.to_srecord(start, end, start_address).into_bytes(),
+    };
+    fs::write(path, data).map_err(|err| CliError::Io(path.clone(), err))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stop {
+    ///
+    /// HLT or BRK at this address, not executed
+    ///
+    Halt(u16),
+    ///
+    /// One of the --stop-opcode opcodes at the address, not executed
+    ///
+    Opcode(u8, u16),
+    Address(u16),
+    StepLimit,
+    CycleLimit,
+}
+
+impl Stop {
+    ///
+    /// true if the program reached a stop condition, false if it ran into
+    /// a limit
+    ///
+    pub fn is_condition(self) -> bool {
+        !matches!(self, Stop::StepLimit | Stop::CycleLimit)
+    }
+}
+
+impl fmt::Display for Stop {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Stop::Halt(addr) => write!(f, "halt at {:04X}", addr),
+            Stop::Opcode(opcode, addr) => write!(f, "opcode {:02X} at {:04X}", opcode, addr),
+            Stop::Address(addr) => write!(f, "address {:04X}", addr),
+            Stop::StepLimit => write!(f, "step limit"),
+            Stop::CycleLimit => write!(f, "cycle limit"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Outcome {
+    pub stop: Stop,
+    pub steps: u64,
+    ///
+    /// Sum of the cycles of the opcode tables, extra cycles of taken
+    /// branches and page crossings are not counted
+    ///
+    pub cycles: u64,
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "stopped on {} after {} steps, {} cycles",
+            self.stop, self.steps, self.cycles
+        )
+    }
+}
+
+///
+/// Executes instructions until a stop condition or a limit. The stop address
+/// is not checked before the first instruction, so a program can start on it.
+///
+pub fn run(cpu: &mut dyn Processor, stop: &StopConditions) -> Outcome {
+    let mut outcome = Outcome {
+        stop: Stop::StepLimit,
+        steps: 0,
+        cycles: 0,
+    };
+    loop {
+        let pc = cpu.pc();
+        let opcode = cpu.memory().read_byte(pc);
+        let reason = if outcome.steps > 0 && stop.addresses.contains(&pc) {
+            Some(Stop::Address(pc))
+        } else if stop.opcodes.contains(&opcode) {
+            Some(Stop::Opcode(opcode, pc))
+        } else if stop.halt && cpu.is_halt(pc) {
+            Some(Stop::Halt(pc))
+        } else if outcome.steps >= stop.max_steps {
+            Some(Stop::StepLimit)
+        } else if stop.max_cycles.is_some_and(|max| outcome.cycles >= max) {
+            Some(Stop::CycleLimit)
+        } else {
+            None
+        };
+        if let Some(reason) = reason {
+            outcome.stop = reason;
+            return outcome;
+        }
+        outcome.cycles += cpu.instruction_cycles(pc) as u64;
+        cpu.step();
+        outcome.steps += 1;
+    }
+}
+
+///
+/// Hex dump of the inclusive range
+///
+pub fn dump(cpu: &dyn Processor, (start, end): (u16, u16)) -> Vec<String> {
+    cpu.memory().hex_dump_lines(start as usize, end as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::args::parse;
+
+    fn cpu_6502(program: &[u8]) -> Box<dyn Processor> {
+        let mut cpu = cpu::new(CpuKind::Mos6502).unwrap();
+        let mut image = Image::default();
+        image.push(0x0200, program);
+        cpu.memory_mut().load_image(&image);
+        cpu.set_pc(0x0200);
+        cpu
+    }
+
+    #[test]
+    ///
+    /// Every stop condition and the limits
+    ///
+    fn stop_conditions() {
+        // LDA #$01; INX; JMP $0202; BRK
+        let program = [0xA9, 0x01, 0xE8, 0x4C, 0x02, 0x02, 0x00];
+        let conditions = StopConditions::default();
+
+        let mut cpu = cpu_6502(&[0xA9, 0x01, 0x00]);
+        let outcome = run(cpu.as_mut(), &conditions);
+        assert_eq!(
+            outcome,
+            Outcome {
+                stop: Stop::Halt(0x0202),
+                steps: 1,
+                cycles: 2
+            }
+        );
+        assert_eq!(
+            outcome.to_string(),
+            "stopped on halt at 0202 after 1 steps, 2 cycles"
+        );
+
+        let limited = StopConditions {
+            max_steps: 100,
+            ..conditions.clone()
+        };
+        let outcome = run(cpu_6502(&program).as_mut(), &limited);
+        assert_eq!((outcome.stop, outcome.steps), (Stop::StepLimit, 100));
+        assert!(!outcome.stop.is_condition());
+
+        let cycles = StopConditions {
+            max_cycles: Some(10),
+            ..conditions.clone()
+        };
+        let outcome = run(cpu_6502(&program).as_mut(), &cycles);
+        assert_eq!((outcome.stop, outcome.cycles), (Stop::CycleLimit, 12));
+
+        // the start address stops only when it is reached again
+        let address = StopConditions {
+            addresses: vec![0x0200, 0x0203],
+            ..conditions.clone()
+        };
+        let outcome = run(cpu_6502(&program).as_mut(), &address);
+        assert_eq!((outcome.stop, outcome.steps), (Stop::Address(0x0203), 2));
+
+        let opcode = StopConditions {
+            opcodes: vec![0x4C],
+            ..conditions
+        };
+        assert_eq!(
+            run(cpu_6502(&program).as_mut(), &opcode).stop,
+            Stop::Opcode(0x4C, 0x0203)
+        );
+    }
+
+    #[test]
+    ///
+    /// ACME output of the examples, start at the load address
+    ///
+    fn load_acme() {
+        let args: Vec<String> = ["run", "--cpu", "6502", "examples/test.o"]
+            .map(str::to_string)
+            .to_vec();
+        let (_, options) = parse(&args).unwrap();
+        let Program { cpu, image, .. } = load(&options).unwrap();
+        let data = fs::read("examples/test.o").unwrap();
+        assert_eq!(cpu.pc(), u16::from_le_bytes([data[0], data[1]]));
+        assert_eq!(image.unwrap().segments[0].data, data[2..]);
+        assert_eq!(cpu.memory().read_byte(cpu.pc()), data[2]);
+
+        let ihex = Options {
+            load: Some(0x1000),
+            format: Some(ImageFormat::Ihex),
+            ..options
+        };
+        assert!(matches!(load(&ihex), Err(CliError::Usage(_))));
+    }
+}
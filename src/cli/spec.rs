@@ -0,0 +1,350 @@
+//////////////////////////////////////////////////////////
+/// Assertions on the state of the CPU after a batch run, one per line:
+///
+///     # result of the multiplication
+///     A == $83
+///     HL != 0000
+///     mem[$0200..$0203] == 01 02 03
+///     mem[$0300] == FF
+///     word[$0300] - X == 1234
+///     flags.C == 1
+///
+/// The left side is an expression as in breakpoint conditions (registers,
+/// flags.Z, mem[ADDR], word[ADDR] and the operators, see
+/// debugger::expression) or the memory range mem[START..END] with END
+/// excluded or mem[START..=END]. The right side is the value, one byte per
+/// address for ranges. Numbers are hex like the command line options ($83,
+/// 0x83, 83H or 83), values are compared as 16 bits. The operators are ==
+/// and !=. Text after # or ; is a comment.
+///
+/// ```
+/// let assertions = spec::parse(&std::fs::read_to_string("sum.spec")?)?;
+/// for failure in spec::check(cpu, &assertions)? {
+///     println!("{}", failure);
+/// }
+/// ```
+//////////////////////////////////////////////////////////
+use std::fmt;
+
+use super::args::hex;
+use crate::cpu::Processor;
+use crate::debugger::expression::Expression;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpecError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for SpecError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for SpecError {}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Target {
+    Expression(Expression),
+    Memory { start: u16, length: usize },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Assertion {
+    pub line: usize,
+    ///
+    /// The line without the comment, shown in the failure
+    ///
+    pub text: String,
+    pub target: Target,
+    ///
+    /// true for ==, false for !=
+    ///
+    pub equal: bool,
+    pub expected: Vec<u16>,
+}
+
+///
+/// Assertion that does not hold and the values found
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct Failure {
+    pub assertion: Assertion,
+    pub actual: Vec<u16>,
+}
+
+impl Failure {
+    fn values(&self, values: &[u16]) -> String {
+        let digits = match &self.assertion.target {
+            Target::Expression(_) if values.iter().any(|&value| value > 0xFF) => 4,
+            _ => 2,
+        };
+        let values: Vec<String> = values
+            .iter()
+            .map(|value| format!("{:01$X}", value, digits))
+            .collect();
+        values.join(" ")
+    }
+}
+
+impl fmt::Display for Failure {
+    ///
+    /// Diff of expected (-) and found (+) values
+    ///
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let assertion = &self.assertion;
+        writeln!(f, "line {}: {}", assertion.line, assertion.text)?;
+        if assertion.equal {
+            writeln!(f, "  - {}", self.values(&assertion.expected))?;
+            write!(f, "  + {}", self.values(&self.actual))
+        } else {
+            write!(f, "  + {} (must differ)", self.values(&self.actual))
+        }
+    }
+}
+
+fn error(line: usize, message: String) -> SpecError {
+    SpecError { line, message }
+}
+
+///
+/// Address range of "mem[START..END]", the length is the number of bytes
+///
+fn memory(line: usize, range: &str, start: &str, end: &str) -> Result<Target, SpecError> {
+    let address = |text: &str| {
+        hex(text.trim())
+            .and_then(|value| u16::try_from(value).ok())
+            .ok_or_else(|| error(line, format!("'{}' is not an address", text.trim())))
+    };
+    let start = address(start)?;
+    let end = match end.strip_prefix('=') {
+        Some(end) => address(end)? as usize + 1,
+        None => address(end)? as usize,
+    };
+    if end <= start as usize {
+        return Err(error(line, format!("memory range '{}' is empty", range)));
+    }
+    Ok(Target::Memory {
+        start,
+        length: end - start as usize,
+    })
+}
+
+fn target(line: usize, text: &str) -> Result<Target, SpecError> {
+    let range = text
+        .strip_prefix("mem[")
+        .and_then(|rest| rest.strip_suffix(']'))
+        .filter(|range| !range.contains(['[', ']']));
+    if let Some(range) = range
+        && let Some((start, end)) = range.split_once("..")
+    {
+        return memory(line, range, start, end);
+    }
+    Expression::parse_hex(text)
+        .map(Target::Expression)
+        .map_err(|err| error(line, format!("{}: {}", text, err)))
+}
+
+fn parse_line(line: usize, text: &str) -> Result<Assertion, SpecError> {
+    let (left, equal, right) = match (text.split_once("=="), text.split_once("!=")) {
+        (Some((left, right)), None) => (left, true, right),
+        (None, Some((left, right))) => (left, false, right),
+        _ => return Err(error(line, format!("'{}' needs one == or !=", text))),
+    };
+    let target = target(line, left.trim())?;
+    let expected: Vec<u16> = right
+        .split(|c: char| c.is_whitespace() || c == ',')
+        .filter(|value| !value.is_empty())
+        .map(|value| {
+            hex(value)
+                .and_then(|value| u16::try_from(value).ok())
+                .ok_or_else(|| error(line, format!("'{}' is not a hex value", value)))
+        })
+        .collect::<Result<_, _>>()?;
+    let (count, maximum) = match target {
+        Target::Memory { length, .. } => (length, 0xFF),
+        Target::Expression(_) => (1, 0xFFFF),
+    };
+    if expected.len() != count {
+        return Err(error(
+            line,
+            format!("{} values, {} expected", expected.len(), count),
+        ));
+    }
+    if let Some(value) = expected.iter().find(|&&value| value > maximum) {
+        return Err(error(
+            line,
+            format!("{:X} is larger than {:X}", value, maximum),
+        ));
+    }
+    Ok(Assertion {
+        line,
+        text: text.to_string(),
+        target,
+        equal,
+        expected,
+    })
+}
+
+pub fn parse(text: &str) -> Result<Vec<Assertion>, SpecError> {
+    let mut assertions = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        let line_text = line.split(['#', ';']).next().unwrap_or_default().trim();
+        if !line_text.is_empty() {
+            assertions.push(parse_line(index + 1, line_text)?);
+        }
+    }
+    Ok(assertions)
+}
+
+///
+/// Assertions that do not hold, an error if an expression names a register
+/// or flag the CPU does not have
+///
+pub fn check(cpu: &dyn Processor, assertions: &[Assertion]) -> Result<Vec<Failure>, SpecError> {
+    let mut failures = Vec::new();
+    for assertion in assertions {
+        let actual = match &assertion.target {
+            Target::Expression(expression) => {
+                let value = expression
+                    .evaluate(cpu)
+                    .map_err(|err| error(assertion.line, format!("{}: {}", expression, err)))?;
+                vec![value as u16]
+            }
+            Target::Memory { start, length } => (0..*length)
+                .map(|offset| cpu.memory().read_byte(start.wrapping_add(offset as u16)) as u16)
+                .collect(),
+        };
+        if (actual == assertion.expected) != assertion.equal {
+            failures.push(Failure {
+                assertion: assertion.clone(),
+                actual,
+            });
+        }
+    }
+    Ok(failures)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu;
+    use crate::machine::config::CpuKind;
+
+    fn expression(text: &str) -> Target {
+        Target::Expression(Expression::parse_hex(text).unwrap())
+    }
+
+    #[test]
+    ///
+    /// All forms of the left side, comments and blank lines
+    ///
+    fn parse_forms() {
+        let assertions = parse(
+            "# comment\n\nA == $83 ; accumulator\nmem[0200..0203] == 01,02,03\n\
+             mem[$0300..=$0301] != 00 00\nmem[0x10] == FF\nflags.C == 1\nword[10] - X == 1234\n",
+        )
+        .unwrap();
+        let targets: Vec<(usize, &Target, bool)> = assertions
+            .iter()
+            .map(|assertion| (assertion.line, &assertion.target, assertion.equal))
+            .collect();
+        assert_eq!(
+            targets,
+            [
+                (3, &expression("A"), true),
+                (
+                    4,
+                    &Target::Memory {
+                        start: 0x0200,
+                        length: 3
+                    },
+                    true
+                ),
+                (
+                    5,
+                    &Target::Memory {
+                        start: 0x0300,
+                        length: 2
+                    },
+                    false
+                ),
+                (6, &expression("mem[0x10]"), true),
+                (7, &expression("flags.C"), true),
+                (8, &expression("word[10] - X"), true),
+            ]
+        );
+        assert_eq!(assertions[0].text, "A == $83");
+        assert_eq!(assertions[1].expected, [1, 2, 3]);
+        assert_eq!(assertions[5].expected, [0x1234]);
+    }
+
+    #[test]
+    ///
+    /// Errors name the line
+    ///
+    fn parse_errors() {
+        let error = |text: &str| parse(text).unwrap_err().to_string();
+        assert_eq!(error("\nA = 1"), "line 2: 'A = 1' needs one == or !=");
+        assert_eq!(
+            error("mem[0200..0203] == 01 02"),
+            "line 1: 2 values, 3 expected"
+        );
+        assert_eq!(
+            error("mem[0203..0200] == 01"),
+            "line 1: memory range '0203..0200' is empty"
+        );
+        assert_eq!(
+            error("mem[0200..=0200] == 100"),
+            "line 1: 100 is larger than FF"
+        );
+        assert_eq!(error("A == xy"), "line 1: 'xy' is not a hex value");
+        assert_eq!(error("A == 1 2"), "line 1: 2 values, 1 expected");
+        assert!(error("A + == 2").starts_with("line 1: A +: "));
+        assert!(error("mem[0200..0203 == 01").starts_with("line 1: mem[0200..0203: "));
+    }
+
+    #[test]
+    ///
+    /// Checks on a 6502 program, the failures show expected and found values
+    ///
+    fn check_6502() {
+        let mut cpu = cpu::new(CpuKind::Mos6502).unwrap();
+        // LDA #$83; STA $0200; SEC
+        cpu.memory_mut()
+            .load_program(&[0xA9, 0x83, 0x8D, 0x00, 0x02, 0x38], 0x0600);
+        cpu.set_pc(0x0600);
+        for _ in 0..3 {
+            cpu.step();
+        }
+        let passing = parse(
+            "A == 83\nmem[0200] == 83\nflags.C == 1\nflags.CY == 1\nPC == 0606\nX != 1\n\
+             word[0200] + A == 106\n(mem[0200] - A) | (C - 1) == 0",
+        )
+        .unwrap();
+        assert_eq!(check(cpu.as_ref(), &passing).unwrap(), []);
+
+        let failing = parse("A == 84\nmem[0200..0202] == 83 01\nPC == 0600\nA != 83").unwrap();
+        let failures: Vec<String> = check(cpu.as_ref(), &failing)
+            .unwrap()
+            .iter()
+            .map(|failure| failure.to_string())
+            .collect();
+        assert_eq!(
+            failures,
+            [
+                "line 1: A == 84\n  - 84\n  + 83",
+                "line 2: mem[0200..0202] == 83 01\n  - 83 01\n  + 83 00",
+                "line 3: PC == 0600\n  - 0600\n  + 0606",
+                "line 4: A != 83\n  + 83 (must differ)",
+            ]
+        );
+
+        let unknown = parse("HL == 0").unwrap();
+        assert_eq!(
+            check(cpu.as_ref(), &unknown).unwrap_err().to_string(),
+            "line 1: HL: unknown register or flag 'HL'"
+        );
+    }
+}
@@ -0,0 +1,256 @@
+//////////////////////////////////////////////////////////
+/// Reference interpreter of the 8080 instructions the core implements.
+/// Written from the Intel manual without the code of the core: the ALU
+/// works on 9 bit results and takes AC from the carry into bit 4.
+///
+/// The program starts at 0000H, H is never below 40H (MVI H is not
+/// generated) so M does not overwrite the program.
+//////////////////////////////////////////////////////////
+use super::{Rng, State};
+use crate::memory::CAPACITY;
+
+pub(super) const ORIGIN: u16 = 0x0000;
+
+const SIGN: u8 = 0x80;
+const ZERO: u8 = 0x40;
+const AUX_CARRY: u8 = 0x10;
+const PARITY: u8 = 0x04;
+const CARRY: u8 = 0x01;
+
+///
+/// Bits of F that no instruction changes
+///
+const FIXED: u8 = 0x2A;
+
+///
+/// Register numbers of the opcodes: B C D E H L M A
+///
+const H: usize = 4;
+const M: usize = 6;
+const A: usize = 7;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Mvi,
+    Add,
+    Adc,
+    Ana,
+    Cmp,
+    Cma,
+    Cmc,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Form {
+    ///
+    /// Source register in bits 0-2
+    ///
+    Source,
+    ///
+    /// Destination register in bits 3-5 and an immediate byte
+    ///
+    Destination,
+    Immediate,
+    Implied,
+}
+
+const TABLE: [(u8, Op, Form); 11] = [
+    (0x06, Op::Mvi, Form::Destination),
+    (0x80, Op::Add, Form::Source),
+    (0x88, Op::Adc, Form::Source),
+    (0xA0, Op::Ana, Form::Source),
+    (0xB8, Op::Cmp, Form::Source),
+    (0xC6, Op::Add, Form::Immediate),
+    (0xCE, Op::Adc, Form::Immediate),
+    (0xE6, Op::Ana, Form::Immediate),
+    (0xFE, Op::Cmp, Form::Immediate),
+    (0x2F, Op::Cma, Form::Implied),
+    (0x3F, Op::Cmc, Form::Implied),
+];
+
+///
+/// Operation and register number of an opcode
+///
+fn decode(opcode: u8) -> Option<(Op, Form, usize)> {
+    TABLE.iter().find_map(|&(base, op, form)| match form {
+        Form::Source if opcode & 0xF8 == base => Some((op, form, (opcode & 0x07) as usize)),
+        Form::Destination if opcode & 0xC7 == base => {
+            Some((op, form, ((opcode >> 3) & 0x07) as usize))
+        }
+        _ if opcode == base => Some((op, form, 0)),
+        _ => None,
+    })
+}
+
+pub(super) fn random_instruction(rng: &mut Rng) -> Vec<u8> {
+    let (base, _, form) = TABLE[rng.below(TABLE.len())];
+    match form {
+        Form::Source => vec![base | rng.below(8) as u8],
+        Form::Destination => {
+            let register = [0, 1, 2, 3, 5, 6, 7][rng.below(7)];
+            vec![base | (register << 3), rng.byte()]
+        }
+        Form::Immediate => vec![base, rng.byte()],
+        Form::Implied => vec![base],
+    }
+}
+
+pub(super) fn random_state(rng: &mut Rng) -> State {
+    let h = 0x40 | rng.byte();
+    let l = rng.byte();
+    let mut memory = vec![0; CAPACITY];
+    memory[u16::from_be_bytes([h, l]) as usize] = rng.byte();
+    State {
+        registers: vec![
+            ("A", rng.byte() as u16),
+            ("F", ((rng.byte() & !FIXED) | 0x02) as u16),
+            ("B", rng.byte() as u16),
+            ("C", rng.byte() as u16),
+            ("D", rng.byte() as u16),
+            ("E", rng.byte() as u16),
+            ("H", h as u16),
+            ("L", l as u16),
+            ("SP", u16::from_be_bytes([rng.byte(), rng.byte()])),
+            ("PC", ORIGIN),
+        ],
+        memory,
+    }
+}
+
+struct Machine {
+    ///
+    /// B C D E H L - A by the register numbers of the opcodes
+    ///
+    registers: [u8; 8],
+    f: u8,
+    sp: u16,
+    pc: u16,
+    memory: Vec<u8>,
+}
+
+impl Machine {
+    fn hl(&self) -> usize {
+        u16::from_be_bytes([self.registers[H], self.registers[H + 1]]) as usize
+    }
+    fn get(&self, register: usize) -> u8 {
+        match register {
+            M => self.memory[self.hl()],
+            register => self.registers[register],
+        }
+    }
+    fn set(&mut self, register: usize, value: u8) {
+        match register {
+            M => {
+                let hl = self.hl();
+                self.memory[hl] = value;
+            }
+            register => self.registers[register] = value,
+        }
+    }
+    fn fetch(&mut self) -> u8 {
+        let byte = self.memory[self.pc as usize];
+        self.pc = self.pc.wrapping_add(1);
+        byte
+    }
+    ///
+    /// Sets all flags from a 9 bit result, AC from the carry into bit 4
+    ///
+    fn set_flags(&mut self, result: u16, carry_into_4: bool, carry: bool) {
+        let value = result as u8;
+        let mut f = self.f & FIXED;
+        f |= value & SIGN;
+        if value == 0 {
+            f |= ZERO;
+        }
+        if carry_into_4 {
+            f |= AUX_CARRY;
+        }
+        if value.count_ones().is_multiple_of(2) {
+            f |= PARITY;
+        }
+        if carry {
+            f |= CARRY;
+        }
+        self.f = f;
+    }
+    fn step(&mut self) {
+        let opcode = self.fetch();
+        let Some((op, form, register)) = decode(opcode) else {
+            panic!("reference: opcode {:02X} not in the table", opcode);
+        };
+        let operand = match form {
+            Form::Source => self.get(register),
+            Form::Destination | Form::Immediate => self.fetch(),
+            Form::Implied => 0,
+        };
+        let a = self.registers[A];
+        match op {
+            Op::Mvi => self.set(register, operand),
+            Op::Add | Op::Adc => {
+                let carry = (op == Op::Adc && self.f & CARRY != 0) as u16;
+                let result = a as u16 + operand as u16 + carry;
+                let into_4 = (a as u16 ^ operand as u16 ^ result) & 0x10 != 0;
+                self.set_flags(result, into_4, result > 0xFF);
+                self.registers[A] = result as u8;
+            }
+            Op::Cmp => {
+                // A + two's complement, no carry out means a borrow
+                let result = a as u16 + (!operand) as u16 + 1;
+                let into_4 = (a as u16 ^ (!operand) as u16 ^ result) & 0x10 != 0;
+                self.set_flags(result, into_4, result <= 0xFF);
+            }
+            Op::Ana => {
+                let result = a & operand;
+                self.set_flags(result as u16, (a | operand) & 0x08 != 0, false);
+                self.registers[A] = result;
+            }
+            Op::Cma => self.registers[A] = !a,
+            Op::Cmc => self.f ^= CARRY,
+        }
+    }
+}
+
+pub(super) fn run(state: &State, steps: usize) -> State {
+    let mut machine = Machine {
+        registers: [
+            state.register("B") as u8,
+            state.register("C") as u8,
+            state.register("D") as u8,
+            state.register("E") as u8,
+            state.register("H") as u8,
+            state.register("L") as u8,
+            0,
+            state.register("A") as u8,
+        ],
+        f: state.register("F") as u8,
+        sp: state.register("SP"),
+        pc: state.register("PC"),
+        memory: state.memory.clone(),
+    };
+    for _ in 0..steps {
+        machine.step();
+    }
+    let value = |name: &'static str| -> (&'static str, u16) {
+        let value = match name {
+            "A" => machine.registers[A] as u16,
+            "F" => machine.f as u16,
+            "B" => machine.registers[0] as u16,
+            "C" => machine.registers[1] as u16,
+            "D" => machine.registers[2] as u16,
+            "E" => machine.registers[3] as u16,
+            "H" => machine.registers[H] as u16,
+            "L" => machine.registers[H + 1] as u16,
+            "SP" => machine.sp,
+            _ => machine.pc,
+        };
+        (name, value)
+    };
+    State {
+        registers: state
+            .registers
+            .iter()
+            .map(|&(name, _)| value(name))
+            .collect(),
+        memory: machine.memory,
+    }
+}
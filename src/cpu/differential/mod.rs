@@ -0,0 +1,338 @@
+//////////////////////////////////////////////////////////
+/// Differential fuzzing of the CPU cores. Random programs run from random
+/// initial states on the core and on a small table driven reference
+/// interpreter (differential/i8080.rs, differential/mos6502.rs), then the
+/// registers and the whole memory are compared. A program that makes them
+/// differ is minimised by dropping instructions as long as the difference
+/// stays, the report shows the shortest program found.
+///
+/// The instruction mix is the part of the instruction set both sides
+/// implement, see the tables of the reference interpreters.
+///
+/// cargo test runs a fixed seed. Longer runs use the fuzz profile (release
+/// speed with overflow checks) and take seed and count from the environment:
+///
+///     FUZZ_SEED=12345 FUZZ_CASES=10000000 \
+///         cargo test --profile fuzz differential_long -- --ignored
+//////////////////////////////////////////////////////////
+mod i8080;
+mod mos6502;
+
+use std::panic::{self, AssertUnwindSafe};
+
+use crate::cpu;
+use crate::machine::config::CpuKind;
+use crate::memory::CAPACITY;
+
+const SEED: u64 = 0x5EED_8080_6502;
+const CASES: usize = 3000;
+const LONG_CASES: usize = 1_000_000;
+const MAX_INSTRUCTIONS: usize = 12;
+
+///
+/// Memory differences shown in a report
+///
+const SHOWN_DIFFERENCES: usize = 8;
+
+///
+/// xorshift64* generator, the same seed gives the same programs
+///
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Self(seed.max(1))
+    }
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+    fn byte(&mut self) -> u8 {
+        (self.next() >> 32) as u8
+    }
+    fn below(&mut self, count: usize) -> usize {
+        (self.next() >> 33) as usize % count
+    }
+}
+
+///
+/// Registers by the names of Processor::register and the whole memory
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct State {
+    registers: Vec<(&'static str, u16)>,
+    memory: Vec<u8>,
+}
+
+impl State {
+    fn register(&self, name: &str) -> u16 {
+        self.registers
+            .iter()
+            .find(|(register, _)| *register == name)
+            .map_or(0, |(_, value)| *value)
+    }
+}
+
+///
+/// What the harness needs to know of a CPU
+///
+struct Target {
+    kind: CpuKind,
+    ///
+    /// Program address, the random data must not overlap the program
+    ///
+    origin: u16,
+    random_state: fn(&mut Rng) -> State,
+    random_instruction: fn(&mut Rng) -> Vec<u8>,
+    ///
+    /// Runs the reference interpreter for a number of instructions
+    ///
+    reference: fn(&State, usize) -> State,
+}
+
+const I8080: Target = Target {
+    kind: CpuKind::I8080,
+    origin: i8080::ORIGIN,
+    random_state: i8080::random_state,
+    random_instruction: i8080::random_instruction,
+    reference: i8080::run,
+};
+
+const MOS6502: Target = Target {
+    kind: CpuKind::Mos6502,
+    origin: mos6502::ORIGIN,
+    random_state: mos6502::random_state,
+    random_instruction: mos6502::random_instruction,
+    reference: mos6502::run,
+};
+
+///
+/// Runs the core, a panic of the core is returned as error
+///
+fn run_core(kind: CpuKind, state: &State, steps: usize) -> Result<State, String> {
+    panic::catch_unwind(AssertUnwindSafe(|| {
+        let mut cpu = cpu::new(kind).expect("targets have an emulation core");
+        let memory: &[u8; CAPACITY] = state.memory.as_slice().try_into().unwrap();
+        cpu.memory_mut().set_data(memory);
+        for &(name, value) in &state.registers {
+            cpu.set_register(name, value);
+        }
+        for _ in 0..steps {
+            cpu.step();
+        }
+        State {
+            registers: state
+                .registers
+                .iter()
+                .map(|&(name, _)| (name, cpu.register(name).unwrap_or_default()))
+                .collect(),
+            memory: cpu.memory().data().to_vec(),
+        }
+    }))
+    .map_err(|payload| {
+        let message = payload
+            .downcast_ref::<String>()
+            .cloned()
+            .or_else(|| payload.downcast_ref::<&str>().map(|text| text.to_string()))
+            .unwrap_or_default();
+        format!("core panicked: {}", message)
+    })
+}
+
+///
+/// Random initial state and program, one instruction per entry
+///
+#[derive(Debug, Clone)]
+struct Case {
+    state: State,
+    program: Vec<Vec<u8>>,
+}
+
+impl Case {
+    fn random(target: &Target, rng: &mut Rng) -> Self {
+        let length = 1 + rng.below(MAX_INSTRUCTIONS);
+        Case {
+            state: (target.random_state)(rng),
+            program: (0..length)
+                .map(|_| (target.random_instruction)(rng))
+                .collect(),
+        }
+    }
+    ///
+    /// Initial state with the program at the origin
+    ///
+    fn initial(&self, target: &Target) -> State {
+        let mut state = self.state.clone();
+        let bytes = self.program.concat();
+        let origin = target.origin as usize;
+        state.memory[origin..origin + bytes.len()].copy_from_slice(&bytes);
+        state
+    }
+    ///
+    /// Differences between core and reference, empty if they agree
+    ///
+    fn differences(&self, target: &Target) -> Vec<String> {
+        let initial = self.initial(target);
+        let steps = self.program.len();
+        let expected = (target.reference)(&initial, steps);
+        let found = match run_core(target.kind, &initial, steps) {
+            Ok(found) => found,
+            Err(message) => return vec![message],
+        };
+        let mut differences: Vec<String> = expected
+            .registers
+            .iter()
+            .zip(&found.registers)
+            .filter(|(expected, found)| expected.1 != found.1)
+            .map(|((name, expected), (_, found))| {
+                format!("{}: reference {:02X}, core {:02X}", name, expected, found)
+            })
+            .collect();
+        if expected.memory == found.memory {
+            return differences;
+        }
+        differences.extend(
+            (0..CAPACITY)
+                .filter(|&addr| expected.memory[addr] != found.memory[addr])
+                .take(SHOWN_DIFFERENCES)
+                .map(|addr| {
+                    format!(
+                        "mem[{:04X}]: reference {:02X}, core {:02X}",
+                        addr, expected.memory[addr], found.memory[addr]
+                    )
+                }),
+        );
+        differences
+    }
+    ///
+    /// Drops instructions as long as core and reference still differ
+    ///
+    fn minimise(mut self, target: &Target) -> Self {
+        let mut index = 0;
+        while index < self.program.len() {
+            let mut shorter = self.clone();
+            shorter.program.remove(index);
+            if !shorter.program.is_empty() && !shorter.differences(target).is_empty() {
+                self = shorter;
+            } else {
+                index += 1;
+            }
+        }
+        self
+    }
+    fn report(&self, target: &Target, seed: u64, number: usize) -> String {
+        let initial = self.initial(target);
+        let mut cpu = cpu::new(target.kind).expect("targets have an emulation core");
+        cpu.memory_mut()
+            .set_data(initial.memory.as_slice().try_into().unwrap());
+        let listing: Vec<String> = cpu
+            .disassemble(target.origin, self.program.len())
+            .iter()
+            .map(|instruction| format!("  {:04X}  {}", instruction.address, instruction.text()))
+            .collect();
+        let registers: Vec<String> = initial
+            .registers
+            .iter()
+            .map(|(name, value)| format!("{}={:02X}", name, value))
+            .collect();
+        format!(
+            "{:?} differs from the reference in case {} of seed {:#X}\ninitial: {}\n{}\n{}",
+            target.kind,
+            number,
+            seed,
+            registers.join(" "),
+            listing.join("\n"),
+            self.differences(target).join("\n")
+        )
+    }
+}
+
+///
+/// Runs count random cases, the report of the first difference (minimised)
+/// is the error
+///
+fn fuzz(target: &Target, seed: u64, count: usize) -> Result<(), String> {
+    let mut rng = Rng::new(seed);
+    for number in 0..count {
+        let case = Case::random(target, &mut rng);
+        if !case.differences(target).is_empty() {
+            return Err(case.minimise(target).report(target, seed, number));
+        }
+    }
+    Ok(())
+}
+
+fn environment(name: &str, default: u64) -> u64 {
+    std::env::var(name)
+        .ok()
+        .and_then(|value| match value.strip_prefix("0x") {
+            Some(hex) => u64::from_str_radix(hex, 16).ok(),
+            None => value.parse().ok(),
+        })
+        .unwrap_or(default)
+}
+
+#[test]
+///
+/// 8080 core against the reference with the fixed seed
+///
+fn differential_8080() {
+    if let Err(report) = fuzz(&I8080, SEED, CASES) {
+        panic!("{}", report);
+    }
+}
+
+#[test]
+///
+/// 6502 core against the reference with the fixed seed
+///
+fn differential_6502() {
+    if let Err(report) = fuzz(&MOS6502, SEED, CASES) {
+        panic!("{}", report);
+    }
+}
+
+#[test]
+#[ignore = "long run, use cargo test --profile fuzz differential_long -- --ignored"]
+///
+/// Both cores with FUZZ_SEED and FUZZ_CASES from the environment
+///
+fn differential_long() {
+    let seed = environment("FUZZ_SEED", SEED);
+    let count = environment("FUZZ_CASES", LONG_CASES as u64) as usize;
+    for target in [&I8080, &MOS6502] {
+        if let Err(report) = fuzz(target, seed, count) {
+            panic!("{}", report);
+        }
+    }
+}
+
+#[test]
+///
+/// A wrong reference is found and the program shrinks to the instruction
+/// that makes the difference
+///
+fn minimise() {
+    fn wrong_reference(state: &State, steps: usize) -> State {
+        let mut state = mos6502::run(state, steps);
+        if state.memory[0x0200..0x0240].contains(&0xE8) {
+            state.registers[0].1 ^= 1;
+        }
+        state
+    }
+    let target = Target {
+        reference: wrong_reference,
+        ..MOS6502
+    };
+    let case = Case {
+        state: mos6502::random_state(&mut Rng::new(SEED)),
+        // LDA #1; INX; CLC; TAX
+        program: vec![vec![0xA9, 0x01], vec![0xE8], vec![0x18], vec![0xAA]],
+    };
+    assert!(!case.differences(&target).is_empty());
+    let minimal = case.minimise(&target);
+    assert_eq!(minimal.program, [vec![0xE8]]);
+    assert!(minimal.report(&target, SEED, 0).contains("0200  INX"));
+}
@@ -0,0 +1,383 @@
+//////////////////////////////////////////////////////////
+/// Reference interpreter of a 6502 subset: loads, stores, binary
+/// arithmetic, logic, compares, shifts, increments, transfers and flag
+/// instructions with immediate, zero page and indexed zero page operands.
+/// Written from the MOS programming manual without the code of the core.
+///
+/// The program starts at $0200, the data is the random zero page. D is
+/// always clear, the core has no decimal mode.
+//////////////////////////////////////////////////////////
+use super::{Rng, State};
+use crate::memory::CAPACITY;
+
+pub(super) const ORIGIN: u16 = 0x0200;
+
+const CARRY: u8 = 0x01;
+const ZERO: u8 = 0x02;
+const DECIMAL: u8 = 0x08;
+const OVERFLOW: u8 = 0x40;
+const NEGATIVE: u8 = 0x80;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Lda,
+    Ldx,
+    Ldy,
+    Sta,
+    Stx,
+    Sty,
+    Adc,
+    Sbc,
+    And,
+    Ora,
+    Eor,
+    Cmp,
+    Cpx,
+    Cpy,
+    Bit,
+    Inc,
+    Dec,
+    Asl,
+    Lsr,
+    Rol,
+    Ror,
+    Inx,
+    Iny,
+    Dex,
+    Dey,
+    Tax,
+    Tay,
+    Txa,
+    Tya,
+    Tsx,
+    Txs,
+    Clc,
+    Sec,
+    Clv,
+    Nop,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Implied,
+    Accumulator,
+    Immediate,
+    ZeroPage,
+    ZeroPageX,
+    ZeroPageY,
+}
+
+const TABLE: [(u8, Op, Mode); 59] = [
+    (0xA9, Op::Lda, Mode::Immediate),
+    (0xA5, Op::Lda, Mode::ZeroPage),
+    (0xB5, Op::Lda, Mode::ZeroPageX),
+    (0xA2, Op::Ldx, Mode::Immediate),
+    (0xA6, Op::Ldx, Mode::ZeroPage),
+    (0xB6, Op::Ldx, Mode::ZeroPageY),
+    (0xA0, Op::Ldy, Mode::Immediate),
+    (0xA4, Op::Ldy, Mode::ZeroPage),
+    (0xB4, Op::Ldy, Mode::ZeroPageX),
+    (0x85, Op::Sta, Mode::ZeroPage),
+    (0x95, Op::Sta, Mode::ZeroPageX),
+    (0x86, Op::Stx, Mode::ZeroPage),
+    (0x96, Op::Stx, Mode::ZeroPageY),
+    (0x84, Op::Sty, Mode::ZeroPage),
+    (0x94, Op::Sty, Mode::ZeroPageX),
+    (0x69, Op::Adc, Mode::Immediate),
+    (0x65, Op::Adc, Mode::ZeroPage),
+    (0x75, Op::Adc, Mode::ZeroPageX),
+    (0xE9, Op::Sbc, Mode::Immediate),
+    (0xE5, Op::Sbc, Mode::ZeroPage),
+    (0xF5, Op::Sbc, Mode::ZeroPageX),
+    (0x29, Op::And, Mode::Immediate),
+    (0x25, Op::And, Mode::ZeroPage),
+    (0x09, Op::Ora, Mode::Immediate),
+    (0x05, Op::Ora, Mode::ZeroPage),
+    (0x49, Op::Eor, Mode::Immediate),
+    (0x45, Op::Eor, Mode::ZeroPage),
+    (0xC9, Op::Cmp, Mode::Immediate),
+    (0xC5, Op::Cmp, Mode::ZeroPage),
+    (0xE0, Op::Cpx, Mode::Immediate),
+    (0xE4, Op::Cpx, Mode::ZeroPage),
+    (0xC0, Op::Cpy, Mode::Immediate),
+    (0xC4, Op::Cpy, Mode::ZeroPage),
+    (0x24, Op::Bit, Mode::ZeroPage),
+    (0xE6, Op::Inc, Mode::ZeroPage),
+    (0xF6, Op::Inc, Mode::ZeroPageX),
+    (0xC6, Op::Dec, Mode::ZeroPage),
+    (0x0A, Op::Asl, Mode::Accumulator),
+    (0x06, Op::Asl, Mode::ZeroPage),
+    (0x4A, Op::Lsr, Mode::Accumulator),
+    (0x46, Op::Lsr, Mode::ZeroPage),
+    (0x2A, Op::Rol, Mode::Accumulator),
+    (0x26, Op::Rol, Mode::ZeroPage),
+    (0x6A, Op::Ror, Mode::Accumulator),
+    (0x66, Op::Ror, Mode::ZeroPage),
+    (0xE8, Op::Inx, Mode::Implied),
+    (0xC8, Op::Iny, Mode::Implied),
+    (0xCA, Op::Dex, Mode::Implied),
+    (0x88, Op::Dey, Mode::Implied),
+    (0xAA, Op::Tax, Mode::Implied),
+    (0xA8, Op::Tay, Mode::Implied),
+    (0x8A, Op::Txa, Mode::Implied),
+    (0x98, Op::Tya, Mode::Implied),
+    (0xBA, Op::Tsx, Mode::Implied),
+    (0x9A, Op::Txs, Mode::Implied),
+    (0x18, Op::Clc, Mode::Implied),
+    (0x38, Op::Sec, Mode::Implied),
+    (0xB8, Op::Clv, Mode::Implied),
+    (0xEA, Op::Nop, Mode::Implied),
+];
+
+pub(super) fn random_instruction(rng: &mut Rng) -> Vec<u8> {
+    let (opcode, _, mode) = TABLE[rng.below(TABLE.len())];
+    match mode {
+        Mode::Implied | Mode::Accumulator => vec![opcode],
+        _ => vec![opcode, rng.byte()],
+    }
+}
+
+pub(super) fn random_state(rng: &mut Rng) -> State {
+    let mut memory = vec![0; CAPACITY];
+    for byte in &mut memory[..0x100] {
+        *byte = rng.byte();
+    }
+    State {
+        registers: vec![
+            ("A", rng.byte() as u16),
+            ("X", rng.byte() as u16),
+            ("Y", rng.byte() as u16),
+            ("P", (rng.byte() & !DECIMAL) as u16),
+            ("SP", rng.byte() as u16),
+            ("PC", ORIGIN),
+        ],
+        memory,
+    }
+}
+
+struct Machine {
+    a: u8,
+    x: u8,
+    y: u8,
+    p: u8,
+    sp: u8,
+    pc: u16,
+    memory: Vec<u8>,
+}
+
+impl Machine {
+    fn fetch(&mut self) -> u8 {
+        let byte = self.memory[self.pc as usize];
+        self.pc = self.pc.wrapping_add(1);
+        byte
+    }
+    fn flag(&mut self, flag: u8, on: bool) {
+        if on {
+            self.p |= flag;
+        } else {
+            self.p &= !flag;
+        }
+    }
+    fn nz(&mut self, value: u8) -> u8 {
+        self.flag(ZERO, value == 0);
+        self.flag(NEGATIVE, value & 0x80 != 0);
+        value
+    }
+    ///
+    /// Binary add with carry, SBC adds the complement
+    ///
+    fn add(&mut self, value: u8) {
+        let sum = self.a as u16 + value as u16 + (self.p & CARRY) as u16;
+        let result = sum as u8;
+        self.flag(OVERFLOW, !(self.a ^ value) & (self.a ^ result) & 0x80 != 0);
+        self.flag(CARRY, sum > 0xFF);
+        self.a = self.nz(result);
+    }
+    fn compare(&mut self, register: u8, value: u8) {
+        self.flag(CARRY, register >= value);
+        self.nz(register.wrapping_sub(value));
+    }
+    fn step(&mut self) {
+        let opcode = self.fetch();
+        let Some(&(_, op, mode)) = TABLE.iter().find(|(code, _, _)| *code == opcode) else {
+            panic!("reference: opcode {:02X} not in the table", opcode);
+        };
+        // zero page address, or the immediate value
+        let operand = match mode {
+            Mode::Implied | Mode::Accumulator => 0,
+            _ => self.fetch(),
+        };
+        let address = match mode {
+            Mode::ZeroPageX => operand.wrapping_add(self.x),
+            Mode::ZeroPageY => operand.wrapping_add(self.y),
+            _ => operand,
+        } as usize;
+        let value = match mode {
+            Mode::Immediate => operand,
+            Mode::Accumulator => self.a,
+            Mode::Implied => 0,
+            _ => self.memory[address],
+        };
+        // read-modify-write result
+        let modified = match op {
+            Op::Lda => {
+                self.a = self.nz(value);
+                None
+            }
+            Op::Ldx => {
+                self.x = self.nz(value);
+                None
+            }
+            Op::Ldy => {
+                self.y = self.nz(value);
+                None
+            }
+            Op::Sta => Some(self.a),
+            Op::Stx => Some(self.x),
+            Op::Sty => Some(self.y),
+            Op::Adc => {
+                self.add(value);
+                None
+            }
+            Op::Sbc => {
+                self.add(!value);
+                None
+            }
+            Op::And => {
+                self.a = self.nz(self.a & value);
+                None
+            }
+            Op::Ora => {
+                self.a = self.nz(self.a | value);
+                None
+            }
+            Op::Eor => {
+                self.a = self.nz(self.a ^ value);
+                None
+            }
+            Op::Cmp => {
+                self.compare(self.a, value);
+                None
+            }
+            Op::Cpx => {
+                self.compare(self.x, value);
+                None
+            }
+            Op::Cpy => {
+                self.compare(self.y, value);
+                None
+            }
+            Op::Bit => {
+                self.flag(ZERO, self.a & value == 0);
+                self.flag(NEGATIVE, value & 0x80 != 0);
+                self.flag(OVERFLOW, value & 0x40 != 0);
+                None
+            }
+            Op::Inc => Some(self.nz(value.wrapping_add(1))),
+            Op::Dec => Some(self.nz(value.wrapping_sub(1))),
+            Op::Asl | Op::Lsr | Op::Rol | Op::Ror => {
+                let carry_in = self.p & CARRY;
+                let (result, carry_out) = match op {
+                    Op::Asl => (value << 1, value & 0x80),
+                    Op::Lsr => (value >> 1, value & 0x01),
+                    Op::Rol => (value << 1 | carry_in, value & 0x80),
+                    _ => (value >> 1 | carry_in << 7, value & 0x01),
+                };
+                self.flag(CARRY, carry_out != 0);
+                Some(self.nz(result))
+            }
+            Op::Inx => {
+                self.x = self.nz(self.x.wrapping_add(1));
+                None
+            }
+            Op::Iny => {
+                self.y = self.nz(self.y.wrapping_add(1));
+                None
+            }
+            Op::Dex => {
+                self.x = self.nz(self.x.wrapping_sub(1));
+                None
+            }
+            Op::Dey => {
+                self.y = self.nz(self.y.wrapping_sub(1));
+                None
+            }
+            Op::Tax => {
+                self.x = self.nz(self.a);
+                None
+            }
+            Op::Tay => {
+                self.y = self.nz(self.a);
+                None
+            }
+            Op::Txa => {
+                self.a = self.nz(self.x);
+                None
+            }
+            Op::Tya => {
+                self.a = self.nz(self.y);
+                None
+            }
+            Op::Tsx => {
+                self.x = self.nz(self.sp);
+                None
+            }
+            Op::Txs => {
+                self.sp = self.x;
+                None
+            }
+            Op::Clc => {
+                self.flag(CARRY, false);
+                None
+            }
+            Op::Sec => {
+                self.flag(CARRY, true);
+                None
+            }
+            Op::Clv => {
+                self.flag(OVERFLOW, false);
+                None
+            }
+            Op::Nop => None,
+        };
+        match (modified, mode) {
+            (Some(result), Mode::Accumulator) => self.a = result,
+            (Some(result), _) => self.memory[address] = result,
+            (None, _) => {}
+        }
+    }
+}
+
+pub(super) fn run(state: &State, steps: usize) -> State {
+    let mut machine = Machine {
+        a: state.register("A") as u8,
+        x: state.register("X") as u8,
+        y: state.register("Y") as u8,
+        p: state.register("P") as u8,
+        sp: state.register("SP") as u8,
+        pc: state.register("PC"),
+        memory: state.memory.clone(),
+    };
+    for _ in 0..steps {
+        machine.step();
+    }
+    let value = |name: &'static str| -> (&'static str, u16) {
+        let value = match name {
+            "A" => machine.a as u16,
+            "X" => machine.x as u16,
+            "Y" => machine.y as u16,
+            "P" => machine.p as u16,
+            "SP" => machine.sp as u16,
+            _ => machine.pc,
+        };
+        (name, value)
+    };
+    State {
+        registers: state
+            .registers
+            .iter()
+            .map(|&(name, _)| value(name))
+            .collect(),
+        memory: machine.memory,
+    }
+}
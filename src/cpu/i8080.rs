@@ -1,6 +1,14 @@
+use std::io;
+
+use crate::cpu::Processor;
+use crate::disassembler::i8080::{decode, opcodes};
 use crate::disassembler::i8080_opcodes_const::*;
-use crate::memory::Memory;
+use crate::disassembler::{self, Instruction, Reader};
+use crate::machine::config::CpuKind;
+use crate::memory::{Access, AccessKind, Memory};
 use crate::status::i8080::Psw;
+use crate::symbols::SymbolTable;
+use crate::trace::{self, TraceSink, text::TextSink};
 
 pub struct Cpu {
     pub a: u8,
@@ -14,7 +22,18 @@ pub struct Cpu {
     pub pc: u16,
     pub sp: u16,
     pub memory: Memory,
-    pub debug: bool,
+    ///
+    /// I/O ports: IN reads from here, OUT writes here
+    ///
+    pub ports: [u8; 256],
+    ///
+    /// Port accessed by IN or OUT in the last step, addr is the port number
+    ///
+    pub port_access: Option<Access>,
+    ///
+    /// Receives a record of every executed instruction, see trace module
+    ///
+    trace: Option<Box<dyn TraceSink>>,
 }
 
 impl Cpu {
@@ -31,7 +50,9 @@ impl Cpu {
             pc: 0,
             sp: 0,
             memory: Memory::new(),
-            debug: true,
+            ports: [0; 256],
+            port_access: None,
+            trace: None,
         }
     }
     ///
@@ -65,37 +86,38 @@ impl Cpu {
             self.psw.is_carry() as u8
         )
     }
+    ///
+    /// Debug mode prints the trace of every instruction to stdout
+    ///
     pub fn set_debug(&mut self, debug: bool) {
-        self.debug = debug;
+        self.trace = if debug {
+            Some(Box::new(TextSink::new(io::stdout())))
+        } else {
+            None
+        };
+    }
+    pub fn set_trace(&mut self, sink: Option<Box<dyn TraceSink>>) {
+        self.trace = sink;
+    }
+    ///
+    /// Executes one instruction, with a trace sink the instruction is traced
+    ///
+    pub fn step(&mut self) {
+        match self.trace.take() {
+            Some(mut sink) => {
+                // trace::step() calls step() again, now without the sink
+                let record = trace::step(self);
+                sink.record(&record);
+                self.trace = Some(sink);
+            }
+            None => self.execute(),
+        }
     }
     fn read_immediate_byte(&mut self) -> u8 {
         let value = self.memory.read_byte(self.pc);
         self.pc += 1;
         value
     }
-    ///
-    /// This function formats HEX string from address and opcode and
-    /// is used in debug mode to print address and opcode of the instruction that
-    /// is currently being processed
-    /// For example if you are processing the following code at address 0x200
-    /// MVI A, 55H
-    /// Then it looks like this in memory
-    /// 0x200 0x3E, 0x55
-    /// and PC points to the address of 0x0202 (just after the operand).
-    /// To get the opcode you have to go back 2 bytes so neg_offset is 2.
-    /// And the resulting string is '0200 3E'
-    ///  
-    fn code_to_str(&mut self, mut neg_offset: u8) -> String {
-        let mut addr = self.pc.wrapping_sub(neg_offset as u16);
-        let mut result = String::new();
-        result.push_str(format!("{:04X}  ", addr).as_str());
-        while neg_offset != 0 {
-            result.push_str(format!("{:02X} ", self.memory.read_byte(addr)).as_str());
-            neg_offset -= 1;
-            addr += 1;
-        }
-        format!("{:<18}", result)
-    }
 
     fn set_parity(&mut self, data: u8) {
         let mut mask = 0x01;
@@ -162,9 +184,8 @@ impl Cpu {
         self.memory.read_byte(hl)
     }
     fn cmp(&self, value: u8) {}
-    pub fn step(&mut self) {
-        macro_rules! dbg { ($($x:tt)*) => { if self.debug { println!($($x)*); } } }
-
+    fn execute(&mut self) {
+        self.port_access = None;
         let opcode = self.memory.read_byte(self.pc);
         self.pc += 1;
 
@@ -173,201 +194,172 @@ impl Cpu {
             ACI => {
                 let value = self.read_immediate_byte();
                 self.addc(value);
-                dbg!("{}ACI {:02X}H", self.code_to_str(2), value);
             }
             ////////////////// End of ACI
             ////////////////// Start of ADC B
             ADC_B => {
                 let value = self.b;
                 self.addc(value);
-                dbg!("{}ADC B", self.code_to_str(1));
             }
             ////////////////// End of ADC B
             ////////////////// Start of ADC C
             ADC_C => {
                 let value = self.c;
                 self.addc(value);
-                dbg!("{}ADC C", self.code_to_str(1));
             }
             ////////////////// End of ADC C
             ////////////////// Start of ADC D
             ADC_D => {
                 let value = self.d;
                 self.addc(value);
-                dbg!("{}ADC D", self.code_to_str(1));
             }
             ////////////////// End of ADC D
             ////////////////// Start of ADC E
             ADC_E => {
                 let value = self.e;
                 self.addc(value);
-                dbg!("{}ADC E", self.code_to_str(1));
             }
             ////////////////// End of ADC E
             ////////////////// Start of ADC H
             ADC_H => {
                 let value = self.h;
                 self.addc(value);
-                dbg!("{}ADC H", self.code_to_str(1));
             }
             ////////////////// End of ADC H
             ////////////////// Start of ADC L
             ADC_L => {
                 let value = self.l;
                 self.addc(value);
-                dbg!("{}ADC L", self.code_to_str(1));
             }
             ////////////////// End of ADC L
             ////////////////// Start of ADC M
             ADC_M => {
                 let value = self.read_m();
                 self.addc(value);
-                dbg!("{}ADC M", self.code_to_str(1));
             }
             ////////////////// End of ADC M
             ////////////////// Start of ADC A
             ADC_A => {
                 let value = self.a;
                 self.addc(value);
-                dbg!("{}ADC A", self.code_to_str(1));
             }
             ////////////////// End of ADC A
             ////////////////// Start of ADD B
             ADD_B => {
                 let value = self.b;
                 self.add(value, false);
-                dbg!("{}ADD B", self.code_to_str(1));
             }
             ////////////////// End of ADD B
             ////////////////// Start of ADD C
             ADD_C => {
                 let value = self.c;
                 self.add(value, false);
-                dbg!("{}ADD C", self.code_to_str(1));
             }
             ////////////////// End of ADD C
             ////////////////// Start of ADD D
             ADD_D => {
                 let value = self.d;
                 self.add(value, false);
-                dbg!("{}ADD D", self.code_to_str(1));
             }
             ////////////////// End of ADD D
             ////////////////// Start of ADD E
             ADD_E => {
                 let value = self.e;
                 self.add(value, false);
-                dbg!("{}ADD E", self.code_to_str(1));
             }
             ////////////////// End of ADD E
             ////////////////// Start of ADD H
             ADD_H => {
                 let value = self.h;
                 self.add(value, false);
-                dbg!("{}ADD H", self.code_to_str(1));
             }
             ////////////////// End of ADD H
             ////////////////// Start of ADD L
             ADD_L => {
                 let value = self.l;
                 self.add(value, false);
-                dbg!("{}ADD L", self.code_to_str(1));
             }
             ////////////////// End of ADD L
             ////////////////// Start of ADD M
             ADD_M => {
                 let value = self.read_m();
                 self.add(value, false);
-                dbg!("{}ADD M", self.code_to_str(1));
             }
             ////////////////// End of ADD M
             ////////////////// Start of ADD A
             ADD_A => {
                 let value = self.a;
                 self.add(value, false);
-                dbg!("{}ADD A", self.code_to_str(1));
             }
             ////////////////// End of ADD A
             ////////////////// Start of ADI
             ADI => {
                 let value = self.read_immediate_byte();
                 self.add(value, false);
-                dbg!("{}ADI ,{:02X}H", self.code_to_str(2), value);
             }
             ////////////////// End of ADI
             ////////////////// Start of ANA B
             ANA_B => {
                 let value = self.b;
                 self.and(value);
-                dbg!("{}ANA B", self.code_to_str(1));
             }
             ////////////////// End of ANA B
             ////////////////// Start of ANA C
             ANA_C => {
                 let value = self.c;
                 self.and(value);
-                dbg!("{}ANA C", self.code_to_str(1));
             }
             ////////////////// End of ANA C
             ////////////////// Start of ANA D
             ANA_D => {
                 let value = self.d;
                 self.and(value);
-                dbg!("{}ANA D", self.code_to_str(1));
             }
             ////////////////// End of ANA D
             ////////////////// Start of ANA E
             ANA_E => {
                 let value = self.e;
                 self.and(value);
-                dbg!("{}ANA E", self.code_to_str(1));
             }
             ////////////////// End of ANA E
             ////////////////// Start of ANA H
             ANA_H => {
                 let value = self.h;
                 self.and(value);
-                dbg!("{}ANA H", self.code_to_str(1));
             }
             ////////////////// End of ANA H
             ////////////////// Start of ANA L
             ANA_L => {
                 let value = self.l;
                 self.and(value);
-                dbg!("{}ANA L", self.code_to_str(1));
             }
             ////////////////// End of ANA L
             ////////////////// Start of ANA M
             ANA_M => {
-                let value = self.read_immediate_byte();
+                let value = self.read_m();
                 self.and(value);
-                dbg!("{}ANA M", self.code_to_str(1));
             }
             ////////////////// End of ANA M
             ////////////////// Start of ANA A
             ANA_A => {
                 let value = self.a;
                 self.and(value);
-                dbg!("{}ANA A", self.code_to_str(1));
             }
             ////////////////// End of ANA A
             ////////////////// Start of ANI
             ANI => {
                 let value = self.read_immediate_byte();
                 self.and(value);
-                dbg!("{}ANI ,{:02X}H", self.code_to_str(2), value);
             }
             ////////////////// End of ANI
             ////////////////// Start of CMA
             CMA => {
                 self.a = !self.a;
-                dbg!("{}CMA", self.code_to_str(1));
             }
             ////////////////// End of CMA
             ////////////////// Start of CMC
             CMC => {
                 self.psw.set_carry(!self.psw.is_carry());
-                dbg!("{}CMC", self.code_to_str(1));
             }
             ////////////////// End of CMC
             ////////////////// Start of CMP B
@@ -375,7 +367,6 @@ impl Cpu {
                 let tmp = self.a;
                 self.sub(self.b);
                 self.a = tmp;
-                dbg!("{}CMP B", self.code_to_str(1));
             }
             ////////////////// End of CMP B
             ////////////////// Start of CMP C
@@ -383,7 +374,6 @@ impl Cpu {
                 let tmp = self.a;
                 self.sub(self.c);
                 self.a = tmp;
-                dbg!("{}CMP C", self.code_to_str(1));
             }
             ////////////////// End of CMP C
             ////////////////// Start of CMP D
@@ -391,7 +381,6 @@ impl Cpu {
                 let tmp = self.a;
                 self.sub(self.d);
                 self.a = tmp;
-                dbg!("{}CMP D", self.code_to_str(1));
             }
             ////////////////// End of CMP D
             ////////////////// Start of CMP E
@@ -399,7 +388,6 @@ impl Cpu {
                 let tmp = self.a;
                 self.sub(self.e);
                 self.a = tmp;
-                dbg!("{}CMP E", self.code_to_str(1));
             }
             ////////////////// End of CMP E
             ////////////////// Start of CMP H
@@ -407,7 +395,6 @@ impl Cpu {
                 let tmp = self.a;
                 self.sub(self.h);
                 self.a = tmp;
-                dbg!("{}CMP H", self.code_to_str(1));
             }
             ////////////////// End of CMP H
             ////////////////// Start of CMP L
@@ -415,7 +402,6 @@ impl Cpu {
                 let tmp = self.a;
                 self.sub(self.l);
                 self.a = tmp;
-                dbg!("{}CMP L", self.code_to_str(1));
             }
             ////////////////// End of CMP L
             ////////////////// Start of CMP M
@@ -423,7 +409,6 @@ impl Cpu {
                 let tmp = self.a;
                 self.sub(self.read_m());
                 self.a = tmp;
-                dbg!("{}CMP M", self.code_to_str(1));
             }
             ////////////////// End of CMP M
             ////////////////// Start of CMP A
@@ -431,7 +416,6 @@ impl Cpu {
                 let tmp = self.a;
                 self.sub(self.a);
                 self.a = tmp;
-                dbg!("{}CMP A", self.code_to_str(1));
             }
             ////////////////// End of CMP A
             ////////////////// Start of CPI
@@ -440,7 +424,6 @@ impl Cpu {
                 let data = self.read_immediate_byte();
                 self.sub(data);
                 self.a = tmp;
-                dbg!("{}CPI", self.code_to_str(1));
             }
             ////////////////// End of CPI
 
@@ -448,59 +431,216 @@ impl Cpu {
             MVI_A => {
                 let value = self.read_immediate_byte();
                 self.a = value;
-                dbg!("{}MVI A,{:02X}H", self.code_to_str(2), value);
             }
             ////////////////// End of MVI A
             ////////////////// Start of MVI B
             MVI_B => {
                 let value = self.read_immediate_byte();
                 self.b = value;
-                dbg!("{}MVI B,{:02X}H", self.code_to_str(2), value);
             }
             ////////////////// End of MVI B
             ////////////////// Start of MVI C
             MVI_C => {
                 let value = self.read_immediate_byte();
                 self.c = value;
-                dbg!("{}MVI C,{:02X}H", self.code_to_str(2), value);
             }
             ////////////////// End of MVI C
             ////////////////// Start of MVI D
             MVI_D => {
                 let value = self.read_immediate_byte();
                 self.d = value;
-                dbg!("{}MVI D,{:02X}H", self.code_to_str(2), value);
             }
             ////////////////// End of MVI D
             ////////////////// Start of MVI E
             MVI_E => {
                 let value = self.read_immediate_byte();
                 self.e = value;
-                dbg!("{}MVI E,{:02X}H", self.code_to_str(2), value);
             }
             ////////////////// End of MVI E
             ////////////////// Start of MVI H
             MVI_H => {
                 let value = self.read_immediate_byte();
                 self.h = value;
-                dbg!("{}MVI H,{:02X}H", self.code_to_str(2), value);
             }
             ////////////////// End of MVI H
             ////////////////// Start of MVI L
             MVI_L => {
                 let value = self.read_immediate_byte();
                 self.l = value;
-                dbg!("{}MVI L,{:02X}H", self.code_to_str(2), value);
             }
             ////////////////// End of MVI L
+            ////////////////// Start of MVI M
+            MVI_M => {
+                let value = self.read_immediate_byte();
+                let hl = (self.h as u16) << 8 | self.l as u16;
+                self.memory.write_byte(hl, value);
+            }
+            ////////////////// End of MVI M
+            ////////////////// Start of IN
+            IN => {
+                let port = self.read_immediate_byte();
+                self.a = self.ports[port as usize];
+                self.port_access = Some(Access {
+                    addr: port as u16,
+                    value: self.a,
+                    kind: AccessKind::Read,
+                });
+            }
+            ////////////////// End of IN
+            ////////////////// Start of OUT
+            OUT => {
+                let port = self.read_immediate_byte();
+                self.ports[port as usize] = self.a;
+                self.port_access = Some(Access {
+                    addr: port as u16,
+                    value: self.a,
+                    kind: AccessKind::Write,
+                });
+            }
+            ////////////////// End of OUT
             ////////////////// Start of HLT
-            0x76 => {
-                dbg!("{}HLT", self.code_to_str(1));
-            }
+            0x76 => {}
             ////////////////// End of HLT
-            _ => {
-                dbg!("{}!byte {:02X}H", self.code_to_str(1), opcode);
-            }
+            _ => {}
+        }
+    }
+}
+
+impl Processor for Cpu {
+    fn kind(&self) -> CpuKind {
+        CpuKind::I8080
+    }
+    fn step(&mut self) {
+        Cpu::step(self)
+    }
+    fn pc(&self) -> u16 {
+        self.pc
+    }
+    fn set_pc(&mut self, pc: u16) {
+        self.pc = pc;
+    }
+    fn memory(&self) -> &Memory {
+        &self.memory
+    }
+    fn memory_mut(&mut self) -> &mut Memory {
+        &mut self.memory
+    }
+    fn set_debug(&mut self, debug: bool) {
+        Cpu::set_debug(self, debug)
+    }
+    fn set_trace(&mut self, sink: Option<Box<dyn TraceSink>>) {
+        Cpu::set_trace(self, sink)
+    }
+    fn print_registers(&self) -> String {
+        Cpu::print_registers(self)
+    }
+    fn stack_address(&self) -> u16 {
+        self.sp
+    }
+    fn stack_base(&self) -> u16 {
+        // The stack of the 8080 can be anywhere, show a few words above SP
+        self.sp.saturating_add(0x0F)
+    }
+    fn instruction_length(&self, addr: u16) -> u8 {
+        let opcode = self.memory.read_byte(addr);
+        opcodes().get(&opcode).map_or(1, |def| def.bytes())
+    }
+    fn instruction_cycles(&self, addr: u16) -> u8 {
+        let opcode = self.memory.read_byte(addr);
+        opcodes().get(&opcode).map_or(0, |def| def.cycles())
+    }
+    fn is_call(&self, addr: u16) -> bool {
+        let opcode = self.memory.read_byte(addr);
+        // CALL, conditional calls and RST n
+        matches!(opcode, CALL | CNZ | CZ | CNC | CC | CPO | CPE | CP | CM)
+            || opcode & 0xC7 == 0xC7
+    }
+    fn is_halt(&self, addr: u16) -> bool {
+        self.memory.read_byte(addr) == HLT
+    }
+    fn disassemble(&self, start: u16, count: usize) -> Vec<Instruction> {
+        let reader = Reader::new(&self.memory);
+        let symbols = SymbolTable::new();
+        disassembler::decode_count(start, count, |pc| decode(&reader, pc, opcodes(), &symbols))
+    }
+    fn register(&self, name: &str) -> Option<u16> {
+        let pair = |hi: u8, lo: u8| (hi as u16) << 8 | lo as u16;
+        let value = match name.to_ascii_uppercase().as_str() {
+            "A" => self.a as u16,
+            "B" => self.b as u16,
+            "C" => self.c as u16,
+            "D" => self.d as u16,
+            "E" => self.e as u16,
+            "H" => self.h as u16,
+            "L" => self.l as u16,
+            "BC" => pair(self.b, self.c),
+            "DE" => pair(self.d, self.e),
+            "HL" => pair(self.h, self.l),
+            "PSW" => pair(self.a, self.psw.value),
+            "F" => self.psw.value as u16,
+            "SP" => self.sp,
+            "PC" => self.pc,
+            _ => return None,
+        };
+        Some(value)
+    }
+    fn set_register(&mut self, name: &str, value: u16) -> bool {
+        let [lo, hi] = value.to_le_bytes();
+        match name.to_ascii_uppercase().as_str() {
+            "A" => self.a = lo,
+            "B" => self.b = lo,
+            "C" => self.c = lo,
+            "D" => self.d = lo,
+            "E" => self.e = lo,
+            "H" => self.h = lo,
+            "L" => self.l = lo,
+            "BC" => (self.b, self.c) = (hi, lo),
+            "DE" => (self.d, self.e) = (hi, lo),
+            "HL" => (self.h, self.l) = (hi, lo),
+            "PSW" => (self.a, self.psw.value) = (hi, lo),
+            "F" => self.psw.value = lo,
+            "SP" => self.sp = value,
+            "PC" => self.pc = value,
+            _ => return false,
         }
+        true
+    }
+    fn register_layout(&self) -> &'static [(&'static str, u8)] {
+        &[
+            ("A", 8),
+            ("F", 8),
+            ("B", 8),
+            ("C", 8),
+            ("D", 8),
+            ("E", 8),
+            ("H", 8),
+            ("L", 8),
+            ("SP", 16),
+            ("PC", 16),
+        ]
+    }
+    fn flag(&self, name: &str) -> Option<bool> {
+        // C is the register, the carry flag is CY as in the Intel manual
+        let value = match name.to_ascii_uppercase().as_str() {
+            "S" => self.psw.is_negative(),
+            "Z" => self.psw.is_zero(),
+            "AC" => self.psw.is_ac(),
+            "P" => self.psw.is_parity(),
+            "CY" => self.psw.is_carry(),
+            _ => return None,
+        };
+        Some(value)
+    }
+    fn port_access(&self) -> Option<Access> {
+        self.port_access
+    }
+    fn ports(&self) -> &[u8] {
+        &self.ports
+    }
+    fn ports_mut(&mut self) -> &mut [u8] {
+        &mut self.ports
+    }
+    fn load_program(&mut self, program: &[u8], start_addr: u16) {
+        Cpu::load_program(self, program, start_addr);
     }
 }
@@ -0,0 +1,335 @@
+//////////////////////////////////////////////////////////
+/// Table driven flag tests of the 8080 core. The tables have the format
+/// of documents/test_notes_i8080.txt, measured on a PMI-80: a header line
+/// names the columns, the rows below it hold the values.
+///
+///     ACC  -  data -> PSW                 CMP B, CMP M, CPI
+///     ACC  +  op  ->  ACC | PSW           ADD B, ADD M, ADI
+///     ACC  +  op   +  CY  ->  ACC | PSW   ADC B, ADC M, ACI
+///     ACC  &  op  ->  ACC | PSW           ANA B, ANA M, ANI
+///
+/// Values are hex with the suffix H (0AAH or AAH) or decimal, columns are
+/// separated by spaces, ',' or '|' so CSV works as well. Any other line
+/// ends the table. Every row runs through the register, memory and
+/// immediate form of the instruction; without a CY column each form runs
+/// with the carry cleared and set, the result must not depend on it.
+///
+/// ```
+/// let tables = i8080_tables::parse(&std::fs::read_to_string(path)?)?;
+/// for table in &tables {
+///     for mismatch in i8080_tables::run(table) {
+///         println!("{}", mismatch);
+///     }
+/// }
+/// ```
+//////////////////////////////////////////////////////////
+use std::fmt;
+
+use crate::cpu::i8080::Cpu;
+use crate::disassembler::i8080::opcodes;
+use crate::disassembler::i8080_opcodes_const::*;
+use crate::status::i8080::Psw;
+
+///
+/// Where the memory forms find the operand (HL)
+///
+const OPERAND_ADDRESS: u16 = 0x2000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TableError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for TableError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for TableError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    Add,
+    AddWithCarry,
+    Compare,
+    And,
+}
+
+impl Operation {
+    ///
+    /// Mnemonics and opcodes of the register, memory and immediate forms
+    ///
+    fn forms(self) -> [(&'static str, u8); 3] {
+        match self {
+            Operation::Add => [("ADD B", ADD_B), ("ADD M", ADD_M), ("ADI", ADI)],
+            Operation::AddWithCarry => [("ADC B", ADC_B), ("ADC M", ADC_M), ("ACI", ACI)],
+            Operation::Compare => [("CMP B", CMP_B), ("CMP M", CMP_M), ("CPI", CPI)],
+            Operation::And => [("ANA B", ANA_B), ("ANA M", ANA_M), ("ANI", ANI)],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Row {
+    pub line: usize,
+    pub acc: u8,
+    pub operand: u8,
+    ///
+    /// Carry before the instruction, None if the table has no CY column
+    ///
+    pub carry: Option<bool>,
+    ///
+    /// ACC after the instruction, None for compares (ACC is kept)
+    ///
+    pub result: Option<u8>,
+    pub psw: u8,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Table {
+    pub line: usize,
+    pub operation: Operation,
+    pub rows: Vec<Row>,
+}
+
+impl Table {
+    fn columns(&self) -> usize {
+        let carry = self.operation == Operation::AddWithCarry;
+        let result = self.operation != Operation::Compare;
+        3 + carry as usize + result as usize
+    }
+}
+
+///
+/// Row whose instruction gave other values than the table
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mismatch {
+    pub row: Row,
+    pub form: &'static str,
+    pub carry: bool,
+    pub acc: u8,
+    pub psw: u8,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let row = &self.row;
+        write!(
+            f,
+            "line {}: {} with A={:02X}H, operand={:02X}H, CY={}: ",
+            row.line, self.form, row.acc, row.operand, self.carry as u8
+        )?;
+        let expected = row.result.unwrap_or(row.acc);
+        if self.acc != expected {
+            write!(f, "ACC {:02X}H expected, got {:02X}H, ", expected, self.acc)?;
+        }
+        write!(f, "PSW {:02X}H expected, got {:02X}H", row.psw, self.psw)
+    }
+}
+
+///
+/// Value in the notation of the notes: hex with suffix H or decimal
+///
+fn number(text: &str) -> Option<u8> {
+    match text.strip_suffix('H').or_else(|| text.strip_suffix('h')) {
+        Some(hex) => u8::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+fn fields(line: &str) -> Vec<&str> {
+    line.split(|c: char| c.is_whitespace() || matches!(c, ',' | '|'))
+        .filter(|field| !field.is_empty())
+        .collect()
+}
+
+///
+/// Operation of a header line like "ACC + op + CY -> ACC | PSW"
+///
+fn header(line: &str) -> Option<Result<Operation, String>> {
+    let (inputs, outputs) = line.split_once("->")?;
+    let inputs = fields(inputs);
+    let outputs = fields(outputs);
+    let carry = inputs.iter().any(|field| field.eq_ignore_ascii_case("CY"));
+    let result = outputs
+        .iter()
+        .any(|field| field.eq_ignore_ascii_case("ACC"));
+    let operator = inputs.get(1).copied().unwrap_or_default();
+    let operation = match (operator, carry, result) {
+        ("+", false, true) => Operation::Add,
+        ("+", true, true) => Operation::AddWithCarry,
+        ("-", false, false) => Operation::Compare,
+        ("&", false, true) => Operation::And,
+        _ => {
+            return Some(Err(format!(
+                "no instruction for the table '{}'",
+                line.trim()
+            )));
+        }
+    };
+    Some(Ok(operation))
+}
+
+///
+/// Tables of the text, the prose between them is skipped
+///
+pub fn parse(text: &str) -> Result<Vec<Table>, TableError> {
+    let mut tables: Vec<Table> = Vec::new();
+    let mut open = false;
+    for (index, line) in text.lines().enumerate() {
+        let line_number = index + 1;
+        if let Some(operation) = header(line) {
+            let operation = operation.map_err(|message| TableError {
+                line: line_number,
+                message,
+            })?;
+            tables.push(Table {
+                line: line_number,
+                operation,
+                rows: Vec::new(),
+            });
+            open = true;
+            continue;
+        }
+        let values: Option<Vec<u8>> = fields(line).into_iter().map(number).collect();
+        let (Some(table), Some(values)) = (tables.last_mut().filter(|_| open), values) else {
+            open = false;
+            continue;
+        };
+        if values.is_empty() {
+            continue;
+        }
+        if values.len() != table.columns() {
+            return Err(TableError {
+                line: line_number,
+                message: format!(
+                    "{} values, the table has {} columns",
+                    values.len(),
+                    table.columns()
+                ),
+            });
+        }
+        let mut values = values.into_iter();
+        let mut next = || values.next().unwrap_or_default();
+        let (acc, operand) = (next(), next());
+        let carry = (table.operation == Operation::AddWithCarry).then(|| next() != 0);
+        let result = (table.operation != Operation::Compare).then(&mut next);
+        table.rows.push(Row {
+            line: line_number,
+            acc,
+            operand,
+            carry,
+            result,
+            psw: next(),
+        });
+    }
+    Ok(tables)
+}
+
+///
+/// Executes one form of the instruction, returns ACC and PSW
+///
+fn execute(opcode: u8, row: &Row, carry: bool) -> (u8, u8) {
+    let mut cpu = Cpu::new();
+    cpu.a = row.acc;
+    cpu.b = row.operand;
+    [cpu.h, cpu.l] = OPERAND_ADDRESS.to_be_bytes();
+    cpu.memory.write_byte(OPERAND_ADDRESS, row.operand);
+    cpu.psw = Psw::new();
+    cpu.psw.set_carry(carry);
+    // only the immediate form has the operand after the opcode
+    let program = match opcodes().get(&opcode).map(|def| def.bytes()) {
+        Some(2) => vec![opcode, row.operand, HLT],
+        _ => vec![opcode, HLT],
+    };
+    cpu.load_program(&program, 0x0000);
+    cpu.step();
+    (cpu.a, cpu.psw.value)
+}
+
+///
+/// Runs every row of the table, empty if all forms give the values of
+/// the table
+///
+pub fn run(table: &Table) -> Vec<Mismatch> {
+    let mut mismatches = Vec::new();
+    for row in &table.rows {
+        let carries = match row.carry {
+            Some(carry) => vec![carry],
+            None => vec![false, true],
+        };
+        for (form, opcode) in table.operation.forms() {
+            for &carry in &carries {
+                let (acc, psw) = execute(opcode, row, carry);
+                if acc != row.result.unwrap_or(row.acc) || psw != row.psw {
+                    mismatches.push(Mismatch {
+                        row: *row,
+                        form,
+                        carry,
+                        acc,
+                        psw,
+                    });
+                }
+            }
+        }
+    }
+    mismatches
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    ///
+    /// All tables measured on the PMI-80
+    ///
+    fn hardware_notes() {
+        let text = std::fs::read_to_string("documents/test_notes_i8080.txt").unwrap();
+        let tables = parse(&text).unwrap();
+        let operations: Vec<(Operation, usize)> = tables
+            .iter()
+            .map(|table| (table.operation, table.rows.len()))
+            .collect();
+        assert_eq!(
+            operations,
+            [
+                (Operation::Compare, 15),
+                (Operation::AddWithCarry, 12),
+                (Operation::Add, 9),
+                (Operation::And, 9),
+            ]
+        );
+        let mismatches: Vec<String> = tables
+            .iter()
+            .flat_map(run)
+            .map(|mismatch| mismatch.to_string())
+            .collect();
+        assert!(mismatches.is_empty(), "{}", mismatches.join("\n"));
+    }
+
+    #[test]
+    ///
+    /// CSV rows, a wrong PSW is reported for every form, format errors
+    ///
+    fn csv_and_errors() {
+        let tables = parse("ACC,+,op,->,ACC,PSW\n12H,35H,47H,06H\n12H,34H,46H,06H\n").unwrap();
+        assert_eq!(tables[0].rows.len(), 2);
+        let mismatches = run(&tables[0]);
+        assert_eq!(mismatches.len(), 6);
+        assert_eq!(
+            mismatches[0].to_string(),
+            "line 3: ADD B with A=12H, operand=34H, CY=0: PSW 06H expected, got 02H"
+        );
+
+        let error = parse("ACC + op -> ACC | PSW\n12H 35H 47H\n").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "line 2: 3 values, the table has 4 columns"
+        );
+        assert!(parse("ACC ^ op -> ACC | PSW\n").is_err());
+    }
+}
@@ -692,6 +692,43 @@ fn ana_m() {
 }
 #[test]
 ///
+/// Tests ANA M, the operand is read from (HL)
+///
+fn ana_m_reads_hl() {
+    let mut cpu = Cpu::new();
+    cpu.memory.write_byte(0x1234, 0x55);
+    let program: Vec<u8> = vec![MVI_A, 0x1F, MVI_H, 0x12, MVI_L, 0x34, ANA_M, HLT];
+    cpu.load_program(&program, 0x0600);
+    loop {
+        let opcode = cpu.memory.read_byte(cpu.pc);
+        cpu.step();
+        if opcode == HLT {
+            break;
+        }
+    }
+    assert_eq!(cpu.a, 0x15u8);
+    assert_eq!(cpu.pc, 0x0608);
+}
+#[test]
+///
+/// Tests MVI M, the immediate byte is written to (HL)
+///
+fn mvi_m() {
+    let mut cpu = Cpu::new();
+    let program: Vec<u8> = vec![MVI_H, 0x12, MVI_L, 0x34, MVI_M, 0xA5, HLT];
+    cpu.load_program(&program, 0x0600);
+    loop {
+        let opcode = cpu.memory.read_byte(cpu.pc);
+        cpu.step();
+        if opcode == HLT {
+            break;
+        }
+    }
+    assert_eq!(cpu.memory.read_byte(0x1234), 0xA5);
+    assert_eq!(cpu.pc, 0x0607);
+}
+#[test]
+///
 /// Tests ANA A
 ///
 fn ana_a() {
@@ -1109,3 +1146,24 @@ fn cpi_55_aa() {
     assert_eq!(cpu.a, 0x55u8);
     assert_eq!(cpu.psw.value, 0x83);
 }
+#[test]
+///
+/// Tests IN and OUT, the accessed port is recorded for watchpoints
+///
+fn in_out_ports() {
+    let mut cpu = Cpu::new();
+    cpu.ports[0x10] = 0x42;
+    let program: Vec<u8> = vec![IN, 0x10, OUT, 0x20, HLT];
+    cpu.load_program(&program, 0x0600);
+    cpu.step();
+    assert_eq!(cpu.a, 0x42);
+    assert_eq!(cpu.port_access.map(|access| access.addr), Some(0x10));
+    cpu.step();
+    assert_eq!(cpu.ports[0x20], 0x42);
+    assert_eq!(
+        cpu.port_access.map(|access| access.kind),
+        Some(crate::memory::AccessKind::Write)
+    );
+    cpu.step();
+    assert_eq!(cpu.port_access, None);
+}
@@ -1,4 +1,145 @@
 pub mod i8080;
+pub mod i8080_tables;
 pub mod mos6502;
+pub mod single_step;
 //pub mod mos6502_tests;
 pub mod i8080_tests;
+#[cfg(test)]
+mod differential;
+#[cfg(test)]
+mod mos6502_functional;
+
+use crate::disassembler::Instruction;
+use crate::machine::config::CpuKind;
+use crate::memory::{Access, AccessKind, Memory};
+use crate::trace::TraceSink;
+
+///
+/// Common interface of the CPU cores. Front ends (debugger, ...) use it
+/// so that they work with every CPU without knowing its registers.
+///
+pub trait Processor {
+    fn kind(&self) -> CpuKind;
+    fn step(&mut self);
+    fn pc(&self) -> u16;
+    fn set_pc(&mut self, pc: u16);
+    fn memory(&self) -> &Memory;
+    fn memory_mut(&mut self) -> &mut Memory;
+    fn set_debug(&mut self, debug: bool);
+    fn set_trace(&mut self, sink: Option<Box<dyn TraceSink>>);
+    ///
+    /// Registers and flags formatted as a table
+    ///
+    fn print_registers(&self) -> String;
+    ///
+    /// Address of the top of the stack in memory
+    ///
+    fn stack_address(&self) -> u16;
+    ///
+    /// Address where the stack starts (the stack grows down from it)
+    ///
+    fn stack_base(&self) -> u16;
+    ///
+    /// Length of the instruction at addr in bytes
+    ///
+    fn instruction_length(&self, addr: u16) -> u8;
+    ///
+    /// Cycles of the instruction at addr from the opcode table
+    ///
+    fn instruction_cycles(&self, addr: u16) -> u8;
+    ///
+    /// true if the instruction at addr is a subroutine call (JSR, CALL, RST, ...)
+    ///
+    fn is_call(&self, addr: u16) -> bool;
+    ///
+    /// true if the instruction at addr ends the program (HLT, or BRK on the 6502
+    /// which is used as end of program in all the tests)
+    ///
+    fn is_halt(&self, addr: u16) -> bool;
+    ///
+    /// count instructions from start, wrapping at the top of memory
+    ///
+    fn disassemble(&self, start: u16, count: usize) -> Vec<Instruction>;
+    ///
+    /// Value of a register by its name (case insensitive), e.g. "A", "SP", "HL".
+    /// None if the CPU has no such register.
+    ///
+    fn register(&self, name: &str) -> Option<u16>;
+    ///
+    /// Sets a register by its name, returns false if the CPU has no such register.
+    /// 8 bit registers take the low byte of value.
+    ///
+    fn set_register(&mut self, name: &str, value: u16) -> bool;
+    ///
+    /// Names and widths in bits of all registers, in the order used by
+    /// the GDB stub and the front ends
+    ///
+    fn register_layout(&self) -> &'static [(&'static str, u8)];
+    ///
+    /// State of a flag by its name (case insensitive), e.g. "Z", "C" or "CY"
+    ///
+    fn flag(&self, name: &str) -> Option<bool>;
+    ///
+    /// I/O port read or written by the last step, only CPUs with a separate
+    /// I/O space (8080) have it
+    ///
+    fn port_access(&self) -> Option<Access> {
+        None
+    }
+    ///
+    /// Latches of the I/O ports, empty for CPUs without I/O space
+    ///
+    fn ports(&self) -> &[u8] {
+        &[]
+    }
+    fn ports_mut(&mut self) -> &mut [u8] {
+        &mut []
+    }
+    ///
+    /// Loads program to the memory and sets PC to its start address
+    ///
+    fn load_program(&mut self, program: &[u8], start_addr: u16) {
+        self.memory_mut().load_program(program, start_addr);
+        self.set_pc(start_addr);
+    }
+    ///
+    /// Maskable interrupt request, true if it was taken. CPUs without
+    /// interrupt inputs ignore it.
+    ///
+    fn irq(&mut self) -> bool {
+        false
+    }
+    ///
+    /// Non maskable interrupt, true if it was taken
+    ///
+    fn nmi(&mut self) -> bool {
+        false
+    }
+}
+
+///
+/// Core of the CPU kind, None if there is no emulation of it yet
+///
+pub fn new(kind: CpuKind) -> Option<Box<dyn Processor>> {
+    match kind {
+        CpuKind::I8080 => Some(Box::new(i8080::Cpu::new())),
+        CpuKind::Mos6502 => Some(Box::new(mos6502::Cpu::new())),
+        CpuKind::I8085 | CpuKind::Wdc65C02 => None,
+    }
+}
+
+///
+/// Executes one instruction and returns the memory accesses it made, without
+/// the reads of the instruction bytes themselves. Memory tracking has to be on.
+///
+pub fn step_tracked(cpu: &mut dyn Processor) -> Vec<Access> {
+    let pc = cpu.pc();
+    let length = cpu.instruction_length(pc) as u16;
+    let mark = cpu.memory().access_count();
+    cpu.step();
+    cpu.memory()
+        .accesses_since(mark)
+        .into_iter()
+        .filter(|access| access.kind == AccessKind::Write || access.addr.wrapping_sub(pc) >= length)
+        .collect()
+}
@@ -18,6 +18,7 @@ use log::debug;
 ///     ];
 ///
 ///     self.load_program(&program, 0x0600);
+///     self.set_debug(true);
 ///
 ///     loop {
 ///         let opcode = self.memory.read_byte(self.pc);
@@ -33,14 +34,22 @@ use log::debug;
 /// ```
 ///
 /// Result should be:
-/// LDA #$00
-/// LDX #$FF
-/// BRK (break)
+/// 0600  A9 00     LDA #$00                        A:00 X:00 Y:00 P:00 SP:FF CYC:0
+/// 0602  A2 FF     LDX #$FF                        A:00 X:00 Y:00 P:02 SP:FF CYC:2
+/// 0604  00        BRK                             A:00 X:FF Y:00 P:80 SP:FF CYC:4
 /// A = 00, X = FF
 /// Flags: Z=false, N=true
 ///////////////////////////////////////////////////////////////////////////////
+use std::io;
+
+use crate::cpu::Processor;
+use crate::disassembler::mos6502::{decode, opcodes};
+use crate::disassembler::{self, Instruction, Reader};
+use crate::machine::config::CpuKind;
 use crate::memory::Memory;
 use crate::status::mos6502;
+use crate::symbols::SymbolTable;
+use crate::trace::{self, TraceSink, text::TextSink};
 
 pub struct Cpu {
     pub a: u8,
@@ -50,7 +59,10 @@ pub struct Cpu {
     pub pc: u16,
     pub p: mos6502::Status,
     pub memory: Memory,
-    debug: bool,
+    ///
+    /// Receives a record of every executed instruction, see trace module
+    ///
+    trace: Option<Box<dyn TraceSink>>,
 }
 
 impl Cpu {
@@ -63,7 +75,7 @@ impl Cpu {
             pc: 0,
             p: mos6502::Status::default(),
             memory: Memory::new(),
-            debug: true,
+            trace: None,
         }
     }
 
@@ -97,24 +109,71 @@ impl Cpu {
             self.p.is_carry() as u8
         )
     }
+    ///
+    /// Debug mode prints the trace of every instruction to stdout
+    ///
     pub fn set_debug(&mut self, debug: bool) {
-        self.debug = debug;
+        self.trace = if debug {
+            Some(Box::new(TextSink::new(io::stdout())))
+        } else {
+            None
+        };
+    }
+    pub fn set_trace(&mut self, sink: Option<Box<dyn TraceSink>>) {
+        self.trace = sink;
+    }
+    ///
+    /// Executes one instruction, with a trace sink the instruction is traced
+    ///
+    pub fn step(&mut self) {
+        match self.trace.take() {
+            Some(mut sink) => {
+                // trace::step() calls step() again, now without the sink
+                let record = trace::step(self);
+                sink.record(&record);
+                self.trace = Some(sink);
+            }
+            None => self.execute(),
+        }
+    }
+    ///
+    /// Maskable interrupt request, taken between instructions if the I flag
+    /// is clear. Returns true if the interrupt was taken.
+    ///
+    pub fn irq(&mut self) -> bool {
+        if self.p.is_interrupt_disable() {
+            return false;
+        }
+        self.interrupt(0xFFFE, false);
+        true
+    }
+    ///
+    /// Non maskable interrupt, continues at the NMI vector
+    ///
+    pub fn nmi(&mut self) {
+        self.interrupt(0xFFFA, false);
     }
     fn brk(&mut self) {
         self.pc += 1; // BRK is a 2-byte instruction (but the second byte is ignored)
+        self.interrupt(0xFFFE, true);
+    }
+    ///
+    /// Pushes PC and status and continues at the address in vector. The
+    /// Break flag of the pushed status tells BRK from IRQ.
+    ///
+    fn interrupt(&mut self, vector: u16, brk: bool) {
         // Push PC to stack (high byte first)
-        self.push((self.pc >> 8) as u8);
-        self.push((self.pc & 0xFF) as u8);
-        // Push status with Break flag set
-        let mut status = self.p.value;
-        status |= mos6502::BREAK;
-        status |= mos6502::UNUSED; // Bit 5 is always set in stack copy
+        self.push_word(self.pc);
+        let mut status = self.p.value | mos6502::UNUSED; // Bit 5 is always set in stack copy
+        if brk {
+            status |= mos6502::BREAK;
+        } else {
+            status &= !mos6502::BREAK;
+        }
         self.push(status);
         // Set Interrupt Disable flag
         self.p.set_interrupt_disable(true);
-        // Load IRQ vector
-        let pc = self.memory.read_word(0xFFFE) as u16;
-        self.pc = pc;
+        self.pc = self.memory.read_word(vector);
     }
     fn push(&mut self, value: u8) {
         let addr = 0x0100u16 + self.sp as u16;
@@ -300,9 +359,9 @@ impl Cpu {
     fn sbc(&mut self, value: u8) {
         let carry = if self.p.is_carry() { 1 } else { 0 };
         let a = self.a;
-        // Perform the subtraction using two's complement: A + (~M + 1) - C)) = A - M - C
+        // A - M - (1 - C) is the addition of the one's complement: A + ~M + C
         let value_inv = value ^ 0xFF;
-        let sum = a as u16 + value_inv.wrapping_add(1) as u16 - carry as u16;
+        let sum = a as u16 + value_inv as u16 + carry as u16;
         self.p
             .set_overflow(((self.a ^ sum as u8) & (self.a ^ value) & 0x80) != 0);
         self.a = sum as u8;
@@ -362,32 +421,7 @@ impl Cpu {
         flag = if value & 0x80 != 0 { true } else { false };
         self.p.set_negative(flag);
     }
-    ///
-    /// This function formats HEX string from address and opcode and
-    /// is used in debug mode to print address and opcode of the instruction that
-    /// is currently being processed
-    /// For example if you are processing the following code at address 0x200
-    /// LDA #$01
-    /// Then it looks like this in memory
-    /// 0x200 0xA9, 0x01,
-    /// and PC points to the address of 0x0202 (just after the operand).
-    /// To get the opcode you have to go back 2 bytes so neg_offset is 2.
-    /// And the resulting string is '0200 3E'
-    ///  
-    fn code_to_str(&mut self, mut neg_offset: u8) -> String {
-        let mut addr = self.pc.wrapping_sub(neg_offset as u16);
-        let mut result = String::new();
-        result.push_str(format!("{:04X}  ", addr).as_str());
-        while neg_offset != 0 {
-            result.push_str(format!("{:02X} ", self.memory.read_byte(addr)).as_str());
-            neg_offset -= 1;
-            addr += 1;
-        }
-        format!("{:<18}", result)
-    }
-    pub fn step(&mut self) {
-        macro_rules! dbg { ($($x:tt)*) => { if self.debug { println!($($x)*); } } }
-
+    fn execute(&mut self) {
         let opcode = self.memory.read_byte(self.pc);
         self.pc += 1;
 
@@ -397,125 +431,88 @@ impl Cpu {
             0x69 => {
                 let value = self.read_immediate_byte();
                 self.adc(value);
-                dbg!("{}ADC #${:02X}", self.code_to_str(2), value);
             }
             // ADC zp
             0x65 => {
                 let value = self.read_zero_page();
                 self.adc(value);
-                dbg!(
-                    "{}ADC ${:02X}",
-                    self.code_to_str(2),
-                    self.memory.read_byte(self.pc.wrapping_sub(1))
-                );
             }
             // ADC oper ;zero page,X
             0x75 => {
                 let value = self.read_zero_page_x();
                 self.adc(value);
-                dbg!(
-                    "{}ADC ${:02X},X",
-                    self.code_to_str(2),
-                    self.memory.read_byte(self.pc.wrapping_sub(1))
-                );
             }
             // ADC oper ;absolute
             0x6D => {
-                let addr = self.get_absolute_address();
                 let value = self.read_absolute();
                 self.adc(value);
-                dbg!("{}ADC ${:04X}", self.code_to_str(3), addr);
             }
             // ADC oper ;absolute,X
             0x7D => {
-                let addr = self.get_absolute_address();
                 let value = self.read_absolute_x();
                 self.adc(value);
-                dbg!("{}ADC ${:04X},X", self.code_to_str(3), addr);
             }
             // ADC abs,Y ;absolute,Y
             0x79 => {
-                let addr = self.get_absolute_address();
                 let value = self.read_absolute_y();
                 self.adc(value);
-                dbg!("{}ADC ${:02X},Y", self.code_to_str(3), addr);
             }
             // ADC (oper,X) ;(indexed indirect)
             0x61 => {
-                let addr = self.get_zero_page_address();
                 let value = self.read_indexed_indirect();
                 self.adc(value);
-                dbg!("{}ADC (${:02X},X)", self.code_to_str(2), addr);
             }
             // ADC (oper),Y ;(indexed indirect),Y
             0x71 => {
-                let addr = self.get_zero_page_address();
                 let value = self.read_indirect_indexed();
                 self.adc(value);
-                dbg!("{}ADC (${:02X}),Y", self.code_to_str(2), addr);
             }
             ////////////////// End of ADC
             ////////////////// Start of AND
             0x29 => {
                 let value = self.read_immediate_byte();
                 self.and(value);
-                dbg!("{}AND #${:02X}", self.code_to_str(2), value);
             }
             0x25 => {
                 // AND zp
-                let addr = self.get_zero_page_address();
                 let value = self.read_zero_page();
                 self.and(value);
-                dbg!("{}AND ${:02X}", self.code_to_str(2), addr);
             }
             0x35 => {
                 // AND zp,X
-                let addr = self.get_zero_page_address();
                 let value = self.read_zero_page_x();
                 self.and(value);
-                dbg!("{}AND ${:02X},X", self.code_to_str(2), addr);
             }
             0x2D => {
                 // AND abs
-                let addr = self.get_absolute_address();
                 let value = self.read_absolute();
                 self.and(value);
-                dbg!("{}AND ${:04X}", self.code_to_str(3), addr);
             }
             0x3D => {
                 // AND abs,X
-                let addr = self.get_absolute_address();
                 let value = self.read_absolute_x();
                 self.and(value);
-                dbg!("{}AND ${:04X},X", self.code_to_str(3), addr);
             }
             0x39 => {
                 // AND abs,Y
-                let addr = self.get_absolute_address();
                 let value = self.read_absolute_y();
                 self.and(value);
-                dbg!("{}AND ${:04X},Y", self.code_to_str(3), addr);
             }
             0x21 => {
                 // AND (indirect,X)
-                let addr = self.get_zero_page_address();
                 let value = self.read_indexed_indirect();
                 self.and(value);
-                dbg!("{}AND (${:02X},X)", self.code_to_str(2), addr);
             }
             0x31 => {
                 // AND (indirect),Y
-                let addr = self.get_zero_page_address();
                 let value = self.read_indirect_indexed();
                 self.and(value);
-                dbg!("{}AND (${:02X}),Y", self.code_to_str(2), addr);
             }
             ////////////////// End of AND
             ////////////////// Start of ASL
             0x0A => {
                 // ASL A
                 self.a = self.asl(self.a);
-                dbg!("{}ASL A", self.code_to_str(1));
             }
             0x06 => {
                 // ASL Zero Page
@@ -523,16 +520,13 @@ impl Cpu {
                 let value = self.read_zero_page();
                 let result = self.asl(value);
                 self.memory.write_byte_zero_page(addr, result);
-                dbg!("{}ASL ${:02X}", self.code_to_str(2), addr);
             }
             0x16 => {
                 // ASL Zero Page,X
-                let addr_zp = self.get_zero_page_address();
                 let addr = self.get_zero_page_address_x();
                 let value = self.read_zero_page_x();
                 let result = self.asl(value);
                 self.memory.write_byte(addr as u16, result);
-                dbg!("{}ASL ${:02X},X", self.code_to_str(2), addr_zp);
             }
             0x0E => {
                 // ASL Absolute
@@ -540,7 +534,6 @@ impl Cpu {
                 let value = self.read_absolute();
                 let result = self.asl(value);
                 self.memory.write_byte(addr, result);
-                dbg!("{}ASL ${:04X}", self.code_to_str(3), addr);
             }
             0x1E => {
                 // ASL Absolute,X
@@ -549,7 +542,6 @@ impl Cpu {
                 let value = self.read_absolute_x();
                 let result = self.asl(value);
                 self.memory.write_byte(addr, result);
-                dbg!("{}ASL ${:04X},X", self.code_to_str(3), addr_zp);
             }
             ////////////////// End of ASL
             ////////////////// Start of BCC
@@ -557,10 +549,8 @@ impl Cpu {
                 // BCC
                 let offset = self.read_immediate_byte() as i8;
                 let addr = self.pc.wrapping_add(offset as u16);
-                dbg!("{}BCC ${:04X}", self.code_to_str(2), addr);
                 if !self.p.is_carry() {
                     self.pc = addr;
-                    dbg!("----");
                 }
             }
             ////////////////// End of BCC
@@ -569,10 +559,8 @@ impl Cpu {
                 // BCS
                 let offset = self.read_immediate_byte() as i8;
                 let addr = self.pc.wrapping_add(offset as u16);
-                dbg!("{}BCS ${:04X}", self.code_to_str(2), addr);
                 if self.p.is_carry() {
                     self.pc = addr;
-                    dbg!("----");
                 }
             }
             ////////////////// End of BCS
@@ -581,27 +569,22 @@ impl Cpu {
                 // BEQ (Branch if Equal / Zero flag set)
                 let offset = self.read_immediate_byte() as i8;
                 let addr = self.pc.wrapping_add(offset as u16);
-                dbg!("{}BEQ ${:04X}", self.code_to_str(2), addr);
                 if self.p.is_zero() {
                     self.pc = addr;
-                    dbg!("----");
                 }
             }
             ////////////////// End of BEQ
             ////////////////// Start of BIT
             0x24 => {
                 // BIT Zero Page
-                let addr = self.get_zero_page_address();
                 let value = self.read_zero_page();
                 self.bit(value);
-                dbg!("{}BIT ${:02X}", self.code_to_str(2), addr);
             }
             0x2C => {
                 // BIT Absolute
                 let addr = self.read_immediate_word();
                 let value = self.memory.read_byte(addr);
                 self.bit(value);
-                dbg!("{}BIT ${:04X}", self.code_to_str(3), addr);
             }
             ////////////////// End of BIT
             ////////////////// Start of BMI
@@ -609,10 +592,8 @@ impl Cpu {
                 // BMI
                 let offset = self.read_immediate_byte() as i8;
                 let addr = self.pc.wrapping_add(offset as u16);
-                dbg!("{}BMI ${:04X}", self.code_to_str(2), addr);
                 if self.p.is_negative() {
                     self.pc = addr;
-                    dbg!("----");
                 }
             }
             ////////////////// End of BMI
@@ -621,10 +602,8 @@ impl Cpu {
                 // BNE (Branch if Not Equal / Zero flag clear)
                 let offset = self.read_immediate_byte() as i8;
                 let addr = self.pc.wrapping_add(offset as u16);
-                dbg!("{}BNE ${:04X}", self.code_to_str(3), addr);
                 if !self.p.is_zero() {
                     self.pc = addr;
-                    dbg!("----");
                 }
             }
             ////////////////// End of BNE
@@ -633,17 +612,13 @@ impl Cpu {
                 // BPL
                 let offset = self.read_immediate_byte() as i8;
                 let addr = self.pc.wrapping_add(offset as u16);
-                dbg!("{}BPL ${:04X}", self.code_to_str(3), addr);
                 if !self.p.is_negative() {
                     self.pc = addr;
-                    dbg!("----");
                 }
             }
             ////////////////// End of BPL
             ////////////////// Start of BRK
             0x00 => {
-                dbg!("{}BRK", self.code_to_str(1));
-                dbg!("----");
                 self.brk();
             }
             ////////////////// End of BRK
@@ -652,10 +627,8 @@ impl Cpu {
                 // BVC
                 let offset = self.read_immediate_byte() as i8;
                 let addr = self.pc.wrapping_add(offset as u16);
-                dbg!("{}BVC ${:04X}", self.code_to_str(3), addr);
                 if self.p.value & 0x40 == 0 {
                     self.pc = addr;
-                    dbg!("----");
                 }
             }
             ////////////////// End of BVC
@@ -664,35 +637,29 @@ impl Cpu {
                 // BVS
                 let offset = self.read_immediate_byte() as i8;
                 let addr = self.pc.wrapping_add(offset as u16);
-                dbg!("{}BVS ${:04X}", self.code_to_str(3), addr);
                 if self.p.value & 0x40 != 0 {
                     self.pc = addr;
-                    dbg!("----");
                 }
             }
             ////////////////// End of BVS
             ////////////////// Start of CLC
             0x18 => {
                 self.p.set_carry(false);
-                dbg!("{}CLC", self.code_to_str(1));
             }
             ////////////////// End of CLC
             ////////////////// Start of CLD
             0xD8 => {
                 self.p.set_decimal_mode(false);
-                dbg!("{}CLD", self.code_to_str(1));
             }
             ////////////////// End of CLD
             ////////////////// Start of CLI
             0x58 => {
                 self.p.set_interrupt_disable(false);
-                dbg!("{}CLI", self.code_to_str(1));
             }
             ////////////////// End of CLI
             ////////////////// Start of CLV
             0xB8 => {
                 self.p.set_overflow(false);
-                dbg!("{}CLV", self.code_to_str(1));
             }
             ////////////////// End of CLV
             ////////////////// Start of CMP
@@ -700,56 +667,41 @@ impl Cpu {
                 // CMP #imm
                 let value = self.read_immediate_byte();
                 self.cmp(value);
-                dbg!("{}CMP #${:02X}", self.code_to_str(2), value);
             }
             0xC5 => {
                 // CMP zp
-                let addr = self.get_zero_page_address();
                 let value = self.read_zero_page();
                 self.cmp(value);
-                dbg!("{}CMP ${:02X}", self.code_to_str(2), addr);
             }
             0xD5 => {
                 // CMP zp,X
-                let addr = self.get_zero_page_address();
                 let value = self.read_zero_page_x();
                 self.cmp(value);
-                dbg!("{}CMP ${:02X},X", self.code_to_str(2), addr);
             }
             0xCD => {
                 // CMP abs
-                let addr = self.get_absolute_address();
                 let value = self.read_absolute();
                 self.cmp(value);
-                dbg!("{}CMP ${:04X}", self.code_to_str(3), addr);
             }
             0xDD => {
                 // CMP abs,X
-                let addr = self.get_absolute_address();
                 let value = self.read_absolute_x();
                 self.cmp(value);
-                dbg!("{}CMP ${:04X},X", self.code_to_str(3), addr);
             }
             0xD9 => {
                 // CMP abs,Y
-                let addr = self.get_absolute_address();
                 let value = self.read_absolute_y();
                 self.cmp(value);
-                dbg!("{}CMP ${:04X},Y", self.code_to_str(3), addr);
             }
             0xC1 => {
                 // CMP (zp,X)
-                let addr = self.get_zero_page_address();
                 let value = self.read_indexed_indirect();
                 self.cmp(value);
-                dbg!("{}CMP (${:02X},X)", self.code_to_str(2), addr);
             }
             0xD1 => {
                 // CMP (zp),Y
-                let addr = self.get_zero_page_address();
                 let value = self.read_indirect_indexed();
                 self.cmp(value);
-                dbg!("{}CMP (${:02X}),Y", self.code_to_str(2), addr);
             }
             ////////////////// End of CMP
             ////////////////// Start of CPX
@@ -757,21 +709,16 @@ impl Cpu {
                 // CPX #imm
                 let value = self.read_immediate_byte();
                 self.cpx(value);
-                dbg!("{}CPX #${:02X}", self.code_to_str(2), value);
             }
             0xE4 => {
                 // CPX zp
-                let addr = self.get_zero_page_address();
                 let value = self.read_zero_page();
                 self.cpx(value);
-                dbg!("{}CPX ${:02X}", self.code_to_str(2), addr);
             }
             0xEC => {
                 // CPX abs
-                let addr = self.get_absolute_address();
                 let value = self.read_absolute();
                 self.cpx(value);
-                dbg!("{}CPX ${:04X}", self.code_to_str(3), addr);
             }
             ////////////////// End of CPX
             ////////////////// Start of CPY
@@ -779,21 +726,16 @@ impl Cpu {
                 // CPY #imm
                 let value = self.read_immediate_byte();
                 self.cpy(value);
-                dbg!("{}CPY #${:02X}", self.code_to_str(2), value);
             }
             0xC4 => {
                 // CPY zp
-                let addr = self.get_zero_page_address();
                 let value = self.read_zero_page();
                 self.cpy(value);
-                dbg!("{}CPY ${:02X}", self.code_to_str(2), addr);
             }
             0xCC => {
                 // CPY abs
-                let addr = self.get_absolute_address();
                 let value = self.read_absolute();
                 self.cpy(value);
-                dbg!("{}CPY ${:04X}", self.code_to_str(3), addr);
             }
             ////////////////// End of CPY
             ////////////////// Start of DEC
@@ -803,16 +745,13 @@ impl Cpu {
                 let val = self.read_zero_page();
                 let result = self.dec(val);
                 self.memory.write_byte(addr as u16, result);
-                dbg!("{}DEC ${:02X}", self.code_to_str(2), addr);
             }
             0xD6 => {
                 // DEC Zero Page,X
-                let addr_zp = self.get_zero_page_address();
                 let addr = self.get_zero_page_address_x();
                 let val = self.read_zero_page_x();
                 let result = self.dec(val);
                 self.memory.write_byte(addr as u16, result);
-                dbg!("{}DEC ${:02X},X", self.code_to_str(2), addr_zp);
             }
             0xCE => {
                 // DEC Absolute
@@ -820,7 +759,6 @@ impl Cpu {
                 let val = self.read_absolute();
                 let result = self.dec(val);
                 self.memory.write_byte(addr, result);
-                dbg!("{}DEC ${:04X}", self.code_to_str(3), addr);
             }
             0xDE => {
                 // DEC Absolute,X
@@ -829,21 +767,18 @@ impl Cpu {
                 let val = self.read_absolute_x();
                 let result = self.dec(val);
                 self.memory.write_byte(addr, result);
-                dbg!("{}DEC ${:04X},X", self.code_to_str(3), addr_abs);
             }
             ////////////////// End of DEC
             ////////////////// Start of DEX
             0xCA => {
                 // DEX
                 self.x = self.dec(self.x);
-                dbg!("{}DEX", self.code_to_str(1));
             }
             ////////////////// End of DEX
             ////////////////// Start of DEY
             0x88 => {
                 // DEY
                 self.y = self.dec(self.y);
-                dbg!("{}DEY", self.code_to_str(1));
             }
             ////////////////// Stop of DEY
             ////////////////// Start of EOR
@@ -851,56 +786,41 @@ impl Cpu {
                 // EOR #imm
                 let value = self.read_immediate_byte();
                 self.eor(value);
-                dbg!("{}EOR #${:02X}", self.code_to_str(2), value);
             }
             0x45 => {
                 // EOR zp
-                let addr = self.get_zero_page_address();
                 let value = self.read_zero_page();
                 self.eor(value);
-                dbg!("{}EOR ${:02X}", self.code_to_str(2), addr);
             }
             0x55 => {
                 // EOR zp,X
-                let addr = self.get_zero_page_address();
                 let value = self.read_zero_page_x();
                 self.eor(value);
-                dbg!("{}EOR ${:02X},X", self.code_to_str(2), addr);
             }
             0x4D => {
                 // EOR abs
-                let addr = self.get_absolute_address();
                 let value = self.read_absolute();
                 self.eor(value);
-                dbg!("{}EOR ${:04X}", self.code_to_str(3), addr);
             }
             0x5D => {
                 // EOR abs,X
-                let addr = self.get_absolute_address();
                 let value = self.read_absolute_x();
                 self.eor(value);
-                dbg!("{}EOR ${:04X},X", self.code_to_str(3), addr);
             }
             0x59 => {
                 // EOR abs,Y
-                let addr = self.get_absolute_address();
                 let value = self.read_absolute_y();
                 self.eor(value);
-                dbg!("{}EOR ${:04X},Y", self.code_to_str(3), addr);
             }
             0x41 => {
                 // EOR indirect,X
-                let addr = self.get_zero_page_address();
                 let value = self.read_indexed_indirect();
                 self.eor(value);
-                dbg!("{}EOR (${:02X},X)", self.code_to_str(2), addr);
             }
             0x51 => {
                 // EOR indirect,Y
-                let addr = self.get_zero_page_address();
                 let value = self.read_indirect_indexed();
                 self.eor(value);
-                dbg!("{}EOR (${:02X}),Y", self.code_to_str(2), addr);
             }
             ////////////////// End of EOR
             ////////////////// Start of INC
@@ -910,7 +830,6 @@ impl Cpu {
                 let value = self.read_zero_page();
                 let result = self.inc(value);
                 self.memory.write_byte_zero_page(addr, result);
-                dbg!("{}INC ${:02X}", self.code_to_str(2), addr);
             }
             0xF6 => {
                 // INC Zero Page,X
@@ -918,11 +837,6 @@ impl Cpu {
                 let value = self.read_zero_page_x();
                 let result = self.inc(value);
                 self.memory.write_byte_zero_page(addr, result);
-                dbg!(
-                    "{}INC ${:02X},X",
-                    self.code_to_str(2),
-                    addr.wrapping_sub(self.x)
-                );
             }
             0xEE => {
                 // INC Absolute
@@ -930,7 +844,6 @@ impl Cpu {
                 let value = self.read_absolute();
                 let result = self.inc(value);
                 self.memory.write_byte(addr, result);
-                dbg!("{}INC ${:04X}", self.code_to_str(3), addr);
             }
             0xFE => {
                 // INC Absolute,X
@@ -938,25 +851,18 @@ impl Cpu {
                 let value = self.read_absolute_x();
                 let result = self.inc(value);
                 self.memory.write_byte(addr, result);
-                dbg!(
-                    "{}INC ${:04X},X",
-                    self.code_to_str(3),
-                    addr.wrapping_sub(self.x as u16)
-                );
             }
             ////////////////// End of INC
             ////////////////// Start of INX
             0xE8 => {
                 // INX
                 self.x = self.inc(self.x);
-                dbg!("{}INX", self.code_to_str(1));
             }
             ////////////////// End of INX
             ////////////////// Start of INY
             0xC8 => {
                 // INY
                 self.y = self.inc(self.y);
-                dbg!("{}INY", self.code_to_str(1));
             }
             ////////////////// End of INY
             ////////////////// Start of JMP
@@ -964,12 +870,10 @@ impl Cpu {
                 // JMP absolute
                 let addr = self.memory.read_word(self.pc);
                 self.pc += 2;
-                dbg!("{}JMP ${:04X}\n----", self.code_to_str(3), addr);
                 self.pc = addr;
             }
             0x6C => {
                 // JMP indirect
-                let addr = self.get_absolute_address();
                 let addr_lo = self.memory.read_byte(self.pc);
                 let addr_hi = self.memory.read_byte(self.pc.wrapping_add(1));
                 let jmp_addr_lo = self
@@ -979,7 +883,6 @@ impl Cpu {
                     .memory
                     .read_byte((addr_hi as u16) << 0x8 | addr_lo.wrapping_add(1) as u16);
                 self.pc += 2;
-                dbg!("{}JMP (${:04X})\n----", self.code_to_str(3), addr);
                 self.pc = (jmp_addr_hi as u16) << 8 | jmp_addr_lo as u16;
             }
             ////////////////// End of JMP
@@ -989,7 +892,6 @@ impl Cpu {
                 let addr = self.read_immediate_word();
                 //                self.pc += 2;
                 self.push_word(self.pc.wrapping_sub(1)); // push return address - 1
-                dbg!("{}JSR ${:04X}\n----", self.code_to_str(3), addr);
                 self.pc = addr;
             }
             ////////////////// End of JSR
@@ -998,56 +900,41 @@ impl Cpu {
                 // LDA Immediate
                 let value = self.read_immediate_byte();
                 self.lda(value);
-                dbg!("{}LDA #${:02X}", self.code_to_str(2), value);
             }
             0xA5 => {
                 // LDA Zero Page
-                let addr = self.get_zero_page_address();
                 let value = self.read_zero_page();
                 self.lda(value);
-                dbg!("{}LDA ${:02X}", self.code_to_str(2), addr);
             }
             0xB5 => {
                 // LDA Zero Page,X
-                let addr = self.get_zero_page_address();
                 let value = self.read_zero_page_x();
                 self.lda(value);
-                dbg!("{}LDA ${:02X},X", self.code_to_str(2), addr);
             }
             0xAD => {
                 // LDA Absolute
-                let addr = self.get_absolute_address();
                 let value = self.read_absolute();
                 self.lda(value);
-                dbg!("{}LDA ${:04X}", self.code_to_str(3), addr);
             }
             0xBD => {
                 // LDA Absolute,X
-                let addr = self.get_absolute_address();
                 let value = self.read_absolute_x();
                 self.lda(value);
-                dbg!("{}LDA ${:04X},X", self.code_to_str(3), addr);
             }
             0xB9 => {
                 // LDA Absolute,Y
-                let addr = self.get_absolute_address();
                 let value = self.read_absolute_y();
                 self.lda(value);
-                dbg!("{}LDA ${:02X},Y", self.code_to_str(2), addr);
             }
             0xA1 => {
                 // LDA (zp,X)
-                let addr = self.get_zero_page_address();
                 let value = self.read_indexed_indirect();
                 self.lda(value);
-                dbg!("{}LDA (${:02X},X)", self.code_to_str(2), addr);
             }
             0xB1 => {
                 // LDA (zp),Y
-                let addr = self.get_zero_page_address();
                 let value = self.read_indirect_indexed();
                 self.lda(value);
-                dbg!("{}LDA (${:02X}),Y", self.code_to_str(2), addr);
             }
             ////////////////// Stop of LDA
             ////////////////// Start of LDX
@@ -1055,35 +942,26 @@ impl Cpu {
                 // LDX Immediate
                 let value = self.read_immediate_byte();
                 self.ldx(value);
-                dbg!("{}LDX #${:02X}", self.code_to_str(2), value);
             }
             0xA6 => {
                 // LDX zp
-                let addr = self.get_zero_page_address();
                 let value = self.read_zero_page();
                 self.ldx(value);
-                dbg!("{}LDX ${:02X}", self.code_to_str(2), addr);
             }
             0xB6 => {
                 // LDX zp,Y
-                let addr = self.get_zero_page_address();
                 let value = self.read_zero_page_y();
                 self.ldx(value);
-                dbg!("{}LDX ${:02X},Y", self.code_to_str(2), addr);
             }
             0xAE => {
                 // LDX abs
-                let addr = self.get_absolute_address();
                 let value = self.read_absolute();
                 self.ldx(value);
-                dbg!("{}LDX ${:04X}", self.code_to_str(3), addr);
             }
             0xBE => {
                 // LDX abs,Y
-                let addr = self.get_absolute_address();
                 let value = self.read_absolute_y();
                 self.ldx(value);
-                dbg!("{}LDX ${:04X},Y", self.code_to_str(3), addr);
             }
             ////////////////// End of LDX
             ////////////////// Start of LDY
@@ -1091,42 +969,32 @@ impl Cpu {
                 // LDY Immediate
                 let value = self.read_immediate_byte();
                 self.ldy(value);
-                dbg!("{}LDY #${:02X}", self.code_to_str(2), value);
             }
             0xA4 => {
                 // LDY Zero Page
-                let addr = self.get_zero_page_address();
                 let value = self.read_zero_page();
                 self.ldy(value);
-                dbg!("{}LDY ${:02X}", self.code_to_str(2), addr);
             }
             0xB4 => {
                 // LDY zp,X
-                let addr = self.get_zero_page_address();
                 let value = self.read_zero_page_x();
                 self.ldy(value);
-                dbg!("{}LDY ${:02X},X", self.code_to_str(2), addr);
             }
             0xAC => {
                 // LDY abs
-                let addr = self.get_absolute_address();
                 let value = self.read_absolute();
                 self.ldy(value);
-                dbg!("{}LDY ${:04X}", self.code_to_str(3), addr);
             }
             0xBC => {
                 // LDY abs,X
-                let addr = self.get_absolute_address();
                 let value = self.read_absolute_x();
                 self.ldy(value);
-                dbg!("{}LDY ${:02X},X", self.code_to_str(3), addr);
             }
             ////////////////// End of LDY
             ////////////////// Start of LSR
             0x4A => {
                 // LSR A
                 self.a = self.lsr(self.a);
-                dbg!("{}LSR A", self.code_to_str(1));
             }
             0x46 => {
                 // LSR zp
@@ -1134,7 +1002,6 @@ impl Cpu {
                 let value = self.read_zero_page();
                 let result = self.lsr(value);
                 self.memory.write_byte(addr as u16, result);
-                dbg!("{}LSR ${:02X}", self.code_to_str(2), addr);
             }
             0x56 => {
                 // LSR zp,X
@@ -1142,11 +1009,6 @@ impl Cpu {
                 let value = self.read_zero_page_x();
                 let result = self.lsr(value);
                 self.memory.write_byte(addr as u16, result);
-                dbg!(
-                    "{}LSR ${:02X},X",
-                    self.code_to_str(2),
-                    addr.wrapping_sub(self.x)
-                );
             }
             0x4E => {
                 // LSR abs
@@ -1154,7 +1016,6 @@ impl Cpu {
                 let value = self.read_absolute();
                 let result = self.lsr(value);
                 self.memory.write_byte(addr, result);
-                dbg!("{}LSR ${:04X}", self.code_to_str(3), addr);
             }
             0x5E => {
                 // LSR abs,X
@@ -1162,87 +1023,63 @@ impl Cpu {
                 let value = self.read_absolute_x();
                 let result = self.lsr(value);
                 self.memory.write_byte(addr, result);
-                dbg!(
-                    "{}LSR ${:04X},X",
-                    self.code_to_str(3),
-                    addr.wrapping_sub(self.x as u16)
-                );
             }
             ////////////////// End of LSR
             ////////////////// Start of NOP
-            0xEA => {
-                dbg!("{}NOP", self.code_to_str(1))
-            }
+            0xEA => {}
             ////////////////// Stop of NOP
             ////////////////// Start of ORA
             0x09 => {
                 // ORA #imm
                 let value = self.read_immediate_byte();
                 self.ora(value);
-                dbg!("{}ORA #${:02X}", self.code_to_str(2), value);
             }
             0x05 => {
                 // ORA zp
-                let addr = self.get_zero_page_address();
                 let value = self.read_zero_page();
                 self.ora(value);
-                dbg!("{}ORA ${:02X}", self.code_to_str(2), addr);
             }
             0x15 => {
                 // ORA zp,X
-                let addr = self.get_zero_page_address();
                 let value = self.read_zero_page_x();
                 self.ora(value);
-                dbg!("{}ORA ${:02X},X", self.code_to_str(2), addr);
             }
             0x0D => {
                 // ORA abs
-                let addr = self.get_absolute_address();
                 let value = self.read_absolute();
                 self.ora(value);
-                dbg!("{}ORA ${:04X}", self.code_to_str(3), addr);
             }
             0x1D => {
                 // ORA abs,X
-                let addr = self.get_absolute_address();
                 let value = self.read_absolute_x();
                 self.ora(value);
-                dbg!("{}ORA ${:04X},X", self.code_to_str(3), addr);
             }
             0x19 => {
                 // ORA abs,Y
-                let addr = self.get_absolute_address();
                 let value = self.read_absolute_y();
                 self.ora(value);
-                dbg!("{}ORA ${:04X},Y", self.code_to_str(3), addr);
             }
             0x01 => {
                 // ORA (zp,X)
-                let addr = self.get_zero_page_address();
                 let value = self.read_indexed_indirect();
                 self.ora(value);
-                dbg!("{}ORA (${:02X},X)", self.code_to_str(2), addr);
             }
             0x11 => {
                 // ORA (zp),Y
-                let addr = self.get_zero_page_address();
                 let value = self.read_indirect_indexed();
                 self.ora(value);
-                dbg!("{}ORA (${:02X}),Y", self.code_to_str(2), addr);
             }
             ////////////////// End of ORA
             ////////////////// Start of PHA
             0x48 => {
                 // PHA
                 self.push(self.a);
-                dbg!("{}PHA", self.code_to_str(1));
             }
             ////////////////// End of PHA
             ////////////////// Start of PHP
             0x08 => {
                 // PHP
                 self.push(self.p.value | mos6502::BREAK | mos6502::UNUSED); // emulate B and Unused flag set when pushed
-                dbg!("{}PHP", self.code_to_str(1));
             }
             ////////////////// Stop of PHP
             ////////////////// Start of PLA
@@ -1251,21 +1088,18 @@ impl Cpu {
                 self.a = self.pop();
                 self.p.set_zero(self.a == 0);
                 self.p.set_negative(self.a & 0x80 != 0);
-                dbg!("{}PLA", self.code_to_str(1));
             }
             ////////////////// End of PLA
             ////////////////// Start of PLP
             0x28 => {
                 // PLP
                 self.p.value = self.pop() & 0b1100_1111; // B and unused bits masked off
-                dbg!("{}PLP (pull P)", self.code_to_str(1));
             }
             ////////////////// End of PLP
             ////////////////// Start of ROL
             0x2A => {
                 // ROL A
                 self.a = self.rol(self.a);
-                dbg!("{}ROL A", self.code_to_str(1));
             }
             0x26 => {
                 // ROL zp
@@ -1273,7 +1107,6 @@ impl Cpu {
                 let value = self.read_zero_page();
                 let result = self.rol(value);
                 self.memory.write_byte(addr as u16, result);
-                dbg!("{}ROL ${:02X}", self.code_to_str(2), addr);
             }
             0x36 => {
                 // ROL zp,X
@@ -1281,11 +1114,6 @@ impl Cpu {
                 let value = self.read_zero_page_x();
                 let result = self.rol(value);
                 self.memory.write_byte(addr as u16, result);
-                dbg!(
-                    "{}ROL ${:02X},X",
-                    self.code_to_str(2),
-                    addr.wrapping_sub(self.x)
-                );
             }
             0x2E => {
                 // ROL abs
@@ -1293,7 +1121,6 @@ impl Cpu {
                 let value = self.read_absolute();
                 let result = self.rol(value);
                 self.memory.write_byte(addr as u16, result);
-                dbg!("{}ROL ${:04X}", self.code_to_str(3), addr);
             }
             0x3E => {
                 // ROL abs,X
@@ -1301,18 +1128,12 @@ impl Cpu {
                 let value = self.read_absolute_x();
                 let result = self.rol(value);
                 self.memory.write_byte(addr as u16, result);
-                dbg!(
-                    "{}ROL ${:04X},X",
-                    self.code_to_str(3),
-                    addr.wrapping_sub(self.x as u16)
-                );
             }
             ////////////////// End of ROL
             ////////////////// Start of ROR
             0x6A => {
                 // ROR A
                 self.a = self.ror(self.a);
-                dbg!("{}ROR A", self.code_to_str(1));
             }
             0x66 => {
                 // ROR zp
@@ -1320,7 +1141,6 @@ impl Cpu {
                 let value = self.read_zero_page();
                 let result = self.ror(value);
                 self.memory.write_byte(addr as u16, result);
-                dbg!("{}ROR ${:02X}", self.code_to_str(2), addr);
             }
             0x76 => {
                 // ROR zp,X
@@ -1328,11 +1148,6 @@ impl Cpu {
                 let value = self.read_zero_page_x();
                 let result = self.ror(value);
                 self.memory.write_byte(addr as u16, result);
-                dbg!(
-                    "{}ROR ${:02X},X",
-                    self.code_to_str(2),
-                    addr.wrapping_sub(self.x)
-                );
             }
             0x6E => {
                 // ROR abs
@@ -1340,7 +1155,6 @@ impl Cpu {
                 let value = self.read_absolute();
                 let result = self.ror(value);
                 self.memory.write_byte(addr, result);
-                dbg!("{}ROR ${:04X}", self.code_to_str(3), addr);
             }
             0x7E => {
                 // ROR abs,X
@@ -1348,18 +1162,11 @@ impl Cpu {
                 let value = self.read_absolute_x();
                 let result = self.ror(value);
                 self.memory.write_byte(addr, result);
-                dbg!(
-                    "{}ROR ${:04X},X",
-                    self.code_to_str(3),
-                    addr.wrapping_sub(self.x as u16)
-                );
             }
             ////////////////// End of ROR
             ////////////////// Start of RTI
             0x40 => {
                 // RTI
-                dbg!("{}RTI", self.code_to_str(1));
-                dbg!("----");
                 self.p.value = self.pop() & !mos6502::BREAK & !mos6502::UNUSED; // B and unused bits masked off
                 self.pc = self.pop_word();
             }
@@ -1368,7 +1175,6 @@ impl Cpu {
             0x60 => {
                 // RTS
                 self.pc = self.pop_word().wrapping_add(1);
-                dbg!("{}RTS\n----", self.code_to_str(1));
             }
             ////////////////// End of RTS
             ////////////////// Start of SBC
@@ -1376,75 +1182,57 @@ impl Cpu {
                 // SBC #imm
                 let value = self.read_immediate_byte();
                 self.sbc(value);
-                dbg!("{}SBC #${:02X}", self.code_to_str(2), value);
             }
             0xE5 => {
                 // SBC zp
-                let addr = self.get_zero_page_address();
                 let value = self.read_zero_page();
                 self.sbc(value);
-                dbg!("{}SBC ${:02x}", self.code_to_str(2), addr)
             }
             0xF5 => {
                 // SBC zp,X
-                let addr = self.get_zero_page_address();
                 let value = self.read_zero_page_x();
                 self.sbc(value);
-                dbg!("{}SBC ${:02x},X", self.code_to_str(2), addr);
             }
             0xED => {
                 // SBC absolute
-                let addr = self.get_absolute_address();
                 let value = self.read_absolute();
                 self.sbc(value);
-                dbg!("{}SBC ${:04x}", self.code_to_str(3), addr);
             }
             0xFD => {
                 // SBC absolute,X
-                let addr = self.get_absolute_address();
                 let value = self.read_absolute_x();
                 self.sbc(value);
-                dbg!("{}SBC ${:04x},X", self.code_to_str(3), addr);
             }
             0xF9 => {
                 // SBC absolute,Y
-                let addr = self.get_absolute_address();
                 let value = self.read_absolute_y();
                 self.sbc(value);
-                dbg!("{}SBC ${:04x},Y", self.code_to_str(3), addr);
             }
             0xE1 => {
                 // SBC (indirect,X)
-                let addr = self.get_absolute_address();
                 let value = self.read_indexed_indirect();
                 self.sbc(value);
-                dbg!("{}SBC $({:02x},X)", self.code_to_str(2), addr);
             }
             0xF1 => {
                 // SBC(indirect),Y
-                let addr = self.get_absolute_address();
                 let value = self.read_indirect_indexed();
                 self.sbc(value);
-                dbg!("{}SBC $({:02x}),Y", self.code_to_str(2), addr);
             }
             ////////////////// End of SBC
             ////////////////// Start of SEC
             0x38 => {
                 // SEC
                 self.p.set_carry(true);
-                dbg!("{}SEC", self.code_to_str(1));
             }
             ////////////////// End of SEC
             ////////////////// Start of SED
             0xF8 => {
                 self.p.set_decimal_mode(true);
-                dbg!("{}SED", self.code_to_str(1));
             }
             ////////////////// End of SED
             ////////////////// Start of SEI
             0x78 => {
                 self.p.set_interrupt_disable(true);
-                dbg!("{}SEI", self.code_to_str(1));
             }
             ////////////////// End of SEI
             ////////////////// Start of STA
@@ -1453,54 +1241,41 @@ impl Cpu {
                 let addr = self.get_zero_page_address();
                 self.pc += 1;
                 self.memory.write_byte_zero_page(addr, self.a);
-                dbg!("{}STA ${:02X}", self.code_to_str(2), addr);
             }
             0x95 => {
                 // STA zp,X
                 let addr = self.get_zero_page_address_x();
                 self.pc += 1;
                 self.memory.write_byte_zero_page(addr, self.a);
-                dbg!(
-                    "{}STA ${:02X},X",
-                    self.code_to_str(2),
-                    addr.wrapping_sub(self.x)
-                );
             }
             0x8D => {
                 // STA $nnnn
                 let addr = self.get_absolute_address();
                 self.pc += 2;
                 self.memory.write_byte(addr, self.a);
-                dbg!("{}STA ${:04X}", self.code_to_str(3), addr);
             }
             0x9D => {
                 // STA $nnnn,X
                 let addr = self.get_absolute_address_x();
                 self.pc += 2;
                 self.memory.write_byte(addr, self.a);
-                dbg!("{}STA ${:04X},X", self.code_to_str(3), addr);
             }
             0x99 => {
                 // STA $nnnn,Y
                 let addr = self.get_absolute_address_y();
                 self.pc += 2;
                 self.memory.write_byte(addr, self.a);
-                dbg!("{}STA ${:04X},Y", self.code_to_str(3), addr);
             }
             0x81 => {
                 // STA (indirect,X)
-                let addr_zp = self.get_zero_page_address();
                 let addr = self.get_indirect_address_x();
                 self.pc += 1;
                 self.memory.write_byte(addr, self.a);
-                dbg!("{}STA (${:02X},X)", self.code_to_str(2), addr_zp);
             }
             0x91 => {
-                let addr_zp = self.get_zero_page_address();
                 let addr = self.get_indirect_address_y();
                 self.pc += 1;
                 self.memory.write_byte(addr, self.a);
-                dbg!("{}STA (${:02X}),Y", self.code_to_str(2), addr_zp);
             }
             ////////////////// End of STA
             ////////////////// Start of STX
@@ -1508,20 +1283,17 @@ impl Cpu {
                 // STX zp
                 let addr = self.read_immediate_byte();
                 self.memory.write_byte_zero_page(addr, self.x);
-                dbg!("{}STX ${:02X}", self.code_to_str(2), addr);
             }
             0x96 => {
                 // STX zp,Y
                 let addr = self.read_immediate_byte();
                 self.memory
                     .write_byte_zero_page(addr.wrapping_add(self.y), self.x);
-                dbg!("{}STX ${:02X},Y", self.code_to_str(2), addr);
             }
             0x8E => {
                 // STX abs
                 let addr = self.read_immediate_word();
                 self.memory.write_byte(addr, self.x);
-                dbg!("{}STX ${:02X}", self.code_to_str(2), addr);
             }
             ////////////////// End of STX
             ////////////////// Start of STY
@@ -1529,20 +1301,17 @@ impl Cpu {
                 // STY zp
                 let addr = self.read_immediate_byte();
                 self.memory.write_byte_zero_page(addr, self.y);
-                dbg!("{}STY ${:02X}", self.code_to_str(2), addr);
             }
             0x94 => {
                 // STY zp,X
                 let addr = self.read_immediate_byte();
                 self.memory
                     .write_byte_zero_page(addr.wrapping_add(self.x), self.y);
-                dbg!("{}STY ${:02X},X", self.code_to_str(2), addr);
             }
             0x8C => {
                 // STY abs
                 let addr = self.read_immediate_word();
                 self.memory.write_byte(addr, self.y);
-                dbg!("{}STY ${:04X}", self.code_to_str(3), addr);
             }
             ////////////////// End of STY
             ////////////////// Start of TAX
@@ -1550,7 +1319,6 @@ impl Cpu {
                 // TAX
                 self.x = self.a;
                 self.set_n_z(self.x);
-                dbg!("{}TAX", self.code_to_str(1));
             }
             ////////////////// End of TAX
             ////////////////// Start of TAY
@@ -1558,7 +1326,6 @@ impl Cpu {
                 // TAY
                 self.y = self.a;
                 self.set_n_z(self.y);
-                dbg!("{}TAY", self.code_to_str(1));
             }
             ////////////////// End of TAY
             ////////////////// Start of TSX
@@ -1566,7 +1333,6 @@ impl Cpu {
                 // TSX
                 self.x = self.sp;
                 self.set_n_z(self.x);
-                dbg!("{}TSX", self.code_to_str(1));
             }
             ////////////////// End of TSX
             ////////////////// Start of TXA
@@ -1574,14 +1340,12 @@ impl Cpu {
                 // TXA
                 self.a = self.x;
                 self.set_n_z(self.a);
-                dbg!("{}TXA", self.code_to_str(1));
             }
             ////////////////// End of TXA
             ////////////////// Start of TXS
             0x9A => {
                 // TXS
                 self.sp = self.x;
-                dbg!("{}TXS", self.code_to_str(1));
             }
             ////////////////// End of TXS
             ////////////////// Start of TYA
@@ -1589,12 +1353,122 @@ impl Cpu {
                 // TYA
                 self.a = self.y;
                 self.set_n_z(self.a);
-                dbg!("{}TYA", self.code_to_str(1));
             }
             ////////////////// End of TYA
-            _ => {
-                dbg!("{}!byte ${:02X}", self.code_to_str(1), opcode);
-            }
+            _ => {}
         }
     }
 }
+
+impl Processor for Cpu {
+    fn kind(&self) -> CpuKind {
+        CpuKind::Mos6502
+    }
+    fn step(&mut self) {
+        Cpu::step(self)
+    }
+    fn pc(&self) -> u16 {
+        self.pc
+    }
+    fn set_pc(&mut self, pc: u16) {
+        self.pc = pc;
+    }
+    fn memory(&self) -> &Memory {
+        &self.memory
+    }
+    fn memory_mut(&mut self) -> &mut Memory {
+        &mut self.memory
+    }
+    fn set_debug(&mut self, debug: bool) {
+        Cpu::set_debug(self, debug)
+    }
+    fn set_trace(&mut self, sink: Option<Box<dyn TraceSink>>) {
+        Cpu::set_trace(self, sink)
+    }
+    fn print_registers(&self) -> String {
+        Cpu::print_registers(self)
+    }
+    fn stack_address(&self) -> u16 {
+        0x0100 + self.sp as u16
+    }
+    fn stack_base(&self) -> u16 {
+        0x01FF
+    }
+    fn instruction_length(&self, addr: u16) -> u8 {
+        let opcode = self.memory.read_byte(addr);
+        opcodes().get(&opcode).map_or(1, |def| def.bytes())
+    }
+    fn instruction_cycles(&self, addr: u16) -> u8 {
+        let opcode = self.memory.read_byte(addr);
+        opcodes().get(&opcode).map_or(0, |def| def.cycles())
+    }
+    fn is_call(&self, addr: u16) -> bool {
+        self.memory.read_byte(addr) == 0x20 // JSR
+    }
+    fn is_halt(&self, addr: u16) -> bool {
+        self.memory.read_byte(addr) == 0x00 // BRK
+    }
+    fn disassemble(&self, start: u16, count: usize) -> Vec<Instruction> {
+        let reader = Reader::new(&self.memory);
+        let symbols = SymbolTable::new();
+        disassembler::decode_count(start, count, |pc| decode(&reader, pc, opcodes(), &symbols))
+    }
+    fn register(&self, name: &str) -> Option<u16> {
+        let value = match name.to_ascii_uppercase().as_str() {
+            "A" => self.a as u16,
+            "X" => self.x as u16,
+            "Y" => self.y as u16,
+            "SP" | "S" => self.sp as u16,
+            "P" => self.p.value as u16,
+            "PC" => self.pc,
+            _ => return None,
+        };
+        Some(value)
+    }
+    fn set_register(&mut self, name: &str, value: u16) -> bool {
+        let lo = value as u8;
+        match name.to_ascii_uppercase().as_str() {
+            "A" => self.a = lo,
+            "X" => self.x = lo,
+            "Y" => self.y = lo,
+            "SP" | "S" => self.sp = lo,
+            "P" => self.p.value = lo,
+            "PC" => self.pc = value,
+            _ => return false,
+        }
+        true
+    }
+    fn register_layout(&self) -> &'static [(&'static str, u8)] {
+        &[
+            ("A", 8),
+            ("X", 8),
+            ("Y", 8),
+            ("P", 8),
+            ("SP", 8),
+            ("PC", 16),
+        ]
+    }
+    fn flag(&self, name: &str) -> Option<bool> {
+        let value = match name.to_ascii_uppercase().as_str() {
+            "N" => self.p.is_negative(),
+            "V" => self.p.is_overflow(),
+            "B" => self.p.is_break(),
+            "D" => self.p.is_decimal_mode(),
+            "I" => self.p.is_interrupt_disable(),
+            "Z" => self.p.is_zero(),
+            "C" => self.p.is_carry(),
+            _ => return None,
+        };
+        Some(value)
+    }
+    fn load_program(&mut self, program: &[u8], start_addr: u16) {
+        Cpu::load_program(self, program, start_addr);
+    }
+    fn irq(&mut self) -> bool {
+        Cpu::irq(self)
+    }
+    fn nmi(&mut self) -> bool {
+        Cpu::nmi(self);
+        true
+    }
+}
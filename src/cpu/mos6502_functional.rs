@@ -0,0 +1,280 @@
+//////////////////////////////////////////////////////////
+/// Harness for Klaus Dormann's 6502 test suite
+/// (https://github.com/Klaus2m5/6502_65C02_functional_tests).
+///
+/// The programs run until the PC gets stuck in a trap (JMP * or a branch to
+/// itself). The trap at the success address means passed, any other trap
+/// is a failed test; the number of the failed test case is in memory.
+///
+/// The binaries are looked up in tests/6502/. They are not checked in (the
+/// repository has no copy of them yet), so the Dormann tests are ignored
+/// and are not part of a plain `cargo test`. `cargo test -- --ignored` runs
+/// them after the binaries are copied there and fails on a missing one:
+///   6502_functional_test.bin   64KB image, start $0400
+///   6502_decimal_test.bin      assembled at $0200, ERROR byte at $000B
+///   6502_interrupt_test.bin    64KB image, start $0400, feedback port $BFFC
+///
+/// The success addresses are the ones of the binaries in the bin_files
+/// folder of the suite, a binary assembled with other options has its own.
+///
+/// Until then 6502_trap_test.bin, a small program of the same kind built
+/// from 6502_trap_test.a, is checked in and runs by default. It checks the
+/// harness and a few instructions, not the whole core.
+//////////////////////////////////////////////////////////
+use std::path::{Path, PathBuf};
+
+use crate::cpu::mos6502::Cpu;
+
+///
+/// Instructions executed before the run is given up
+///
+const STEP_LIMIT: u64 = 200_000_000;
+
+///
+/// Test case number of the functional and interrupt tests
+///
+const TEST_CASE: u16 = 0x0200;
+
+///
+/// Feedback register of the interrupt test: bit 0 holds the IRQ line
+/// (level triggered), bit 1 the NMI line (edge triggered)
+///
+const FEEDBACK_PORT: u16 = 0xBFFC;
+const IRQ_BIT: u8 = 0x01;
+const NMI_BIT: u8 = 0x02;
+
+///
+/// How a test program reports the result
+///
+#[derive(Debug, Clone, Copy)]
+enum Verdict {
+    ///
+    /// The program ends in the trap at this address
+    ///
+    SuccessTrap(u16),
+    ///
+    /// The program ends in a trap or BRK, the byte at this address is 0 on
+    /// success
+    ///
+    ErrorByte(u16),
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Suite {
+    file: &'static str,
+    load: u16,
+    start: u16,
+    verdict: Verdict,
+    interrupts: bool,
+}
+
+const FUNCTIONAL: Suite = Suite {
+    file: "6502_functional_test.bin",
+    load: 0x0000,
+    start: 0x0400,
+    verdict: Verdict::SuccessTrap(0x3469),
+    interrupts: false,
+};
+
+const DECIMAL: Suite = Suite {
+    file: "6502_decimal_test.bin",
+    load: 0x0200,
+    start: 0x0200,
+    verdict: Verdict::ErrorByte(0x000B),
+    interrupts: false,
+};
+
+const TRAP: Suite = Suite {
+    file: "6502_trap_test.bin",
+    load: 0x0400,
+    start: 0x0400,
+    verdict: Verdict::SuccessTrap(0x047C),
+    interrupts: false,
+};
+
+const INTERRUPT: Suite = Suite {
+    file: "6502_interrupt_test.bin",
+    load: 0x0000,
+    start: 0x0400,
+    verdict: Verdict::SuccessTrap(0x06F5),
+    interrupts: true,
+};
+
+///
+/// Where and why the program stopped
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Outcome {
+    trap: u16,
+    test_case: u8,
+    steps: u64,
+}
+
+///
+/// Runs until the PC does not move any more, a BRK (with stop_at_brk) or
+/// the step limit. With interrupts the feedback port drives IRQ and NMI.
+///
+fn run(cpu: &mut Cpu, interrupts: bool, stop_at_brk: bool, limit: u64) -> Result<Outcome, String> {
+    let mut nmi_line = false;
+    for steps in 0..limit {
+        let pc = cpu.pc;
+        if stop_at_brk && cpu.memory.read_byte(pc) == 0x00 {
+            return Ok(outcome(cpu, steps));
+        }
+        cpu.step();
+        let mut interrupted = false;
+        if interrupts {
+            let feedback = cpu.memory.read_byte(FEEDBACK_PORT);
+            if feedback & NMI_BIT != 0 && !nmi_line {
+                cpu.nmi();
+                interrupted = true;
+            }
+            nmi_line = feedback & NMI_BIT != 0;
+            if feedback & IRQ_BIT != 0 {
+                interrupted |= cpu.irq();
+            }
+        }
+        if cpu.pc == pc && !interrupted {
+            return Ok(outcome(cpu, steps + 1));
+        }
+    }
+    Err(format!(
+        "no trap after {} instructions, PC = ${:04X}, test case ${:02X}",
+        limit,
+        cpu.pc,
+        cpu.memory.read_byte(TEST_CASE)
+    ))
+}
+
+fn outcome(cpu: &Cpu, steps: u64) -> Outcome {
+    Outcome {
+        trap: cpu.pc,
+        test_case: cpu.memory.read_byte(TEST_CASE),
+        steps,
+    }
+}
+
+///
+/// Loads the binary of the suite and runs it
+///
+fn run_suite(directory: &Path, suite: &Suite) -> Result<Outcome, String> {
+    let path: PathBuf = directory.join(suite.file);
+    let binary = std::fs::read(&path).map_err(|err| format!("{}: {}", path.display(), err))?;
+    let mut cpu = Cpu::new();
+    cpu.load_program(&binary, suite.load);
+    cpu.pc = suite.start;
+    let stop_at_brk = matches!(suite.verdict, Verdict::ErrorByte(_));
+    let outcome = run(&mut cpu, suite.interrupts, stop_at_brk, STEP_LIMIT)?;
+    let passed = match suite.verdict {
+        Verdict::SuccessTrap(success) => outcome.trap == success,
+        Verdict::ErrorByte(address) => cpu.memory.read_byte(address) == 0,
+    };
+    if !passed {
+        return Err(format!(
+            "{}: trapped at ${:04X} in test case ${:02X} after {} instructions\n{}",
+            suite.file,
+            outcome.trap,
+            outcome.test_case,
+            outcome.steps,
+            cpu.print_registers()
+        ));
+    }
+    Ok(outcome)
+}
+
+fn check(suite: &Suite) {
+    let directory = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/6502");
+    if let Err(message) = run_suite(&directory, suite) {
+        panic!("{}", message);
+    }
+}
+
+#[test]
+#[ignore = "needs tests/6502/6502_functional_test.bin, its decimal mode cases need BCD ADC/SBC"]
+///
+/// Klaus Dormann's functional test of all documented opcodes and modes
+///
+fn functional_test() {
+    check(&FUNCTIONAL);
+}
+
+#[test]
+#[ignore = "needs tests/6502/6502_decimal_test.bin, BCD ADC/SBC is not emulated yet"]
+///
+/// Decimal mode ADC/SBC of all operands
+///
+fn decimal_test() {
+    check(&DECIMAL);
+}
+
+#[test]
+#[ignore = "needs tests/6502/6502_interrupt_test.bin"]
+///
+/// IRQ and NMI driven by the feedback port
+///
+fn interrupt_test() {
+    check(&INTERRUPT);
+}
+
+#[test]
+///
+/// Checked-in trap program, runs without the Dormann binaries
+///
+fn trap_test() {
+    check(&TRAP);
+}
+
+#[test]
+///
+/// The harness finds the success trap, reports the test case of a failed
+/// trap, drives IRQ and NMI from the feedback port and fails on a missing
+/// binary
+///
+fn harness() {
+    // test case 1 fails with BNE * at $0405
+    let mut cpu = Cpu::new();
+    cpu.load_program(
+        &[
+            0xA9, 0x01, 0x8D, 0x00, 0x02, // LDA #1; STA $0200
+            0xD0, 0xFE, //                   BNE *
+        ],
+        0x0400,
+    );
+    let outcome = run(&mut cpu, false, false, 100).unwrap();
+    assert_eq!((outcome.trap, outcome.test_case), (0x0405, 0x01));
+
+    // IRQ handler at $0500 clears the IRQ bit and sets the NMI bit,
+    // NMI handler at $0600 clears it and the main loop traps at $0409
+    let mut cpu = Cpu::new();
+    cpu.load_program(
+        &[
+            0x58, //                         CLI
+            0xA9, 0x01, 0x8D, 0xFC, 0xBF, // LDA #IRQ_BIT; STA $BFFC
+            0xEA, //                         NOP
+            0xEA, //                         NOP
+            0xEA, //                         NOP
+            0x4C, 0x09, 0x04, //             JMP *
+        ],
+        0x0400,
+    );
+    cpu.memory.load_program(
+        &[0xA9, 0x02, 0x8D, 0xFC, 0xBF, 0x40], // LDA #NMI_BIT; STA $BFFC; RTI
+        0x0500,
+    );
+    cpu.memory.load_program(
+        &[0xA9, 0x00, 0x8D, 0xFC, 0xBF, 0xE6, 0x10, 0x40], // clear port; INC $10; RTI
+        0x0600,
+    );
+    cpu.memory.write_byte(0xFFFE, 0x00);
+    cpu.memory.write_byte(0xFFFF, 0x05);
+    cpu.memory.write_byte(0xFFFA, 0x00);
+    cpu.memory.write_byte(0xFFFB, 0x06);
+    cpu.pc = 0x0400;
+    let outcome = run(&mut cpu, true, false, 100).unwrap();
+    assert_eq!(outcome.trap, 0x0409);
+    assert_eq!(cpu.memory.read_byte(0x0010), 1);
+    assert_eq!(cpu.sp, 0xFF);
+
+    // a missing binary fails instead of passing
+    assert!(run_suite(Path::new("tests/none"), &FUNCTIONAL).is_err());
+}
@@ -0,0 +1,435 @@
+//////////////////////////////////////////////////////////
+/// Runner for the single step test vectors of the SingleStepTests /
+/// ProcessorTests suites (https://github.com/SingleStepTests). Every file
+/// holds the cases of one opcode, e.g. 6502/v1/a9.json or 8080/v1/c6.json:
+///
+/// [
+///   {
+///     "name": "a9 2e 88",
+///     "initial": { "pc": 1234, "s": 253, "a": 0, "x": 5, "y": 7, "p": 36,
+///                  "ram": [[1234, 169], [1235, 46]] },
+///     "final":   { "pc": 1236, "s": 253, "a": 46, "x": 5, "y": 7, "p": 36,
+///                  "ram": [[1234, 169], [1235, 46]] },
+///     "cycles":  [[1234, 169, "read"], [1235, 46, "read"]]
+///   }
+/// ]
+///
+/// The registers are set by their names through Processor::set_register
+/// ("s" is the 6502 stack pointer, "f" the 8080 flags), names a core does
+/// not have (interrupt state, ...) are skipped. One instruction is executed
+/// and registers, flags and the listed RAM are compared with "final". The
+/// cores are not cycle exact, the bus activity in "cycles" is not compared.
+///
+/// ```
+/// let reports = single_step::run_directory(CpuKind::Mos6502, "tests/6502/v1")?;
+/// for report in reports.iter().filter(|report| !report.passed()) {
+///     println!("{}", report);
+/// }
+/// ```
+//////////////////////////////////////////////////////////
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::cpu::{self, Processor};
+use crate::machine::config::CpuKind;
+
+///
+/// Failed cases shown by the Display of a report
+///
+const SHOWN_FAILURES: usize = 3;
+
+///
+/// Flag names of the bits 7..0 of P and F
+///
+const FLAGS_6502: [&str; 8] = ["N", "V", "-", "B", "D", "I", "Z", "C"];
+const FLAGS_8080: [&str; 8] = ["S", "Z", "5", "AC", "3", "P", "1", "CY"];
+
+#[derive(Debug)]
+pub enum SingleStepError {
+    Io(PathBuf, std::io::Error),
+    Parse(PathBuf, serde_json::Error),
+    UnsupportedCpu(CpuKind),
+}
+
+impl fmt::Display for SingleStepError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SingleStepError::Io(path, err) => write!(f, "{}: {}", path.display(), err),
+            SingleStepError::Parse(path, err) => {
+                write!(f, "{}: invalid test vectors: {}", path.display(), err)
+            }
+            SingleStepError::UnsupportedCpu(kind) => {
+                write!(f, "CPU {:?} has no emulation core yet", kind)
+            }
+        }
+    }
+}
+
+impl std::error::Error for SingleStepError {}
+
+///
+/// CPU state before or after the instruction
+///
+#[derive(Debug, Clone, Deserialize)]
+pub struct State {
+    #[serde(default)]
+    pub ram: Vec<(u16, u8)>,
+    #[serde(flatten)]
+    pub registers: BTreeMap<String, Value>,
+}
+
+impl State {
+    fn registers(&self) -> impl Iterator<Item = (&str, u16)> {
+        self.registers
+            .iter()
+            .filter_map(|(name, value)| Some((name.as_str(), u16::try_from(value.as_u64()?).ok()?)))
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TestCase {
+    pub name: String,
+    pub initial: State,
+    #[serde(rename = "final")]
+    pub expected: State,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Failure {
+    pub name: String,
+    pub differences: Vec<String>,
+}
+
+///
+/// Result of the cases of one opcode
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Report {
+    pub opcode: String,
+    pub cases: usize,
+    pub failures: Vec<Failure>,
+}
+
+impl Report {
+    pub fn passed(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} of {} cases failed",
+            self.opcode,
+            self.failures.len(),
+            self.cases
+        )?;
+        for failure in self.failures.iter().take(SHOWN_FAILURES) {
+            write!(
+                f,
+                "\n  {}: {}",
+                failure.name,
+                failure.differences.join(", ")
+            )?;
+        }
+        if self.failures.len() > SHOWN_FAILURES {
+            write!(f, "\n  ...")?;
+        }
+        Ok(())
+    }
+}
+
+///
+/// Names of the flags that differ between the values of P or F
+///
+fn flag_differences(kind: CpuKind, expected: u16, found: u16) -> String {
+    let names = match kind {
+        CpuKind::I8080 | CpuKind::I8085 => FLAGS_8080,
+        CpuKind::Mos6502 | CpuKind::Wdc65C02 => FLAGS_6502,
+    };
+    let changed = (expected ^ found) as u8;
+    let differ: Vec<&str> = (0..8)
+        .filter(|bit| changed & (0x80 >> bit) != 0)
+        .map(|bit| names[bit])
+        .collect();
+    differ.join(" ")
+}
+
+///
+/// Runs one case and returns the differences, empty if it passed.
+/// The RAM of the case is cleared again afterwards so the CPU can run
+/// the next case.
+///
+pub fn run_case(cpu: &mut dyn Processor, case: &TestCase) -> Vec<String> {
+    for (name, value) in case.initial.registers() {
+        cpu.set_register(name, value);
+    }
+    for &(addr, value) in &case.initial.ram {
+        cpu.memory_mut().write_byte(addr, value);
+    }
+    cpu.step();
+
+    let mut differences = Vec::new();
+    for (name, expected) in case.expected.registers() {
+        let Some(found) = cpu.register(name) else {
+            continue;
+        };
+        if found != expected {
+            let name = name.to_ascii_uppercase();
+            let mut difference =
+                format!("{}: expected ${:02X}, got ${:02X}", name, expected, found);
+            if matches!(name.as_str(), "P" | "F") {
+                difference +=
+                    &format!(" (flags {})", flag_differences(cpu.kind(), expected, found));
+            }
+            differences.push(difference);
+        }
+    }
+    for &(addr, expected) in &case.expected.ram {
+        let found = cpu.memory().read_byte(addr);
+        if found != expected {
+            differences.push(format!(
+                "mem[${:04X}]: expected ${:02X}, got ${:02X}",
+                addr, expected, found
+            ));
+        }
+    }
+
+    for &(addr, _) in case.initial.ram.iter().chain(&case.expected.ram) {
+        cpu.memory_mut().write_byte(addr, 0);
+    }
+    differences
+}
+
+///
+/// Runs all cases of a test vector file, the opcode of the report is the
+/// file name without extension
+///
+pub fn run_file<P: AsRef<Path>>(kind: CpuKind, path: P) -> Result<Report, SingleStepError> {
+    let path = path.as_ref();
+    let mut cpu = cpu::new(kind).ok_or(SingleStepError::UnsupportedCpu(kind))?;
+    let text =
+        fs::read_to_string(path).map_err(|err| SingleStepError::Io(path.to_path_buf(), err))?;
+    let cases: Vec<TestCase> = serde_json::from_str(&text)
+        .map_err(|err| SingleStepError::Parse(path.to_path_buf(), err))?;
+    let failures = cases
+        .iter()
+        .filter_map(|case| {
+            let differences = run_case(cpu.as_mut(), case);
+            (!differences.is_empty()).then(|| Failure {
+                name: case.name.clone(),
+                differences,
+            })
+        })
+        .collect();
+    Ok(Report {
+        opcode: path
+            .file_stem()
+            .map_or_else(String::new, |stem| stem.to_string_lossy().to_string()),
+        cases: cases.len(),
+        failures,
+    })
+}
+
+///
+/// Runs every .json file of the directory in the order of the file names
+///
+pub fn run_directory<P: AsRef<Path>>(
+    kind: CpuKind,
+    directory: P,
+) -> Result<Vec<Report>, SingleStepError> {
+    let directory = directory.as_ref();
+    let entries =
+        fs::read_dir(directory).map_err(|err| SingleStepError::Io(directory.to_path_buf(), err))?;
+    let mut files: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|extension| extension == "json")
+        })
+        .collect();
+    files.sort();
+    files.iter().map(|path| run_file(kind, path)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::{i8080, mos6502};
+    use crate::disassembler::{i8080 as i8080_opcodes, mos6502 as mos6502_opcodes};
+
+    fn cases(json: &str) -> Vec<TestCase> {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    ///
+    /// Passing and failing cases of both CPUs, flags are named in the diff
+    ///
+    fn run_cases() {
+        // LDA #$00 sets Z, the second case expects N instead and a RAM byte
+        let cases_6502 = cases(
+            r#"[
+                {"name": "a9 00", "initial": {"pc": 512, "s": 253, "a": 5, "x": 0, "y": 0, "p": 32,
+                                              "ram": [[512, 169], [513, 0]]},
+                 "final": {"pc": 514, "s": 253, "a": 0, "x": 0, "y": 0, "p": 34,
+                           "ram": [[512, 169], [513, 0]]},
+                 "cycles": [[512, 169, "read"], [513, 0, "read"]]},
+                {"name": "a9 00 wrong", "initial": {"pc": 512, "s": 253, "a": 5, "x": 0, "y": 0, "p": 32,
+                                                    "ram": [[512, 169], [513, 0]]},
+                 "final": {"pc": 514, "s": 253, "a": 0, "x": 0, "y": 0, "p": 160,
+                           "ram": [[512, 169], [513, 0], [768, 1]]},
+                 "cycles": []}
+            ]"#,
+        );
+        let mut cpu = mos6502::Cpu::new();
+        assert!(run_case(&mut cpu, &cases_6502[0]).is_empty());
+        assert_eq!(cpu.memory.read_byte(512), 0);
+        assert_eq!(
+            run_case(&mut cpu, &cases_6502[1]),
+            [
+                "P: expected $A0, got $22 (flags N Z)",
+                "mem[$0300]: expected $01, got $00"
+            ]
+        );
+
+        // ADI 01H with A = FFH, "inte" is not a register of the core
+        let cases_8080 = cases(
+            r#"[{"name": "c6 01", "initial": {"pc": 256, "sp": 0, "a": 255, "b": 0, "c": 0, "d": 0,
+                                              "e": 0, "f": 2, "h": 0, "l": 0, "inte": 0,
+                                              "ram": [[256, 198], [257, 1]]},
+                 "final": {"pc": 258, "sp": 0, "a": 0, "b": 0, "c": 0, "d": 0, "e": 0,
+                           "f": 87, "h": 0, "l": 0, "inte": 0, "ram": [[256, 198], [257, 1]]}}]"#,
+        );
+        let mut cpu = i8080::Cpu::new();
+        assert_eq!(run_case(&mut cpu, &cases_8080[0]), Vec::<String>::new());
+    }
+
+    #[test]
+    ///
+    /// SBC subtracts the borrow, the inverted carry
+    ///
+    fn sbc_borrow() {
+        // SBC #$00 with C set leaves A, SBC #$01 with C clear subtracts 2
+        let cases_6502 = cases(
+            r#"[
+                {"name": "e9 00", "initial": {"pc": 512, "s": 253, "a": 0, "x": 0, "y": 0, "p": 33,
+                                              "ram": [[512, 233], [513, 0]]},
+                 "final": {"pc": 514, "s": 253, "a": 0, "x": 0, "y": 0, "p": 35,
+                           "ram": [[512, 233], [513, 0]]}},
+                {"name": "e9 01", "initial": {"pc": 512, "s": 253, "a": 5, "x": 0, "y": 0, "p": 32,
+                                              "ram": [[512, 233], [513, 1]]},
+                 "final": {"pc": 514, "s": 253, "a": 3, "x": 0, "y": 0, "p": 33,
+                           "ram": [[512, 233], [513, 1]]}}
+            ]"#,
+        );
+        let mut cpu = mos6502::Cpu::new();
+        for case in &cases_6502 {
+            assert_eq!(
+                run_case(&mut cpu, case),
+                Vec::<String>::new(),
+                "{}",
+                case.name
+            );
+        }
+    }
+
+    #[test]
+    ///
+    /// Reports of files and directories, the Display shows the failures
+    ///
+    fn reports() {
+        let directory = std::env::temp_dir().join(format!("single_step_{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        fs::write(
+            directory.join("e8.json"),
+            r#"[{"name": "e8", "initial": {"pc": 0, "x": 1, "ram": [[0, 232]]},
+                 "final": {"pc": 1, "x": 3, "ram": [[0, 232]]}}]"#,
+        )
+        .unwrap();
+        fs::write(directory.join("notes.txt"), "not a test").unwrap();
+        let reports = run_directory(CpuKind::Mos6502, &directory).unwrap();
+        fs::remove_dir_all(&directory).unwrap();
+
+        assert_eq!(reports.len(), 1);
+        assert!(!reports[0].passed());
+        assert_eq!(
+            reports[0].to_string(),
+            "e8: 1 of 1 cases failed\n  e8: X: expected $03, got $02"
+        );
+        assert!(matches!(
+            run_file(CpuKind::Mos6502, directory.join("e8.json")),
+            Err(SingleStepError::Io(..))
+        ));
+        assert!(matches!(
+            run_file(CpuKind::Wdc65C02, "any.json"),
+            Err(SingleStepError::UnsupportedCpu(CpuKind::Wdc65C02))
+        ));
+    }
+
+    ///
+    /// Runs the suite in directory and fails on the opcodes of the table
+    /// (undocumented opcodes are not emulated) or a missing directory
+    ///
+    fn check_suite(kind: CpuKind, directory: &str, documented: &dyn Fn(u8) -> bool) {
+        let directory = Path::new(env!("CARGO_MANIFEST_DIR")).join(directory);
+        let reports = run_directory(kind, &directory).unwrap_or_else(|err| panic!("{}", err));
+        assert!(
+            !reports.is_empty(),
+            "no test vectors in {}",
+            directory.display()
+        );
+        let failed: Vec<String> = reports
+            .iter()
+            .filter(|report| {
+                !report.passed() && u8::from_str_radix(&report.opcode, 16).is_ok_and(documented)
+            })
+            .map(|report| report.to_string())
+            .collect();
+        assert!(failed.is_empty(), "{}", failed.join("\n"));
+    }
+
+    #[test]
+    ///
+    /// Checked-in vectors in the format of the suite, tests/6502/sample
+    ///
+    fn single_step_6502_sample() {
+        check_suite(CpuKind::Mos6502, "tests/6502/sample", &|_| true);
+    }
+
+    #[test]
+    ///
+    /// Checked-in vectors in the format of the suite, tests/8080/sample
+    ///
+    fn single_step_8080_sample() {
+        check_suite(CpuKind::I8080, "tests/8080/sample", &|_| true);
+    }
+
+    #[test]
+    #[ignore = "needs the SingleStepTests vectors in tests/6502/v1"]
+    ///
+    /// SingleStepTests 6502 vectors in tests/6502/v1
+    ///
+    fn single_step_6502() {
+        check_suite(CpuKind::Mos6502, "tests/6502/v1", &|opcode| {
+            mos6502_opcodes::opcodes().contains_key(&opcode)
+        });
+    }
+
+    #[test]
+    #[ignore = "needs the SingleStepTests vectors in tests/8080/v1"]
+    ///
+    /// SingleStepTests 8080 vectors in tests/8080/v1
+    ///
+    fn single_step_8080() {
+        check_suite(CpuKind::I8080, "tests/8080/v1", &|opcode| {
+            i8080_opcodes::opcodes().contains_key(&opcode)
+        });
+    }
+}
@@ -0,0 +1,469 @@
+//////////////////////////////////////////////////////////
+/// Command line of the debugger, typed after ':' in the TUI.
+/// Addresses and counts are expressions without spaces ($0610, PC+3, 10).
+///
+///   break ADDR [if COND]       breakpoint, stops only if COND is true
+///   ignore ADDR COUNT          breakpoint ignores the next COUNT hits
+///   delete [ADDR]              removes the breakpoint, all without ADDR
+///   watch START [END] [r|w|rw] memory watchpoint, rw if not given
+///   port START [END] [r|w|rw]  8080 I/O port watchpoint
+///   list                       breakpoints and watchpoints
+///   history                    instructions the debugger can go back to
+///   rewind N                   goes back to the state before instruction N
+///   save FILE                  saves a snapshot of CPU and memory
+///   restore FILE               restores a snapshot
+///   load FILE ADDR             loads a raw binary at ADDR and sets PC to it
+///   irq                        maskable interrupt request (6502)
+///   nmi                        non maskable interrupt (6502)
+///
+/// ```
+/// let message = command::execute(&mut debugger, "break $0610 if A == $FF")?;
+/// ```
+//////////////////////////////////////////////////////////
+use std::fmt;
+use std::fs;
+
+use super::engine::{Breakpoint, Space, Watch, Watchpoint};
+use super::expression::Expression;
+use super::{Debugger, StopReason};
+use crate::machine::snapshot::Snapshot;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommandError(pub String);
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for CommandError {}
+
+fn error(message: String) -> CommandError {
+    CommandError(message)
+}
+
+///
+/// Value of an expression argument in 0..=maximum
+///
+fn value(debugger: &Debugger, text: Option<&str>, maximum: i64) -> Result<i64, CommandError> {
+    let text = text.ok_or_else(|| error("argument missing".to_string()))?;
+    let value = Expression::parse(text)
+        .and_then(|expression| expression.evaluate_with(debugger.cpu(), Some(debugger.symbols())))
+        .map_err(|err| error(format!("{}: {}", text, err)))?;
+    if !(0..=maximum).contains(&value) {
+        return Err(error(format!("{}: {:X} is out of range", text, value)));
+    }
+    Ok(value)
+}
+
+fn address(debugger: &Debugger, text: Option<&str>) -> Result<u16, CommandError> {
+    Ok(value(debugger, text, 0xFFFF)? as u16)
+}
+
+fn watch(text: &str) -> Option<Watch> {
+    match text {
+        "r" => Some(Watch::Read),
+        "w" => Some(Watch::Write),
+        "rw" => Some(Watch::ReadWrite),
+        _ => None,
+    }
+}
+
+///
+/// "START [END] [r|w|rw]" of watch and port
+///
+fn watchpoint(
+    debugger: &Debugger,
+    args: &[&str],
+    space: Space,
+) -> Result<Watchpoint, CommandError> {
+    let (kind, range) = match args.split_last() {
+        Some((last, range)) if watch(last).is_some() => (watch(last), range),
+        _ => (None, args),
+    };
+    let kind = kind.unwrap_or(Watch::ReadWrite);
+    if range.is_empty() || range.len() > 2 {
+        return Err(error("START [END] [r|w|rw] expected".to_string()));
+    }
+    let maximum = match space {
+        Space::Memory => 0xFFFF,
+        Space::Port => 0xFF,
+    };
+    let start = value(debugger, Some(range[0]), maximum)?;
+    let end = match range.get(1) {
+        Some(&end) => value(debugger, Some(end), maximum)?,
+        None => start,
+    };
+    if end < start {
+        return Err(error(format!(
+            "{:X}..{:X} ends before it starts",
+            start, end
+        )));
+    }
+    Ok(match space {
+        Space::Memory => Watchpoint::new(start as u16, end as u16, kind),
+        Space::Port => Watchpoint::port(start as u8, end as u8, kind),
+    })
+}
+
+fn describe(breakpoint: &Breakpoint) -> String {
+    let mut text = format!("{:04X}", breakpoint.addr);
+    if let Some(condition) = &breakpoint.condition {
+        text.push_str(&format!(" if {}", condition));
+    }
+    if breakpoint.ignore_count > 0 {
+        text.push_str(&format!(" ignore {}", breakpoint.ignore_count));
+    }
+    text
+}
+
+fn describe_watchpoint(watchpoint: &Watchpoint) -> String {
+    let (name, digits) = match watchpoint.space {
+        Space::Memory => ("watch", 4),
+        Space::Port => ("port", 2),
+    };
+    let kind = match watchpoint.watch {
+        Watch::Read => "r",
+        Watch::Write => "w",
+        Watch::ReadWrite => "rw",
+    };
+    format!(
+        "{} {:0digits$X}-{:0digits$X} {}",
+        name, watchpoint.start, watchpoint.end, kind
+    )
+}
+
+///
+/// Executes one command line and returns the message for the status line
+///
+pub fn execute(debugger: &mut Debugger, line: &str) -> Result<String, CommandError> {
+    let (line, condition) = match line.split_once(" if ") {
+        Some((line, condition)) => (line, Some(condition)),
+        None => (line, None),
+    };
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let Some((&name, args)) = words.split_first() else {
+        return Ok(String::new());
+    };
+    if condition.is_some() && name != "break" {
+        return Err(error(format!("{} has no condition", name)));
+    }
+    match (name, args.len()) {
+        ("break", 1) => {
+            let mut breakpoint = Breakpoint::new(address(debugger, args.first().copied())?);
+            if let Some(condition) = condition {
+                breakpoint = breakpoint
+                    .with_condition(condition)
+                    .map_err(|err| error(format!("{}: {}", condition, err)))?;
+            }
+            let message = format!("breakpoint {}", describe(&breakpoint));
+            debugger.breakpoints_mut().insert(breakpoint);
+            Ok(message)
+        }
+        ("ignore", 2) => {
+            let addr = address(debugger, args.first().copied())?;
+            let count = value(debugger, args.get(1).copied(), u32::MAX as i64)? as u32;
+            let breakpoint = debugger
+                .breakpoints()
+                .get(addr)
+                .cloned()
+                .ok_or_else(|| error(format!("no breakpoint at {:04X}", addr)))?
+                .with_ignore_count(count);
+            let message = format!("breakpoint {}", describe(&breakpoint));
+            debugger.breakpoints_mut().insert(breakpoint);
+            Ok(message)
+        }
+        ("delete", 0) => {
+            debugger.breakpoints_mut().clear();
+            Ok("all breakpoints and watchpoints deleted".to_string())
+        }
+        ("delete", 1) => {
+            let addr = address(debugger, args.first().copied())?;
+            match debugger.breakpoints_mut().remove(addr) {
+                Some(_) => Ok(format!("breakpoint {:04X} deleted", addr)),
+                None => Err(error(format!("no breakpoint at {:04X}", addr))),
+            }
+        }
+        ("watch", _) | ("port", _) => {
+            let space = match name {
+                "watch" => Space::Memory,
+                _ => Space::Port,
+            };
+            let watchpoint = watchpoint(debugger, args, space)?;
+            debugger.breakpoints_mut().add_watchpoint(watchpoint);
+            Ok(describe_watchpoint(&watchpoint))
+        }
+        ("list", 0) => {
+            let breakpoints = debugger.breakpoints();
+            let mut items: Vec<String> = breakpoints.iter().map(describe).collect();
+            items.extend(breakpoints.watchpoints().iter().map(describe_watchpoint));
+            match items.is_empty() {
+                true => Ok("no breakpoints".to_string()),
+                false => Ok(items.join(", ")),
+            }
+        }
+        ("history", 0) => match debugger.history() {
+            Some(history) => Ok(format!(
+                "instructions {}..{} recorded",
+                history.oldest(),
+                history.count()
+            )),
+            None => Err(error("rewind is off".to_string())),
+        },
+        ("rewind", 1) => {
+            let instruction = args[0]
+                .parse()
+                .map_err(|_| error(format!("'{}' is no instruction number", args[0])))?;
+            match debugger.rewind_to(instruction) {
+                true => Ok(format!("at instruction {}", instruction)),
+                false => Err(error(format!(
+                    "instruction {} is not recorded",
+                    instruction
+                ))),
+            }
+        }
+        ("save", 1) => {
+            Snapshot::capture(debugger.cpu())
+                .save(args[0])
+                .map_err(|err| error(err.to_string()))?;
+            Ok(format!("saved {}", args[0]))
+        }
+        ("restore", 1) => {
+            Snapshot::load(args[0])
+                .and_then(|snapshot| snapshot.restore(debugger.cpu))
+                .map_err(|err| error(format!("{}: {}", args[0], err)))?;
+            debugger.stopped(StopReason::Step);
+            Ok(format!("restored {}", args[0]))
+        }
+        ("load", 2) => {
+            let addr = address(debugger, Some(args[1]))?;
+            let program =
+                fs::read(args[0]).map_err(|err| error(format!("{}: {}", args[0], err)))?;
+            debugger.cpu.load_program(&program, addr);
+            debugger.stopped(StopReason::Step);
+            Ok(format!("loaded {} bytes at {:04X}", program.len(), addr))
+        }
+        ("irq", 0) | ("nmi", 0) => {
+            let taken = match name {
+                "irq" => debugger.cpu.irq(),
+                _ => debugger.cpu.nmi(),
+            };
+            let name = name.to_ascii_uppercase();
+            if !taken {
+                return Err(error(format!("{} not taken", name)));
+            }
+            debugger.stopped(StopReason::Step);
+            Ok(format!("{} taken, PC {:04X}", name, debugger.cpu().pc()))
+        }
+        (
+            "break" | "ignore" | "delete" | "list" | "history" | "rewind" | "save" | "restore"
+            | "load" | "irq" | "nmi",
+            _,
+        ) => Err(error(format!("wrong number of arguments for {}", name))),
+        _ => Err(error(format!("unknown command '{}'", name))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::{i8080, mos6502};
+
+    fn cpu_with_loop() -> mos6502::Cpu {
+        let mut cpu = mos6502::Cpu::new();
+        let program = vec![
+            0xA2, 0x00, //       LDX #$00
+            0xE8, //             $0602 INX
+            0x8E, 0x00, 0x02, // STX $0200
+            0xE0, 0x05, //       CPX #$05
+            0xD0, 0xF8, //       BNE $0602
+            0x00, //             BRK
+        ];
+        cpu.load_program(&program, 0x0600);
+        cpu
+    }
+
+    #[test]
+    ///
+    /// Conditional breakpoint with an ignore count stops on the right pass
+    ///
+    fn condition_and_ignore_count() {
+        let mut cpu = cpu_with_loop();
+        let mut debugger = Debugger::new(&mut cpu);
+        assert_eq!(
+            execute(&mut debugger, "break $0603 if X >= 2").unwrap(),
+            "breakpoint 0603 if X >= 2"
+        );
+        assert_eq!(
+            execute(&mut debugger, "ignore PC+3 1").unwrap(),
+            "breakpoint 0603 if X >= 2 ignore 1"
+        );
+        assert_eq!(
+            debugger.run(100, None),
+            Some(StopReason::Breakpoint(0x0603))
+        );
+        assert_eq!(debugger.cpu().register("X"), Some(3));
+        assert_eq!(
+            execute(&mut debugger, "delete $0603").unwrap(),
+            "breakpoint 0603 deleted"
+        );
+        assert_eq!(execute(&mut debugger, "list").unwrap(), "no breakpoints");
+    }
+
+    #[test]
+    ///
+    /// Addresses of the commands can be symbol names
+    ///
+    fn symbol_addresses() {
+        let mut cpu = cpu_with_loop();
+        let mut debugger = Debugger::new(&mut cpu);
+        let mut symbols = crate::symbols::SymbolTable::new();
+        symbols.insert("loop", 0x0602);
+        debugger.set_symbols(symbols);
+        assert_eq!(
+            execute(&mut debugger, "break loop+1").unwrap(),
+            "breakpoint 0603"
+        );
+        assert!(execute(&mut debugger, "break done").is_err());
+    }
+
+    #[test]
+    ///
+    /// NMI goes to its vector, IRQ only with the I flag clear, the 8080
+    /// has no interrupt inputs
+    ///
+    fn interrupts() {
+        let mut cpu = cpu_with_loop();
+        cpu.memory.write_word(0xFFFA, 0x0700);
+        cpu.memory.write_word(0xFFFE, 0x0800);
+        cpu.p.set_interrupt_disable(true);
+        let mut debugger = Debugger::new(&mut cpu);
+        assert_eq!(execute(&mut debugger, "nmi").unwrap(), "NMI taken, PC 0700");
+        assert_eq!(
+            execute(&mut debugger, "irq"),
+            Err(CommandError("IRQ not taken".to_string()))
+        );
+        let mut cpu = i8080::Cpu::new();
+        let mut debugger = Debugger::new(&mut cpu);
+        assert!(execute(&mut debugger, "nmi").is_err());
+        assert!(execute(&mut debugger, "irq 1").is_err());
+    }
+
+    #[test]
+    ///
+    /// Memory and port watchpoints, list shows all
+    ///
+    fn watchpoints() {
+        let mut cpu = i8080::Cpu::new();
+        // OUT 20H, HLT
+        cpu.load_program(&[0xD3, 0x20, 0x76], 0x0100);
+        let mut debugger = Debugger::new(&mut cpu);
+        assert_eq!(
+            execute(&mut debugger, "port $10 $20 w").unwrap(),
+            "port 10-20 w"
+        );
+        assert_eq!(
+            execute(&mut debugger, "watch $0200 $02FF").unwrap(),
+            "watch 0200-02FF rw"
+        );
+        execute(&mut debugger, "break $0102").unwrap();
+        assert_eq!(
+            execute(&mut debugger, "list").unwrap(),
+            "0102, port 10-20 w, watch 0200-02FF rw"
+        );
+        assert!(matches!(
+            debugger.run(100, None),
+            Some(StopReason::PortWatchpoint { pc: 0x0100, .. })
+        ));
+        execute(&mut debugger, "delete").unwrap();
+        assert!(debugger.breakpoints().watchpoints().is_empty());
+    }
+
+    #[test]
+    ///
+    /// Errors of the arguments
+    ///
+    fn errors() {
+        let mut cpu = cpu_with_loop();
+        let mut debugger = Debugger::new(&mut cpu);
+        let mut error = |line: &str| execute(&mut debugger, line).unwrap_err().to_string();
+        assert_eq!(error("go"), "unknown command 'go'");
+        assert_eq!(error("break"), "wrong number of arguments for break");
+        assert_eq!(error("ignore $0600 1"), "no breakpoint at 0600");
+        assert_eq!(error("watch $0600 $05FF"), "600..5FF ends before it starts");
+        assert_eq!(error("port $100"), "$100: 100 is out of range");
+        assert_eq!(error("list if A"), "list has no condition");
+        assert!(error("break $0600 if A ==").starts_with("A ==: "));
+        assert_eq!(error("history"), "rewind is off");
+    }
+
+    #[test]
+    ///
+    /// Rewind to a recorded instruction number
+    ///
+    fn rewind() {
+        let mut cpu = cpu_with_loop();
+        let mut debugger = Debugger::new(&mut cpu);
+        debugger.enable_rewind();
+        for _ in 0..6 {
+            debugger.step();
+        }
+        assert_eq!(
+            execute(&mut debugger, "history").unwrap(),
+            "instructions 0..6 recorded"
+        );
+        assert_eq!(
+            execute(&mut debugger, "rewind 2").unwrap(),
+            "at instruction 2"
+        );
+        assert_eq!(debugger.cpu().pc(), 0x0603);
+        assert_eq!(debugger.cpu().register("X"), Some(1));
+        assert_eq!(
+            execute(&mut debugger, "rewind 3").unwrap_err().to_string(),
+            "instruction 3 is not recorded"
+        );
+    }
+
+    #[test]
+    ///
+    /// Snapshot saved and restored from the command line
+    ///
+    fn save_and_restore() {
+        let path = std::env::temp_dir().join(format!("debugger_{}.json", std::process::id()));
+        let path = path.to_str().unwrap();
+        let mut cpu = cpu_with_loop();
+        let mut debugger = Debugger::new(&mut cpu);
+        debugger.step();
+        debugger.step();
+        execute(&mut debugger, &format!("save {}", path)).unwrap();
+        debugger.run(100, None);
+        assert_eq!(
+            execute(&mut debugger, &format!("restore {}", path)).unwrap(),
+            format!("restored {}", path)
+        );
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(debugger.cpu().pc(), 0x0603);
+        assert_eq!(debugger.cursor, 0x0603);
+        assert_eq!(debugger.cpu().register("X"), Some(1));
+    }
+
+    #[test]
+    ///
+    /// Raw binary loaded at an address, PC follows it
+    ///
+    fn load() {
+        let path = std::env::temp_dir().join(format!("debugger_{}.bin", std::process::id()));
+        std::fs::write(&path, [0xA9, 0x42, 0x00]).unwrap();
+        let path = path.to_str().unwrap();
+        let mut cpu = cpu_with_loop();
+        let mut debugger = Debugger::new(&mut cpu);
+        let message = execute(&mut debugger, &format!("load {} $0700", path));
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(message.unwrap(), "loaded 3 bytes at 0700");
+        assert_eq!(debugger.cpu().pc(), 0x0700);
+        assert_eq!(debugger.cpu().memory().read_byte(0x0701), 0x42);
+        assert!(execute(&mut debugger, &format!("load {}", path)).is_err());
+        assert!(execute(&mut debugger, &format!("load {} $0700", path)).is_err());
+    }
+}
@@ -0,0 +1,427 @@
+//////////////////////////////////////////////////////////
+/// Execution engine: runs a cpu::Processor until a breakpoint, watchpoint,
+/// halt instruction or target address stops it and tells why it stopped.
+/// The debugger front ends and the test harnesses use it the same way.
+///
+/// ```
+/// let mut breakpoints = Breakpoints::new();
+/// breakpoints.insert(Breakpoint::new(0x0610).with_condition("A == $FF && Z")?);
+/// breakpoints.add_watchpoint(Watchpoint::new(0x0200, 0x02FF, Watch::Write));
+/// match run(&mut cpu, &mut breakpoints, 1_000_000, None) {
+///     Some(StopReason::Breakpoint(addr)) => println!("breakpoint at {:04X}", addr),
+///     reason => println!("{:?}", reason),
+/// }
+/// ```
+//////////////////////////////////////////////////////////
+use std::collections::BTreeMap;
+
+use crate::cpu::{self, Processor};
+use crate::debugger::expression::{ExprError, Expression};
+use crate::debugger::rewind::History;
+use crate::memory::{Access, AccessKind};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    Step,
+    Breakpoint(u16),
+    ///
+    /// Memory watchpoint hit by the instruction at pc
+    ///
+    Watchpoint {
+        pc: u16,
+        access: Access,
+    },
+    ///
+    /// I/O port watchpoint hit by the IN/OUT instruction at pc
+    ///
+    PortWatchpoint {
+        pc: u16,
+        access: Access,
+    },
+    Halt(u16),
+    Target(u16),
+    Limit,
+    Interrupted,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Breakpoint {
+    pub addr: u16,
+    ///
+    /// Stops only if the condition is true. A condition which can not be
+    /// evaluated (unknown register, ...) always stops.
+    ///
+    pub condition: Option<Expression>,
+    ///
+    /// Number of hits ignored before the breakpoint stops
+    ///
+    pub ignore_count: u32,
+    ///
+    /// Number of times PC reached the breakpoint with the condition true
+    ///
+    pub hits: u32,
+}
+
+impl Breakpoint {
+    pub fn new(addr: u16) -> Self {
+        Self {
+            addr,
+            condition: None,
+            ignore_count: 0,
+            hits: 0,
+        }
+    }
+    pub fn with_condition(mut self, condition: &str) -> Result<Self, ExprError> {
+        self.condition = Some(Expression::parse(condition)?);
+        Ok(self)
+    }
+    pub fn with_ignore_count(mut self, ignore_count: u32) -> Self {
+        self.ignore_count = ignore_count;
+        self
+    }
+    ///
+    /// Counts the hit and returns true if the execution has to stop
+    ///
+    fn hit(&mut self, cpu: &dyn Processor) -> bool {
+        if let Some(condition) = &self.condition
+            && !condition.is_true(cpu).unwrap_or(true)
+        {
+            return false;
+        }
+        self.hits += 1;
+        self.hits > self.ignore_count
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Watch {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+impl Watch {
+    fn matches(self, kind: AccessKind) -> bool {
+        matches!(
+            (self, kind),
+            (Watch::ReadWrite, _)
+                | (Watch::Read, AccessKind::Read)
+                | (Watch::Write, AccessKind::Write)
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Space {
+    Memory,
+    ///
+    /// I/O ports of the 8080, addresses are port numbers
+    ///
+    Port,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub start: u16,
+    pub end: u16,
+    pub watch: Watch,
+    pub space: Space,
+}
+
+impl Watchpoint {
+    ///
+    /// Memory watchpoint on the range start..=end
+    ///
+    pub fn new(start: u16, end: u16, watch: Watch) -> Self {
+        Self {
+            start,
+            end,
+            watch,
+            space: Space::Memory,
+        }
+    }
+    ///
+    /// Watchpoint on the I/O ports start..=end
+    ///
+    pub fn port(start: u8, end: u8, watch: Watch) -> Self {
+        Self {
+            start: start as u16,
+            end: end as u16,
+            watch,
+            space: Space::Port,
+        }
+    }
+    fn matches(&self, space: Space, access: &Access) -> bool {
+        self.space == space
+            && access.addr >= self.start
+            && access.addr <= self.end
+            && self.watch.matches(access.kind)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Breakpoints {
+    breakpoints: BTreeMap<u16, Breakpoint>,
+    watchpoints: Vec<Watchpoint>,
+}
+
+impl Breakpoints {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    ///
+    /// Adds breakpoint, an existing one at the same address is replaced
+    ///
+    pub fn insert(&mut self, breakpoint: Breakpoint) {
+        self.breakpoints.insert(breakpoint.addr, breakpoint);
+    }
+    pub fn remove(&mut self, addr: u16) -> Option<Breakpoint> {
+        self.breakpoints.remove(&addr)
+    }
+    ///
+    /// Adds unconditional breakpoint or removes the existing one
+    ///
+    pub fn toggle(&mut self, addr: u16) {
+        if self.remove(addr).is_none() {
+            self.insert(Breakpoint::new(addr));
+        }
+    }
+    pub fn contains(&self, addr: u16) -> bool {
+        self.breakpoints.contains_key(&addr)
+    }
+    pub fn get(&self, addr: u16) -> Option<&Breakpoint> {
+        self.breakpoints.get(&addr)
+    }
+    pub fn iter(&self) -> impl Iterator<Item = &Breakpoint> {
+        self.breakpoints.values()
+    }
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        self.watchpoints.push(watchpoint);
+    }
+    pub fn remove_watchpoint(&mut self, index: usize) -> Watchpoint {
+        self.watchpoints.remove(index)
+    }
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+    pub fn clear(&mut self) {
+        self.breakpoints.clear();
+        self.watchpoints.clear();
+    }
+    fn watches(&self, space: Space) -> bool {
+        self.watchpoints.iter().any(|w| w.space == space)
+    }
+    fn watched(&self, space: Space, accesses: &[Access]) -> Option<Access> {
+        accesses
+            .iter()
+            .find(|access| self.watchpoints.iter().any(|w| w.matches(space, access)))
+            .copied()
+    }
+}
+
+///
+/// Executes one instruction and checks the watchpoints. Reads of the
+/// instruction bytes themselves do not hit read watchpoints.
+///
+fn step(
+    cpu: &mut dyn Processor,
+    breakpoints: &Breakpoints,
+    history: &mut Option<&mut History>,
+) -> Option<StopReason> {
+    let pc = cpu.pc();
+    // Drop what the debugger read since the last step
+    cpu.memory().clear_accesses();
+    if let Some(history) = history {
+        history.begin(cpu);
+    }
+    let accesses = cpu::step_tracked(cpu);
+    if let Some(history) = history {
+        history.end(cpu, &accesses);
+    }
+    if let Some(access) = breakpoints.watched(Space::Memory, &accesses) {
+        return Some(StopReason::Watchpoint { pc, access });
+    }
+    let port = cpu.port_access();
+    if let Some(access) = breakpoints.watched(Space::Port, port.as_slice()) {
+        return Some(StopReason::PortWatchpoint { pc, access });
+    }
+    None
+}
+
+///
+/// Runs at most max_steps instructions. Execution stops after an instruction
+/// which hit a watchpoint, or when PC reaches the target address, a breakpoint
+/// or a halt instruction. Returns None if nothing stopped it, so that a front
+/// end can call it repeatedly and stay responsive.
+///
+pub fn run(
+    cpu: &mut dyn Processor,
+    breakpoints: &mut Breakpoints,
+    max_steps: usize,
+    target: Option<u16>,
+) -> Option<StopReason> {
+    run_with_history(cpu, breakpoints, None, max_steps, target)
+}
+
+///
+/// Same as run(), every executed instruction is recorded in history
+/// so that the execution can be rewound
+///
+pub fn run_with_history(
+    cpu: &mut dyn Processor,
+    breakpoints: &mut Breakpoints,
+    mut history: Option<&mut History>,
+    max_steps: usize,
+    target: Option<u16>,
+) -> Option<StopReason> {
+    let tracking = cpu.memory().is_tracking();
+    let track = breakpoints.watches(Space::Memory) || history.is_some();
+    if track && !tracking {
+        cpu.memory_mut().set_tracking(true);
+    }
+    let mut reason = None;
+    for _ in 0..max_steps {
+        if let Some(watched) = step(cpu, breakpoints, &mut history) {
+            reason = Some(watched);
+            break;
+        }
+        let pc = cpu.pc();
+        if target == Some(pc) {
+            reason = Some(StopReason::Target(pc));
+        } else if let Some(breakpoint) = breakpoints.breakpoints.get_mut(&pc)
+            && breakpoint.hit(cpu)
+        {
+            reason = Some(StopReason::Breakpoint(pc));
+        } else if cpu.is_halt(pc) {
+            reason = Some(StopReason::Halt(pc));
+        }
+        if reason.is_some() {
+            break;
+        }
+    }
+    if track && !tracking {
+        cpu.memory_mut().set_tracking(false);
+    }
+    reason
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::{i8080, mos6502};
+
+    fn cpu_with_loop() -> mos6502::Cpu {
+        let mut cpu = mos6502::Cpu::new();
+        let program = vec![
+            0xA2, 0x00, //       LDX #$00
+            0xE8, //             $0602 INX
+            0x8E, 0x00, 0x02, // STX $0200
+            0xAD, 0x10, 0x02, // LDA $0210
+            0xE0, 0x05, //       CPX #$05
+            0xD0, 0xF5, //       BNE $0602
+            0x00, //             BRK
+        ];
+        cpu.load_program(&program, 0x0600);
+        cpu.set_debug(false);
+        cpu
+    }
+
+    #[test]
+    ///
+    /// Conditional breakpoint and hit count
+    ///
+    fn conditional_breakpoint() {
+        let mut cpu = cpu_with_loop();
+        let mut breakpoints = Breakpoints::new();
+        breakpoints.insert(Breakpoint::new(0x0603).with_condition("X == 3").unwrap());
+        assert_eq!(
+            run(&mut cpu, &mut breakpoints, 100, None),
+            Some(StopReason::Breakpoint(0x0603))
+        );
+        assert_eq!(cpu.x, 3);
+        assert_eq!(breakpoints.get(0x0603).unwrap().hits, 1);
+
+        let mut cpu = cpu_with_loop();
+        let mut breakpoints = Breakpoints::new();
+        breakpoints.insert(Breakpoint::new(0x0602).with_ignore_count(2));
+        assert_eq!(
+            run(&mut cpu, &mut breakpoints, 100, None),
+            Some(StopReason::Breakpoint(0x0602))
+        );
+        assert_eq!(cpu.x, 2);
+        assert_eq!(
+            run(&mut cpu, &mut breakpoints, 100, None),
+            Some(StopReason::Breakpoint(0x0602))
+        );
+        assert_eq!(cpu.x, 3);
+        breakpoints.remove(0x0602);
+        assert_eq!(
+            run(&mut cpu, &mut breakpoints, 100, None),
+            Some(StopReason::Halt(0x060D))
+        );
+        assert_eq!(run(&mut cpu, &mut breakpoints, 0, None), None);
+    }
+
+    #[test]
+    ///
+    /// Read and write watchpoints stop after the accessing instruction,
+    /// fetching the instruction bytes does not hit them
+    ///
+    fn memory_watchpoints() {
+        let mut cpu = cpu_with_loop();
+        let mut breakpoints = Breakpoints::new();
+        breakpoints.add_watchpoint(Watchpoint::new(0x0200, 0x0201, Watch::Write));
+        assert_eq!(
+            run(&mut cpu, &mut breakpoints, 100, None),
+            Some(StopReason::Watchpoint {
+                pc: 0x0603,
+                access: Access {
+                    addr: 0x0200,
+                    value: 1,
+                    kind: AccessKind::Write
+                }
+            })
+        );
+        assert!(!cpu.memory.is_tracking());
+
+        let mut cpu = cpu_with_loop();
+        let mut breakpoints = Breakpoints::new();
+        breakpoints.add_watchpoint(Watchpoint::new(0x0600, 0x0610, Watch::Read));
+        breakpoints.add_watchpoint(Watchpoint::new(0x0210, 0x0210, Watch::Read));
+        match run(&mut cpu, &mut breakpoints, 100, None) {
+            Some(StopReason::Watchpoint { pc, access }) => {
+                assert_eq!(pc, 0x0606);
+                assert_eq!(access.addr, 0x0210);
+            }
+            reason => panic!("unexpected {:?}", reason),
+        }
+    }
+
+    #[test]
+    ///
+    /// 8080 port watchpoints
+    ///
+    fn port_watchpoints() {
+        let mut cpu = i8080::Cpu::new();
+        cpu.set_debug(false);
+        // IN 10H, OUT 20H, OUT 21H, HLT
+        cpu.load_program(&[0xDB, 0x10, 0xD3, 0x20, 0xD3, 0x21, 0x76], 0x0100);
+        let mut breakpoints = Breakpoints::new();
+        breakpoints.add_watchpoint(Watchpoint::port(0x10, 0x20, Watch::Write));
+        assert_eq!(
+            run(&mut cpu, &mut breakpoints, 100, None),
+            Some(StopReason::PortWatchpoint {
+                pc: 0x0102,
+                access: Access {
+                    addr: 0x20,
+                    value: 0,
+                    kind: AccessKind::Write
+                }
+            })
+        );
+        assert_eq!(
+            run(&mut cpu, &mut breakpoints, 100, None),
+            Some(StopReason::Halt(0x0106))
+        );
+    }
+}
@@ -0,0 +1,301 @@
+//////////////////////////////////////////////////////////
+/// Expressions of conditional breakpoints, e.g. `A == $FF && Z`.
+///
+/// Operands:
+///   numbers     $FF, 0FFH, 0xFF, %1010, 255
+///   registers   A, X, SP, HL, ... as Processor::register() knows them
+///   flags       Z, C, CY, ... or flags.Z; a name which is not a register
+///               is taken as a flag, so C is the carry on the 6502 and
+///               the register on the 8080 (use CY or flags.C there)
+///   memory      mem[addr] reads a byte, word[addr] a little endian word
+///   symbols     names which are neither register nor flag, when a
+///               symbol table is given (debugger commands)
+///
+/// Operators from the highest priority, as in Rust:
+///   ! - (unary)   + -   &   ^   |   == != < > <= >=   &&   ||
+///
+/// A value different from 0 is true, comparisons give 1 or 0.
+///
+/// parse_hex() reads numbers without prefix or suffix as hex (0200, 0FF),
+/// like the command line options and the spec files of batch.
+//////////////////////////////////////////////////////////
+use std::fmt;
+
+use crate::cpu::Processor;
+use crate::expression::{self, Level, Node, Op, Operands, Parser, Syntax, Token, Unary};
+use crate::machine::config::parse_number;
+use crate::symbols::SymbolTable;
+
+pub use crate::expression::ExprError;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expression {
+    text: String,
+    root: Node,
+}
+
+///
+/// Breakpoint conditions, with the operators of Rust. hex: numbers without
+/// prefix or suffix are hex instead of decimal.
+///
+struct Condition {
+    hex: bool,
+}
+
+impl Syntax for Condition {
+    fn symbols(&self) -> &'static [&'static str] {
+        &[
+            "&&", "||", "==", "!=", "<=", ">=", "<", ">", "+", "-", "&", "^", "|", "!", "(", ")",
+            "[", "]",
+        ]
+    }
+    fn levels(&self) -> &'static [Level] {
+        &[
+            &[("||", Op::LogicalOr)],
+            &[("&&", Op::LogicalAnd)],
+            &[
+                ("==", Op::Eq),
+                ("!=", Op::Ne),
+                ("<=", Op::Le),
+                (">=", Op::Ge),
+                ("<", Op::Lt),
+                (">", Op::Gt),
+            ],
+            &[("|", Op::Or)],
+            &[("^", Op::Xor)],
+            &[("&", Op::And)],
+            &[("+", Op::Add), ("-", Op::Sub)],
+        ]
+    }
+    fn scan(&self, rest: &str, _: bool) -> Result<Option<(Token, usize)>, ExprError> {
+        if !rest.starts_with(|c: char| c.is_ascii_digit() || c == '$' || c == '%') {
+            return Ok(None);
+        }
+        let length = expression::number_length(rest);
+        let word = &rest[..length];
+        let plain = word.bytes().all(|c| c.is_ascii_hexdigit());
+        let value = match word.strip_prefix('%') {
+            Some(binary) => u32::from_str_radix(binary, 2).ok(),
+            None if self.hex && plain => u32::from_str_radix(word, 16).ok(),
+            None => parse_number(word),
+        }
+        .ok_or_else(|| ExprError(format!("invalid number '{}'", word)))?;
+        Ok(Some((Token::Number(value as i64), length)))
+    }
+    fn is_name_char(&self, c: char) -> bool {
+        c.is_ascii_alphanumeric() || c == '_' || c == '.'
+    }
+    fn unary(&self, text: &str) -> Option<Unary> {
+        match text {
+            "!" => Some(Unary::LogicalNot),
+            "-" => Some(Unary::Negate),
+            _ => None,
+        }
+    }
+    fn is_indexed(&self, name: &str) -> bool {
+        name.eq_ignore_ascii_case("mem") || name.eq_ignore_ascii_case("word")
+    }
+}
+
+///
+/// Registers, flags and memory of the CPU, addresses of the symbols
+///
+struct State<'a> {
+    cpu: &'a dyn Processor,
+    symbols: Option<&'a SymbolTable>,
+}
+
+impl State<'_> {
+    ///
+    /// Flag by its name, the carry is C on the 6502 and CY on the 8080 and
+    /// both names work on both
+    ///
+    fn flag(&self, name: &str) -> Result<Option<i64>, ExprError> {
+        let alias = match name.to_ascii_uppercase().as_str() {
+            "C" => Some("CY"),
+            "CY" => Some("C"),
+            _ => None,
+        };
+        self.cpu
+            .flag(name)
+            .or_else(|| alias.and_then(|alias| self.cpu.flag(alias)))
+            .map(|value| Some(value as i64))
+            .ok_or_else(|| ExprError(format!("unknown register or flag '{}'", name)))
+    }
+    ///
+    /// Flag, or symbol if the CPU has no flag of this name
+    ///
+    fn flag_or_symbol(&self, name: &str) -> Result<Option<i64>, ExprError> {
+        let symbol = self.symbols.and_then(|symbols| symbols.address_of(name));
+        match (self.cpu.flag(name), symbol) {
+            (None, Some(addr)) => Ok(Some(addr as i64)),
+            (None, None) if self.symbols.is_some() => Err(ExprError(format!(
+                "unknown register, flag or symbol '{}'",
+                name
+            ))),
+            _ => self.flag(name),
+        }
+    }
+}
+
+impl Operands for State<'_> {
+    ///
+    /// Register, or flag if the CPU has no register of this name
+    ///
+    fn name(&self, name: &str) -> Result<Option<i64>, ExprError> {
+        let lower = name.to_ascii_lowercase();
+        if let Some(flag) = lower.strip_prefix("flags.") {
+            return self.flag(flag);
+        }
+        match self.cpu.register(name) {
+            Some(value) => Ok(Some(value as i64)),
+            None => self.flag_or_symbol(name),
+        }
+    }
+    fn index(&self, name: &str, addr: i64) -> Result<Option<i64>, ExprError> {
+        let memory = self.cpu.memory();
+        Ok(Some(match name.eq_ignore_ascii_case("mem") {
+            true => memory.read_byte(addr as u16) as i64,
+            false => memory.read_word(addr as u16) as i64,
+        }))
+    }
+}
+
+impl Expression {
+    pub fn parse(text: &str) -> Result<Self, ExprError> {
+        Self::parse_with(text, Condition { hex: false })
+    }
+    ///
+    /// Same as parse(), numbers without prefix or suffix are hex
+    ///
+    pub fn parse_hex(text: &str) -> Result<Self, ExprError> {
+        Self::parse_with(text, Condition { hex: true })
+    }
+    fn parse_with(text: &str, syntax: Condition) -> Result<Self, ExprError> {
+        Ok(Self {
+            text: text.trim().to_string(),
+            root: Parser::parse(text, &syntax)?,
+        })
+    }
+    pub fn evaluate(&self, cpu: &dyn Processor) -> Result<i64, ExprError> {
+        self.evaluate_with(cpu, None)
+    }
+    ///
+    /// Same as evaluate(), names that are neither register nor flag are
+    /// looked up in symbols
+    ///
+    pub fn evaluate_with(
+        &self,
+        cpu: &dyn Processor,
+        symbols: Option<&SymbolTable>,
+    ) -> Result<i64, ExprError> {
+        // All operands of the CPU are known, the value is never None
+        let state = State { cpu, symbols };
+        Ok(expression::evaluate(&self.root, &state)?.unwrap_or_default())
+    }
+    pub fn is_true(&self, cpu: &dyn Processor) -> Result<bool, ExprError> {
+        Ok(self.evaluate(cpu)? != 0)
+    }
+}
+
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::{i8080, mos6502};
+
+    fn is_true(text: &str, cpu: &dyn Processor) -> bool {
+        Expression::parse(text).unwrap().is_true(cpu).unwrap()
+    }
+
+    #[test]
+    ///
+    /// Registers, flags, memory and numbers in all notations
+    ///
+    fn evaluate_6502() {
+        let mut cpu = mos6502::Cpu::new();
+        cpu.a = 0xFF;
+        cpu.x = 3;
+        cpu.p.set_zero(true);
+        cpu.memory.write_word(0x0203, 0x1234);
+        assert!(is_true("A == $FF && Z", &cpu));
+        assert!(!is_true("A == $FF && !Z", &cpu));
+        assert!(is_true("a == 0FFH || C", &cpu));
+        assert!(is_true("mem[$0200 + X] == $34", &cpu));
+        assert!(is_true("word[0x0203] == 4660", &cpu));
+        assert!(is_true("(A & %10000000) != 0 && flags.Z == 1", &cpu));
+        assert!(is_true("X - 4 < 0", &cpu));
+    }
+
+    #[test]
+    ///
+    /// On the 8080 C is the register and CY the carry flag
+    ///
+    fn evaluate_8080() {
+        let mut cpu = i8080::Cpu::new();
+        cpu.h = 0x12;
+        cpu.l = 0x34;
+        cpu.c = 5;
+        cpu.psw.set_carry(true);
+        assert!(is_true("HL == $1234", &cpu));
+        assert!(is_true("C == 5 && CY", &cpu));
+        assert!(Expression::parse("X == 1").unwrap().is_true(&cpu).is_err());
+        assert!(is_true("flags.C && flags.CY", &cpu));
+    }
+
+    #[test]
+    ///
+    /// parse_hex() reads plain numbers as hex, prefixes and suffixes work
+    /// as in parse()
+    ///
+    fn hex_numbers() {
+        let mut cpu = mos6502::Cpu::new();
+        cpu.a = 0x83;
+        cpu.memory.write_byte(0x0200, 0x10);
+        let value = |text: &str| Expression::parse_hex(text).unwrap().evaluate(&cpu).unwrap();
+        assert_eq!(value("83"), 0x83);
+        assert_eq!(value("mem[0200] + 0FF"), 0x10F);
+        assert_eq!(value("$10 + 10H + 0x10 + %10"), 0x32);
+        assert_eq!(value("A"), 0x83);
+        assert_eq!(Expression::parse("83").unwrap().evaluate(&cpu).unwrap(), 83);
+    }
+
+    #[test]
+    ///
+    /// Symbols are addresses, registers and flags win over them
+    ///
+    fn evaluate_symbols() {
+        let mut cpu = mos6502::Cpu::new();
+        cpu.a = 7;
+        let mut symbols = SymbolTable::new();
+        symbols.insert("loop", 0x0610);
+        symbols.insert("A", 0x0200);
+        let value = |text: &str| {
+            Expression::parse(text)
+                .unwrap()
+                .evaluate_with(&cpu, Some(&symbols))
+        };
+        assert_eq!(value("loop + 2").unwrap(), 0x0612);
+        assert_eq!(value("A").unwrap(), 7);
+        assert!(value("buffer").is_err());
+        assert!(Expression::parse("loop").unwrap().evaluate(&cpu).is_err());
+    }
+
+    #[test]
+    ///
+    /// Syntax errors are reported by parse()
+    ///
+    fn parse_errors() {
+        assert!(Expression::parse("A ==").is_err());
+        assert!(Expression::parse("(A == 1").is_err());
+        assert!(Expression::parse("A = 1").is_err());
+        assert!(Expression::parse("$XY").is_err());
+        assert!(Expression::parse("mem $10").is_err());
+        assert!(Expression::parse("A 1").is_err());
+    }
+}
@@ -0,0 +1,555 @@
+//////////////////////////////////////////////////////////
+/// GDB remote serial protocol stub. GDB (or an IDE which speaks the
+/// protocol) connects over TCP and controls the CPU through the execution
+/// engine: registers, memory, breakpoints, watchpoints, step and continue.
+///
+/// The registers are described to GDB by target.xml (qXfer:features:read),
+/// in the order of Processor::register_layout().
+///
+/// ```
+/// mod cpu;
+/// mod debugger;
+/// ...
+/// fn main() -> std::io::Result<()> {
+///     let mut cpu = cpu::i8080::Cpu::new();
+///     cpu.load_program(&program, 0x0100);
+///     debugger::gdb::serve(&mut cpu, 1234)
+/// }
+/// ```
+/// and then in gdb: `target remote localhost:1234`
+//////////////////////////////////////////////////////////
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+
+use crate::cpu::Processor;
+use crate::debugger::engine::{self, Breakpoint, Breakpoints, StopReason, Watch, Watchpoint};
+use crate::memory::AccessKind;
+
+///
+/// Number of instructions executed between two checks for Ctrl-C from GDB
+///
+const RUN_CHUNK: usize = 10_000;
+
+const INTERRUPT: u8 = 0x03;
+
+///
+/// Stream to GDB. Besides reading packets the stub has to find out
+/// whether GDB sent Ctrl-C while the CPU runs.
+///
+pub trait Connection: Read + Write {
+    fn interrupted(&mut self) -> io::Result<bool>;
+}
+
+impl Connection for TcpStream {
+    fn interrupted(&mut self) -> io::Result<bool> {
+        self.set_nonblocking(true)?;
+        let mut byte = [0u8];
+        let result = match self.read(&mut byte) {
+            Ok(1) => Ok(byte[0] == INTERRUPT),
+            // Closed connection stops the CPU, the next read ends the session
+            Ok(_) => Ok(true),
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => Ok(false),
+            Err(err) => Err(err),
+        };
+        self.set_nonblocking(false)?;
+        result
+    }
+}
+
+pub struct GdbStub<'a, C: Connection> {
+    cpu: &'a mut dyn Processor,
+    connection: C,
+    breakpoints: Breakpoints,
+    last_stop: StopReason,
+}
+
+fn checksum(data: &str) -> u8 {
+    data.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn from_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+fn parse_hex(text: &str) -> Option<u32> {
+    u32::from_str_radix(text, 16).ok()
+}
+
+///
+/// Parses "addr,length" of m, M and Z packets
+///
+fn parse_range(text: &str) -> Option<(u16, u32)> {
+    let (addr, length) = text.split_once(',')?;
+    Some((parse_hex(addr)? as u16, parse_hex(length)?))
+}
+
+fn stop_reply(reason: StopReason) -> String {
+    match reason {
+        StopReason::Watchpoint { access, .. } => {
+            let kind = match access.kind {
+                AccessKind::Write => "watch",
+                AccessKind::Read => "rwatch",
+            };
+            format!("T05{}:{:04x};", kind, access.addr)
+        }
+        StopReason::Interrupted => "S02".to_string(),
+        _ => "S05".to_string(),
+    }
+}
+
+impl<'a, C: Connection> GdbStub<'a, C> {
+    pub fn new(cpu: &'a mut dyn Processor, connection: C) -> Self {
+        cpu.set_debug(false);
+        Self {
+            cpu,
+            connection,
+            breakpoints: Breakpoints::new(),
+            last_stop: StopReason::Step,
+        }
+    }
+    ///
+    /// Answers packets until GDB detaches, kills the target or closes the connection
+    ///
+    pub fn serve(&mut self) -> io::Result<()> {
+        while let Some(packet) = self.read_packet()? {
+            if !self.handle(&packet)? {
+                break;
+            }
+        }
+        Ok(())
+    }
+    ///
+    /// Reads next packet, acks and Ctrl-C outside of a run are skipped.
+    /// Returns None when the connection is closed.
+    ///
+    fn read_packet(&mut self) -> io::Result<Option<String>> {
+        let mut byte = [0u8];
+        loop {
+            if self.connection.read(&mut byte)? == 0 {
+                return Ok(None);
+            }
+            if byte[0] != b'$' {
+                continue;
+            }
+            let mut data = Vec::new();
+            loop {
+                if self.connection.read(&mut byte)? == 0 {
+                    return Ok(None);
+                }
+                if byte[0] == b'#' {
+                    break;
+                }
+                data.push(byte[0]);
+            }
+            let mut sum = [0u8; 2];
+            self.connection.read_exact(&mut sum)?;
+            let data = String::from_utf8_lossy(&data).into_owned();
+            let expected = std::str::from_utf8(&sum)
+                .ok()
+                .and_then(|sum| u8::from_str_radix(sum, 16).ok());
+            if expected == Some(checksum(&data)) {
+                self.connection.write_all(b"+")?;
+                return Ok(Some(data));
+            }
+            self.connection.write_all(b"-")?;
+        }
+    }
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        write!(self.connection, "${}#{:02x}", data, checksum(data))?;
+        self.connection.flush()
+    }
+    ///
+    /// Executes packet and sends the reply. Returns false if the session is over.
+    ///
+    fn handle(&mut self, packet: &str) -> io::Result<bool> {
+        let (command, args) = packet.split_at(packet.chars().next().map_or(0, char::len_utf8));
+        let reply = match command {
+            "?" => stop_reply(self.last_stop),
+            "g" => self.read_registers(),
+            "G" => self.write_registers(args),
+            "p" => self.read_register(args),
+            "P" => self.write_register(args),
+            "m" => self.read_memory(args),
+            "M" => self.write_memory(args),
+            "Z" => self.insert_breakpoint(args),
+            "z" => self.remove_breakpoint(args),
+            "s" | "c" => {
+                if let Some(addr) = parse_hex(args) {
+                    self.cpu.set_pc(addr as u16);
+                }
+                let reason = if command == "s" {
+                    engine::run(self.cpu, &mut self.breakpoints, 1, None)
+                        .unwrap_or(StopReason::Step)
+                } else {
+                    self.resume()?
+                };
+                self.last_stop = reason;
+                stop_reply(reason)
+            }
+            "H" => "OK".to_string(),
+            "D" => {
+                self.send("OK")?;
+                return Ok(false);
+            }
+            "k" => return Ok(false),
+            "q" => self.query(args),
+            // Everything else is not supported, which is an empty reply
+            _ => String::new(),
+        };
+        self.send(&reply)?;
+        Ok(true)
+    }
+    ///
+    /// Runs until the engine stops or GDB sends Ctrl-C
+    ///
+    fn resume(&mut self) -> io::Result<StopReason> {
+        loop {
+            if let Some(reason) = engine::run(self.cpu, &mut self.breakpoints, RUN_CHUNK, None) {
+                return Ok(reason);
+            }
+            if self.connection.interrupted()? {
+                return Ok(StopReason::Interrupted);
+            }
+        }
+    }
+    fn query(&self, args: &str) -> String {
+        if args.starts_with("Supported") {
+            "PacketSize=1000;qXfer:features:read+".to_string()
+        } else if args == "Attached" {
+            "1".to_string()
+        } else if let Some(range) = args.strip_prefix("Xfer:features:read:target.xml:") {
+            let Some((offset, length)) = parse_range(range) else {
+                return "E01".to_string();
+            };
+            let xml = self.target_xml();
+            let start = (offset as usize).min(xml.len());
+            let end = (start + length as usize).min(xml.len());
+            let marker = if end == xml.len() { 'l' } else { 'm' };
+            format!("{}{}", marker, &xml[start..end])
+        } else {
+            String::new()
+        }
+    }
+    fn target_xml(&self) -> String {
+        let registers: String = self
+            .cpu
+            .register_layout()
+            .iter()
+            .map(|(name, bits)| {
+                let kind = if *name == "PC" {
+                    " type=\"code_ptr\""
+                } else {
+                    ""
+                };
+                format!(
+                    "<reg name=\"{}\" bitsize=\"{}\"{}/>",
+                    name.to_ascii_lowercase(),
+                    bits,
+                    kind
+                )
+            })
+            .collect();
+        format!(
+            "<?xml version=\"1.0\"?><!DOCTYPE target SYSTEM \"gdb-target.dtd\">\
+             <target version=\"1.0\"><feature name=\"org.sbc8micro.cpu\">{}</feature></target>",
+            registers
+        )
+    }
+    fn register_hex(&self, name: &str, bits: u8) -> String {
+        let value = self.cpu.register(name).unwrap_or(0);
+        to_hex(&value.to_le_bytes()[..bits as usize / 8])
+    }
+    fn read_registers(&self) -> String {
+        self.cpu
+            .register_layout()
+            .iter()
+            .map(|(name, bits)| self.register_hex(name, *bits))
+            .collect()
+    }
+    fn write_registers(&mut self, args: &str) -> String {
+        let Some(bytes) = from_hex(args) else {
+            return "E01".to_string();
+        };
+        let mut bytes = bytes.iter();
+        for (name, bits) in self.cpu.register_layout() {
+            let mut value = [0u8; 2];
+            for byte in value.iter_mut().take(*bits as usize / 8) {
+                match bytes.next() {
+                    Some(next) => *byte = *next,
+                    None => return "E01".to_string(),
+                }
+            }
+            self.cpu.set_register(name, u16::from_le_bytes(value));
+        }
+        "OK".to_string()
+    }
+    fn read_register(&self, args: &str) -> String {
+        let layout = self.cpu.register_layout();
+        match parse_hex(args).and_then(|index| layout.get(index as usize)) {
+            Some((name, bits)) => self.register_hex(name, *bits),
+            None => "E01".to_string(),
+        }
+    }
+    fn write_register(&mut self, args: &str) -> String {
+        let layout = self.cpu.register_layout();
+        let register = args.split_once('=').and_then(|(index, value)| {
+            let (name, _) = layout.get(parse_hex(index)? as usize)?;
+            let mut bytes = from_hex(value)?;
+            bytes.resize(2, 0);
+            Some((name, u16::from_le_bytes([bytes[0], bytes[1]])))
+        });
+        match register {
+            Some((name, value)) => {
+                self.cpu.set_register(name, value);
+                "OK".to_string()
+            }
+            None => "E01".to_string(),
+        }
+    }
+    fn read_memory(&self, args: &str) -> String {
+        let Some((addr, length)) = parse_range(args) else {
+            return "E01".to_string();
+        };
+        let memory = self.cpu.memory();
+        let bytes: Vec<u8> = (0..length.min(0x10000))
+            .map(|i| memory.read_byte(addr.wrapping_add(i as u16)))
+            .collect();
+        to_hex(&bytes)
+    }
+    fn write_memory(&mut self, args: &str) -> String {
+        let data = args
+            .split_once(':')
+            .and_then(|(range, data)| Some((parse_range(range)?, from_hex(data)?)));
+        let Some(((addr, length), bytes)) = data else {
+            return "E01".to_string();
+        };
+        if bytes.len() != length as usize {
+            return "E01".to_string();
+        }
+        let memory = self.cpu.memory_mut();
+        for (i, byte) in bytes.iter().enumerate() {
+            memory.write_byte(addr.wrapping_add(i as u16), *byte);
+        }
+        "OK".to_string()
+    }
+    ///
+    /// Parses "type,addr,kind" of Z and z packets. For watchpoints kind is
+    /// the length of the watched range.
+    ///
+    fn parse_breakpoint(args: &str) -> Option<(char, u16, u32)> {
+        let (kind, range) = args.split_once(',')?;
+        let (addr, length) = parse_range(range)?;
+        Some((kind.chars().next()?, addr, length))
+    }
+    fn watchpoint(kind: char, addr: u16, length: u32) -> Option<Watchpoint> {
+        let watch = match kind {
+            '2' => Watch::Write,
+            '3' => Watch::Read,
+            '4' => Watch::ReadWrite,
+            _ => return None,
+        };
+        let end = (addr as u32).saturating_add(length.max(1) - 1).min(0xFFFF) as u16;
+        Some(Watchpoint::new(addr, end, watch))
+    }
+    fn insert_breakpoint(&mut self, args: &str) -> String {
+        match Self::parse_breakpoint(args) {
+            Some(('0' | '1', addr, _)) => self.breakpoints.insert(Breakpoint::new(addr)),
+            Some((kind, addr, length)) => match Self::watchpoint(kind, addr, length) {
+                Some(watchpoint) => self.breakpoints.add_watchpoint(watchpoint),
+                None => return String::new(),
+            },
+            None => return "E01".to_string(),
+        }
+        "OK".to_string()
+    }
+    fn remove_breakpoint(&mut self, args: &str) -> String {
+        match Self::parse_breakpoint(args) {
+            Some(('0' | '1', addr, _)) => {
+                self.breakpoints.remove(addr);
+            }
+            Some((kind, addr, length)) => {
+                let Some(watchpoint) = Self::watchpoint(kind, addr, length) else {
+                    return String::new();
+                };
+                if let Some(index) = self
+                    .breakpoints
+                    .watchpoints()
+                    .iter()
+                    .position(|w| *w == watchpoint)
+                {
+                    self.breakpoints.remove_watchpoint(index);
+                }
+            }
+            None => return "E01".to_string(),
+        }
+        "OK".to_string()
+    }
+}
+
+///
+/// Waits for GDB on localhost:port and serves the first connection
+///
+pub fn serve(cpu: &mut dyn Processor, port: u16) -> io::Result<()> {
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+    println!("Waiting for GDB on {}", listener.local_addr()?);
+    let (stream, _) = listener.accept()?;
+    stream.set_nodelay(true)?;
+    GdbStub::new(cpu, stream).serve()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::{i8080, mos6502};
+    use std::thread;
+
+    ///
+    /// Client side: sends packets and collects the replies
+    ///
+    fn client(port: u16, packets: &'static [&'static str]) -> thread::JoinHandle<Vec<String>> {
+        thread::spawn(move || {
+            let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+            stream.set_nodelay(true).unwrap();
+            let mut replies = Vec::new();
+            for packet in packets {
+                write!(stream, "${}#{:02x}", packet, checksum(packet)).unwrap();
+                let mut ack = [0u8];
+                stream.read_exact(&mut ack).unwrap();
+                assert_eq!(ack[0], b'+');
+                if *packet == "k" {
+                    break;
+                }
+                let mut reply = Vec::new();
+                let mut byte = [0u8];
+                while byte[0] != b'#' {
+                    stream.read_exact(&mut byte).unwrap();
+                    reply.push(byte[0]);
+                }
+                let mut sum = [0u8; 2];
+                stream.read_exact(&mut sum).unwrap();
+                stream.write_all(b"+").unwrap();
+                let reply = String::from_utf8(reply).unwrap();
+                replies.push(reply[1..reply.len() - 1].to_string());
+            }
+            replies
+        })
+    }
+
+    fn session(cpu: &mut dyn Processor, packets: &'static [&'static str]) -> Vec<String> {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let client = client(listener.local_addr().unwrap().port(), packets);
+        let (stream, _) = listener.accept().unwrap();
+        stream.set_nodelay(true).unwrap();
+        GdbStub::new(cpu, stream).serve().unwrap();
+        client.join().unwrap()
+    }
+
+    #[test]
+    ///
+    /// Registers, memory, breakpoint, watchpoint, step and continue on the 6502
+    ///
+    fn session_6502() {
+        let mut cpu = mos6502::Cpu::new();
+        let program = vec![
+            0xA2, 0x00, //       LDX #$00
+            0xE8, //             $0602 INX
+            0x8E, 0x00, 0x02, // STX $0200
+            0xE0, 0x05, //       CPX #$05
+            0xD0, 0xF8, //       BNE $0602
+            0x00, //             BRK
+        ];
+        cpu.load_program(&program, 0x0600);
+        let replies = session(
+            &mut cpu,
+            &[
+                "qSupported:multiprocess+;swbreak+",
+                "?",
+                "m600,3",
+                "M210,2:abcd",
+                "m210,2",
+                "Z0,603,1",
+                "c",
+                "p5",
+                "p1",
+                "z0,603,1",
+                "Z2,200,1",
+                "c",
+                "P0=42",
+                "s",
+                "g",
+                "k",
+            ],
+        );
+        assert!(replies[0].contains("qXfer:features:read+"));
+        assert_eq!(replies[1], "S05");
+        assert_eq!(replies[2], "a200e8");
+        assert_eq!(replies[3], "OK");
+        assert_eq!(replies[4], "abcd");
+        assert_eq!(replies[5], "OK");
+        assert_eq!(replies[6], "S05");
+        assert_eq!(replies[7], "0306");
+        assert_eq!(replies[8], "01");
+        assert_eq!(replies[10], "OK");
+        assert_eq!(replies[11], "T05watch:0200;");
+        assert_eq!(replies[12], "OK");
+        assert_eq!(replies[13], "S05");
+        // A=42 X=01, PC at BNE after CPX
+        assert!(replies[14].starts_with("4201"));
+        assert!(replies[14].ends_with("0806"));
+        assert_eq!(cpu.x, 1);
+    }
+
+    #[test]
+    ///
+    /// Watched range of Z2..Z4 ends at the top of memory, also for a
+    /// length of 64KB
+    ///
+    fn watchpoint_range() {
+        let range = |addr: u16, length: u32| {
+            let watchpoint = GdbStub::<TcpStream>::watchpoint('2', addr, length).unwrap();
+            (watchpoint.start, watchpoint.end)
+        };
+        assert_eq!(range(0x0200, 0), (0x0200, 0x0200));
+        assert_eq!(range(0x0200, 4), (0x0200, 0x0203));
+        assert_eq!(range(0xFFF0, 0x20), (0xFFF0, 0xFFFF));
+        assert_eq!(range(0x0000, 0x10000), (0x0000, 0xFFFF));
+        assert_eq!(range(0x1000, u32::MAX - 0x800), (0x1000, 0xFFFF));
+    }
+
+    #[test]
+    ///
+    /// 8080 registers are described by target.xml and written with G
+    ///
+    fn session_8080() {
+        let mut cpu = i8080::Cpu::new();
+        let replies = session(
+            &mut cpu,
+            &[
+                "qXfer:features:read:target.xml:0,1000",
+                "G11000203040506070020ff00",
+                "g",
+                "p9",
+                "D",
+            ],
+        );
+        assert!(replies[0].starts_with("l<?xml"));
+        assert!(!replies[0].contains("<reg name=\"psw\""));
+        assert!(replies[0].contains("<reg name=\"sp\" bitsize=\"16\"/>"));
+        assert_eq!(replies[1], "OK");
+        assert_eq!(replies[2], "11000203040506070020ff00");
+        assert_eq!(replies[3], "ff00");
+        assert_eq!(replies[4], "OK");
+        assert_eq!(cpu.sp, 0x2000);
+        assert_eq!(cpu.pc, 0x00FF);
+    }
+}
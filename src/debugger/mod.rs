@@ -0,0 +1,363 @@
+//////////////////////////////////////////////////////////
+/// Debugger for all CPUs implementing cpu::Processor.
+/// This part holds the state (breakpoints, cursor, memory view) and the
+/// execution commands, tui.rs draws it with ratatui.
+///
+/// ```
+/// mod cpu;
+/// mod debugger;
+/// mod disassembler;
+/// mod memory;
+/// mod status;
+///
+/// fn main() -> Result<(), Box<dyn std::error::Error>> {
+///     let mut cpu = cpu::mos6502::Cpu::new();
+///     cpu.load_program(&[0xA9, 0x01, 0x00], 0x0600);
+///     debugger::tui::debug(&mut cpu, symbols::SymbolTable::new())
+/// }
+/// ```
+//////////////////////////////////////////////////////////
+pub mod command;
+pub mod engine;
+pub mod expression;
+pub mod gdb;
+pub mod rewind;
+pub mod tui;
+
+use crate::cpu::Processor;
+use crate::symbols::SymbolTable;
+pub use engine::{Breakpoints, StopReason};
+use rewind::{CHECKPOINT_INTERVAL, History, MAX_CHECKPOINTS};
+
+///
+/// Maximal number of instructions executed by step over before it gives up
+/// (the subroutine may never return)
+///
+pub const STEP_OVER_LIMIT: usize = 1_000_000;
+
+pub struct Debugger<'a> {
+    cpu: &'a mut dyn Processor,
+    breakpoints: Breakpoints,
+    ///
+    /// Address of the selected line in the disassembly
+    ///
+    pub cursor: u16,
+    ///
+    /// First address of the disassembly pane
+    ///
+    pub code_view: u16,
+    ///
+    /// First address of the memory pane
+    ///
+    pub memory_view: u16,
+    pub last_stop: Option<StopReason>,
+    ///
+    /// Command line being typed, see command.rs
+    ///
+    pub prompt: Option<String>,
+    ///
+    /// Result of the last command, shown instead of the stop reason
+    ///
+    pub message: Option<String>,
+    ///
+    /// Recorded execution for stepping back, None if rewind is off
+    ///
+    history: Option<History>,
+    ///
+    /// Names usable as addresses in the commands
+    ///
+    symbols: SymbolTable,
+}
+
+impl<'a> Debugger<'a> {
+    pub fn new(cpu: &'a mut dyn Processor) -> Self {
+        cpu.set_debug(false);
+        let pc = cpu.pc();
+        Self {
+            cpu,
+            breakpoints: Breakpoints::new(),
+            cursor: pc,
+            code_view: pc,
+            memory_view: pc & 0xFFF0,
+            last_stop: None,
+            prompt: None,
+            message: None,
+            history: None,
+            symbols: SymbolTable::new(),
+        }
+    }
+    pub fn cpu(&self) -> &dyn Processor {
+        self.cpu
+    }
+    pub fn symbols(&self) -> &SymbolTable {
+        &self.symbols
+    }
+    pub fn set_symbols(&mut self, symbols: SymbolTable) {
+        self.symbols = symbols;
+    }
+    pub fn breakpoints(&self) -> &Breakpoints {
+        &self.breakpoints
+    }
+    pub fn breakpoints_mut(&mut self) -> &mut Breakpoints {
+        &mut self.breakpoints
+    }
+    pub fn toggle_breakpoint(&mut self, addr: u16) {
+        self.breakpoints.toggle(addr);
+    }
+    ///
+    /// Starts recording the execution so that it can be stepped back
+    ///
+    pub fn enable_rewind(&mut self) {
+        if self.history.is_none() {
+            self.history = Some(History::new(CHECKPOINT_INTERVAL, MAX_CHECKPOINTS));
+        }
+    }
+    pub fn history(&self) -> Option<&History> {
+        self.history.as_ref()
+    }
+    pub fn step(&mut self) -> StopReason {
+        match &mut self.history {
+            Some(history) => history.step(self.cpu),
+            None => self.cpu.step(),
+        }
+        self.stopped(StopReason::Step)
+    }
+    ///
+    /// Undoes the last instruction, returns false if it is not recorded
+    ///
+    pub fn step_back(&mut self) -> bool {
+        let done = self
+            .history
+            .as_mut()
+            .is_some_and(|history| history.step_back(self.cpu));
+        if done {
+            self.stopped(StopReason::Step);
+        }
+        done
+    }
+    ///
+    /// Goes back to the instruction number (counted since rewind was enabled)
+    ///
+    pub fn rewind_to(&mut self, instruction: u64) -> bool {
+        let done = self
+            .history
+            .as_mut()
+            .is_some_and(|history| history.rewind_to(self.cpu, instruction));
+        if done {
+            self.stopped(StopReason::Step);
+        }
+        done
+    }
+    ///
+    /// Executes subroutine calls as one step. Other instructions are single stepped.
+    ///
+    pub fn step_over(&mut self) -> StopReason {
+        let pc = self.cpu.pc();
+        if !self.cpu.is_call(pc) {
+            return self.step();
+        }
+        let return_addr = pc.wrapping_add(self.cpu.instruction_length(pc) as u16);
+        let reason = self
+            .run(STEP_OVER_LIMIT, Some(return_addr))
+            .unwrap_or(StopReason::Limit);
+        if reason == StopReason::Target(return_addr) {
+            self.stopped(StopReason::Step)
+        } else {
+            reason
+        }
+    }
+    ///
+    /// Runs at most max_steps instructions, see engine::run().
+    /// Returns None if nothing stopped the execution, so that a front end can
+    /// call it repeatedly and stay responsive.
+    ///
+    pub fn run(&mut self, max_steps: usize, target: Option<u16>) -> Option<StopReason> {
+        let reason = engine::run_with_history(
+            self.cpu,
+            &mut self.breakpoints,
+            self.history.as_mut(),
+            max_steps,
+            target,
+        )?;
+        Some(self.stopped(reason))
+    }
+    pub fn run_to_cursor(&mut self, max_steps: usize) -> Option<StopReason> {
+        self.run(max_steps, Some(self.cursor))
+    }
+    ///
+    /// Stop requested by the user (key pressed while running)
+    ///
+    pub fn interrupt(&mut self) -> StopReason {
+        self.stopped(StopReason::Interrupted)
+    }
+    fn stopped(&mut self, reason: StopReason) -> StopReason {
+        self.last_stop = Some(reason);
+        self.cursor = self.cpu.pc();
+        reason
+    }
+    ///
+    /// Disassembly lines with their addresses, starting at code_view.
+    /// If PC is not among them, the view is moved so that it starts at PC.
+    ///
+    pub fn code_lines(&mut self, rows: usize) -> Vec<(u16, String)> {
+        let mut lines = self.disassemble_from(self.code_view, rows);
+        let pc = self.cpu.pc();
+        if !lines.iter().any(|(addr, _)| *addr == pc) {
+            self.code_view = pc;
+            lines = self.disassemble_from(pc, rows);
+        }
+        lines
+    }
+    fn disassemble_from(&self, start: u16, rows: usize) -> Vec<(u16, String)> {
+        self.cpu
+            .disassemble(start, rows)
+            .into_iter()
+            .map(|instruction| (instruction.address, instruction.to_string()))
+            .collect()
+    }
+    ///
+    /// Moves the cursor to the next (down = true) or previous disassembly line
+    ///
+    pub fn move_cursor(&mut self, down: bool, rows: usize) {
+        let lines = self.code_lines(rows);
+        let index = lines.iter().position(|(addr, _)| *addr == self.cursor);
+        match (index, down) {
+            (Some(i), true) if i + 1 < lines.len() => self.cursor = lines[i + 1].0,
+            (Some(i), false) if i > 0 => self.cursor = lines[i - 1].0,
+            (None, _) => self.cursor = lines.first().map_or(self.cursor, |(addr, _)| *addr),
+            _ => {}
+        }
+    }
+    ///
+    /// Words on the stack from its top (SP) to its base
+    ///
+    pub fn stack_lines(&self, rows: usize) -> Vec<String> {
+        let memory = self.cpu.memory();
+        let mut lines = Vec::new();
+        let mut addr = self.cpu.stack_address();
+        while addr <= self.cpu.stack_base() && lines.len() < rows {
+            lines.push(format!("{:04X}  {:02X}", addr, memory.read_byte(addr)));
+            if addr == 0xFFFF {
+                break;
+            }
+            addr += 1;
+        }
+        lines
+    }
+    pub fn memory_lines(&self, rows: usize) -> Vec<String> {
+        if rows == 0 {
+            return Vec::new();
+        }
+        let start = self.memory_view as usize;
+        let end = (start + rows * 16).min(0x10000) - 1;
+        self.cpu.memory().hex_dump_lines(start, end)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::mos6502;
+
+    fn cpu_with_subroutine() -> mos6502::Cpu {
+        let mut cpu = mos6502::Cpu::new();
+        let program = vec![
+            0x20, 0x08, 0x06, // JSR $0608
+            0xA2, 0x05, //       LDX #$05
+            0x00, //             BRK
+            0xEA, 0xEA, //       NOP NOP
+            0xA9, 0x42, //       $0608 LDA #$42
+            0xE8, //             INX
+            0x60, //             RTS
+        ];
+        cpu.load_program(&program, 0x0600);
+        cpu
+    }
+
+    #[test]
+    ///
+    /// Step over executes the whole subroutine and stops after JSR
+    ///
+    fn step_over_subroutine() {
+        let mut cpu = cpu_with_subroutine();
+        let mut debugger = Debugger::new(&mut cpu);
+        assert_eq!(debugger.step_over(), StopReason::Step);
+        assert_eq!(debugger.cpu().pc(), 0x0603);
+        drop(debugger);
+        assert_eq!(cpu.a, 0x42);
+        assert_eq!(cpu.x, 0x01);
+    }
+
+    #[test]
+    ///
+    /// Run stops at breakpoint, then at BRK; run to cursor stops at the cursor
+    ///
+    fn run_until_breakpoint_and_cursor() {
+        let mut cpu = cpu_with_subroutine();
+        let mut debugger = Debugger::new(&mut cpu);
+        debugger.toggle_breakpoint(0x060A);
+        assert_eq!(
+            debugger.run(100, None),
+            Some(StopReason::Breakpoint(0x060A))
+        );
+        assert_eq!(debugger.run(100, None), Some(StopReason::Halt(0x0605)));
+        let mut cpu = cpu_with_subroutine();
+        let mut debugger = Debugger::new(&mut cpu);
+        debugger.cursor = 0x0603;
+        assert_eq!(
+            debugger.run_to_cursor(100),
+            Some(StopReason::Target(0x0603))
+        );
+    }
+
+    #[test]
+    ///
+    /// With rewind on, steps and runs can be undone
+    ///
+    fn step_back() {
+        let mut cpu = cpu_with_subroutine();
+        let mut debugger = Debugger::new(&mut cpu);
+        assert!(!debugger.step_back());
+        debugger.enable_rewind();
+        debugger.step();
+        debugger.run(100, None);
+        assert_eq!(debugger.cpu().pc(), 0x0605);
+        assert_eq!(debugger.history().unwrap().count(), 5);
+        assert!(debugger.step_back());
+        assert_eq!(debugger.cpu().pc(), 0x0603);
+        assert_eq!(debugger.cpu().register("X"), Some(1));
+        assert!(debugger.rewind_to(1));
+        assert_eq!(debugger.cpu().pc(), 0x0608);
+        assert_eq!(debugger.cursor, 0x0608);
+    }
+
+    #[test]
+    ///
+    /// Disassembly follows PC and cursor moves over instructions
+    ///
+    fn code_view_and_cursor() {
+        let mut cpu = cpu_with_subroutine();
+        let mut debugger = Debugger::new(&mut cpu);
+        let lines = debugger.code_lines(3);
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[1].0, 0x0603);
+        debugger.move_cursor(true, 3);
+        assert_eq!(debugger.cursor, 0x0603);
+        debugger.cpu.set_pc(0x0608);
+        assert_eq!(debugger.code_lines(3)[0].0, 0x0608);
+    }
+
+    #[test]
+    ///
+    /// Memory view of the requested rows, none for a window without room
+    ///
+    fn memory_view_rows() {
+        let mut cpu = cpu_with_subroutine();
+        let mut debugger = Debugger::new(&mut cpu);
+        assert_eq!(debugger.memory_lines(2).len(), 2);
+        debugger.memory_view = 0x0000;
+        assert!(debugger.memory_lines(0).is_empty());
+        debugger.memory_view = 0xFFF0;
+        assert_eq!(debugger.memory_lines(4).len(), 1);
+    }
+}
@@ -0,0 +1,324 @@
+//////////////////////////////////////////////////////////
+/// Rewind (reverse execution). The history keeps periodic checkpoints of
+/// the registers, the whole memory and the I/O ports and, for every
+/// instruction since the oldest checkpoint, a journal entry with the
+/// registers before the instruction and the bytes and port it wrote. Going
+/// back to instruction N restores the last checkpoint before N and replays
+/// the journaled writes up to N, nothing is executed again.
+///
+/// ```
+/// let mut history = History::new(1000, 100);
+/// for _ in 0..5000 {
+///     history.step(&mut cpu);
+/// }
+/// history.step_back(&mut cpu);
+/// history.rewind_to(&mut cpu, 4200);
+/// ```
+//////////////////////////////////////////////////////////
+use std::collections::VecDeque;
+
+use crate::cpu::{self, Processor};
+use crate::memory::{Access, AccessKind};
+
+///
+/// Instructions between two checkpoints used by the debugger
+///
+pub const CHECKPOINT_INTERVAL: u64 = 1000;
+
+///
+/// Checkpoints kept by the debugger, with the interval above it can go
+/// 100000 instructions back using about 6.5MB
+///
+pub const MAX_CHECKPOINTS: usize = 100;
+
+struct Checkpoint {
+    instruction: u64,
+    registers: Vec<u16>,
+    memory: Box<[u8; 0x10000]>,
+    ///
+    /// Latches of the I/O ports, empty for CPUs without I/O space
+    ///
+    ports: Vec<u8>,
+}
+
+struct Entry {
+    registers: Vec<u16>,
+    writes: Vec<(u16, u8)>,
+    ///
+    /// Port and value of an OUT instruction
+    ///
+    port_write: Option<(u8, u8)>,
+}
+
+pub struct History {
+    interval: u64,
+    max_checkpoints: usize,
+    checkpoints: VecDeque<Checkpoint>,
+    ///
+    /// Entry of instruction n is journal[n - first_entry]
+    ///
+    journal: VecDeque<Entry>,
+    first_entry: u64,
+    ///
+    /// Number of instructions executed, it is the number of the next one
+    ///
+    count: u64,
+}
+
+fn registers(cpu: &dyn Processor) -> Vec<u16> {
+    cpu.register_layout()
+        .iter()
+        .map(|(name, _)| cpu.register(name).unwrap_or(0))
+        .collect()
+}
+
+fn set_registers(cpu: &mut dyn Processor, values: &[u16]) {
+    for ((name, _), value) in cpu.register_layout().iter().zip(values) {
+        cpu.set_register(name, *value);
+    }
+}
+
+impl History {
+    pub fn new(interval: u64, max_checkpoints: usize) -> Self {
+        Self {
+            interval: interval.max(1),
+            max_checkpoints: max_checkpoints.max(1),
+            checkpoints: VecDeque::new(),
+            journal: VecDeque::new(),
+            first_entry: 0,
+            count: 0,
+        }
+    }
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+    ///
+    /// Number of the oldest instruction the history can go back to
+    ///
+    pub fn oldest(&self) -> u64 {
+        self.checkpoints
+            .front()
+            .map_or(self.count, |checkpoint| checkpoint.instruction)
+    }
+    ///
+    /// Called before an instruction is executed. Memory tracking has to be on
+    /// until end() so that the writes are known.
+    ///
+    pub fn begin(&mut self, cpu: &dyn Processor) {
+        let due = self.count.is_multiple_of(self.interval) || self.checkpoints.is_empty();
+        let taken = self
+            .checkpoints
+            .back()
+            .is_some_and(|checkpoint| checkpoint.instruction == self.count);
+        if due && !taken {
+            self.checkpoints.push_back(Checkpoint {
+                instruction: self.count,
+                registers: registers(cpu),
+                memory: Box::new(cpu.memory().get_data()),
+                ports: cpu.ports().to_vec(),
+            });
+            if self.checkpoints.len() > self.max_checkpoints {
+                self.checkpoints.pop_front();
+                self.drop_old_entries();
+            }
+        }
+        if self.journal.is_empty() {
+            self.first_entry = self.count;
+        }
+        self.journal.push_back(Entry {
+            registers: registers(cpu),
+            writes: Vec::new(),
+            port_write: None,
+        });
+    }
+    ///
+    /// Called after the instruction with the memory accesses it made
+    ///
+    pub fn end(&mut self, cpu: &dyn Processor, accesses: &[Access]) {
+        if let Some(entry) = self.journal.back_mut() {
+            entry.writes = accesses
+                .iter()
+                .filter(|access| access.kind == AccessKind::Write)
+                .map(|access| (access.addr, access.value))
+                .collect();
+            entry.port_write = cpu
+                .port_access()
+                .filter(|access| access.kind == AccessKind::Write)
+                .map(|access| (access.addr as u8, access.value));
+        }
+        self.count += 1;
+    }
+    ///
+    /// Executes one instruction and records it
+    ///
+    pub fn step(&mut self, cpu: &mut dyn Processor) {
+        let tracking = cpu.memory().is_tracking();
+        if !tracking {
+            cpu.memory_mut().set_tracking(true);
+        }
+        self.begin(cpu);
+        let accesses = cpu::step_tracked(cpu);
+        self.end(cpu, &accesses);
+        if !tracking {
+            cpu.memory_mut().set_tracking(false);
+        }
+    }
+    fn drop_old_entries(&mut self) {
+        let oldest = self.oldest();
+        while self.first_entry < oldest && !self.journal.is_empty() {
+            self.journal.pop_front();
+            self.first_entry += 1;
+        }
+    }
+    ///
+    /// Restores the state before the instruction number target was executed.
+    /// Everything recorded after it is dropped. Returns false if the history
+    /// does not go back that far.
+    ///
+    pub fn rewind_to(&mut self, cpu: &mut dyn Processor, target: u64) -> bool {
+        if target > self.count || target < self.oldest() {
+            return false;
+        }
+        if target == self.count {
+            return true;
+        }
+        let Some(index) = self
+            .checkpoints
+            .iter()
+            .rposition(|checkpoint| checkpoint.instruction <= target)
+        else {
+            return false;
+        };
+        self.checkpoints.truncate(index + 1);
+        let checkpoint = &self.checkpoints[index];
+        cpu.memory_mut().set_data(&checkpoint.memory);
+        cpu.ports_mut().copy_from_slice(&checkpoint.ports);
+        set_registers(cpu, &checkpoint.registers);
+        let first = (checkpoint.instruction - self.first_entry) as usize;
+        let last = (target - self.first_entry) as usize;
+        for entry in self.journal.range(first..last) {
+            for &(addr, value) in &entry.writes {
+                cpu.memory_mut().write_byte(addr, value);
+            }
+            if let Some((port, value)) = entry.port_write {
+                cpu.ports_mut()[port as usize] = value;
+            }
+        }
+        set_registers(cpu, &self.journal[last].registers);
+        self.journal.truncate(last);
+        self.count = target;
+        true
+    }
+    ///
+    /// Goes back by one instruction
+    ///
+    pub fn step_back(&mut self, cpu: &mut dyn Processor) -> bool {
+        self.count > 0 && self.rewind_to(cpu, self.count - 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::{i8080, mos6502};
+
+    fn state(cpu: &mos6502::Cpu) -> (u8, u8, u8, u16, Vec<u8>) {
+        let memory = (0x0200..0x0210)
+            .map(|addr| cpu.memory.read_byte(addr))
+            .collect();
+        (cpu.a, cpu.x, cpu.sp, cpu.pc, memory)
+    }
+
+    #[test]
+    ///
+    /// Rewinding restores registers and memory of any recorded instruction
+    /// and the program runs the same way again
+    ///
+    fn rewind_6502() {
+        let mut cpu = mos6502::Cpu::new();
+        let program = vec![
+            0xA2, 0x00, //       LDX #$00
+            0x8A, //             $0602 TXA
+            0x9D, 0x00, 0x02, // STA $0200,X
+            0xE8, //             INX
+            0xE0, 0x10, //       CPX #$10
+            0xD0, 0xF7, //       BNE $0602
+            0xA2, 0x00, //       LDX #$00
+            0xF0, 0xF3, //       BEQ $0602
+        ];
+        cpu.load_program(&program, 0x0600);
+        cpu.memory.write_byte(0x0205, 0x55);
+        let mut history = History::new(16, 4);
+        let mut states = Vec::new();
+        for _ in 0..120 {
+            states.push(state(&cpu));
+            history.step(&mut cpu);
+        }
+        assert_eq!(history.count(), 120);
+        assert!(history.oldest() > 0);
+        assert!(!history.rewind_to(&mut cpu, history.oldest() - 1));
+
+        assert!(history.rewind_to(&mut cpu, 70));
+        assert_eq!(state(&cpu), states[70]);
+        assert!(history.step_back(&mut cpu));
+        assert_eq!(state(&cpu), states[69]);
+        assert_eq!(history.count(), 69);
+        for expected in &states[69..] {
+            assert_eq!(&state(&cpu), expected);
+            history.step(&mut cpu);
+        }
+        assert!(history.rewind_to(&mut cpu, 100));
+        assert_eq!(state(&cpu), states[100]);
+        assert!(!cpu.memory.is_tracking());
+    }
+
+    #[test]
+    ///
+    /// The 8080 registers are restored including the flags
+    ///
+    fn rewind_8080() {
+        let mut cpu = i8080::Cpu::new();
+        // MVI A,55H; ADI 0ABH; MVI B,12H; HLT
+        cpu.load_program(&[0x3E, 0x55, 0xC6, 0xAB, 0x06, 0x12, 0x76], 0x0100);
+        let mut history = History::new(CHECKPOINT_INTERVAL, MAX_CHECKPOINTS);
+        history.step(&mut cpu);
+        let (a, psw) = (cpu.a, cpu.psw.value);
+        history.step(&mut cpu);
+        history.step(&mut cpu);
+        assert_eq!(cpu.b, 0x12);
+        assert!(history.rewind_to(&mut cpu, 1));
+        assert_eq!((cpu.a, cpu.psw.value, cpu.pc), (a, psw, 0x0102));
+        assert!(history.step_back(&mut cpu));
+        assert!(!history.step_back(&mut cpu));
+        assert_eq!(cpu.pc, 0x0100);
+    }
+
+    #[test]
+    ///
+    /// Ports written by OUT are restored from the checkpoint and the journal,
+    /// a jump back to a checkpoint restores its registers
+    ///
+    fn rewind_8080_ports() {
+        let mut cpu = i8080::Cpu::new();
+        // MVI A,11H; OUT 10H; MVI A,22H; OUT 10H; OUT 20H; HLT
+        let program = [
+            0x3E, 0x11, 0xD3, 0x10, 0x3E, 0x22, 0xD3, 0x10, 0xD3, 0x20, 0x76,
+        ];
+        cpu.load_program(&program, 0x0100);
+        cpu.ports[0x20] = 0x99;
+        let mut history = History::new(3, 4);
+        for _ in 0..5 {
+            history.step(&mut cpu);
+        }
+        assert_eq!((cpu.ports[0x10], cpu.ports[0x20]), (0x22, 0x22));
+        assert!(history.rewind_to(&mut cpu, 4));
+        assert_eq!((cpu.ports[0x10], cpu.ports[0x20]), (0x22, 0x99));
+        assert!(history.rewind_to(&mut cpu, 3));
+        assert_eq!((cpu.ports[0x10], cpu.a, cpu.pc), (0x11, 0x22, 0x0106));
+        assert!(history.rewind_to(&mut cpu, 0));
+        assert_eq!(
+            (cpu.ports[0x10], cpu.ports[0x20], cpu.pc),
+            (0x00, 0x99, 0x0100)
+        );
+    }
+}
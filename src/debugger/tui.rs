@@ -0,0 +1,293 @@
+//////////////////////////////////////////////////////////
+/// Full screen debugger built on ratatui/crossterm, like the opcode viewer.
+/// Panes: disassembly around PC, registers and flags, stack and memory.
+///
+/// Keys:
+///   s        step
+///   o        step over (JSR/CALL/RST executed as one step)
+///   u        step back (undo the last instruction)
+///   r        run until breakpoint or halt (any key stops it)
+///   c        run to cursor
+///   b        toggle breakpoint at cursor
+///   :        command line for conditional breakpoints, ignore counts,
+///            watchpoints and rewind (see command.rs), Enter executes, Esc cancels
+///   j/k      move cursor (also Down/Up)
+///   PgDn/PgUp scroll memory view
+///   q/Esc    quit
+//////////////////////////////////////////////////////////
+use std::time::Duration;
+
+use crossterm::{
+    event::{self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyEventKind},
+    execute,
+    terminal::{EnterAlternateScreen, LeaveAlternateScreen, disable_raw_mode, enable_raw_mode},
+};
+use ratatui::{
+    Frame, Terminal,
+    layout::{Constraint, Direction, Layout},
+    prelude::{Backend, CrosstermBackend},
+    style::{Color, Style},
+    text::Line,
+    widgets::{Block, Borders, Paragraph},
+};
+
+use crate::cpu::Processor;
+use crate::debugger::{Debugger, StopReason, command};
+use crate::symbols::SymbolTable;
+
+///
+/// Number of instructions executed between two checks of the keyboard while running
+///
+const RUN_CHUNK: usize = 10_000;
+
+fn code_rows(frame_height: u16) -> usize {
+    frame_height.saturating_sub(2) as usize
+}
+
+pub fn draw(debugger: &mut Debugger, frame: &mut Frame) {
+    let rows = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Min(10),
+            Constraint::Length(10),
+            Constraint::Length(1),
+        ])
+        .split(frame.area());
+    let top = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([Constraint::Min(40), Constraint::Length(92)])
+        .split(rows[0]);
+    let right = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Length(8), Constraint::Min(3)])
+        .split(top[1]);
+
+    // Disassembly
+    let pc = debugger.cpu().pc();
+    let lines: Vec<Line> = debugger
+        .code_lines(code_rows(top[0].height))
+        .into_iter()
+        .map(|(addr, text)| {
+            let marker = match (addr == pc, debugger.breakpoints().contains(addr)) {
+                (true, true) => "*>",
+                (true, false) => " >",
+                (false, true) => "* ",
+                (false, false) => "  ",
+            };
+            let style = if addr == debugger.cursor {
+                Style::default().fg(Color::Black).bg(Color::Yellow)
+            } else if addr == pc {
+                Style::default().fg(Color::Green)
+            } else {
+                Style::default()
+            };
+            Line::styled(format!("{}{}", marker, text), style)
+        })
+        .collect();
+    frame.render_widget(
+        Paragraph::new(lines).block(Block::default().borders(Borders::ALL).title("Code")),
+        top[0],
+    );
+
+    // Registers, same table as print_registers()
+    frame.render_widget(
+        Paragraph::new(debugger.cpu().print_registers())
+            .block(Block::default().borders(Borders::ALL).title("Registers")),
+        right[0],
+    );
+
+    // Stack
+    let stack: Vec<Line> = debugger
+        .stack_lines(code_rows(right[1].height))
+        .into_iter()
+        .map(Line::from)
+        .collect();
+    frame.render_widget(
+        Paragraph::new(stack).block(Block::default().borders(Borders::ALL).title("Stack")),
+        right[1],
+    );
+
+    // Memory
+    let memory: Vec<Line> = debugger
+        .memory_lines(code_rows(rows[1].height))
+        .into_iter()
+        .map(Line::from)
+        .collect();
+    frame.render_widget(
+        Paragraph::new(memory).block(Block::default().borders(Borders::ALL).title("Memory")),
+        rows[1],
+    );
+
+    // Status line
+    let status = match debugger.last_stop {
+        Some(StopReason::Breakpoint(addr)) => format!("Breakpoint at {:04X}", addr),
+        Some(StopReason::Watchpoint { pc, access }) => format!(
+            "Watchpoint: {:?} {:04X} = {:02X} at {:04X}",
+            access.kind, access.addr, access.value, pc
+        ),
+        Some(StopReason::PortWatchpoint { pc, access }) => format!(
+            "Port watchpoint: {:?} {:02X} = {:02X} at {:04X}",
+            access.kind, access.addr, access.value, pc
+        ),
+        Some(StopReason::Halt(addr)) => format!("Halted at {:04X}", addr),
+        Some(StopReason::Target(addr)) => format!("Stopped at cursor {:04X}", addr),
+        Some(StopReason::Limit) => "Instruction limit reached".to_string(),
+        Some(StopReason::Interrupted) => "Interrupted".to_string(),
+        Some(StopReason::Step) | None => String::new(),
+    };
+    let status = match (&debugger.prompt, &debugger.message) {
+        (Some(prompt), _) => format!(":{}", prompt),
+        (None, message) => format!(
+            "s step | o step over | u step back | r run | c run to cursor | b breakpoint | : command | q quit   {}",
+            message.as_deref().unwrap_or(&status)
+        ),
+    };
+    frame.render_widget(
+        Paragraph::new(status).style(Style::default().fg(Color::Cyan)),
+        rows[2],
+    );
+}
+
+///
+/// Key typed on the command line, Enter executes it
+///
+fn prompt_key(debugger: &mut Debugger, code: KeyCode) {
+    let Some(prompt) = &mut debugger.prompt else {
+        return;
+    };
+    match code {
+        KeyCode::Char(c) => prompt.push(c),
+        KeyCode::Backspace => {
+            prompt.pop();
+        }
+        KeyCode::Esc => debugger.prompt = None,
+        KeyCode::Enter => {
+            let line = debugger.prompt.take().unwrap_or_default();
+            debugger.message = Some(match command::execute(debugger, &line) {
+                Ok(message) => message,
+                Err(err) => format!("error: {}", err),
+            });
+        }
+        _ => {}
+    }
+}
+
+///
+/// Keeps executing in chunks until the debugger stops or a key is pressed
+///
+fn run<B: Backend>(
+    debugger: &mut Debugger,
+    terminal: &mut Terminal<B>,
+    to_cursor: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    loop {
+        let stopped = if to_cursor {
+            debugger.run_to_cursor(RUN_CHUNK)
+        } else {
+            debugger.run(RUN_CHUNK, None)
+        };
+        if stopped.is_some() {
+            return Ok(());
+        }
+        if event::poll(Duration::ZERO)? {
+            // The key only stops the execution
+            event::read()?;
+            debugger.interrupt();
+            return Ok(());
+        }
+        terminal.draw(|frame| draw(debugger, frame))?;
+    }
+}
+
+pub fn run_loop<B: Backend>(
+    debugger: &mut Debugger,
+    mut terminal: Terminal<B>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    loop {
+        terminal.draw(|frame| draw(debugger, frame))?;
+        let rows = code_rows(terminal.size()?.height.saturating_sub(11));
+
+        if let Event::Key(key) = event::read()?
+            && key.kind == KeyEventKind::Press
+        {
+            if debugger.prompt.is_some() {
+                prompt_key(debugger, key.code);
+                continue;
+            }
+            debugger.message = None;
+            match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+                KeyCode::Char('s') => {
+                    debugger.step();
+                }
+                KeyCode::Char('o') => {
+                    debugger.step_over();
+                }
+                KeyCode::Char('u') => {
+                    debugger.step_back();
+                }
+                KeyCode::Char('r') => run(debugger, &mut terminal, false)?,
+                KeyCode::Char('c') => run(debugger, &mut terminal, true)?,
+                KeyCode::Char('b') => debugger.toggle_breakpoint(debugger.cursor),
+                KeyCode::Char(':') => debugger.prompt = Some(String::new()),
+                KeyCode::Char('j') | KeyCode::Down => debugger.move_cursor(true, rows),
+                KeyCode::Char('k') | KeyCode::Up => debugger.move_cursor(false, rows),
+                KeyCode::PageDown => debugger.memory_view = debugger.memory_view.wrapping_add(0x80),
+                KeyCode::PageUp => debugger.memory_view = debugger.memory_view.wrapping_sub(0x80),
+                _ => {}
+            }
+        }
+    }
+}
+
+///
+/// Debugger screen until q, commands take the names of symbols as addresses
+///
+pub fn debug(
+    cpu: &mut dyn Processor,
+    symbols: SymbolTable,
+) -> Result<(), Box<dyn std::error::Error>> {
+    enable_raw_mode()?;
+    let mut stdout = std::io::stdout();
+    execute!(stdout, EnterAlternateScreen, EnableMouseCapture)?;
+    let backend = CrosstermBackend::new(stdout);
+    let terminal = Terminal::new(backend)?;
+    let mut debugger = Debugger::new(cpu);
+    debugger.enable_rewind();
+    if !symbols.is_empty() {
+        debugger.message = Some(format!("{} symbols loaded", symbols.len()));
+    }
+    debugger.set_symbols(symbols);
+    let app_result = run_loop(&mut debugger, terminal);
+    execute!(std::io::stdout(), LeaveAlternateScreen, DisableMouseCapture)?;
+    disable_raw_mode()?;
+    ratatui::restore();
+    app_result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::i8080;
+    use ratatui::backend::TestBackend;
+
+    #[test]
+    ///
+    /// Draws the 8080 debugger into a test backend and checks the panes
+    ///
+    fn draw_panes() {
+        let mut cpu = i8080::Cpu::new();
+        cpu.load_program(&[0x3E, 0x55, 0xCE, 0x74, 0x76], 0x0600);
+        cpu.sp = 0x2000;
+        let mut debugger = Debugger::new(&mut cpu);
+        debugger.step();
+        let mut terminal = Terminal::new(TestBackend::new(140, 40)).unwrap();
+        terminal.draw(|frame| draw(&mut debugger, frame)).unwrap();
+        let buffer = terminal.backend().buffer();
+        let text: String = buffer.content().iter().map(|cell| cell.symbol()).collect();
+        assert!(text.contains(" >0602  CE 74"));
+        assert!(text.contains("| 55H |"));
+        assert!(text.contains("2000  00"));
+        assert!(text.contains("00000600: 3E 55 CE 74 76"));
+    }
+}
@@ -1,8 +1,13 @@
 use serde::Deserialize;
 use std::collections::HashMap;
+use std::sync::OnceLock;
 
 use crate::disassembler::i8080_opcodes;
+use crate::disassembler::mos6502::leading_number;
+use crate::disassembler::tracing::{self, CodeMap, Item, Successors};
+use crate::disassembler::{self, Instruction, Line, Reader};
 use crate::memory::Memory;
+use crate::symbols::SymbolTable;
 
 #[derive(Debug, Deserialize)]
 pub struct OpcodeDef {
@@ -11,7 +16,40 @@ pub struct OpcodeDef {
     mode: String,
     bytes: u8,
     //    cycles: String,
+    #[serde(default)]
+    states: String,
 }
+impl OpcodeDef {
+    pub fn mnemonic(&self) -> &str {
+        &self.mnemonic
+    }
+    pub fn bytes(&self) -> u8 {
+        self.bytes
+    }
+    ///
+    /// Number of states (clock periods); for conditional instructions
+    /// it is the smaller number, when the condition is false
+    ///
+    pub fn cycles(&self) -> u8 {
+        leading_number(&self.states)
+    }
+    ///
+    /// Number of states of conditional instructions when the condition is
+    /// true, cycles() for the other instructions
+    ///
+    pub fn max_cycles(&self) -> u8 {
+        match self.states.split_once('/') {
+            Some((_, taken)) => leading_number(taken),
+            None => self.cycles(),
+        }
+    }
+}
+
+///
+/// Bytes per DB directive in source output
+///
+const SOURCE_BYTES_PER_LINE: usize = 8;
+
 pub fn load_opcodes_table() -> HashMap<u8, OpcodeDef> {
     let defs: Vec<OpcodeDef> =
         serde_json::from_str(i8080_opcodes::OPCODES).expect("Failed to parse JSON");
@@ -20,100 +58,415 @@ pub fn load_opcodes_table() -> HashMap<u8, OpcodeDef> {
         .collect()
 }
 
+///
+/// Opcode table parsed only once and shared by all callers
+///
+pub fn opcodes() -> &'static HashMap<u8, OpcodeDef> {
+    static OPCODES: OnceLock<HashMap<u8, OpcodeDef>> = OnceLock::new();
+    OPCODES.get_or_init(load_opcodes_table)
+}
+
 pub fn disassemble(
     memory: &Memory,
     start: u16,
-    end: u16,
+    end: u32,
     opcodes: &HashMap<u8, OpcodeDef>,
-) -> Vec<String> {
-    let mut output = Vec::new();
-    let mut pc = start;
+) -> Vec<Instruction> {
+    let reader = Reader::new(memory);
+    let symbols = SymbolTable::new();
+    disassembler::decode_range(start, end, |pc| decode(&reader, pc, opcodes, &symbols))
+}
+
+///
+/// Target of jumps, calls and RST, conditional ones included
+///
+pub fn branch_target(def: &OpcodeDef, args: &[u8]) -> Option<u16> {
+    if let Some(n) = def.mnemonic.strip_prefix("RST ") {
+        return n.parse::<u16>().ok().map(|n| n * 8);
+    }
+    let jump = def.mnemonic.starts_with('J') || def.mnemonic.starts_with('C');
+    (def.mode == "immediate16" && jump).then(|| u16::from_le_bytes([args[0], args[1]]))
+}
 
+///
+/// Copy of symbols with auto-generated labels (L0634) for the jump and call
+/// targets inside start..end that have no name. Pass the result to
+/// disassemble_with_symbols().
+///
+pub fn auto_labels(
+    memory: &Memory,
+    start: u16,
+    end: u32,
+    opcodes: &HashMap<u8, OpcodeDef>,
+    symbols: &SymbolTable,
+) -> SymbolTable {
+    let mut labels = symbols.clone();
+    let reader = Reader::new(memory);
+    let mut pc = start as u32;
     while pc < end {
-        let memory_data = memory.get_data();
-        let opcode_byte = memory_data[pc as usize];
-        let mut mnemonic = "";
-        if let Some(def) = opcodes.get(&opcode_byte) {
-            let args = &memory_data[(pc + 1) as usize..];
-            let operand_str = match def.mode.as_str() {
-                "immediate8" | "direct port" => {
-                    mnemonic = &def.mnemonic;
-                    mnemonic = mnemonic.trim_end_matches("data");
-                    mnemonic = mnemonic.trim_end_matches("port");
-                    if args[0] > 0x9F {
-                        format!("0{:02X}H", args[0])
-                    } else {
-                        format!("{:02X}H", args[0])
-                    }
-                }
-                "immediate16" | "direct" => {
-                    mnemonic = &def.mnemonic;
-                    mnemonic = mnemonic.trim_end_matches("address").trim();
-                    let data = u16::from_le_bytes([args[0], args[1]]);
-                    if args[1] > 0x9F {
-                        format!(" 0{:04X}H", data)
-                    } else {
-                        format!(" {:04X}H", data)
-                    }
-                }
-                "register" | "none" => {
-                    mnemonic = &def.mnemonic;
-                    "".to_string()
-                }
-                "register indirect" => {
-                    mnemonic = &def.mnemonic;
-                    if mnemonic.contains(",data") {
-                        mnemonic = mnemonic.trim_end_matches("data");
-                        if args[0] > 0x9F {
-                            format!("0{:02X}H", args[0])
-                        } else {
-                            format!("{:02X}H", args[0])
-                        }
-                    } else {
-                        "".to_string()
-                    }
-                }
-                _ => format!("?? {}", def.mode),
-            };
-            let operand_bytes = match def.mode.as_str() {
-                "immediate8" | "direct port" => {
-                    format!("{:02X}", args[0])
-                }
-                "register indirect" => {
-                    mnemonic = &def.mnemonic.trim_end_matches("data");
-                    if mnemonic.ends_with(",") {
-                        format!("{:02X}", args[0])
-                    } else {
-                        "".to_string()
-                    }
-                }
-                "immediate16" | "direct" => {
-                    format!("{:02X} {:02X}", args[0], args[1])
-                }
-                _ => "".to_string(),
-            };
-            output.push(
-                format!(
-                    "{:04X}  {:02X} {:<8} {}{}",
-                    pc, opcode_byte, operand_bytes, mnemonic, operand_str
-                )
-                .trim_end()
-                .to_string()
-                .replace(", ", ","),
-            );
-            pc += def.bytes as u16;
-        } else {
-            output.push(
-                format!(
-                    "{:04X}  {:02X}          !byte {:02X}",
-                    pc, opcode_byte, opcode_byte
-                )
-                .trim_end()
-                .to_string()
-                .replace(", ", ","),
-            );
+        let Some(def) = opcodes.get(&reader.byte(pc as u16)) else {
             pc += 1;
+            continue;
+        };
+        if let Some(target) = branch_target(def, &arguments(&reader, pc as u16))
+            && (start as u32..end).contains(&(target as u32))
+            && labels.name_of(target).is_none()
+        {
+            labels.insert(&format!("L{:04X}", target), target);
         }
+        pc += def.bytes as u32;
+    }
+    labels
+}
+
+///
+/// Same as disassemble(), but every address that has a symbol gets a label line
+/// and 16 bit operands are replaced by symbol names
+///
+pub fn disassemble_with_symbols(
+    memory: &Memory,
+    start: u16,
+    end: u32,
+    opcodes: &HashMap<u8, OpcodeDef>,
+    symbols: &SymbolTable,
+) -> Vec<Line> {
+    let reader = Reader::new(memory);
+    let mut output = Vec::new();
+    for instruction in
+        disassembler::decode_range(start, end, |pc| decode(&reader, pc, opcodes, symbols))
+    {
+        for name in symbols.names_of(instruction.address) {
+            output.push(Line::Label(name.clone()));
+        }
+        output.push(Line::Instruction(instruction));
     }
     output
 }
+
+///
+/// The two bytes following the opcode
+///
+fn arguments(reader: &Reader, pc: u16) -> [u8; 2] {
+    [
+        reader.byte(pc.wrapping_add(1)),
+        reader.byte(pc.wrapping_add(2)),
+    ]
+}
+
+///
+/// Decodes the instruction at pc, unknown opcodes are a single !byte.
+/// The placeholders of the opcode table (data, port, address) are replaced
+/// by the values, addresses by symbol names.
+///
+pub fn decode(
+    reader: &Reader,
+    pc: u16,
+    opcodes: &HashMap<u8, OpcodeDef>,
+    symbols: &SymbolTable,
+) -> Instruction {
+    let opcode_byte = reader.byte(pc);
+    let Some(def) = opcodes.get(&opcode_byte) else {
+        return Instruction {
+            address: pc,
+            bytes: vec![opcode_byte],
+            mnemonic: "!byte".to_string(),
+            operand: format!("{:02X}", opcode_byte),
+            target: None,
+            comment: None,
+        };
+    };
+    let args = arguments(reader, pc);
+    let (mnemonic, template) = def.mnemonic.split_once(' ').unwrap_or((&def.mnemonic, ""));
+    let word = u16::from_le_bytes(args);
+    let operand = match def.bytes {
+        2 => template
+            .replace("data", &data_byte(args[0]))
+            .replace("port", &data_byte(args[0])),
+        3 => template.replace(
+            "address",
+            &symbols
+                .name_of(word)
+                .map_or_else(|| data_word(word), str::to_string),
+        ),
+        _ => template.to_string(),
+    };
+    Instruction {
+        address: pc,
+        bytes: reader.bytes(pc, def.bytes as u16),
+        mnemonic: mnemonic.to_string(),
+        operand,
+        target: branch_target(def, &args),
+        comment: None,
+    }
+}
+
+///
+/// Successors for the tracing disassembler: JMP, RET, PCHL and HLT end the
+/// path, the target of PCHL cannot be followed
+///
+fn successors(def: &OpcodeDef, args: &[u8]) -> Successors {
+    Successors {
+        length: def.bytes as u16,
+        target: branch_target(def, args),
+        falls_through: !matches!(
+            def.mnemonic.as_str(),
+            "JMP address" | "RET" | "PCHL" | "HLT"
+        ),
+    }
+}
+
+///
+/// Reset and RST 1..7 addresses, entry points of a ROM at 0000H
+///
+pub fn vectors() -> Vec<u16> {
+    (0..8).map(|n| n * 8).collect()
+}
+
+fn data_byte(value: u8) -> String {
+    if value > 0x9F {
+        format!("0{:02X}H", value)
+    } else {
+        format!("{:02X}H", value)
+    }
+}
+
+///
+/// Code/data separating disassembly of start..end, see tracing.rs. Bytes
+/// not reached from the entry points are written as DB directives, jump
+/// and call targets without a name get labels L<addr>.
+///
+pub fn disassemble_traced(
+    memory: &Memory,
+    start: u16,
+    end: u32,
+    entries: &[u16],
+    opcodes: &HashMap<u8, OpcodeDef>,
+    symbols: &SymbolTable,
+) -> Vec<Line> {
+    let reader = Reader::new(memory);
+    let map = code_map(memory, start, end, Some(entries), opcodes);
+    let labels = map.labels(symbols);
+    tracing::lines(
+        start,
+        end,
+        &map,
+        &labels,
+        |addr| reader.byte(addr),
+        |pc| decode(&reader, pc, opcodes, &labels),
+        db_directive,
+    )
+}
+
+fn db_directive(bytes: &[u8]) -> String {
+    let values: Vec<String> = bytes.iter().map(|&b| data_byte(b)).collect();
+    format!("DB {}", values.join(","))
+}
+
+///
+/// Instructions of start..end, traced from the entry points or, with None,
+/// found by a linear sweep
+///
+pub fn code_map(
+    memory: &Memory,
+    start: u16,
+    end: u32,
+    entries: Option<&[u16]>,
+    opcodes: &HashMap<u8, OpcodeDef>,
+) -> CodeMap {
+    let reader = Reader::new(memory);
+    let decode = |pc: u16| {
+        let def = opcodes.get(&reader.byte(pc))?;
+        Some(successors(def, &arguments(&reader, pc)))
+    };
+    match entries {
+        Some(entries) => tracing::trace(start, end, entries, decode),
+        None => tracing::sweep(start, end, decode),
+    }
+}
+
+fn data_word(value: u16) -> String {
+    if value > 0x9FFF {
+        format!("0{:04X}H", value)
+    } else {
+        format!("{:04X}H", value)
+    }
+}
+
+///
+/// Source of start..end in Intel syntax (asm80) which assembles to the same
+/// bytes: EQU for symbols outside the code, ORG, labels, instructions, DB
+/// directives for data and END.
+///
+/// ```
+/// let map = code_map(&memory, 0x0100, end, Some(&[0x0100]), opcodes());
+/// std::fs::write("program.asm", to_source(&memory, 0x0100, end, &map, opcodes(), &symbols))?;
+/// ```
+///
+pub fn to_source(
+    memory: &Memory,
+    start: u16,
+    end: u32,
+    map: &CodeMap,
+    opcodes: &HashMap<u8, OpcodeDef>,
+    symbols: &SymbolTable,
+) -> String {
+    let reader = Reader::new(memory);
+    let labels = map.labels(symbols);
+    let read = |addr: u16| reader.byte(addr);
+    let items = tracing::items(start, end, map, &labels, read, SOURCE_BYTES_PER_LINE);
+    let mut source = String::new();
+    for (addr, name) in tracing::unplaced(&items, &labels) {
+        source += &format!("{}\tEQU\t{}\n", name, data_word(addr));
+    }
+    source += &format!("\n\tORG\t{}\n\n", data_word(start));
+    for item in &items {
+        let line = match item {
+            Item::Label(name) => format!("{}:", name),
+            Item::Instruction(pc, _) => {
+                format!("\t{}", decode(&reader, *pc, opcodes, &labels).text())
+            }
+            Item::Data(_, bytes) => format!("\t{}", db_directive(bytes)),
+        };
+        source += &line;
+        source += "\n";
+    }
+    source += "\n\tEND\n";
+    source
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    ///
+    /// Jump, call and memory operands are replaced by names
+    ///
+    fn symbolic_disassembly() {
+        let mut memory = Memory::new();
+        let program = [
+            0x21, 0x00, 0x02, // LXI H,0200H
+            0x3A, 0x80, 0x00, // LDA 0080H
+            0xC2, 0x00, 0x01, // JNZ 0100H
+            0xCD, 0x05, 0x00, // CALL 0005H
+            0xC3, 0x03, 0x01, // JMP 0103H
+        ];
+        memory.load_program(&program, 0x0100);
+        let mut symbols = SymbolTable::new();
+        symbols.insert("BDOS", 0x0005);
+        symbols.insert("buffer", 0x0080);
+        let end = 0x0100 + program.len() as u32;
+        let labels = auto_labels(&memory, 0x0100, end, opcodes(), &symbols);
+        let lines: Vec<String> = disassemble_with_symbols(&memory, 0x0100, end, opcodes(), &labels)
+            .iter()
+            .map(Line::to_string)
+            .collect();
+        assert_eq!(
+            lines,
+            [
+                "L0100:",
+                "0100  21 00 02    LXI H,0200H",
+                "L0103:",
+                "0103  3A 80 00    LDA buffer",
+                "0106  C2 00 01    JNZ L0100",
+                "0109  CD 05 00    CALL BDOS",
+                "010C  C3 03 01    JMP L0103",
+            ]
+        );
+    }
+
+    #[test]
+    ///
+    /// RST and conditional calls are followed, bytes after RET are data
+    ///
+    fn traced_disassembly() {
+        let mut memory = Memory::new();
+        let program = [
+            0xCF, //             RST 1
+            0xC3, 0x00, 0x00, // JMP 0000H
+            0x48, 0x49, 0xFF, // data
+            0x00, //             padding
+            0x3C, //             0008H INR A
+            0xC9, //             RET
+        ];
+        memory.load_program(&program, 0x0000);
+        let end = program.len() as u32;
+        let records = disassemble_traced(&memory, 0, end, &[0], opcodes(), &SymbolTable::new());
+        assert!(matches!(
+            &records[3],
+            Line::Data { address: 0x0004, bytes, .. } if bytes.len() == 4
+        ));
+        let lines: Vec<String> = records.iter().map(Line::to_string).collect();
+        assert_eq!(
+            lines,
+            [
+                "L0000:",
+                "0000  CF          RST 1",
+                "0001  C3 00 00    JMP L0000",
+                "0004  48 49 FF 00 DB 48H,49H,0FFH,00H",
+                "L0008:",
+                "0008  3C          INR A",
+                "0009  C9          RET",
+            ]
+        );
+    }
+
+    #[test]
+    ///
+    /// Intel source with EQU, ORG, labels, DB and END, linear sweep
+    ///
+    fn intel_source() {
+        let mut memory = Memory::new();
+        let program = [
+            0x3E, 0xAB, //       MVI A,0ABH
+            0xCD, 0x05, 0x00, // CALL 0005H
+            0xC2, 0x00, 0xF0, // JNZ 0F000H
+            0xC9, //             RET
+            0xCD, 0x05, //       CALL cut off by the end
+        ];
+        memory.load_program(&program, 0xF000);
+        let mut symbols = SymbolTable::new();
+        symbols.insert("BDOS", 0x0005);
+        let end = 0xF000 + program.len() as u32;
+        let map = code_map(&memory, 0xF000, end, None, opcodes());
+        let source = to_source(&memory, 0xF000, end, &map, opcodes(), &symbols);
+        assert_eq!(
+            source,
+            "BDOS\tEQU\t0005H\n\
+             \n\tORG\t0F000H\n\n\
+             LF000:\n\
+             \tMVI A,0ABH\n\
+             \tCALL BDOS\n\
+             \tJNZ LF000\n\
+             \tRET\n\
+             \tDB 0CDH,05H\n\
+             \n\tEND\n"
+        );
+    }
+
+    #[test]
+    ///
+    /// Records split mnemonic and operand, operands wrap at the top of memory
+    ///
+    fn instruction_records() {
+        let mut memory = Memory::new();
+        memory.load_program(&[0x3E, 0xAB, 0xDB, 0x10, 0x36, 0x05], 0x0100);
+        memory.write_byte(0xFFFE, 0xC3); // JMP 0A000H
+        memory.write_byte(0xFFFF, 0x00);
+        memory.write_byte(0x0000, 0xA0);
+        let instructions = disassemble(&memory, 0x0100, 0x0106, opcodes());
+        let texts: Vec<(&str, &str)> = instructions
+            .iter()
+            .map(|i| (i.mnemonic.as_str(), i.operand.as_str()))
+            .collect();
+        assert_eq!(texts, [("MVI", "A,0ABH"), ("IN", "10H"), ("MVI", "M,05H")]);
+        let jump = decode(
+            &Reader::new(&memory),
+            0xFFFE,
+            opcodes(),
+            &SymbolTable::new(),
+        );
+        assert_eq!(jump.to_string(), "FFFE  C3 00 A0    JMP 0A000H");
+        assert_eq!(jump.target, Some(0xA000));
+    }
+}
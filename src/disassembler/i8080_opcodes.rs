@@ -1704,6 +1704,24 @@ pub static OPCODES: &str = r#"
     "states": "4",
     "description": "The content of register A is inclusive-OR'd with the content of the accumulator. \nThe result is placed in the accumulator. The CY and AC flags are cleared. \n[(A) <- (A) OR (A)]\n\nZ S P CY AC\nx x x 0  0"
   },
+  {
+    "opcode": "F6",
+    "mnemonic": "ORI data",
+    "mode": "immediate8",
+    "bytes": 2,
+    "cycles": "2",
+    "states": "7",
+    "description": "The content of the second byte of the instruction is inclusive-OR'd with \nthe content of the accumulator. The result is placed in the accumulator. \nThe CY and AC flags are cleared.\n[(A) <- (A) OR data]\n\nZ S P CY AC\nx x x 0  0"
+  },
+  {
+    "opcode": "D3",
+    "mnemonic": "OUT port",
+    "mode": "direct port",
+    "bytes": 2,
+    "cycles": "3",
+    "states": "10",
+    "description": "The content of register A is placed on the eight bit bi-directional \ndata bus for transmission to the specified port.\n\nN Z S P CY AC\n- - - - -  -"
+  },
   {
     "opcode": "E9",
     "mnemonic": "PCHL",
@@ -2172,6 +2190,15 @@ pub static OPCODES: &str = r#"
     "states": "7",
     "description": "The content of register A is moved to the memory location whose \naddress is in the register pair DE.\n[((D)(E)) <- (A)]\n\nZ S P CY AC\n- - - -  -"
   },
+  {
+    "opcode": "37",
+    "mnemonic": "STC",
+    "mode": "register",
+    "bytes": 1,
+    "cycles": "1",
+    "states": "4",
+    "description": "The CY flag is set to 1. No other flags are affected. [(CY) <- 1] \n\nN Z S P CY AC\n- - - - 1  -"
+  },
   {
     "opcode": "F9",
     "mnemonic": "SPHL",
@@ -4,10 +4,183 @@ pub mod i8080_opcodes_const;
 pub mod mos6502;
 pub mod mos6502_opcodes;
 pub mod opcode_viewer;
+pub mod tracing;
 use crate::disassembler::opcode_viewer::OpcodeViewer;
+use crate::memory::Memory;
 use ratatui::Frame;
+use std::fmt;
 
 pub trait DrawOpcode<T> {
     fn draw(&self, viewer: &OpcodeViewer<T>, frame: &mut Frame);
     fn opcodes(&self) -> &Vec<T>;
 }
+
+///
+/// Borrowed view of the memory for the disassemblers. Addresses wrap at the
+/// top of memory, an instruction at $FFFF takes its operand from $0000.
+///
+#[derive(Clone, Copy)]
+pub struct Reader<'a> {
+    data: &'a [u8; 0x10000],
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(memory: &'a Memory) -> Self {
+        Self {
+            data: memory.data(),
+        }
+    }
+    pub fn byte(&self, addr: u16) -> u8 {
+        self.data[addr as usize]
+    }
+    ///
+    /// Little endian word, the high byte of $FFFF is at $0000
+    ///
+    pub fn word(&self, addr: u16) -> u16 {
+        u16::from_le_bytes([self.byte(addr), self.byte(addr.wrapping_add(1))])
+    }
+    pub fn bytes(&self, addr: u16, length: u16) -> Vec<u8> {
+        (0..length)
+            .map(|i| self.byte(addr.wrapping_add(i)))
+            .collect()
+    }
+}
+
+///
+/// One disassembled instruction. Unknown opcodes are a single byte with
+/// the mnemonic !byte.
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Instruction {
+    pub address: u16,
+    pub bytes: Vec<u8>,
+    pub mnemonic: String,
+    ///
+    /// Operand in the assembler syntax of the CPU, symbol names included
+    ///
+    pub operand: String,
+    ///
+    /// Target of jumps, calls and branches
+    ///
+    pub target: Option<u16>,
+    ///
+    /// Source line or other remark shown after the instruction
+    ///
+    pub comment: Option<String>,
+}
+
+impl Instruction {
+    pub fn length(&self) -> u16 {
+        self.bytes.len() as u16
+    }
+    ///
+    /// Address of the following instruction, wraps at the top of memory
+    ///
+    pub fn next(&self) -> u16 {
+        self.address.wrapping_add(self.length())
+    }
+    ///
+    /// Mnemonic and operand
+    ///
+    pub fn text(&self) -> String {
+        if self.operand.is_empty() {
+            self.mnemonic.clone()
+        } else {
+            format!("{} {}", self.mnemonic, self.operand)
+        }
+    }
+}
+
+///
+/// Listing line: address, bytes, instruction and comment
+///
+/// 0602  8D 00 02    STA $0200
+///
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let bytes: Vec<String> = self.bytes.iter().map(|b| format!("{:02X}", b)).collect();
+        let line = format!(
+            "{:04X}  {:<11} {}",
+            self.address,
+            bytes.join(" "),
+            self.text()
+        );
+        match &self.comment {
+            Some(comment) => write!(f, "{:<32}; {}", line, comment),
+            None => write!(f, "{}", line),
+        }
+    }
+}
+
+///
+/// Line of a disassembly listing, formatted by the caller or with Display
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Line {
+    Label(String),
+    Instruction(Instruction),
+    ///
+    /// Bytes not reached as code and their directive (DB, !byte)
+    ///
+    Data {
+        address: u16,
+        bytes: Vec<u8>,
+        directive: String,
+    },
+}
+
+///
+/// L0100:
+/// 0100  21 00 02    LXI H,0200H
+/// 0103  48 49 FF 00 DB 48H,49H,0FFH,00H
+///
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Line::Label(name) => write!(f, "{}:", name),
+            Line::Instruction(instruction) => write!(f, "{}", instruction),
+            Line::Data {
+                address,
+                bytes,
+                directive,
+            } => {
+                let hex: Vec<String> = bytes.iter().map(|b| format!("{:02X}", b)).collect();
+                write!(f, "{:04X}  {:<11} {}", address, hex.join(" "), directive)
+            }
+        }
+    }
+}
+
+///
+/// Decodes instructions from start up to (not including) end, 0x10000 for
+/// the top of memory. The address does not wrap, the last instruction may
+/// read its operand beyond end.
+///
+pub fn decode_range(start: u16, end: u32, decode: impl Fn(u16) -> Instruction) -> Vec<Instruction> {
+    let mut instructions = Vec::new();
+    let mut pc = start as u32;
+    while pc < end {
+        let instruction = decode(pc as u16);
+        pc += instruction.length().max(1) as u32;
+        instructions.push(instruction);
+    }
+    instructions
+}
+
+///
+/// Decodes count instructions from start, wrapping at the top of memory
+///
+pub fn decode_count(
+    start: u16,
+    count: usize,
+    decode: impl Fn(u16) -> Instruction,
+) -> Vec<Instruction> {
+    let mut instructions = Vec::with_capacity(count);
+    let mut pc = start;
+    for _ in 0..count {
+        let instruction = decode(pc);
+        pc = instruction.next();
+        instructions.push(instruction);
+    }
+    instructions
+}
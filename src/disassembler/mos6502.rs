@@ -36,9 +36,13 @@
 /////////////////////////////
 use serde::Deserialize;
 use std::collections::HashMap;
+use std::sync::OnceLock;
 
 use crate::disassembler::mos6502_opcodes;
+use crate::disassembler::tracing::{self, CodeMap, Item, Successors};
+use crate::disassembler::{self, Instruction, Line, Reader};
 use crate::memory::Memory;
+use crate::symbols::SymbolTable;
 
 #[derive(Debug, Deserialize)]
 pub struct OpcodeDef {
@@ -46,6 +50,41 @@ pub struct OpcodeDef {
     mnemonic: String,
     mode: String,
     bytes: u8,
+    cycles: String,
+}
+
+impl OpcodeDef {
+    pub fn mnemonic(&self) -> &str {
+        &self.mnemonic
+    }
+    pub fn mode(&self) -> &str {
+        &self.mode
+    }
+    pub fn bytes(&self) -> u8 {
+        self.bytes
+    }
+    ///
+    /// Base number of cycles, without the extra cycles of page crossing
+    /// and taken branches
+    ///
+    pub fn cycles(&self) -> u8 {
+        leading_number(&self.cycles)
+    }
+}
+
+///
+/// Bytes per !byte directive in source output
+///
+const SOURCE_BYTES_PER_LINE: usize = 8;
+
+///
+/// Leading number of table entries like "5/11" (not taken/taken)
+///
+pub(crate) fn leading_number(text: &str) -> u8 {
+    let digits = text
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(text.len());
+    text[..digits].parse().unwrap_or(0)
 }
 
 pub fn load_opcodes_table() -> HashMap<u8, OpcodeDef> {
@@ -56,67 +95,470 @@ pub fn load_opcodes_table() -> HashMap<u8, OpcodeDef> {
         .collect()
 }
 
+///
+/// Opcode table parsed only once and shared by all callers
+///
+pub fn opcodes() -> &'static HashMap<u8, OpcodeDef> {
+    static OPCODES: OnceLock<HashMap<u8, OpcodeDef>> = OnceLock::new();
+    OPCODES.get_or_init(load_opcodes_table)
+}
+
 pub fn disassemble(
     memory: &Memory,
     start: u16,
-    end: u16,
+    end: u32,
     opcodes: &HashMap<u8, OpcodeDef>,
-) -> Vec<String> {
-    let mut output = Vec::new();
-    let mut pc = start;
+) -> Vec<Instruction> {
+    let reader = Reader::new(memory);
+    let symbols = SymbolTable::new();
+    disassembler::decode_range(start, end, |pc| decode(&reader, pc, opcodes, &symbols))
+}
+
+///
+/// Address operand, the symbol name if there is one
+///
+fn address(addr: u16, digits: usize, symbols: &SymbolTable) -> String {
+    match symbols.name_of(addr) {
+        Some(name) => name.to_string(),
+        None => format!("${:01$X}", addr, digits),
+    }
+}
+
+fn operand(def: &OpcodeDef, pc: u16, args: &[u8], symbols: &SymbolTable) -> String {
+    let zeropage = || address(args[0] as u16, 2, symbols);
+    let absolute = || address(u16::from_le_bytes([args[0], args[1]]), 4, symbols);
+    match def.mode.as_str() {
+        "accumulator" => "A".to_string(),
+        "immediate" => format!("#${:02X}", args[0]),
+        "zeropage" => zeropage(),
+        "zeropage,X" => format!("{},X", zeropage()),
+        "zeropage,Y" => format!("{},Y", zeropage()),
+        "absolute" => absolute(),
+        "absolute,X" => format!("{},X", absolute()),
+        "absolute,Y" => format!("{},Y", absolute()),
+        "indirect" => format!("({})", absolute()),
+        "relative" => address(relative_target(pc, args[0]), 4, symbols),
+        "implied" => "".to_string(),
+        "(indirect,X)" => format!("({},X)", zeropage()),
+        "(indirect),Y" => format!("({}),Y", zeropage()),
+        _ => format!("?? {}", def.mode),
+    }
+}
+
+fn relative_target(pc: u16, offset: u8) -> u16 {
+    pc.wrapping_add(2).wrapping_add(offset as i8 as u16)
+}
 
+///
+/// Target of branch, JMP and JSR instructions (not of JMP indirect)
+///
+pub fn branch_target(def: &OpcodeDef, pc: u16, args: &[u8]) -> Option<u16> {
+    match (def.mode.as_str(), &def.mnemonic[..3]) {
+        ("relative", _) => Some(relative_target(pc, args[0])),
+        ("absolute", "JMP" | "JSR") => Some(u16::from_le_bytes([args[0], args[1]])),
+        _ => None,
+    }
+}
+
+///
+/// Copy of symbols with auto-generated labels (L0634) for the branch targets
+/// inside start..end that have no name. Pass the result to
+/// disassemble_with_symbols().
+///
+pub fn auto_labels(
+    memory: &Memory,
+    start: u16,
+    end: u32,
+    opcodes: &HashMap<u8, OpcodeDef>,
+    symbols: &SymbolTable,
+) -> SymbolTable {
+    let mut labels = symbols.clone();
+    let reader = Reader::new(memory);
+    let mut pc = start as u32;
     while pc < end {
-        let memory_data = memory.get_data();
-        let opcode_byte = memory_data[pc as usize];
-        if let Some(def) = opcodes.get(&opcode_byte) {
-            let args = &memory_data[(pc + 1) as usize..];
-            let operand_str = match def.mode.as_str() {
-                "accumulator" => "A".to_string(),
-                "immediate" => format!("#${:02X}", args[0]),
-                "zeropage" => format!("${:02X}", args[0]),
-                "zeropage,X" => format!("${:02X},X", args[0]),
-                "zeropage,Y" => format!("${:02X},Y", args[0]),
-                "absolute" => format!("${:04X}", u16::from_le_bytes([args[0], args[1]])),
-                "absolute,X" => format!("${:04X},X", u16::from_le_bytes([args[0], args[1]])),
-                "absolute,Y" => format!("${:04X},Y", u16::from_le_bytes([args[0], args[1]])),
-                "indirect" => format!("(${:04X})", u16::from_le_bytes([args[0], args[1]])),
-                "relative" => {
-                    let offset = args[0] as i8;
-                    let target = (pc as i16 + 2 + offset as i16) as u16;
-                    format!("${:04X}", target)
-                }
-                "implied" => "".to_string(),
-                "(indirect,X)" => format!("(${:02X},X)", args[0]),
-                "(indirect),Y" => format!("(${:02X}),Y", args[0]),
-                _ => format!("?? {}", def.mode),
-            };
-            let operand_bytes = match def.mode.as_str() {
-                "immediate" | "zeropage" | "zeropage,X" | "zeropage,Y" | "relative"
-                | "(indirect,X)" | "(indirect),Y" => {
-                    format!("{:02X}", args[0])
-                }
-                "absolute" | "absolute,X" | "absolute,Y" => {
-                    format!("{:02X} {:02X}", args[0], args[1])
-                }
-                "implied" => "".to_string(),
-                _ => "".to_string(),
-            };
-            output.push(format!(
-                "{:04X}  {:02X} {:<8} {} {}",
-                pc,
-                opcode_byte,
-                operand_bytes,
-                &def.mnemonic[..3],
-                operand_str
-            ));
-            pc += def.bytes as u16;
-        } else {
-            output.push(format!(
-                "{:04X}  {:02X}          !byte {:02X}",
-                pc, opcode_byte, opcode_byte
-            ));
+        let Some(def) = opcodes.get(&reader.byte(pc as u16)) else {
             pc += 1;
+            continue;
+        };
+        if let Some(target) = branch_target(def, pc as u16, &arguments(&reader, pc as u16))
+            && (start as u32..end).contains(&(target as u32))
+            && labels.name_of(target).is_none()
+        {
+            labels.insert(&format!("L{:04X}", target), target);
         }
+        pc += def.bytes as u32;
+    }
+    labels
+}
+
+///
+/// Same as disassemble(), but every address that has a symbol gets a label line,
+/// address operands are replaced by symbol names and instructions with known
+/// source line get it as a comment
+///
+pub fn disassemble_with_symbols(
+    memory: &Memory,
+    start: u16,
+    end: u32,
+    opcodes: &HashMap<u8, OpcodeDef>,
+    symbols: &SymbolTable,
+) -> Vec<Line> {
+    let reader = Reader::new(memory);
+    let mut output = Vec::new();
+    for instruction in
+        disassembler::decode_range(start, end, |pc| decode(&reader, pc, opcodes, symbols))
+    {
+        for name in symbols.names_of(instruction.address) {
+            output.push(Line::Label(name.clone()));
+        }
+        output.push(Line::Instruction(instruction));
     }
     output
 }
+
+///
+/// The two bytes following the opcode
+///
+fn arguments(reader: &Reader, pc: u16) -> [u8; 2] {
+    [
+        reader.byte(pc.wrapping_add(1)),
+        reader.byte(pc.wrapping_add(2)),
+    ]
+}
+
+///
+/// Decodes the instruction at pc, unknown opcodes are a single !byte.
+/// Address operands are replaced by symbol names, the source line of pc
+/// becomes the comment.
+///
+pub fn decode(
+    reader: &Reader,
+    pc: u16,
+    opcodes: &HashMap<u8, OpcodeDef>,
+    symbols: &SymbolTable,
+) -> Instruction {
+    let opcode_byte = reader.byte(pc);
+    let comment = symbols.line_at(pc).map(|source| source.to_string());
+    let Some(def) = opcodes.get(&opcode_byte) else {
+        return Instruction {
+            address: pc,
+            bytes: vec![opcode_byte],
+            mnemonic: "!byte".to_string(),
+            operand: format!("{:02X}", opcode_byte),
+            target: None,
+            comment,
+        };
+    };
+    let args = arguments(reader, pc);
+    Instruction {
+        address: pc,
+        bytes: reader.bytes(pc, def.bytes as u16),
+        mnemonic: def.mnemonic[..3].to_string(),
+        operand: operand(def, pc, &args, symbols),
+        target: branch_target(def, pc, &args),
+        comment,
+    }
+}
+
+///
+/// Successors for the tracing disassembler: JMP, RTS, RTI and BRK end the
+/// path, JMP (indirect) cannot be followed
+///
+fn successors(def: &OpcodeDef, pc: u16, args: &[u8]) -> Successors {
+    Successors {
+        length: def.bytes as u16,
+        target: branch_target(def, pc, args),
+        falls_through: !matches!(&def.mnemonic[..3], "JMP" | "RTS" | "RTI" | "BRK"),
+    }
+}
+
+///
+/// NMI, RESET and IRQ/BRK vectors, entry points of a ROM
+///
+pub fn vectors(memory: &Memory) -> Vec<u16> {
+    let reader = Reader::new(memory);
+    [0xFFFA, 0xFFFC, 0xFFFE]
+        .iter()
+        .map(|&addr| reader.word(addr))
+        .collect()
+}
+
+///
+/// Code/data separating disassembly of start..end, see tracing.rs. Bytes
+/// not reached from the entry points are written as !byte directives,
+/// branch targets without a name get labels L<addr>.
+///
+pub fn disassemble_traced(
+    memory: &Memory,
+    start: u16,
+    end: u32,
+    entries: &[u16],
+    opcodes: &HashMap<u8, OpcodeDef>,
+    symbols: &SymbolTable,
+) -> Vec<Line> {
+    let reader = Reader::new(memory);
+    let map = code_map(memory, start, end, Some(entries), opcodes);
+    let labels = map.labels(symbols);
+    tracing::lines(
+        start,
+        end,
+        &map,
+        &labels,
+        |addr| reader.byte(addr),
+        |pc| decode(&reader, pc, opcodes, &labels),
+        byte_directive,
+    )
+}
+
+fn byte_directive(bytes: &[u8]) -> String {
+    let values: Vec<String> = bytes.iter().map(|b| format!("${:02X}", b)).collect();
+    format!("!byte {}", values.join(","))
+}
+
+///
+/// Instructions of start..end, traced from the entry points or, with None,
+/// found by a linear sweep
+///
+pub fn code_map(
+    memory: &Memory,
+    start: u16,
+    end: u32,
+    entries: Option<&[u16]>,
+    opcodes: &HashMap<u8, OpcodeDef>,
+) -> CodeMap {
+    let reader = Reader::new(memory);
+    let decode = |pc: u16| {
+        let def = opcodes.get(&reader.byte(pc))?;
+        Some(successors(def, pc, &arguments(&reader, pc)))
+    };
+    match entries {
+        Some(entries) => tracing::trace(start, end, entries, decode),
+        None => tracing::sweep(start, end, decode),
+    }
+}
+
+///
+/// Instruction in ACME syntax. Absolute operands below $0100 get the +2
+/// postfix, otherwise ACME would choose zero page addressing.
+///
+fn source_instruction(def: &OpcodeDef, pc: u16, args: &[u8], symbols: &SymbolTable) -> String {
+    let mnemonic = &def.mnemonic[..3];
+    let absolute = matches!(def.mode.as_str(), "absolute" | "absolute,X" | "absolute,Y");
+    match def.mode.as_str() {
+        "accumulator" | "implied" => mnemonic.to_string(),
+        _ if absolute && args[1] == 0 && !matches!(mnemonic, "JMP" | "JSR") => {
+            format!("{}+2 {}", mnemonic, operand(def, pc, args, symbols))
+        }
+        _ => format!("{} {}", mnemonic, operand(def, pc, args, symbols)),
+    }
+}
+
+///
+/// Source of start..end in ACME syntax which assembles to the same bytes:
+/// equates for symbols outside the code, origin, labels, instructions and
+/// !byte directives for data.
+///
+/// ```
+/// let map = code_map(&memory, 0xE000, 0xFFFA, Some(&vectors(&memory)), opcodes());
+/// let source = to_source(&memory, 0xE000, 0xFFFA, &map, opcodes(), &symbols);
+/// std::fs::write("rom.a", source)?;
+/// ```
+///
+pub fn to_source(
+    memory: &Memory,
+    start: u16,
+    end: u32,
+    map: &CodeMap,
+    opcodes: &HashMap<u8, OpcodeDef>,
+    symbols: &SymbolTable,
+) -> String {
+    let reader = Reader::new(memory);
+    let labels = map.labels(symbols);
+    let read = |addr: u16| reader.byte(addr);
+    let items = tracing::items(start, end, map, &labels, read, SOURCE_BYTES_PER_LINE);
+    let mut source = String::from("\t!cpu 6502\n\n");
+    for (addr, name) in tracing::unplaced(&items, &labels) {
+        // Leading zeros would make ACME use absolute addressing for zero page
+        let digits = if addr < 0x100 { 2 } else { 4 };
+        source += &format!("{} = ${:02$X}\n", name, addr, digits);
+    }
+    source += &format!("\n\t* = ${:04X}\n\n", start);
+    for item in &items {
+        let line = match item {
+            Item::Label(name) => format!("{}:", name),
+            Item::Instruction(pc, _) => {
+                let def = &opcodes[&read(*pc)];
+                let args = arguments(&reader, *pc);
+                format!("\t{}", source_instruction(def, *pc, &args, &labels))
+            }
+            Item::Data(_, bytes) => format!("\t{}", byte_directive(bytes)),
+        };
+        source += &line;
+        source += "\n";
+    }
+    source
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    ///
+    /// Operands are replaced by names, branch targets get labels L<addr>
+    ///
+    fn symbolic_disassembly() {
+        let mut memory = Memory::new();
+        let program = [
+            0xA2, 0x00, //       LDX #$00
+            0xB5, 0xFB, //       LDA $FB,X
+            0x9D, 0x00, 0x02, // STA $0200,X
+            0xE8, //             INX
+            0xD0, 0xF8, //       BNE $0602
+            0x20, 0xD2, 0xFF, // JSR $FFD2
+            0x4C, 0x00, 0x06, // JMP $0600
+        ];
+        memory.load_program(&program, 0x0600);
+        let mut symbols = SymbolTable::new();
+        symbols.insert("start", 0x0600);
+        symbols.insert("ptr", 0x00FB);
+        symbols.insert("CHROUT", 0xFFD2);
+        let end = 0x0600 + program.len() as u32;
+        let labels = auto_labels(&memory, 0x0600, end, opcodes(), &symbols);
+        let lines: Vec<String> = disassemble_with_symbols(&memory, 0x0600, end, opcodes(), &labels)
+            .iter()
+            .map(Line::to_string)
+            .collect();
+        let text: Vec<&str> = lines.iter().map(|line| line.trim_end()).collect();
+        assert_eq!(
+            text,
+            [
+                "start:",
+                "0600  A2 00       LDX #$00",
+                "L0602:",
+                "0602  B5 FB       LDA ptr,X",
+                "0604  9D 00 02    STA $0200,X",
+                "0607  E8          INX",
+                "0608  D0 F8       BNE L0602",
+                "060A  20 D2 FF    JSR CHROUT",
+                "060D  4C 00 06    JMP start",
+            ]
+        );
+    }
+
+    #[test]
+    ///
+    /// Bytes after JMP and RTS that are not reached are data, the reset
+    /// vector is the entry point
+    ///
+    fn traced_disassembly() {
+        let mut memory = Memory::new();
+        let program = [
+            0x20, 0x09, 0xF0, // JSR $F009
+            0x4C, 0x00, 0xF0, // JMP $F000
+            0x01, 0x02, 0x03, // table
+            0xBD, 0x06, 0xF0, // LDA $F006,X
+            0x60, //             RTS
+            0xA9, 0x10, 0x20, 0x30, 0x40, // text
+        ];
+        memory.load_program(&program, 0xF000);
+        memory.write_word(0xFFFC, 0xF000);
+        let mut symbols = SymbolTable::new();
+        symbols.insert("table", 0xF006);
+        symbols.insert("text", 0xF00D);
+        let end = 0xF000 + program.len() as u32;
+        let entries = vectors(&memory);
+        let lines: Vec<String> =
+            disassemble_traced(&memory, 0xF000, end, &entries, opcodes(), &symbols)
+                .iter()
+                .map(Line::to_string)
+                .collect();
+        let text: Vec<&str> = lines.iter().map(|line| line.trim_end()).collect();
+        assert_eq!(
+            text,
+            [
+                "LF000:",
+                "F000  20 09 F0    JSR LF009",
+                "F003  4C 00 F0    JMP LF000",
+                "table:",
+                "F006  01 02 03    !byte $01,$02,$03",
+                "LF009:",
+                "F009  BD 06 F0    LDA table,X",
+                "F00C  60          RTS",
+                "text:",
+                "F00D  A9 10 20 30 !byte $A9,$10,$20,$30",
+                "F011  40          !byte $40",
+            ]
+        );
+    }
+
+    #[test]
+    ///
+    /// ACME source with equates, origin, labels, +2 for absolute zero page
+    /// operands and !byte data
+    ///
+    fn acme_source() {
+        let mut memory = Memory::new();
+        let program = [
+            0xAD, 0xFB, 0x00, // LDA $00FB
+            0xA5, 0xFB, //       LDA $FB
+            0x0A, //             ASL A
+            0x20, 0xD2, 0xFF, // JSR $FFD2
+            0xD0, 0xF5, //       BNE $C000
+            0x60, //             RTS
+            0x01, 0x02, //       data
+        ];
+        memory.load_program(&program, 0xC000);
+        let mut symbols = SymbolTable::new();
+        symbols.insert("ptr", 0x00FB);
+        symbols.insert("CHROUT", 0xFFD2);
+        let end = 0xC000 + program.len() as u32;
+        let map = code_map(&memory, 0xC000, end, Some(&[0xC000]), opcodes());
+        let source = to_source(&memory, 0xC000, end, &map, opcodes(), &symbols);
+        assert_eq!(
+            source,
+            "\t!cpu 6502\n\n\
+             ptr = $FB\n\
+             CHROUT = $FFD2\n\
+             \n\t* = $C000\n\n\
+             LC000:\n\
+             \tLDA+2 ptr\n\
+             \tLDA ptr\n\
+             \tASL\n\
+             \tJSR CHROUT\n\
+             \tBNE LC000\n\
+             \tRTS\n\
+             \t!byte $01,$02\n"
+        );
+    }
+
+    #[test]
+    ///
+    /// Instructions at the top of memory take their operands from $0000 and
+    /// a range ending at $FFFF terminates
+    ///
+    fn top_of_memory() {
+        let mut memory = Memory::new();
+        memory.write_byte(0xFFFD, 0x4C); // JMP $1234
+        memory.write_byte(0xFFFE, 0x34);
+        memory.write_byte(0xFFFF, 0xAD); // LDA $5678
+        memory.write_byte(0x0000, 0x78);
+        memory.write_byte(0x0001, 0x56);
+        let instructions = disassemble(&memory, 0xFFFD, 0xFFFF, opcodes());
+        assert_eq!(instructions.len(), 1);
+        assert_eq!(instructions[0].bytes, vec![0x4C, 0x34, 0xAD]);
+        assert_eq!(instructions[0].target, Some(0xAD34));
+        assert_eq!(instructions[0].to_string(), "FFFD  4C 34 AD    JMP $AD34");
+
+        let reader = Reader::new(&memory);
+        let lda = decode(&reader, 0xFFFF, opcodes(), &SymbolTable::new());
+        assert_eq!(lda.bytes, vec![0xAD, 0x78, 0x56]);
+        assert_eq!(lda.text(), "LDA $5678");
+        assert_eq!(lda.next(), 0x0002);
+        let wrapped = disassembler::decode_count(0xFFFF, 2, |pc| {
+            decode(&reader, pc, opcodes(), &SymbolTable::new())
+        });
+        assert_eq!(wrapped[1].address, 0x0002);
+        assert!(disassemble(&memory, 0xF000, 0xFFFF, opcodes()).len() > 1);
+    }
+}
@@ -1025,6 +1025,38 @@ pub static OPCODES: &str = r#"
     "cycles": "2",
     "description": "Rotate One Bit Right (Memory or Accumulator). [C -> [76543210] -> C]\n\nN Z C I D V\n+ + + - - -"
   },
+  {
+    "opcode": "66",
+    "mnemonic": "ROR oper",
+    "mode": "zeropage",
+    "bytes": 2,
+    "cycles": "5",
+    "description": "Rotate One Bit Right (Memory or Accumulator). [C -> [76543210] -> C]\n\nN Z C I D V\n+ + + - - -"
+  },
+  {
+    "opcode": "76",
+    "mnemonic": "ROR oper,X",
+    "mode": "zeropage,X",
+    "bytes": 2,
+    "cycles": "6",
+    "description": "Rotate One Bit Right (Memory or Accumulator). [C -> [76543210] -> C]\n\nN Z C I D V\n+ + + - - -"
+  },
+  {
+    "opcode": "6E",
+    "mnemonic": "ROR oper",
+    "mode": "absolute",
+    "bytes": 3,
+    "cycles": "6",
+    "description": "Rotate One Bit Right (Memory or Accumulator). [C -> [76543210] -> C]\n\nN Z C I D V\n+ + + - - -"
+  },
+  {
+    "opcode": "7E",
+    "mnemonic": "ROR oper,X",
+    "mode": "absolute,X",
+    "bytes": 3,
+    "cycles": "7",
+    "description": "Rotate One Bit Right (Memory or Accumulator). [C -> [76543210] -> C]\n\nN Z C I D V\n+ + + - - -"
+  },
   {
     "opcode": "40",
     "mnemonic": "RTI",
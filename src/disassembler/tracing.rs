@@ -0,0 +1,282 @@
+//////////////////////////////////////////////////////////
+/// Recursive descent (tracing) disassembly. Starting from the entry points
+/// the control flow is followed through jumps, calls and branches; every
+/// byte that is not reached is data. Indirect jumps (JMP ($xxxx), PCHL) and
+/// computed targets cannot be followed, add their targets as entry points.
+///
+/// The CPU specific parts are in the disassemblers: successors of an
+/// instruction and the data directive (!byte / DB), see
+/// mos6502::disassemble_traced() and i8080::disassemble_traced().
+///
+/// ```
+/// let entries = mos6502::vectors(&memory);
+/// for line in mos6502::disassemble_traced(&memory, 0xE000, 0xFFFA, &entries, opcodes(), &symbols) {
+///     println!("{}", line);
+/// }
+/// ```
+//////////////////////////////////////////////////////////
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::disassembler::{Instruction, Line};
+use crate::symbols::SymbolTable;
+
+///
+/// Bytes per data directive
+///
+pub const DATA_BYTES_PER_LINE: usize = 4;
+
+///
+/// How the execution continues after an instruction
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Successors {
+    pub length: u16,
+    ///
+    /// Target of a jump, call or branch
+    ///
+    pub target: Option<u16>,
+    ///
+    /// false after unconditional jumps, returns and halts
+    ///
+    pub falls_through: bool,
+}
+
+///
+/// Result of the tracing: which bytes of start..end are instructions, end
+/// is exclusive and 0x10000 for the top of memory
+///
+pub struct CodeMap {
+    start: u16,
+    end: u32,
+    ///
+    /// First byte and length of the instructions
+    ///
+    instructions: BTreeMap<u16, u16>,
+    code: Vec<bool>,
+    targets: BTreeSet<u16>,
+}
+
+impl CodeMap {
+    fn new(start: u16, end: u32) -> Self {
+        Self {
+            start,
+            end,
+            instructions: BTreeMap::new(),
+            code: vec![false; end.saturating_sub(start as u32) as usize],
+            targets: BTreeSet::new(),
+        }
+    }
+    ///
+    /// Marks an instruction, false if it does not fit or overlaps another one
+    ///
+    fn add(&mut self, pc: u16, length: u16) -> bool {
+        let offset = (pc - self.start) as usize;
+        let bytes = offset..offset + length as usize;
+        if bytes.end > self.code.len() || self.code[bytes.clone()].contains(&true) {
+            return false;
+        }
+        self.instructions.insert(pc, length);
+        self.code[bytes].fill(true);
+        true
+    }
+    ///
+    /// True if addr is the first byte of a reached instruction
+    ///
+    pub fn is_instruction(&self, addr: u16) -> bool {
+        self.instructions.contains_key(&addr)
+    }
+    pub fn instruction_length(&self, addr: u16) -> Option<u16> {
+        self.instructions.get(&addr).copied()
+    }
+    ///
+    /// True if addr is any byte of a reached instruction
+    ///
+    pub fn is_code(&self, addr: u16) -> bool {
+        self.contains(addr) && self.code[(addr - self.start) as usize]
+    }
+    fn contains(&self, addr: u16) -> bool {
+        addr >= self.start && (addr as u32) < self.end
+    }
+    ///
+    /// Jump, call and branch targets inside the traced range
+    ///
+    pub fn targets(&self) -> impl Iterator<Item = u16> + '_ {
+        self.targets.iter().copied()
+    }
+    ///
+    /// Copy of symbols with labels L<addr> for targets without a name
+    ///
+    pub fn labels(&self, symbols: &SymbolTable) -> SymbolTable {
+        let mut labels = symbols.clone();
+        for target in self.targets() {
+            if labels.name_of(target).is_none() {
+                labels.insert(&format!("L{:04X}", target), target);
+            }
+        }
+        labels
+    }
+}
+
+///
+/// Follows the control flow from the entry points. decode returns None for
+/// invalid opcodes, the path ends there. Instructions which would leave
+/// start..end or overlap an already traced instruction are not taken.
+///
+pub fn trace(
+    start: u16,
+    end: u32,
+    entries: &[u16],
+    decode: impl Fn(u16) -> Option<Successors>,
+) -> CodeMap {
+    let mut map = CodeMap::new(start, end);
+    let mut pending: Vec<u16> = entries.to_vec();
+    while let Some(mut pc) = pending.pop() {
+        while map.contains(pc) && !map.is_instruction(pc) {
+            let Some(next) = decode(pc) else {
+                break;
+            };
+            let length = next.length.max(1);
+            if !map.add(pc, length) {
+                break;
+            }
+            if let Some(target) = next.target
+                && map.contains(target)
+            {
+                map.targets.insert(target);
+                pending.push(target);
+            }
+            // add() made sure that the instruction ends inside the range
+            if !next.falls_through || pc as u32 + length as u32 > 0xFFFF {
+                break;
+            }
+            pc += length;
+        }
+    }
+    map
+}
+
+///
+/// Linear sweep: every byte of start..end is decoded as an instruction
+/// following the previous one. Invalid opcodes and an instruction crossing
+/// end are data. Targets are collected as in trace().
+///
+pub fn sweep(start: u16, end: u32, decode: impl Fn(u16) -> Option<Successors>) -> CodeMap {
+    let mut map = CodeMap::new(start, end);
+    let mut pc = start as u32;
+    while pc < end {
+        let Some(next) = decode(pc as u16) else {
+            pc += 1;
+            continue;
+        };
+        let length = next.length.max(1);
+        if !map.add(pc as u16, length) {
+            break;
+        }
+        if let Some(target) = next.target
+            && map.contains(target)
+        {
+            map.targets.insert(target);
+        }
+        pc += length as u32;
+    }
+    map
+}
+
+///
+/// Element of a disassembly in address order
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Item {
+    Label(String),
+    ///
+    /// Address and length of a reached instruction
+    ///
+    Instruction(u16, u16),
+    Data(u16, Vec<u8>),
+}
+
+///
+/// Labels, instructions and data of start..end. Data is grouped by up to
+/// bytes_per_line bytes, symbols split the data.
+///
+pub fn items(
+    start: u16,
+    end: u32,
+    map: &CodeMap,
+    symbols: &SymbolTable,
+    read: impl Fn(u16) -> u8,
+    bytes_per_line: usize,
+) -> Vec<Item> {
+    let mut items = Vec::new();
+    let mut pc = start as u32;
+    while pc < end {
+        let addr = pc as u16;
+        for name in symbols.names_of(addr) {
+            items.push(Item::Label(name.clone()));
+        }
+        if let Some(length) = map.instruction_length(addr) {
+            items.push(Item::Instruction(addr, length));
+            pc += length as u32;
+            continue;
+        }
+        let mut bytes = vec![read(addr)];
+        let mut next = pc + 1;
+        while next < end
+            && bytes.len() < bytes_per_line
+            && !map.is_code(next as u16)
+            && symbols.name_of(next as u16).is_none()
+        {
+            bytes.push(read(next as u16));
+            next += 1;
+        }
+        items.push(Item::Data(addr, bytes));
+        pc = next;
+    }
+    items
+}
+
+///
+/// Listing of start..end: reached instructions are decoded by
+/// instruction(pc), other bytes are grouped to data lines with the
+/// directive of data(bytes). Symbols get label lines and split the data.
+///
+pub fn lines(
+    start: u16,
+    end: u32,
+    map: &CodeMap,
+    symbols: &SymbolTable,
+    read: impl Fn(u16) -> u8,
+    instruction: impl Fn(u16) -> Instruction,
+    data: impl Fn(&[u8]) -> String,
+) -> Vec<Line> {
+    items(start, end, map, symbols, read, DATA_BYTES_PER_LINE)
+        .into_iter()
+        .map(|item| match item {
+            Item::Label(name) => Line::Label(name),
+            Item::Instruction(pc, _) => Line::Instruction(instruction(pc)),
+            Item::Data(address, bytes) => Line::Data {
+                address,
+                directive: data(&bytes),
+                bytes,
+            },
+        })
+        .collect()
+}
+
+///
+/// Symbols which are not at the start of an item and need an equate in
+/// source output, e.g. a jump into the operand of an instruction
+///
+pub fn unplaced<'a>(items: &[Item], symbols: &'a SymbolTable) -> Vec<(u16, &'a str)> {
+    let placed: BTreeSet<&str> = items
+        .iter()
+        .filter_map(|item| match item {
+            Item::Label(name) => Some(name.as_str()),
+            _ => None,
+        })
+        .collect();
+    symbols
+        .iter()
+        .filter(|(_, name)| !placed.contains(name))
+        .collect()
+}
@@ -0,0 +1,465 @@
+//////////////////////////////////////////////////////////
+/// Expression core shared by the assemblers and the debugger: tokenizer,
+/// precedence parser and evaluation. What differs between them is a
+/// Syntax (how numbers, names and the location are written, which
+/// operators exist and how strong they bind) and the Operands (what names,
+/// the location and name[index] stand for when the value is computed).
+///
+/// ```
+/// let root = Parser::parse("HIGH table + 1", &Dialect::Intel)?;
+/// let value = evaluate(&root, &symbols)?;
+/// ```
+//////////////////////////////////////////////////////////
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExprError(pub String);
+
+impl fmt::Display for ExprError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for ExprError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    LogicalOr,
+    LogicalAnd,
+    Or,
+    Xor,
+    And,
+    Eq,
+    Ne,
+    Lt,
+    Gt,
+    Le,
+    Ge,
+    Shl,
+    Shr,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Unary {
+    Negate,
+    ///
+    /// Bitwise complement
+    ///
+    Not,
+    ///
+    /// 1 for 0, else 0
+    ///
+    LogicalNot,
+    Low,
+    High,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Node {
+    Number(i64),
+    Name(String),
+    Location,
+    ///
+    /// name[index], e.g. mem[$0200] in the debugger
+    ///
+    Index(String, Box<Node>),
+    Unary(Unary, Box<Node>),
+    Binary(Op, Box<Node>, Box<Node>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    Number(i64),
+    Name(String),
+    Location,
+    Symbol(&'static str),
+}
+
+///
+/// Binary operators of one priority level and their spelling, keywords
+/// are matched case insensitive
+///
+pub type Level = &'static [(&'static str, Op)];
+
+///
+/// How expressions are written
+///
+pub trait Syntax {
+    ///
+    /// Operators and brackets, longer ones first so that "<<" is not read
+    /// as two "<"
+    ///
+    fn symbols(&self) -> &'static [&'static str];
+    ///
+    /// Binary operators grouped by priority, the lowest first
+    ///
+    fn levels(&self) -> &'static [Level];
+    ///
+    /// Number, character constant or location at the start of rest with
+    /// its length in bytes, None if rest starts with something else.
+    /// operand_before tells if the previous token ends an operand, so that
+    /// e.g. * can be the location or the multiplication.
+    ///
+    fn scan(&self, rest: &str, operand_before: bool) -> Result<Option<(Token, usize)>, ExprError>;
+    fn is_name_start(&self, c: char) -> bool {
+        c.is_ascii_alphabetic() || c == '_'
+    }
+    fn is_name_char(&self, c: char) -> bool {
+        c.is_ascii_alphanumeric() || c == '_'
+    }
+    ///
+    /// Unary operator written as a symbol ("-") or keyword ("LOW")
+    ///
+    fn unary(&self, text: &str) -> Option<Unary>;
+    ///
+    /// true if name[index] is an operand
+    ///
+    fn is_indexed(&self, _name: &str) -> bool {
+        false
+    }
+}
+
+///
+/// Values of the operands, None for a value which is not known (yet)
+///
+pub trait Operands {
+    fn name(&self, name: &str) -> Result<Option<i64>, ExprError>;
+    fn location(&self) -> Result<Option<i64>, ExprError> {
+        Err(ExprError("no location here".to_string()))
+    }
+    fn index(&self, name: &str, _index: i64) -> Result<Option<i64>, ExprError> {
+        Err(ExprError(format!("'{}' cannot be indexed", name)))
+    }
+}
+
+///
+/// Length of a number starting with a digit or a one character prefix,
+/// the number ends at the first character which is not alphanumeric
+///
+pub fn number_length(rest: &str) -> usize {
+    rest.char_indices()
+        .skip(1)
+        .find(|(_, c)| !c.is_ascii_alphanumeric())
+        .map_or(rest.len(), |(i, _)| i)
+}
+
+pub fn tokenize(text: &str, syntax: &dyn Syntax) -> Result<Vec<Token>, ExprError> {
+    let mut tokens: Vec<Token> = Vec::new();
+    let mut rest = text.trim_start();
+    while let Some(c) = rest.chars().next() {
+        let operand_before = matches!(
+            tokens.last(),
+            Some(Token::Number(_) | Token::Name(_) | Token::Location | Token::Symbol(")" | "]"))
+        );
+        let (token, length) = match syntax.scan(rest, operand_before)? {
+            Some(scanned) => scanned,
+            None if syntax.is_name_start(c) => {
+                let length = rest
+                    .find(|c: char| !syntax.is_name_char(c))
+                    .unwrap_or(rest.len());
+                (Token::Name(rest[..length].to_string()), length)
+            }
+            None => match syntax
+                .symbols()
+                .iter()
+                .find(|symbol| rest.starts_with(**symbol))
+            {
+                Some(symbol) => (Token::Symbol(symbol), symbol.len()),
+                None => return Err(ExprError(format!("unexpected character '{}'", c))),
+            },
+        };
+        tokens.push(token);
+        rest = rest[length..].trim_start();
+    }
+    Ok(tokens)
+}
+
+pub struct Parser<'a> {
+    tokens: Vec<Token>,
+    position: usize,
+    syntax: &'a dyn Syntax,
+}
+
+impl<'a> Parser<'a> {
+    ///
+    /// Tree of the whole text, an error if anything is left over
+    ///
+    pub fn parse(text: &str, syntax: &'a dyn Syntax) -> Result<Node, ExprError> {
+        let mut parser = Parser {
+            tokens: tokenize(text, syntax)?,
+            position: 0,
+            syntax,
+        };
+        let root = parser.binary(0)?;
+        if let Some(token) = parser.peek() {
+            return Err(ExprError(format!("unexpected {:?}", token)));
+        }
+        Ok(root)
+    }
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+    fn expect(&mut self, symbol: &str) -> Result<(), ExprError> {
+        match self.next() {
+            Some(Token::Symbol(found)) if found == symbol => Ok(()),
+            _ => Err(ExprError(format!("'{}' expected", symbol))),
+        }
+    }
+    ///
+    /// Binary operator of the level at the current position
+    ///
+    fn operator(&self, level: usize) -> Option<Op> {
+        let text = match self.peek()? {
+            Token::Symbol(symbol) => symbol.to_string(),
+            Token::Name(name) => name.to_ascii_uppercase(),
+            _ => return None,
+        };
+        self.syntax.levels()[level]
+            .iter()
+            .find(|(s, _)| *s == text)
+            .map(|&(_, op)| op)
+    }
+    fn binary(&mut self, level: usize) -> Result<Node, ExprError> {
+        if level == self.syntax.levels().len() {
+            return self.unary();
+        }
+        let mut left = self.binary(level + 1)?;
+        while let Some(op) = self.operator(level) {
+            self.position += 1;
+            let right = self.binary(level + 1)?;
+            left = Node::Binary(op, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+    fn unary(&mut self) -> Result<Node, ExprError> {
+        let token = self.next();
+        let text = match &token {
+            Some(Token::Symbol(symbol)) => Some(symbol.to_string()),
+            Some(Token::Name(name)) => Some(name.to_ascii_uppercase()),
+            _ => None,
+        };
+        if let Some(op) = text.and_then(|text| self.syntax.unary(&text)) {
+            return Ok(Node::Unary(op, Box::new(self.unary()?)));
+        }
+        match token {
+            Some(Token::Symbol("(")) => {
+                let node = self.binary(0)?;
+                self.expect(")")?;
+                Ok(node)
+            }
+            Some(Token::Number(value)) => Ok(Node::Number(value)),
+            Some(Token::Location) => Ok(Node::Location),
+            Some(Token::Name(name)) if self.syntax.is_indexed(&name) => {
+                self.expect("[")?;
+                let index = self.binary(0)?;
+                self.expect("]")?;
+                Ok(Node::Index(name, Box::new(index)))
+            }
+            Some(Token::Name(name)) => Ok(Node::Name(name)),
+            Some(token) => Err(ExprError(format!("unexpected {:?}", token))),
+            None => Err(ExprError("unexpected end of expression".to_string())),
+        }
+    }
+}
+
+///
+/// Value of the tree, None if an operand is not known. && and || do not
+/// evaluate the right side if the left one decides.
+///
+pub fn evaluate(node: &Node, operands: &dyn Operands) -> Result<Option<i64>, ExprError> {
+    Ok(match node {
+        Node::Number(value) => Some(*value),
+        Node::Name(name) => operands.name(name)?,
+        Node::Location => operands.location()?,
+        Node::Index(name, index) => match evaluate(index, operands)? {
+            Some(index) => operands.index(name, index)?,
+            None => None,
+        },
+        Node::Unary(op, node) => evaluate(node, operands)?.map(|value| match op {
+            Unary::Negate => value.wrapping_neg(),
+            Unary::Not => !value,
+            Unary::LogicalNot => (value == 0) as i64,
+            Unary::Low => value & 0xFF,
+            Unary::High => (value >> 8) & 0xFF,
+        }),
+        Node::Binary(op, left, right) => {
+            let left = evaluate(left, operands)?;
+            match (op, left) {
+                (Op::LogicalAnd, Some(0)) => return Ok(Some(0)),
+                (Op::LogicalOr, Some(left)) if left != 0 => return Ok(Some(1)),
+                _ => {}
+            }
+            let right = evaluate(right, operands)?;
+            let (Some(left), Some(right)) = (left, right) else {
+                return Ok(None);
+            };
+            if matches!(op, Op::Div | Op::Mod) && right == 0 {
+                return Err(ExprError("division by zero".to_string()));
+            }
+            Some(match op {
+                Op::LogicalOr | Op::LogicalAnd => (right != 0) as i64,
+                Op::Or => left | right,
+                Op::Xor => left ^ right,
+                Op::And => left & right,
+                Op::Eq => (left == right) as i64,
+                Op::Ne => (left != right) as i64,
+                Op::Lt => (left < right) as i64,
+                Op::Gt => (left > right) as i64,
+                Op::Le => (left <= right) as i64,
+                Op::Ge => (left >= right) as i64,
+                Op::Shl => left.wrapping_shl(right as u32),
+                Op::Shr => left.wrapping_shr(right as u32),
+                Op::Add => left.wrapping_add(right),
+                Op::Sub => left.wrapping_sub(right),
+                Op::Mul => left.wrapping_mul(right),
+                Op::Div => left.wrapping_div(right),
+                Op::Mod => left.wrapping_rem(right),
+            })
+        }
+    })
+}
+
+impl Node {
+    ///
+    /// Names used by the expression, indexed names not included
+    ///
+    pub fn names(&self) -> Vec<&str> {
+        let mut names = Vec::new();
+        self.collect_names(&mut names);
+        names
+    }
+    fn collect_names<'a>(&'a self, names: &mut Vec<&'a str>) {
+        match self {
+            Node::Name(name) => names.push(name),
+            Node::Index(_, node) | Node::Unary(_, node) => node.collect_names(names),
+            Node::Binary(_, left, right) => {
+                left.collect_names(names);
+                right.collect_names(names);
+            }
+            Node::Number(_) | Node::Location => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    ///
+    /// Decimal numbers, * is the location or the multiplication, a[i] is
+    /// i times 10
+    ///
+    struct Test;
+
+    impl Syntax for Test {
+        fn symbols(&self) -> &'static [&'static str] {
+            &["&&", "||", "+", "-", "*", "/", "!", "(", ")", "[", "]"]
+        }
+        fn levels(&self) -> &'static [Level] {
+            &[
+                &[("||", Op::LogicalOr)],
+                &[("&&", Op::LogicalAnd)],
+                &[("+", Op::Add), ("-", Op::Sub)],
+                &[("*", Op::Mul), ("/", Op::Div), ("MOD", Op::Mod)],
+            ]
+        }
+        fn scan(
+            &self,
+            rest: &str,
+            operand_before: bool,
+        ) -> Result<Option<(Token, usize)>, ExprError> {
+            if rest.starts_with(|c: char| c.is_ascii_digit()) {
+                let length = number_length(rest);
+                let value = rest[..length]
+                    .parse()
+                    .map_err(|_| ExprError(format!("invalid number '{}'", &rest[..length])))?;
+                return Ok(Some((Token::Number(value), length)));
+            }
+            Ok((rest.starts_with('*') && !operand_before).then_some((Token::Location, 1)))
+        }
+        fn unary(&self, text: &str) -> Option<Unary> {
+            match text {
+                "-" => Some(Unary::Negate),
+                "!" => Some(Unary::LogicalNot),
+                "LOW" => Some(Unary::Low),
+                _ => None,
+            }
+        }
+        fn is_indexed(&self, name: &str) -> bool {
+            name == "a"
+        }
+    }
+
+    impl Operands for Test {
+        fn name(&self, name: &str) -> Result<Option<i64>, ExprError> {
+            match name {
+                "known" => Ok(Some(0x1234)),
+                "broken" => Err(ExprError("broken".to_string())),
+                _ => Ok(None),
+            }
+        }
+        fn location(&self) -> Result<Option<i64>, ExprError> {
+            Ok(Some(100))
+        }
+        fn index(&self, _name: &str, index: i64) -> Result<Option<i64>, ExprError> {
+            Ok(Some(index * 10))
+        }
+    }
+
+    fn value(text: &str) -> Result<Option<i64>, ExprError> {
+        evaluate(&Parser::parse(text, &Test)?, &Test)
+    }
+
+    #[test]
+    ///
+    /// Priority levels, unary symbols and keywords, location and index
+    ///
+    fn evaluate_test_syntax() {
+        assert_eq!(value("1 + 2 * 3 - 4"), Ok(Some(3)));
+        assert_eq!(value("(1 + 2) * -3"), Ok(Some(-9)));
+        assert_eq!(value("* * 2"), Ok(Some(200)));
+        assert_eq!(value("a[1 + 1] + LOW known"), Ok(Some(20 + 0x34)));
+        assert_eq!(value("7 mod 4 + !0"), Ok(Some(4)));
+        assert_eq!(value("unknown + 1"), Ok(None));
+        assert_eq!(
+            Parser::parse("known + unknown * a[other]", &Test)
+                .unwrap()
+                .names(),
+            ["known", "unknown", "other"]
+        );
+    }
+
+    #[test]
+    ///
+    /// && and || skip the right side, errors of the operands and the syntax
+    ///
+    fn short_circuit_and_errors() {
+        assert_eq!(value("0 && broken"), Ok(Some(0)));
+        assert_eq!(value("2 || broken"), Ok(Some(1)));
+        assert_eq!(value("1 && 2"), Ok(Some(1)));
+        assert!(value("1 && broken").is_err());
+        assert_eq!(
+            value("1 / 0"),
+            Err(ExprError("division by zero".to_string()))
+        );
+        // the only overflowing division wraps like the other operators
+        assert_eq!(value("(-9223372036854775807 - 1) / -1"), Ok(Some(i64::MIN)));
+        assert_eq!(value("(-9223372036854775807 - 1) MOD -1"), Ok(Some(0)));
+        assert!(value("1 +").is_err());
+        assert!(value("(1").is_err());
+        assert!(value("a 1").is_err());
+        assert!(value("1 ?").is_err());
+        assert!(value("12x").is_err());
+    }
+}
@@ -0,0 +1,172 @@
+//////////////////////////////////////////////////////////
+/// Declarative description of a machine. The file is JSON and is read with serde,
+/// so a new board can be described without writing any Rust code.
+/// Addresses and sizes can be written as plain numbers or as strings in any of the
+/// usual notations: "$FFFC" (6502), "0FFFCH" (i8080), "0xFFFC" or "65532".
+/// Memory regions must not overlap and every image must lie inside its region.
+///
+/// ```json
+/// {
+///     "name": "PMI-80",
+///     "cpu": "8080",
+///     "clock_hz": 1111111,
+///     "memory": [
+///         { "kind": "rom", "start": "0000H", "size": "0400H", "image": "pmi80.bin" },
+///         { "kind": "ram", "start": "1C00H", "size": "0400H" }
+///     ],
+///     "devices": [
+///         { "name": "display", "kind": "8255", "port": "0F8H" }
+///     ],
+///     "start": "0000H"
+/// }
+/// ```
+//////////////////////////////////////////////////////////
+use serde::{Deserialize, Deserializer, Serialize};
+
+///
+/// CPU of the machine. "8085" and "65C02" are accepted by the schema so boards can
+/// already be described, but they have no emulation core yet and Machine::build
+/// rejects them with MachineError::UnsupportedCpu.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum CpuKind {
+    #[serde(rename = "8080", alias = "i8080")]
+    I8080,
+    #[serde(rename = "8085", alias = "i8085")]
+    I8085,
+    #[serde(rename = "6502", alias = "mos6502")]
+    Mos6502,
+    #[serde(rename = "65C02", alias = "65c02")]
+    Wdc65C02,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RegionKind {
+    Ram,
+    Rom,
+}
+
+///
+/// Format of the image file loaded into a memory region.
+/// "raw" is a plain binary placed at the start of the region,
+/// "acme" is the ACME "cbm" output where the first 2 bytes hold the load address,
+/// "ihex" is Intel HEX and "srec" Motorola S-records, both carry their own addresses.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImageFormat {
+    #[default]
+    Raw,
+    Acme,
+    Ihex,
+    Srec,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct RegionConfig {
+    pub kind: RegionKind,
+    #[serde(deserialize_with = "deserialize_u16")]
+    pub start: u16,
+    #[serde(deserialize_with = "deserialize_u32")]
+    pub size: u32,
+    #[serde(default)]
+    pub image: Option<String>,
+    #[serde(default)]
+    pub format: ImageFormat,
+}
+
+///
+/// Peripheral of the machine. Devices are descriptive only: they are validated
+/// against the CPU and listed, but not emulated.
+///
+#[derive(Debug, Clone, Deserialize)]
+pub struct DeviceConfig {
+    pub name: String,
+    pub kind: String,
+    #[serde(default, deserialize_with = "deserialize_opt_u16")]
+    pub base: Option<u16>,
+    #[serde(default, deserialize_with = "deserialize_opt_u16")]
+    pub port: Option<u16>,
+    ///
+    /// Interrupt line the device is wired to: "irq" or "nmi" for the 6502,
+    /// "int" or "rst0".."rst7" for the 8080/8085.
+    ///
+    #[serde(default)]
+    pub irq: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct MachineConfig {
+    #[serde(default)]
+    pub name: String,
+    pub cpu: CpuKind,
+    #[serde(default)]
+    pub clock_hz: u32,
+    #[serde(default)]
+    pub memory: Vec<RegionConfig>,
+    #[serde(default)]
+    pub devices: Vec<DeviceConfig>,
+    ///
+    /// Start address. If it is missing the 6502 starts from the reset vector
+    /// at $FFFC and the 8080 from 0000H.
+    ///
+    #[serde(default, deserialize_with = "deserialize_opt_u16")]
+    pub start: Option<u16>,
+}
+
+impl MachineConfig {
+    pub fn from_json(text: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(text)
+    }
+}
+
+///
+/// Parses a number written as "$1C00", "0x1C00", "1C00H", "01C00h" or "7168"
+///
+pub fn parse_number(text: &str) -> Option<u32> {
+    let text = text.trim();
+    if let Some(hex) = text.strip_prefix('$') {
+        u32::from_str_radix(hex, 16).ok()
+    } else if let Some(hex) = text.strip_prefix("0x").or(text.strip_prefix("0X")) {
+        u32::from_str_radix(hex, 16).ok()
+    } else if let Some(hex) = text.strip_suffix('H').or(text.strip_suffix('h')) {
+        u32::from_str_radix(hex, 16).ok()
+    } else {
+        text.parse().ok()
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Number {
+    Int(u32),
+    Text(String),
+}
+
+impl Number {
+    fn value<E: serde::de::Error>(self) -> Result<u32, E> {
+        match self {
+            Number::Int(value) => Ok(value),
+            Number::Text(text) => {
+                parse_number(&text).ok_or_else(|| E::custom(format!("invalid number '{}'", text)))
+            }
+        }
+    }
+}
+
+fn deserialize_u32<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u32, D::Error> {
+    Number::deserialize(deserializer)?.value()
+}
+
+fn deserialize_u16<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u16, D::Error> {
+    let value = deserialize_u32(deserializer)?;
+    u16::try_from(value)
+        .map_err(|_| serde::de::Error::custom(format!("address {:X} is out of range", value)))
+}
+
+fn deserialize_opt_u16<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<u16>, D::Error> {
+    deserialize_u16(deserializer).map(Some)
+}
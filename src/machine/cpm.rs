@@ -0,0 +1,292 @@
+//////////////////////////////////////////////////////////
+/// Minimal CP/M environment for 8080 programs like the CPU exercisers
+/// (8080PRE.COM, TST8080.COM, CPUTEST.COM, 8080EXM.COM).
+///
+/// The .COM file is loaded at 0100H, the stack holds the return address
+/// 0000H. A CALL 0005H is handled here instead of by a BDOS:
+///   C = 0  system reset, ends the run
+///   C = 2  console output of the character in E
+///   C = 9  console output of the string at DE up to '$'
+/// and returns to the caller. The run ends with the jump to 0000H (warm
+/// boot), a HLT or an unsupported BDOS function. The console output is
+/// collected in a buffer.
+///
+/// ```
+/// let mut cpm = Cpm::load("tests/8080/TST8080.COM")?;
+/// let stop = cpm.run(10_000_000);
+/// println!("{:?}\n{}", stop, cpm.output());
+/// ```
+//////////////////////////////////////////////////////////
+use std::path::Path;
+
+use crate::cpu::i8080::Cpu;
+use crate::disassembler::i8080_opcodes_const::HLT;
+use crate::memory::LoadError;
+
+///
+/// Start of the transient program area, .COM files are loaded here
+///
+pub const TPA: u16 = 0x0100;
+pub const BDOS: u16 = 0x0005;
+
+///
+/// Where the BDOS would be. Programs read the top of their memory from
+/// the jump at 0005H and put their stack there.
+///
+const BDOS_BASE: u16 = 0xFE00;
+
+const JMP: u8 = 0xC3;
+const RET: u8 = 0xC9;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stop {
+    ///
+    /// Jump to 0000H or BDOS function 0
+    ///
+    WarmBoot,
+    ///
+    /// HLT at this address
+    ///
+    Halt(u16),
+    UnsupportedFunction(u8),
+    StepLimit,
+}
+
+pub struct Cpm {
+    pub cpu: Cpu,
+    output: String,
+    steps: u64,
+}
+
+impl Cpm {
+    ///
+    /// CP/M memory with the program in the TPA
+    ///
+    pub fn new(program: &[u8]) -> Result<Self, LoadError> {
+        if program.len() > (BDOS_BASE - TPA) as usize {
+            return Err(LoadError::ImageTooLarge {
+                length: program.len(),
+            });
+        }
+        let mut cpu = Cpu::new();
+        let [low, high] = BDOS_BASE.to_le_bytes();
+        // Warm boot jumps to the BDOS as well, the run ends before
+        cpu.memory.load_program(&[JMP, low, high], 0x0000);
+        cpu.memory.load_program(&[JMP, low, high], BDOS);
+        cpu.memory.write_byte(BDOS_BASE, RET);
+        cpu.load_program(program, TPA);
+        // RET of the program goes to the warm boot
+        cpu.sp = BDOS_BASE - 2;
+        cpu.memory.write_word(cpu.sp, 0x0000);
+        Ok(Self {
+            cpu,
+            output: String::new(),
+            steps: 0,
+        })
+    }
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, LoadError> {
+        let path = path.as_ref();
+        let program = std::fs::read(path).map_err(|err| match err.kind() {
+            std::io::ErrorKind::NotFound => LoadError::FileNotFound(path.to_path_buf()),
+            _ => LoadError::Io(err),
+        })?;
+        Self::new(&program)
+    }
+    ///
+    /// Console output so far
+    ///
+    pub fn output(&self) -> &str {
+        &self.output
+    }
+    ///
+    /// Instructions executed so far, BDOS calls not counted
+    ///
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+    ///
+    /// Runs until the program ends or limit instructions are executed
+    ///
+    pub fn run(&mut self, limit: u64) -> Stop {
+        let mut executed = 0;
+        while executed < limit {
+            match self.cpu.pc {
+                0x0000 => return Stop::WarmBoot,
+                BDOS => {
+                    if let Some(stop) = self.bdos() {
+                        return stop;
+                    }
+                    continue;
+                }
+                pc if self.cpu.memory.read_byte(pc) == HLT => return Stop::Halt(pc),
+                _ => {}
+            }
+            self.cpu.step();
+            self.steps += 1;
+            executed += 1;
+        }
+        Stop::StepLimit
+    }
+    ///
+    /// Executes the BDOS function in C and returns to the caller
+    ///
+    fn bdos(&mut self) -> Option<Stop> {
+        let cpu = &mut self.cpu;
+        match cpu.c {
+            0 => return Some(Stop::WarmBoot),
+            2 => self.output.push(cpu.e as char),
+            9 => {
+                let mut addr = u16::from_be_bytes([cpu.d, cpu.e]);
+                // A string without '$' ends after 64KB
+                for _ in 0..0x10000 {
+                    let byte = cpu.memory.read_byte(addr);
+                    if byte == b'$' {
+                        break;
+                    }
+                    self.output.push(byte as char);
+                    addr = addr.wrapping_add(1);
+                }
+            }
+            function => return Some(Stop::UnsupportedFunction(function)),
+        }
+        // RET
+        cpu.pc = cpu.memory.read_word(cpu.sp);
+        cpu.sp = cpu.sp.wrapping_add(2);
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    ///
+    /// Output and stop of a call of BDOS function c with DE = de
+    ///
+    fn call(cpm: &mut Cpm, c: u8, de: u16) -> Stop {
+        cpm.cpu.c = c;
+        [cpm.cpu.d, cpm.cpu.e] = de.to_be_bytes();
+        cpm.cpu.sp = cpm.cpu.sp.wrapping_sub(2);
+        cpm.cpu.memory.write_word(cpm.cpu.sp, TPA);
+        cpm.cpu.pc = BDOS;
+        cpm.run(1)
+    }
+
+    #[test]
+    ///
+    /// Console output of functions 2 and 9, function 0 ends the run
+    ///
+    fn console_output() {
+        // HLT at 0100H, the message at 0110H
+        let mut program = vec![0; 0x20];
+        program[0] = HLT;
+        program[0x10..0x17].copy_from_slice(b"CPU OK$");
+        let mut cpm = Cpm::new(&program).unwrap();
+        let sp = cpm.cpu.sp;
+
+        assert_eq!(call(&mut cpm, 2, u16::from(b'>')), Stop::Halt(TPA));
+        assert_eq!(call(&mut cpm, 9, 0x0110), Stop::Halt(TPA));
+        assert_eq!(cpm.output(), ">CPU OK");
+        assert_eq!(cpm.cpu.sp, sp);
+        assert_eq!(call(&mut cpm, 12, 0), Stop::UnsupportedFunction(12));
+        assert_eq!(call(&mut cpm, 0, 0), Stop::WarmBoot);
+        assert_eq!(cpm.cpu.memory.read_word(0x0006), BDOS_BASE);
+        assert_eq!(cpm.cpu.memory.read_word(sp), 0x0000);
+        assert!(Cpm::new(&vec![0; 0xFF00]).is_err());
+    }
+
+    ///
+    /// Runs an exerciser from tests/8080, panics if it is not there
+    ///
+    fn exerciser(file: &str, limit: u64) -> (Stop, String) {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/8080")
+            .join(file);
+        let mut cpm = Cpm::load(&path).unwrap_or_else(|err| panic!("{}: {}", path.display(), err));
+        let stop = cpm.run(limit);
+        (stop, cpm.output().to_string())
+    }
+
+    fn check_exerciser(file: &str, limit: u64, passed: &str) {
+        let (stop, output) = exerciser(file, limit);
+        assert!(
+            stop == Stop::WarmBoot && output.contains(passed),
+            "{} stopped with {:?}, output:\n{}",
+            file,
+            stop,
+            output
+        );
+    }
+
+    #[test]
+    ///
+    /// Checked-in tests/8080/ALUTEST.COM, A and the flags at every HLT.
+    /// It only uses the opcodes of the core, so it has no console output.
+    ///
+    fn exerciser_alutest() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/8080/ALUTEST.COM");
+        let mut cpm = Cpm::load(&path).unwrap_or_else(|err| panic!("{}: {}", path.display(), err));
+        for (block, expected) in [
+            (0x00, 0x57),
+            (0x01, 0x02),
+            (0x00, 0x56),
+            (0x80, 0x87),
+            (0x7F, 0x86),
+            (0x80, 0x92),
+        ]
+        .iter()
+        .enumerate()
+        {
+            let Stop::Halt(pc) = cpm.run(100) else {
+                panic!("block {} did not end with HLT", block);
+            };
+            assert_eq!(
+                (cpm.cpu.a, cpm.cpu.psw.value),
+                *expected,
+                "A and F after block {} at {:04X}",
+                block,
+                pc
+            );
+            cpm.cpu.pc = pc + 1;
+        }
+        assert_eq!(cpm.output(), "");
+    }
+
+    #[test]
+    #[ignore = "needs tests/8080/8080PRE.COM, the 8080 core lacks most opcodes"]
+    ///
+    /// Preliminary test of the basic instructions
+    ///
+    fn exerciser_8080pre() {
+        check_exerciser("8080PRE.COM", 10_000_000, "Preliminary tests complete");
+    }
+
+    #[test]
+    #[ignore = "needs tests/8080/TST8080.COM, the 8080 core lacks most opcodes"]
+    ///
+    /// Microcosm Associates 8080/8085 diagnostic
+    ///
+    fn exerciser_tst8080() {
+        check_exerciser("TST8080.COM", 10_000_000, "CPU IS OPERATIONAL");
+    }
+
+    #[test]
+    #[ignore = "needs tests/8080/CPUTEST.COM, the 8080 core lacks most opcodes"]
+    ///
+    /// SuperSoft Associates diagnostic
+    ///
+    fn exerciser_cputest() {
+        check_exerciser("CPUTEST.COM", 1_000_000_000, "CPU TESTS OK");
+    }
+
+    #[test]
+    #[ignore = "runs for billions of instructions, use cargo test --release -- --ignored"]
+    ///
+    /// Instruction exerciser comparing CRCs of all flag results
+    ///
+    fn exerciser_8080exm() {
+        let (stop, output) = exerciser("8080EXM.COM", u64::MAX);
+        assert_eq!(stop, Stop::WarmBoot);
+        assert!(!output.contains("ERROR"), "{}", output);
+    }
+}
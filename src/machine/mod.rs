@@ -0,0 +1,535 @@
+//////////////////////////////////////////////////////////
+/// Builds a complete system (CPU, memory map, devices and start address) from a
+/// machine description file, see config.rs for the format.
+///
+/// ```
+/// mod cpu;
+/// mod machine;
+/// mod memory;
+/// mod status;
+///
+/// fn main() {
+///     let mut machine = machine::Machine::from_file("pmi80.json").unwrap();
+///     if let machine::MachineCpu::I8080(cpu) = &mut machine.cpu {
+///         cpu.step();
+///     }
+/// }
+/// ```
+//////////////////////////////////////////////////////////
+pub mod config;
+pub mod cpm;
+pub mod snapshot;
+
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::cpu::{Processor, i8080, mos6502};
+use crate::memory::{Image, Memory, ihex, split_acme_header, srec};
+use config::{CpuKind, DeviceConfig, ImageFormat, MachineConfig, RegionConfig, RegionKind};
+
+#[derive(Debug)]
+pub enum MachineError {
+    Io(PathBuf, std::io::Error),
+    Parse(serde_json::Error),
+    UnsupportedCpu(CpuKind),
+    RegionOutOfRange {
+        start: u16,
+        size: u32,
+    },
+    RegionOverlap {
+        first: u16,
+        second: u16,
+    },
+    ImageTooLarge {
+        image: String,
+        size: usize,
+        region_size: u32,
+    },
+    ImageOutsideRegion {
+        image: String,
+        address: u16,
+        size: usize,
+        region_start: u16,
+        region_size: u32,
+    },
+    InvalidImage(String),
+    InvalidDevice(String),
+}
+
+impl fmt::Display for MachineError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MachineError::Io(path, err) => write!(f, "{}: {}", path.display(), err),
+            MachineError::Parse(err) => write!(f, "invalid machine description: {}", err),
+            MachineError::UnsupportedCpu(kind) => {
+                write!(f, "CPU {:?} has no emulation core yet", kind)
+            }
+            MachineError::RegionOutOfRange { start, size } => write!(
+                f,
+                "memory region {:04X} with size {:X} does not fit into 64KB",
+                start, size
+            ),
+            MachineError::RegionOverlap { first, second } => write!(
+                f,
+                "memory region {:04X} overlaps memory region {:04X}",
+                second, first
+            ),
+            MachineError::ImageTooLarge {
+                image,
+                size,
+                region_size,
+            } => write!(
+                f,
+                "image {} has {} bytes, region has only {} bytes",
+                image, size, region_size
+            ),
+            MachineError::ImageOutsideRegion {
+                image,
+                address,
+                size,
+                region_start,
+                region_size,
+            } => write!(
+                f,
+                "image {} loads {} bytes at {:04X}, outside region {:04X}-{:04X}",
+                image,
+                size,
+                address,
+                region_start,
+                *region_start as u32 + region_size - 1
+            ),
+            MachineError::InvalidImage(msg) => write!(f, "invalid image: {}", msg),
+            MachineError::InvalidDevice(msg) => write!(f, "invalid device: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for MachineError {}
+
+///
+/// The cores hold the whole 64KB memory, boxed to keep the enum small
+///
+pub enum MachineCpu {
+    I8080(Box<i8080::Cpu>),
+    Mos6502(Box<mos6502::Cpu>),
+}
+
+impl MachineCpu {
+    pub fn memory_mut(&mut self) -> &mut Memory {
+        match self {
+            MachineCpu::I8080(cpu) => &mut cpu.memory,
+            MachineCpu::Mos6502(cpu) => &mut cpu.memory,
+        }
+    }
+    pub fn set_pc(&mut self, pc: u16) {
+        match self {
+            MachineCpu::I8080(cpu) => cpu.pc = pc,
+            MachineCpu::Mos6502(cpu) => cpu.pc = pc,
+        }
+    }
+    pub fn processor(&self) -> &dyn Processor {
+        match self {
+            MachineCpu::I8080(cpu) => cpu.as_ref(),
+            MachineCpu::Mos6502(cpu) => cpu.as_ref(),
+        }
+    }
+}
+
+pub struct Machine {
+    pub name: String,
+    pub cpu_kind: CpuKind,
+    pub clock_hz: u32,
+    pub cpu: MachineCpu,
+    pub regions: Vec<RegionConfig>,
+    ///
+    /// Devices are descriptive only: they are validated and listed, but no
+    /// peripheral is emulated and nothing is mapped into memory or I/O space.
+    /// Front ends can use base address, port and interrupt line to attach their
+    /// own peripheral models.
+    ///
+    pub devices: Vec<DeviceConfig>,
+}
+
+impl Machine {
+    ///
+    /// Reads the machine description. Image files are searched relative
+    /// to the directory of the description file.
+    ///
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, MachineError> {
+        let path = path.as_ref();
+        let text =
+            fs::read_to_string(path).map_err(|err| MachineError::Io(path.to_path_buf(), err))?;
+        let config = MachineConfig::from_json(&text).map_err(MachineError::Parse)?;
+        Self::build(&config, path.parent().unwrap_or(Path::new(".")))
+    }
+
+    ///
+    /// Only the 8080 and 6502 have emulation cores. "8085" and "65C02" are valid
+    /// in the description but fail here with MachineError::UnsupportedCpu.
+    ///
+    pub fn build(config: &MachineConfig, base_dir: &Path) -> Result<Self, MachineError> {
+        let mut cpu = match config.cpu {
+            CpuKind::I8080 => {
+                let mut cpu = i8080::Cpu::new();
+                cpu.set_debug(false);
+                MachineCpu::I8080(Box::new(cpu))
+            }
+            CpuKind::Mos6502 => {
+                let mut cpu = mos6502::Cpu::new();
+                cpu.set_debug(false);
+                MachineCpu::Mos6502(Box::new(cpu))
+            }
+            kind => return Err(MachineError::UnsupportedCpu(kind)),
+        };
+
+        for (index, region) in config.memory.iter().enumerate() {
+            if region.size == 0 || region.start as u32 + region.size > 0x10000 {
+                return Err(MachineError::RegionOutOfRange {
+                    start: region.start,
+                    size: region.size,
+                });
+            }
+            let end = region.start as u32 + region.size;
+            if let Some(other) = config.memory[..index].iter().find(|other| {
+                (region.start as u32) < other.start as u32 + other.size
+                    && (other.start as u32) < end
+            }) {
+                return Err(MachineError::RegionOverlap {
+                    first: other.start,
+                    second: region.start,
+                });
+            }
+            if let Some(image) = &region.image {
+                let image_path = base_dir.join(image);
+                let data =
+                    fs::read(&image_path).map_err(|err| MachineError::Io(image_path, err))?;
+                load_image(cpu.memory_mut(), region, image, &data)?;
+            }
+        }
+        // ROM is protected only after all images are in place
+        for region in config.memory.iter().filter(|r| r.kind == RegionKind::Rom) {
+            let end = (region.start as u32 + region.size - 1) as u16;
+            cpu.memory_mut().set_read_only(region.start, end);
+        }
+
+        for device in &config.devices {
+            validate_device(config.cpu, device)?;
+        }
+
+        let start = match (config.start, &cpu) {
+            (Some(start), _) => start,
+            (None, MachineCpu::Mos6502(cpu)) => cpu.memory.read_word(0xFFFC),
+            (None, MachineCpu::I8080(_)) => 0x0000,
+        };
+        cpu.set_pc(start);
+
+        Ok(Self {
+            name: config.name.clone(),
+            cpu_kind: config.cpu,
+            clock_hz: config.clock_hz,
+            cpu,
+            regions: config.memory.clone(),
+            devices: config.devices.clone(),
+        })
+    }
+}
+
+///
+/// Summary of the machine: CPU, clock and start address, then one line
+/// per memory region and device
+///
+impl fmt::Display for Machine {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = if self.name.is_empty() {
+            "machine"
+        } else {
+            &self.name
+        };
+        write!(f, "{}: {:?}", name, self.cpu_kind)?;
+        if self.clock_hz > 0 {
+            write!(f, " at {} Hz", self.clock_hz)?;
+        }
+        writeln!(f, ", start {:04X}", self.cpu.processor().pc())?;
+        for region in &self.regions {
+            let end = region.start as u32 + region.size - 1;
+            let kind = match region.kind {
+                RegionKind::Ram => "RAM",
+                RegionKind::Rom => "ROM",
+            };
+            write!(f, "{} {:04X}-{:04X}", kind, region.start, end)?;
+            if let Some(image) = &region.image {
+                write!(f, " {}", image)?;
+            }
+            writeln!(f)?;
+        }
+        for device in &self.devices {
+            write!(f, "device {} {}", device.name, device.kind)?;
+            if let Some(base) = device.base {
+                write!(f, " base {:04X}", base)?;
+            }
+            if let Some(port) = device.port {
+                write!(f, " port {:02X}", port)?;
+            }
+            if let Some(irq) = &device.irq {
+                write!(f, " {}", irq)?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+fn load_image(
+    memory: &mut Memory,
+    region: &RegionConfig,
+    image: &str,
+    data: &[u8],
+) -> Result<(), MachineError> {
+    let mut loaded = Image::default();
+    match region.format {
+        ImageFormat::Raw => loaded.push(region.start, data),
+        ImageFormat::Acme => {
+            let (address, payload) = split_acme_header(data)
+                .map_err(|err| MachineError::InvalidImage(format!("{}: {}", image, err)))?;
+            loaded.push(address, payload);
+        }
+        ImageFormat::Ihex | ImageFormat::Srec => {
+            let text = String::from_utf8_lossy(data);
+            let parsed = if region.format == ImageFormat::Ihex {
+                ihex::parse(&text)
+            } else {
+                srec::parse(&text)
+            };
+            loaded =
+                parsed.map_err(|err| MachineError::InvalidImage(format!("{}: {}", image, err)))?;
+        }
+    };
+    let region_end = region.start as u32 + region.size;
+    for segment in &loaded.segments {
+        if segment.data.len() as u32 > region.size {
+            return Err(MachineError::ImageTooLarge {
+                image: image.to_string(),
+                size: segment.data.len(),
+                region_size: region.size,
+            });
+        }
+        if segment.address < region.start
+            || segment.address as u32 + segment.data.len() as u32 > region_end
+        {
+            return Err(MachineError::ImageOutsideRegion {
+                image: image.to_string(),
+                address: segment.address,
+                size: segment.data.len(),
+                region_start: region.start,
+                region_size: region.size,
+            });
+        }
+    }
+    memory.load_image(&loaded);
+    Ok(())
+}
+
+fn validate_device(cpu: CpuKind, device: &DeviceConfig) -> Result<(), MachineError> {
+    let intel = matches!(cpu, CpuKind::I8080 | CpuKind::I8085);
+    match (device.base, device.port) {
+        (None, None) => {
+            return Err(MachineError::InvalidDevice(format!(
+                "{} needs a base address or a port",
+                device.name
+            )));
+        }
+        (_, Some(port)) if !intel || port > 0xFF => {
+            return Err(MachineError::InvalidDevice(format!(
+                "{} uses port {:X}, the CPU has no such I/O port",
+                device.name, port
+            )));
+        }
+        _ => {}
+    }
+    if let Some(irq) = &device.irq {
+        let valid = if intel {
+            irq == "int"
+                || irq
+                    .strip_prefix("rst")
+                    .is_some_and(|n| matches!(n, "0" | "1" | "2" | "3" | "4" | "5" | "6" | "7"))
+        } else {
+            irq == "irq" || irq == "nmi"
+        };
+        if !valid {
+            return Err(MachineError::InvalidDevice(format!(
+                "{} is wired to unknown interrupt line '{}'",
+                device.name, irq
+            )));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(text: &str) -> MachineConfig {
+        MachineConfig::from_json(text).unwrap()
+    }
+
+    #[test]
+    ///
+    /// Addresses can be written in 6502, i8080, C or decimal notation
+    ///
+    fn parse_numbers() {
+        assert_eq!(config::parse_number("$FFFC"), Some(0xFFFC));
+        assert_eq!(config::parse_number("0FFFCH"), Some(0xFFFC));
+        assert_eq!(config::parse_number("0x1C00"), Some(0x1C00));
+        assert_eq!(config::parse_number("1024"), Some(1024));
+        assert_eq!(config::parse_number("$XY"), None);
+    }
+
+    #[test]
+    ///
+    /// Builds a 6502 machine and verifies that PC is taken from the reset vector
+    /// and ROM is write protected
+    ///
+    fn build_6502_from_reset_vector() {
+        let dir =
+            std::env::temp_dir().join(format!("sbc8micro_machine_6502_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let mut rom = vec![0xEA; 0x1000];
+        rom[0x0FFC] = 0x00;
+        rom[0x0FFD] = 0xF8;
+        fs::write(dir.join("rom.bin"), &rom).unwrap();
+        let cfg = config(
+            r#"{
+                "name": "test board",
+                "cpu": "6502",
+                "clock_hz": 1000000,
+                "memory": [
+                    { "kind": "ram", "start": "$0000", "size": "$8000" },
+                    { "kind": "rom", "start": "$F000", "size": 4096, "image": "rom.bin" }
+                ],
+                "devices": [ { "name": "via", "kind": "6522", "base": "$8000", "irq": "irq" } ]
+            }"#,
+        );
+        let mut machine = Machine::build(&cfg, &dir).unwrap();
+        let MachineCpu::Mos6502(cpu) = &mut machine.cpu else {
+            panic!("wrong cpu");
+        };
+        assert_eq!(cpu.pc, 0xF800);
+        cpu.memory.write_byte(0xF800, 0x00);
+        assert_eq!(cpu.memory.read_byte(0xF800), 0xEA);
+        assert_eq!(machine.devices.len(), 1);
+        assert_eq!(
+            machine.to_string(),
+            "test board: Mos6502 at 1000000 Hz, start F800\n\
+             RAM 0000-7FFF\n\
+             ROM F000-FFFF rom.bin\n\
+             device via 6522 base 8000 irq\n"
+        );
+    }
+
+    #[test]
+    ///
+    /// Unsupported CPUs and ports on a 6502 are reported
+    ///
+    fn build_errors() {
+        let dir = std::env::temp_dir();
+        let cfg = config(r#"{ "cpu": "65C02" }"#);
+        assert!(matches!(
+            Machine::build(&cfg, &dir),
+            Err(MachineError::UnsupportedCpu(CpuKind::Wdc65C02))
+        ));
+        let cfg = config(
+            r#"{ "cpu": "6502", "devices": [ { "name": "ppi", "kind": "8255", "port": "0F8H" } ] }"#,
+        );
+        assert!(matches!(
+            Machine::build(&cfg, &dir),
+            Err(MachineError::InvalidDevice(_))
+        ));
+        let cfg = config(
+            r#"{ "cpu": "8080", "memory": [ { "kind": "ram", "start": "0FC00H", "size": "800H" } ] }"#,
+        );
+        assert!(matches!(
+            Machine::build(&cfg, &dir),
+            Err(MachineError::RegionOutOfRange { .. })
+        ));
+        let cfg = config(
+            r#"{ "cpu": "8080", "memory": [
+                { "kind": "rom", "start": "0000H", "size": "0400H" },
+                { "kind": "ram", "start": "0300H", "size": "0400H" }
+            ] }"#,
+        );
+        assert!(matches!(
+            Machine::build(&cfg, &dir),
+            Err(MachineError::RegionOverlap {
+                first: 0x0000,
+                second: 0x0300
+            })
+        ));
+    }
+
+    #[test]
+    ///
+    /// An image that fits into its region but carries a load address outside of it
+    /// is reported separately from an image that is too large
+    ///
+    fn build_image_errors() {
+        let dir =
+            std::env::temp_dir().join(format!("sbc8micro_machine_image_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("low.prg"), [0x00, 0x02, 0xEA, 0xEA]).unwrap();
+        fs::write(dir.join("big.bin"), [0xEA; 0x20]).unwrap();
+        let cfg = config(
+            r#"{ "cpu": "6502", "memory": [
+                { "kind": "rom", "start": "$F000", "size": "$10", "image": "low.prg", "format": "acme" }
+            ] }"#,
+        );
+        let err = Machine::build(&cfg, &dir).err().unwrap();
+        assert!(matches!(
+            err,
+            MachineError::ImageOutsideRegion {
+                address: 0x0200,
+                size: 2,
+                ..
+            }
+        ));
+        assert_eq!(
+            err.to_string(),
+            "image low.prg loads 2 bytes at 0200, outside region F000-F00F"
+        );
+        let cfg = config(
+            r#"{ "cpu": "6502", "memory": [
+                { "kind": "rom", "start": "$F000", "size": "$10", "image": "big.bin" }
+            ] }"#,
+        );
+        assert!(matches!(
+            Machine::build(&cfg, &dir),
+            Err(MachineError::ImageTooLarge { size: 0x20, .. })
+        ));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    ///
+    /// 8080 machine with explicit start address and device on a port
+    ///
+    fn build_8080() {
+        let cfg = config(
+            r#"{
+                "cpu": "8080",
+                "memory": [ { "kind": "ram", "start": 0, "size": "10000H" } ],
+                "devices": [ { "name": "ppi", "kind": "8255", "port": "0F8H", "irq": "rst7" } ],
+                "start": "0100H"
+            }"#,
+        );
+        let machine = Machine::build(&cfg, &std::env::temp_dir()).unwrap();
+        let MachineCpu::I8080(cpu) = &machine.cpu else {
+            panic!("wrong cpu");
+        };
+        assert_eq!(cpu.pc, 0x0100);
+        assert_eq!(
+            machine.to_string(),
+            "machine: I8080, start 0100\nRAM 0000-FFFF\ndevice ppi 8255 port F8 rst7\n"
+        );
+    }
+}
@@ -0,0 +1,331 @@
+//////////////////////////////////////////////////////////
+/// Machine state snapshots. A snapshot is a JSON file with the CPU kind,
+/// every register (flags are part of P / PSW), the I/O port latches of the
+/// 8080, the write protected ranges and the memory contents:
+///
+/// {
+///   "format": "sbc8micro snapshot",
+///   "version": 1,
+///   "machine": "KIM-1",
+///   "cpu": "6502",
+///   "registers": { "A": 1, "P": 52, "PC": 7170, "SP": 253, "X": 0, "Y": 0 },
+///   "read_only": [[6144, 8191]],
+///   "memory": [ { "address": 0, "data": "A9018D0002" }, ... ]
+/// }
+///
+/// Runs of 32 or more zero bytes are left out of the memory blocks, memory
+/// not listed is zero after loading. The cores have no halted or interrupt
+/// state of their own (the 6502 I flag lives in P) and devices are only
+/// described by the machine file, so the port latches are all the device
+/// state there is.
+///
+/// ```
+/// Snapshot::capture(&cpu).save("monitor.snap.json")?;
+/// Snapshot::load("monitor.snap.json")?.restore(&mut cpu)?;
+/// ```
+//////////////////////////////////////////////////////////
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::cpu::Processor;
+use crate::machine::config::CpuKind;
+use crate::memory::ihex::decode_hex;
+
+pub const FORMAT: &str = "sbc8micro snapshot";
+pub const VERSION: u32 = 1;
+
+///
+/// Zero runs of at least this length split the memory into blocks
+///
+const ZERO_RUN: usize = 32;
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    Io(PathBuf, std::io::Error),
+    Parse(serde_json::Error),
+    UnsupportedFormat(String),
+    UnsupportedVersion(u32),
+    CpuMismatch { snapshot: CpuKind, cpu: CpuKind },
+    InvalidData(String),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SnapshotError::Io(path, err) => write!(f, "{}: {}", path.display(), err),
+            SnapshotError::Parse(err) => write!(f, "invalid snapshot: {}", err),
+            SnapshotError::UnsupportedFormat(format) => {
+                write!(f, "not a snapshot file (format \"{}\")", format)
+            }
+            SnapshotError::UnsupportedVersion(version) => write!(
+                f,
+                "snapshot version {} is not supported, expected {}",
+                version, VERSION
+            ),
+            SnapshotError::CpuMismatch { snapshot, cpu } => write!(
+                f,
+                "snapshot of a {:?} cannot be restored into a {:?}",
+                snapshot, cpu
+            ),
+            SnapshotError::InvalidData(msg) => write!(f, "invalid snapshot data: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Block {
+    pub address: u16,
+    ///
+    /// Bytes as hex digits without separators
+    ///
+    pub data: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub format: String,
+    pub version: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub machine: Option<String>,
+    pub cpu: CpuKind,
+    pub registers: BTreeMap<String, u16>,
+    ///
+    /// I/O port latches as hex digits, only for CPUs with I/O space
+    ///
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub ports: String,
+    #[serde(default)]
+    pub read_only: Vec<(u16, u16)>,
+    pub memory: Vec<Block>,
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02X}", b)).collect()
+}
+
+///
+/// Splits the memory at zero runs of ZERO_RUN bytes or more
+///
+fn blocks(data: &[u8]) -> Vec<Block> {
+    let mut blocks = Vec::new();
+    let mut start: Option<usize> = None;
+    let mut zeros = 0;
+    for (addr, &byte) in data.iter().enumerate() {
+        if byte == 0 {
+            zeros += 1;
+            if zeros == ZERO_RUN
+                && let Some(first) = start.take()
+            {
+                blocks.push((first, addr + 1 - ZERO_RUN));
+            }
+        } else {
+            zeros = 0;
+            start.get_or_insert(addr);
+        }
+    }
+    if let Some(first) = start {
+        blocks.push((first, data.len() - zeros));
+    }
+    blocks
+        .into_iter()
+        .map(|(first, end)| Block {
+            address: first as u16,
+            data: to_hex(&data[first..end]),
+        })
+        .collect()
+}
+
+impl Snapshot {
+    ///
+    /// Takes the complete state of a CPU and its memory
+    ///
+    pub fn capture(cpu: &dyn Processor) -> Self {
+        let registers = cpu
+            .register_layout()
+            .iter()
+            .map(|(name, _)| (name.to_string(), cpu.register(name).unwrap_or(0)))
+            .collect();
+        Self {
+            format: FORMAT.to_string(),
+            version: VERSION,
+            machine: None,
+            cpu: cpu.kind(),
+            registers,
+            ports: to_hex(cpu.ports()),
+            read_only: cpu.memory().read_only_ranges().to_vec(),
+            memory: blocks(&cpu.memory().get_data()),
+        }
+    }
+    ///
+    /// Puts the CPU and its memory into the saved state. Nothing is changed
+    /// if the snapshot does not fit the CPU.
+    ///
+    pub fn restore(&self, cpu: &mut dyn Processor) -> Result<(), SnapshotError> {
+        self.check()?;
+        if self.cpu != cpu.kind() {
+            return Err(SnapshotError::CpuMismatch {
+                snapshot: self.cpu,
+                cpu: cpu.kind(),
+            });
+        }
+        for name in self.registers.keys() {
+            if cpu.register(name).is_none() {
+                return Err(SnapshotError::InvalidData(format!(
+                    "unknown register {}",
+                    name
+                )));
+            }
+        }
+        let ports = decode_hex(&self.ports)
+            .ok_or_else(|| SnapshotError::InvalidData("ports are not hex digits".to_string()))?;
+        if ports.len() > cpu.ports().len() {
+            return Err(SnapshotError::InvalidData(format!(
+                "{} ports, the CPU has {}",
+                ports.len(),
+                cpu.ports().len()
+            )));
+        }
+        let mut data = Box::new([0u8; 0x10000]);
+        for block in &self.memory {
+            let bytes = decode_hex(&block.data).ok_or_else(|| {
+                SnapshotError::InvalidData(format!("block {:04X} is not hex digits", block.address))
+            })?;
+            let start = block.address as usize;
+            if start + bytes.len() > data.len() {
+                return Err(SnapshotError::InvalidData(format!(
+                    "block {:04X} does not fit into 64KB",
+                    block.address
+                )));
+            }
+            data[start..start + bytes.len()].copy_from_slice(&bytes);
+        }
+
+        let memory = cpu.memory_mut();
+        memory.set_data(&data);
+        memory.clear_read_only();
+        for &(start, end) in &self.read_only {
+            memory.set_read_only(start, end);
+        }
+        cpu.ports_mut()[..ports.len()].copy_from_slice(&ports);
+        for (name, &value) in &self.registers {
+            cpu.set_register(name, value);
+        }
+        Ok(())
+    }
+    fn check(&self) -> Result<(), SnapshotError> {
+        if self.format != FORMAT {
+            return Err(SnapshotError::UnsupportedFormat(self.format.clone()));
+        }
+        if self.version != VERSION {
+            return Err(SnapshotError::UnsupportedVersion(self.version));
+        }
+        Ok(())
+    }
+    pub fn from_json(text: &str) -> Result<Self, SnapshotError> {
+        let snapshot: Self = serde_json::from_str(text).map_err(SnapshotError::Parse)?;
+        snapshot.check()?;
+        Ok(snapshot)
+    }
+    pub fn to_json(&self) -> String {
+        // Serializing plain strings, numbers and maps cannot fail
+        serde_json::to_string_pretty(self).unwrap()
+    }
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, SnapshotError> {
+        let path = path.as_ref();
+        let text =
+            fs::read_to_string(path).map_err(|err| SnapshotError::Io(path.to_path_buf(), err))?;
+        Self::from_json(&text)
+    }
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), SnapshotError> {
+        let path = path.as_ref();
+        fs::write(path, self.to_json()).map_err(|err| SnapshotError::Io(path.to_path_buf(), err))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::{i8080, mos6502};
+
+    #[test]
+    ///
+    /// A 6502 comes back with registers, memory and ROM ranges and runs on
+    /// exactly like the original
+    ///
+    fn round_trip_6502() {
+        let mut cpu = mos6502::Cpu::new();
+        // LDA #$01; STA $0200; INX; JMP $0600
+        cpu.load_program(
+            &[0xA9, 0x01, 0x8D, 0x00, 0x02, 0xE8, 0x4C, 0x00, 0x06],
+            0x0600,
+        );
+        cpu.memory.write_byte(0x8000, 0x42);
+        cpu.memory.write_byte(0xFFFC, 0x00);
+        cpu.memory.write_byte(0xFFFD, 0x06);
+        cpu.memory.set_read_only(0xF000, 0xFFFF);
+        for _ in 0..5 {
+            cpu.step();
+        }
+        let path =
+            std::env::temp_dir().join(format!("sbc8micro_snapshot_{}.json", std::process::id()));
+        Snapshot::capture(&cpu).save(&path).unwrap();
+
+        let mut copy = mos6502::Cpu::new();
+        copy.memory.write_byte(0x1234, 0x99);
+        Snapshot::load(&path).unwrap().restore(&mut copy).unwrap();
+        assert_eq!(copy.memory.read_byte(0x1234), 0x00);
+        assert_eq!(copy.memory.get_data(), cpu.memory.get_data());
+        assert_eq!(copy.memory.read_only_ranges(), &[(0xF000, 0xFFFF)]);
+        for _ in 0..7 {
+            cpu.step();
+            copy.step();
+            assert_eq!(
+                (copy.a, copy.x, copy.y, copy.sp, copy.pc, copy.p.value),
+                (cpu.a, cpu.x, cpu.y, cpu.sp, cpu.pc, cpu.p.value)
+            );
+        }
+    }
+
+    #[test]
+    ///
+    /// 8080 flags and port latches are saved, wrong versions and CPUs rejected
+    ///
+    fn round_trip_8080() {
+        let mut cpu = i8080::Cpu::new();
+        // MVI A,55H; ADI 0ABH; OUT 10H
+        cpu.load_program(&[0x3E, 0x55, 0xC6, 0xAB, 0xD3, 0x10], 0x0100);
+        for _ in 0..3 {
+            cpu.step();
+        }
+        let snapshot = Snapshot::capture(&cpu);
+        assert_eq!(snapshot.memory.len(), 1);
+        let text = snapshot.to_json();
+
+        let mut copy = i8080::Cpu::new();
+        Snapshot::from_json(&text)
+            .unwrap()
+            .restore(&mut copy)
+            .unwrap();
+        assert_eq!(
+            (copy.a, copy.psw.value, copy.pc, copy.ports[0x10]),
+            (cpu.a, cpu.psw.value, cpu.pc, cpu.ports[0x10])
+        );
+
+        let newer = text.replace("\"version\": 1", "\"version\": 2");
+        assert!(matches!(
+            Snapshot::from_json(&newer),
+            Err(SnapshotError::UnsupportedVersion(2))
+        ));
+        let mut other = mos6502::Cpu::new();
+        assert!(matches!(
+            snapshot.restore(&mut other),
+            Err(SnapshotError::CpuMismatch { .. })
+        ));
+    }
+}
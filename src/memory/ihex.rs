@@ -0,0 +1,294 @@
+//////////////////////////////////////////////////////////
+/// Intel HEX reader and writer.
+/// Supported records:
+/// 00 data, 01 end of file, 02 extended segment address, 03 start segment address,
+/// 04 extended linear address, 05 start linear address.
+///
+/// ```
+/// mod memory;
+///
+/// fn main() {
+///     let mut memory = memory::Memory::new();
+///     let image = memory.load_intel_hex_file("monitor.hex").unwrap();
+///     println!("start address {:04X?}", image.start);
+///     print!("{}", memory.to_intel_hex(0x0000, 0x03FF, None));
+/// }
+/// ```
+//////////////////////////////////////////////////////////
+use crate::memory::{Image, LoadError, Memory};
+
+const DATA: u8 = 0x00;
+const END_OF_FILE: u8 = 0x01;
+const EXTENDED_SEGMENT_ADDRESS: u8 = 0x02;
+const START_SEGMENT_ADDRESS: u8 = 0x03;
+const EXTENDED_LINEAR_ADDRESS: u8 = 0x04;
+const START_LINEAR_ADDRESS: u8 = 0x05;
+
+const BYTES_PER_RECORD: usize = 16;
+
+///
+/// Parses Intel HEX text. Addresses above $FFFF are reported as an error
+/// because the memory of both CPUs is 64KB.
+///
+pub fn parse(text: &str) -> Result<Image, LoadError> {
+    let mut image = Image::default();
+    let mut base: u32 = 0;
+
+    for (index, line) in text.lines().enumerate() {
+        let line_no = index + 1;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let record = decode_record(line, line_no)?;
+        let (count, offset, kind) = (
+            record[0] as usize,
+            u16::from_be_bytes([record[1], record[2]]),
+            record[3],
+        );
+        let data = &record[4..4 + count];
+        match kind {
+            DATA => {
+                let address = base + offset as u32;
+                if address + count as u32 > 0x10000 {
+                    return Err(LoadError::AddressOutOfRange {
+                        line: line_no,
+                        address: address + count as u32 - 1,
+                    });
+                }
+                image.push(address as u16, data);
+            }
+            END_OF_FILE => return Ok(image),
+            EXTENDED_SEGMENT_ADDRESS | EXTENDED_LINEAR_ADDRESS => {
+                if count != 2 {
+                    return Err(syntax(line_no, "address record must have 2 data bytes"));
+                }
+                let value = u16::from_be_bytes([data[0], data[1]]) as u32;
+                base = if kind == EXTENDED_SEGMENT_ADDRESS {
+                    value << 4
+                } else {
+                    value << 16
+                };
+            }
+            START_SEGMENT_ADDRESS | START_LINEAR_ADDRESS => {
+                if count != 4 {
+                    return Err(syntax(line_no, "start record must have 4 data bytes"));
+                }
+                let start = if kind == START_SEGMENT_ADDRESS {
+                    let cs = u16::from_be_bytes([data[0], data[1]]) as u32;
+                    let ip = u16::from_be_bytes([data[2], data[3]]) as u32;
+                    (cs << 4) + ip
+                } else {
+                    u32::from_be_bytes([data[0], data[1], data[2], data[3]])
+                };
+                image.start =
+                    Some(
+                        u16::try_from(start).map_err(|_| LoadError::AddressOutOfRange {
+                            line: line_no,
+                            address: start,
+                        })?,
+                    );
+            }
+            _ => {
+                return Err(syntax(
+                    line_no,
+                    &format!("unknown record type {:02X}", kind),
+                ));
+            }
+        }
+    }
+    Err(syntax(text.lines().count(), "missing end of file record"))
+}
+
+///
+/// Decodes one ':LLAAAATT..CC' line to bytes and verifies its checksum.
+/// The checksum is not part of the returned record.
+///
+fn decode_record(line: &str, line_no: usize) -> Result<Vec<u8>, LoadError> {
+    let hex = line
+        .strip_prefix(':')
+        .ok_or_else(|| syntax(line_no, "record does not start with ':'"))?;
+    let bytes = decode_hex(hex).ok_or_else(|| syntax(line_no, "invalid hex digits"))?;
+    if bytes.len() < 5 || bytes.len() != bytes[0] as usize + 5 {
+        return Err(syntax(line_no, "record length does not match byte count"));
+    }
+    let (record, checksum) = bytes.split_at(bytes.len() - 1);
+    let expected = checksum_of(record);
+    if expected != checksum[0] {
+        return Err(LoadError::Checksum {
+            line: line_no,
+            expected,
+            found: checksum[0],
+        });
+    }
+    Ok(record.to_vec())
+}
+
+pub(crate) fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
+
+///
+/// Two's complement of the sum of all bytes of the record
+///
+fn checksum_of(record: &[u8]) -> u8 {
+    record
+        .iter()
+        .fold(0u8, |sum, &byte| sum.wrapping_add(byte))
+        .wrapping_neg()
+}
+
+fn syntax(line: usize, message: &str) -> LoadError {
+    LoadError::Syntax {
+        line,
+        message: message.to_string(),
+    }
+}
+
+fn push_record(output: &mut String, kind: u8, address: u16, data: &[u8]) {
+    let mut record = vec![data.len() as u8];
+    record.extend_from_slice(&address.to_be_bytes());
+    record.push(kind);
+    record.extend_from_slice(data);
+    output.push(':');
+    for byte in &record {
+        output.push_str(&format!("{:02X}", byte));
+    }
+    output.push_str(&format!("{:02X}\n", checksum_of(&record)));
+}
+
+///
+/// Writes memory range start..=end as Intel HEX with 16 data bytes per record.
+/// If start address is given, a start linear address record is added before
+/// the end of file record.
+///
+pub fn write(memory: &Memory, start: u16, end: u16, start_address: Option<u16>) -> String {
+    let mut output = String::new();
+    let mut address = start as u32;
+    while address <= end as u32 {
+        let count = (end as u32 - address + 1).min(BYTES_PER_RECORD as u32);
+        let data: Vec<u8> = (address..address + count)
+            .map(|a| memory.read_byte(a as u16))
+            .collect();
+        push_record(&mut output, DATA, address as u16, &data);
+        address += count;
+    }
+    push_end(&mut output, start_address);
+    output
+}
+
+///
+/// Writes the segments of an image (e.g. assembler output) and its start
+/// address as Intel HEX, gaps between the segments are left out
+///
+pub fn write_image(image: &Image) -> String {
+    let mut output = String::new();
+    for segment in &image.segments {
+        for (i, data) in segment.data.chunks(BYTES_PER_RECORD).enumerate() {
+            let address = segment.address.wrapping_add((i * BYTES_PER_RECORD) as u16);
+            push_record(&mut output, DATA, address, data);
+        }
+    }
+    push_end(&mut output, image.start);
+    output
+}
+
+///
+/// Start linear address record (if any) and end of file record
+///
+fn push_end(output: &mut String, start_address: Option<u16>) {
+    if let Some(start_address) = start_address {
+        push_record(
+            output,
+            START_LINEAR_ADDRESS,
+            0,
+            &(start_address as u32).to_be_bytes(),
+        );
+    }
+    push_record(output, END_OF_FILE, 0, &[]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    ///
+    /// Parses two data records, start address and end of file
+    ///
+    fn parse_data_and_start() {
+        let image = parse(
+            ":0300000002000CEF\n\
+             :020003000102F8\n\
+             :0400000500000100F6\n\
+             :00000001FF\n",
+        )
+        .unwrap();
+        assert_eq!(image.segments.len(), 1);
+        assert_eq!(image.segments[0].address, 0x0000);
+        assert_eq!(image.segments[0].data, vec![0x02, 0x00, 0x0C, 0x01, 0x02]);
+        assert_eq!(image.start, Some(0x0100));
+    }
+
+    #[test]
+    ///
+    /// Extended segment address moves the data to base * 16
+    ///
+    fn parse_extended_segment() {
+        let image = parse(":020000020100FB\n:01000000AA55\n:00000001FF\n").unwrap();
+        assert_eq!(image.segments[0].address, 0x1000);
+        assert_eq!(image.segments[0].data, vec![0xAA]);
+    }
+
+    #[test]
+    ///
+    /// Wrong checksum, missing end of file and data above 64KB are reported
+    ///
+    fn parse_errors() {
+        assert!(matches!(
+            parse(":01000000AA56\n:00000001FF\n"),
+            Err(LoadError::Checksum {
+                line: 1,
+                expected: 0x55,
+                found: 0x56
+            })
+        ));
+        assert!(matches!(
+            parse(":01000000AA55\n"),
+            Err(LoadError::Syntax { .. })
+        ));
+        assert!(matches!(
+            parse(":020000040001F9\n:01000000AA55\n:00000001FF\n"),
+            Err(LoadError::AddressOutOfRange {
+                line: 2,
+                address: 0x10000
+            })
+        ));
+    }
+
+    #[test]
+    ///
+    /// Memory range written as HEX is loaded back to the same addresses
+    ///
+    fn write_and_load_back() {
+        let mut memory = Memory::new();
+        for i in 0..20u16 {
+            memory.write_byte(0x0800 + i, i as u8 * 3);
+        }
+        let text = memory.to_intel_hex(0x0800, 0x0813, Some(0x0800));
+        assert!(text.starts_with(":10080000"));
+        assert!(text.ends_with(":00000001FF\n"));
+        let mut copy = Memory::new();
+        let image = copy.load_intel_hex(&text).unwrap();
+        assert_eq!(image.start, Some(0x0800));
+        for i in 0..20u16 {
+            assert_eq!(copy.read_byte(0x0800 + i), i as u8 * 3);
+        }
+    }
+}
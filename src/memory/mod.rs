@@ -1,26 +1,269 @@
-use std::fs::File;
-use std::io::{self, Read};
+pub mod ihex;
+pub mod srec;
 
-const CAPACITY: usize = 0x10000;
+use std::cell::RefCell;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+pub const CAPACITY: usize = 0x10000;
+
+#[derive(Debug)]
+pub enum LoadError {
+    Io(io::Error),
+    FileNotFound(PathBuf),
+    TruncatedHeader {
+        length: usize,
+    },
+    ImageTooLarge {
+        length: usize,
+    },
+    AddressOverflow {
+        address: u16,
+        length: usize,
+    },
+    Syntax {
+        line: usize,
+        message: String,
+    },
+    Checksum {
+        line: usize,
+        expected: u8,
+        found: u8,
+    },
+    AddressOutOfRange {
+        line: usize,
+        address: u32,
+    },
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::Io(err) => write!(f, "{}", err),
+            LoadError::FileNotFound(path) => write!(f, "file {} not found", path.display()),
+            LoadError::TruncatedHeader { length } => write!(
+                f,
+                "file has {} bytes, it is too short for the 2 byte load address",
+                length
+            ),
+            LoadError::ImageTooLarge { length } => {
+                write!(f, "image has {} bytes, it does not fit into 64KB", length)
+            }
+            LoadError::AddressOverflow { address, length } => write!(
+                f,
+                "image with {} bytes loaded at {:04X} goes past FFFF",
+                length, address
+            ),
+            LoadError::Syntax { line, message } => write!(f, "line {}: {}", line, message),
+            LoadError::Checksum {
+                line,
+                expected,
+                found,
+            } => write!(
+                f,
+                "line {}: checksum {:02X} does not match calculated {:02X}",
+                line, found, expected
+            ),
+            LoadError::AddressOutOfRange { line, address } => {
+                write!(f, "line {}: address {:X} is out of 64KB", line, address)
+            }
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+impl From<io::Error> for LoadError {
+    fn from(err: io::Error) -> Self {
+        LoadError::Io(err)
+    }
+}
+
+///
+/// What to do with an image that goes past $FFFF
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Overflow {
+    #[default]
+    Error,
+    Wrap,
+}
+
+///
+/// Load address and length of the payload (without any header) of a loaded program
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Loaded {
+    pub address: u16,
+    pub length: usize,
+}
+
+pub(crate) fn read_file<P: AsRef<Path>>(file_name: P) -> Result<Vec<u8>, LoadError> {
+    let path = file_name.as_ref();
+    fs::read(path).map_err(|err| match err.kind() {
+        io::ErrorKind::NotFound => LoadError::FileNotFound(path.to_path_buf()),
+        _ => LoadError::Io(err),
+    })
+}
+
+pub(crate) fn read_text_file<P: AsRef<Path>>(file_name: P) -> Result<String, LoadError> {
+    let data = read_file(file_name)?;
+    String::from_utf8(data)
+        .map_err(|err| LoadError::Io(io::Error::new(io::ErrorKind::InvalidData, err)))
+}
+
+///
+/// Splits ACME "cbm" output into the load address (first 2 bytes, little endian)
+/// and the payload
+///
+pub fn split_acme_header(buffer: &[u8]) -> Result<(u16, &[u8]), LoadError> {
+    if buffer.len() < 2 {
+        return Err(LoadError::TruncatedHeader {
+            length: buffer.len(),
+        });
+    }
+    Ok((u16::from_le_bytes([buffer[0], buffer[1]]), &buffer[2..]))
+}
+
+///
+/// Continuous block of data read from a file
+///
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Segment {
+    pub address: u16,
+    pub data: Vec<u8>,
+}
+
+///
+/// Content of a loadable file (Intel HEX, ...) and its start address if the file has one
+///
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Image {
+    pub segments: Vec<Segment>,
+    pub start: Option<u16>,
+}
+
+impl Image {
+    ///
+    /// Adds data at address, consecutive blocks are merged into one segment
+    ///
+    pub fn push(&mut self, address: u16, data: &[u8]) {
+        if let Some(last) = self.segments.last_mut()
+            && last.address as usize + last.data.len() == address as usize
+        {
+            last.data.extend_from_slice(data);
+            return;
+        }
+        self.segments.push(Segment {
+            address,
+            data: data.to_vec(),
+        });
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessKind {
+    Read,
+    Write,
+}
+
+///
+/// One byte read or written by the CPU, recorded while tracking is on
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Access {
+    pub addr: u16,
+    pub value: u8,
+    pub kind: AccessKind,
+}
 
 pub struct Memory {
     data: [u8; CAPACITY], // 64KB
+    read_only: Vec<(u16, u16)>,
+    // read_byte() takes &self, so the log needs interior mutability
+    accesses: Option<RefCell<Vec<Access>>>,
 }
 
 impl Memory {
     pub fn new() -> Self {
         Self {
             data: [0; CAPACITY],
+            read_only: Vec::new(),
+            accesses: None,
         }
     }
 
     pub fn read_byte(&self, addr: u16) -> u8 {
-        self.data[addr as usize]
+        let value = self.data[addr as usize];
+        self.record(addr, value, AccessKind::Read);
+        value
     }
 
     pub fn write_byte(&mut self, addr: u16, value: u8) {
+        self.record(addr, value, AccessKind::Write);
+        if self.is_read_only(addr) {
+            return;
+        }
         self.data[addr as usize] = value;
     }
+    ///
+    /// Marks the range start..=end as ROM. Writes from the CPU into this range
+    /// are ignored, load_program() and the file loaders still fill it.
+    ///
+    pub fn set_read_only(&mut self, start: u16, end: u16) {
+        self.read_only.push((start, end));
+    }
+    pub fn read_only_ranges(&self) -> &[(u16, u16)] {
+        &self.read_only
+    }
+    pub fn clear_read_only(&mut self) {
+        self.read_only.clear();
+    }
+    pub fn is_read_only(&self, addr: u16) -> bool {
+        self.read_only
+            .iter()
+            .any(|&(start, end)| addr >= start && addr <= end)
+    }
+    ///
+    /// Turns recording of reads and writes on or off. Watchpoints use it,
+    /// it is off by default as it slows every access down.
+    ///
+    pub fn set_tracking(&mut self, on: bool) {
+        self.accesses = if on {
+            Some(RefCell::new(Vec::new()))
+        } else {
+            None
+        };
+    }
+    pub fn is_tracking(&self) -> bool {
+        self.accesses.is_some()
+    }
+    ///
+    /// Number of accesses recorded so far, a mark for accesses_since()
+    ///
+    pub fn access_count(&self) -> usize {
+        self.accesses.as_ref().map_or(0, |log| log.borrow().len())
+    }
+    ///
+    /// Accesses recorded after the mark returned by access_count()
+    ///
+    pub fn accesses_since(&self, mark: usize) -> Vec<Access> {
+        self.accesses.as_ref().map_or_else(Vec::new, |log| {
+            log.borrow().get(mark..).unwrap_or(&[]).to_vec()
+        })
+    }
+    pub fn clear_accesses(&self) {
+        if let Some(log) = &self.accesses {
+            log.borrow_mut().clear();
+        }
+    }
+    fn record(&self, addr: u16, value: u8, kind: AccessKind) {
+        if let Some(log) = &self.accesses {
+            log.borrow_mut().push(Access { addr, value, kind });
+        }
+    }
 
     pub fn read_word(&self, addr: u16) -> u16 {
         let lo = self.read_byte(addr) as u16;
@@ -46,71 +289,155 @@ impl Memory {
     pub fn write_word_zero_page(&mut self, addr: u8, value: u16) {
         self.write_word(addr as u16, value);
     }
+    ///
+    /// Copies program to memory. Bytes past $FFFF wrap around to $0000,
+    /// use load_program_checked() to get an error instead. Loading is not
+    /// a CPU access: it also fills ROM ranges and is not recorded while
+    /// tracking is on.
+    ///
     pub fn load_program(&mut self, program: &[u8], start_addr: u16) {
         for (i, &byte) in program.iter().enumerate() {
-            // Open the binary file
-            self.write_byte(start_addr + i as u16, byte);
+            self.data[start_addr.wrapping_add(i as u16) as usize] = byte;
+        }
+    }
+    pub fn load_program_checked(
+        &mut self,
+        program: &[u8],
+        start_addr: u16,
+        overflow: Overflow,
+    ) -> Result<Loaded, LoadError> {
+        let length = program.len();
+        if length > CAPACITY {
+            return Err(LoadError::ImageTooLarge { length });
         }
+        if overflow == Overflow::Error && start_addr as usize + length > CAPACITY {
+            return Err(LoadError::AddressOverflow {
+                address: start_addr,
+                length,
+            });
+        }
+        self.load_program(program, start_addr);
+        Ok(Loaded {
+            address: start_addr,
+            length,
+        })
+    }
+    ///
+    /// Loads .obj file to memory. the first 2 bytes contain load address.
+    /// This format is generated by ACME 6502 compiler.
+    /// Returns the load address and the length of the program without the header.
+    ///
+    pub fn load_program_from_acme_file<P: AsRef<Path>>(
+        &mut self,
+        file_name: P,
+        overflow: Overflow,
+    ) -> Result<Loaded, LoadError> {
+        let buffer = read_file(file_name)?;
+        let (start_addr, program) = split_acme_header(&buffer)?;
+        self.load_program_checked(program, start_addr, overflow)
+    }
+    pub fn load_image(&mut self, image: &Image) {
+        for segment in &image.segments {
+            self.load_program(&segment.data, segment.address);
+        }
+    }
+    pub fn load_intel_hex(&mut self, text: &str) -> Result<Image, LoadError> {
+        let image = ihex::parse(text)?;
+        self.load_image(&image);
+        Ok(image)
     }
-    // Loads .obj file to memory. the first 2 bytes contain load address.
-    // This format is generated by ACME 6502 compiler
-    pub fn load_program_from_acme_file(&mut self, file_name: &str) -> Option<usize> {
-        // Open the binary file
-        let mut file = File::open(file_name).unwrap();
-        // Create a buffer to hold the data
-        let mut buffer: Vec<u8> = Vec::new();
-        // Read the file into the buffer
-        file.read_to_end(&mut buffer).unwrap();
-        let start_addr_lo = buffer[0];
-        let start_addr_hi = buffer[1];
-        let start_addr = (start_addr_hi as u16) << 8 | start_addr_lo as u16;
-
-        self.load_program(&buffer[2..], start_addr);
-        Some(buffer.len())
+    pub fn load_intel_hex_file<P: AsRef<Path>>(
+        &mut self,
+        file_name: P,
+    ) -> Result<Image, LoadError> {
+        let text = read_text_file(file_name)?;
+        self.load_intel_hex(&text)
+    }
+    ///
+    /// Exports memory range start..=end as Intel HEX text
+    ///
+    pub fn to_intel_hex(&self, start: u16, end: u16, start_address: Option<u16>) -> String {
+        ihex::write(self, start, end, start_address)
+    }
+    pub fn load_srecord(&mut self, text: &str) -> Result<Image, LoadError> {
+        let image = srec::parse(text)?;
+        self.load_image(&image);
+        Ok(image)
+    }
+    pub fn load_srecord_file<P: AsRef<Path>>(&mut self, file_name: P) -> Result<Image, LoadError> {
+        let text = read_text_file(file_name)?;
+        self.load_srecord(&text)
+    }
+    ///
+    /// Exports memory range start..=end as Motorola S-records (S19)
+    ///
+    pub fn to_srecord(&self, start: u16, end: u16, start_address: Option<u16>) -> String {
+        srec::write(self, start, end, start_address)
     }
     pub fn get_data(&self) -> [u8; CAPACITY] {
         self.data
     }
+    ///
+    /// The whole 64KB without copying and without access tracking
+    ///
+    pub fn data(&self) -> &[u8; CAPACITY] {
+        &self.data
+    }
+    ///
+    /// Replaces the whole 64KB, ROM ranges included (rewind, snapshots)
+    ///
+    pub fn set_data(&mut self, data: &[u8; CAPACITY]) {
+        self.data = *data;
+    }
     pub fn hex_dump(&mut self, start_addr: usize, end_addr: usize) {
+        for line in self.hex_dump_lines(start_addr, end_addr) {
+            println!("{}", line);
+        }
+    }
+    ///
+    /// Same output as hex_dump(), one string per row of 16 bytes
+    ///
+    pub fn hex_dump_lines(&self, start_addr: usize, end_addr: usize) -> Vec<String> {
+        let mut lines = Vec::new();
         for (i, chunk) in self.data[start_addr..=end_addr].chunks(16).enumerate() {
-            // Print offset
-            print!("{:08X}: ", (i * 16) + start_addr);
+            // Offset
+            let mut line = format!("{:08X}: ", (i * 16) + start_addr);
 
-            // Print hex values
+            // Hex values
             for byte in chunk {
-                print!("{:02X} ", byte);
+                line.push_str(&format!("{:02X} ", byte));
             }
 
             // Pad if less than 16 bytes
             for _ in 0..(16 - chunk.len()) {
-                print!("   ");
+                line.push_str("   ");
             }
 
-            // Print ASCII representation
-            print!("|");
-            let mut c_count = 16;
+            // ASCII representation
+            line.push('|');
             for &byte in chunk {
                 let c = if byte.is_ascii_graphic() || byte == b' ' {
                     byte as char
                 } else {
                     '.'
                 };
-                print!("{}", c);
-                c_count -= 1;
+                line.push(c);
             }
             // Padding of the last row for number of characters smaller than 16
-            while c_count > 0 {
-                print!(" ");
-                c_count -= 1;
+            for _ in chunk.len()..16 {
+                line.push(' ');
             }
-            println!("|");
+            line.push('|');
+            lines.push(line);
         }
+        lines
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::memory::{self, Memory};
+    use crate::memory::{self, Access, AccessKind, LoadError, Loaded, Memory, Overflow};
+    use std::fs;
     #[test]
     ///
     /// Writes and reads back byte from memory
@@ -238,4 +565,99 @@ mod tests {
         let mem_slice = &memory.data[0..=8];
         assert_eq!(mem_slice, program);
     }
+    #[test]
+    ///
+    /// Program going past FFFF is refused or wrapped to 0000
+    ///
+    fn load_program_overflow() {
+        let mut memory = Memory::new();
+        let program = [0x11, 0x22, 0x33];
+        assert!(matches!(
+            memory.load_program_checked(&program, 0xFFFE, Overflow::Error),
+            Err(LoadError::AddressOverflow {
+                address: 0xFFFE,
+                length: 3
+            })
+        ));
+        assert_eq!(memory.read_byte(0xFFFE), 0x00);
+        let loaded = memory
+            .load_program_checked(&program, 0xFFFE, Overflow::Wrap)
+            .unwrap();
+        assert_eq!(loaded.length, 3);
+        assert_eq!(memory.read_byte(0xFFFF), 0x22);
+        assert_eq!(memory.read_byte(0x0000), 0x33);
+    }
+    #[test]
+    ///
+    /// ACME file returns load address and payload length without the header.
+    /// Missing file and truncated header are reported.
+    ///
+    fn load_acme_file() {
+        let dir = std::env::temp_dir();
+        let file = dir.join("sbc8micro_load_acme.o");
+        fs::write(&file, [0x00, 0x06, 0xA9, 0x01, 0x00]).unwrap();
+        let mut memory = Memory::new();
+        let loaded = memory
+            .load_program_from_acme_file(&file, Overflow::Error)
+            .unwrap();
+        assert_eq!(
+            loaded,
+            Loaded {
+                address: 0x0600,
+                length: 3
+            }
+        );
+        assert_eq!(memory.read_byte(0x0601), 0x01);
+
+        fs::write(&file, [0x00]).unwrap();
+        assert!(matches!(
+            memory.load_program_from_acme_file(&file, Overflow::Error),
+            Err(LoadError::TruncatedHeader { length: 1 })
+        ));
+        assert!(matches!(
+            memory.load_program_from_acme_file(dir.join("sbc8micro_missing.o"), Overflow::Error),
+            Err(LoadError::FileNotFound(_))
+        ));
+    }
+    #[test]
+    ///
+    /// Accesses are recorded only while tracking is on
+    ///
+    fn track_accesses() {
+        let mut memory = Memory::new();
+        memory.write_byte(0x0200, 0x11);
+        assert_eq!(memory.access_count(), 0);
+        memory.set_tracking(true);
+        memory.write_word(0x0300, 0x1234);
+        let mark = memory.access_count();
+        assert_eq!(memory.read_byte(0x0200), 0x11);
+        assert_eq!(memory.accesses_since(mark).len(), 1);
+        let accesses = memory.accesses_since(0);
+        assert_eq!(accesses.len(), 3);
+        assert_eq!(
+            accesses[1],
+            Access {
+                addr: 0x0301,
+                value: 0x12,
+                kind: AccessKind::Write
+            }
+        );
+        assert_eq!(accesses[2].kind, AccessKind::Read);
+        memory.clear_accesses();
+        assert!(memory.accesses_since(0).is_empty());
+    }
+
+    #[test]
+    ///
+    /// Writes into a read only range are ignored, writes next to it are not
+    ///
+    fn write_read_only() {
+        let mut memory = Memory::new();
+        memory.write_byte(0xF000, 0x55);
+        memory.set_read_only(0xF000, 0xFFFF);
+        memory.write_byte(0xF000, 0xAA);
+        memory.write_byte(0xEFFF, 0xAA);
+        assert_eq!(memory.read_byte(0xF000), 0x55);
+        assert_eq!(memory.read_byte(0xEFFF), 0xAA);
+    }
 }
@@ -0,0 +1,252 @@
+//////////////////////////////////////////////////////////
+/// Motorola S-record reader and writer (S19, S28 and S37 files).
+/// S0 header and S5/S6 record counts are checked but otherwise ignored,
+/// S1/S2/S3 carry the data and S9/S8/S7 the start address.
+///
+/// ```
+/// mod memory;
+///
+/// fn main() {
+///     let mut memory = memory::Memory::new();
+///     let image = memory.load_srecord_file("monitor.s19").unwrap();
+///     println!("start address {:04X?}", image.start);
+///     print!("{}", memory.to_srecord(0xE000, 0xFFFF, image.start));
+/// }
+/// ```
+//////////////////////////////////////////////////////////
+use crate::memory::ihex::decode_hex;
+use crate::memory::{Image, LoadError, Memory};
+
+const BYTES_PER_RECORD: usize = 16;
+
+///
+/// Parses S-record text. Addresses above $FFFF are reported as an error
+/// because the memory of both CPUs is 64KB.
+///
+pub fn parse(text: &str) -> Result<Image, LoadError> {
+    let mut image = Image::default();
+    let mut data_records: u32 = 0;
+
+    for (index, line) in text.lines().enumerate() {
+        let line_no = index + 1;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let (kind, bytes) = decode_record(line, line_no)?;
+        let address_len = match kind {
+            b'0' | b'1' | b'5' | b'9' => 2,
+            b'2' | b'6' | b'8' => 3,
+            b'3' | b'7' => 4,
+            _ => {
+                return Err(syntax(
+                    line_no,
+                    &format!("unknown record type S{}", kind as char),
+                ));
+            }
+        };
+        // bytes = count, address, data (checksum is already removed)
+        if bytes.len() < 1 + address_len {
+            return Err(syntax(line_no, "record is shorter than its address"));
+        }
+        let address = bytes[1..=address_len]
+            .iter()
+            .fold(0u32, |acc, &byte| (acc << 8) | byte as u32);
+        let data = &bytes[1 + address_len..];
+        match kind {
+            b'1' | b'2' | b'3' => {
+                if address + data.len() as u32 > 0x10000 {
+                    return Err(LoadError::AddressOutOfRange {
+                        line: line_no,
+                        address: address + data.len() as u32 - 1,
+                    });
+                }
+                image.push(address as u16, data);
+                data_records += 1;
+            }
+            b'5' | b'6' if address != data_records => {
+                return Err(syntax(
+                    line_no,
+                    &format!(
+                        "record count {} does not match {} data records",
+                        address, data_records
+                    ),
+                ));
+            }
+            b'7' | b'8' | b'9' => {
+                let start = u16::try_from(address).map_err(|_| LoadError::AddressOutOfRange {
+                    line: line_no,
+                    address,
+                })?;
+                image.start = Some(start);
+                return Ok(image);
+            }
+            _ => {}
+        }
+    }
+    // The termination record is optional for many tools, file without it is accepted
+    Ok(image)
+}
+
+///
+/// Decodes one 'StCCAAAA..DD..SS' line, verifies the byte count and checksum and
+/// returns the record type and bytes without the checksum.
+///
+fn decode_record(line: &str, line_no: usize) -> Result<(u8, Vec<u8>), LoadError> {
+    let line_bytes = line.as_bytes();
+    if line_bytes.len() < 2 || line_bytes[0] != b'S' {
+        return Err(syntax(line_no, "record does not start with 'S'"));
+    }
+    let bytes = line
+        .get(2..)
+        .and_then(decode_hex)
+        .ok_or_else(|| syntax(line_no, "invalid hex digits"))?;
+    if bytes.len() < 2 || bytes.len() != bytes[0] as usize + 1 {
+        return Err(syntax(line_no, "record length does not match byte count"));
+    }
+    let (record, checksum) = bytes.split_at(bytes.len() - 1);
+    let expected = checksum_of(record);
+    if expected != checksum[0] {
+        return Err(LoadError::Checksum {
+            line: line_no,
+            expected,
+            found: checksum[0],
+        });
+    }
+    Ok((line_bytes[1], record.to_vec()))
+}
+
+///
+/// One's complement of the sum of count, address and data bytes
+///
+fn checksum_of(record: &[u8]) -> u8 {
+    !record.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte))
+}
+
+fn syntax(line: usize, message: &str) -> LoadError {
+    LoadError::Syntax {
+        line,
+        message: message.to_string(),
+    }
+}
+
+fn push_record(output: &mut String, kind: char, address: u16, data: &[u8]) {
+    let mut record = vec![(data.len() + 3) as u8];
+    record.extend_from_slice(&address.to_be_bytes());
+    record.extend_from_slice(data);
+    output.push('S');
+    output.push(kind);
+    for byte in &record {
+        output.push_str(&format!("{:02X}", byte));
+    }
+    output.push_str(&format!("{:02X}\n", checksum_of(&record)));
+}
+
+///
+/// Writes memory range start..=end as S19 file: S0 header, S1 data records
+/// with 16 bytes each, S5 record count and S9 with the start address
+/// (0000 if there is none).
+///
+pub fn write(memory: &Memory, start: u16, end: u16, start_address: Option<u16>) -> String {
+    let mut output = String::new();
+    push_record(&mut output, '0', 0, b"sbc8micro");
+    let mut address = start as u32;
+    let mut count: u16 = 0;
+    while address <= end as u32 {
+        let len = (end as u32 - address + 1).min(BYTES_PER_RECORD as u32);
+        let data: Vec<u8> = (address..address + len)
+            .map(|a| memory.read_byte(a as u16))
+            .collect();
+        push_record(&mut output, '1', address as u16, &data);
+        address += len;
+        count = count.wrapping_add(1);
+    }
+    push_record(&mut output, '5', count, &[]);
+    push_record(&mut output, '9', start_address.unwrap_or(0), &[]);
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    ///
+    /// Parses S19 file with header, two sparse data records, count and start address
+    ///
+    fn parse_s19() {
+        let image = parse(
+            "S00600004844521B\n\
+             S1060600A901E861\n\
+             S1050700AA55F4\n\
+             S5030002FA\n\
+             S9030600F6\n",
+        )
+        .unwrap();
+        assert_eq!(image.segments.len(), 2);
+        assert_eq!(image.segments[0].address, 0x0600);
+        assert_eq!(image.segments[0].data, vec![0xA9, 0x01, 0xE8]);
+        assert_eq!(image.segments[1].address, 0x0700);
+        assert_eq!(image.start, Some(0x0600));
+    }
+
+    #[test]
+    ///
+    /// S2 and S3 records carry 24 and 32-bit addresses
+    ///
+    fn parse_s28_s37() {
+        let image = parse("S205000200AA4E\nS804000000FB\n").unwrap();
+        assert_eq!(image.segments[0].address, 0x0200);
+        let image = parse("S3060000030055A1\nS70500000300F7\n").unwrap();
+        assert_eq!(image.segments[0].address, 0x0300);
+        assert_eq!(image.start, Some(0x0300));
+    }
+
+    #[test]
+    ///
+    /// Checksum, record count and addresses above 64KB are verified
+    ///
+    fn parse_errors() {
+        assert!(matches!(
+            parse("S1050700AA55F5\n"),
+            Err(LoadError::Checksum {
+                line: 1,
+                expected: 0xF4,
+                found: 0xF5
+            })
+        ));
+        assert!(matches!(
+            parse("S1050700AA55F4\nS5030002FA\n"),
+            Err(LoadError::Syntax { line: 2, .. })
+        ));
+        assert!(matches!(
+            parse("S205010000AA4F\n"),
+            Err(LoadError::AddressOutOfRange { line: 1, .. })
+        ));
+        // a multi-byte character after the 'S' is no hex digit
+        assert!(matches!(
+            parse("S\u{e9}050700AA55F4\n"),
+            Err(LoadError::Syntax { line: 1, .. })
+        ));
+    }
+
+    #[test]
+    ///
+    /// Memory range written as S-records is loaded back to the same addresses
+    ///
+    fn write_and_load_back() {
+        let mut memory = Memory::new();
+        for i in 0..40u16 {
+            memory.write_byte(0xE000 + i, (i as u8) ^ 0x5A);
+        }
+        let text = memory.to_srecord(0xE000, 0xE027, Some(0xE000));
+        assert!(text.starts_with("S0"));
+        assert!(text.contains("S5030003F9\n"));
+        let mut copy = Memory::new();
+        let image = copy.load_srecord(&text).unwrap();
+        assert_eq!(image.start, Some(0xE000));
+        for i in 0..40u16 {
+            assert_eq!(copy.read_byte(0xE000 + i), (i as u8) ^ 0x5A);
+        }
+    }
+}
@@ -0,0 +1,96 @@
+//////////////////////////////////////////////////////////
+/// Symbol lists with one assignment per line, as written by
+/// acme --symbollist or by hand:
+///
+///     start   = $c000
+///     loop    = $c005 ; ?
+///     !addr   ptr = $fb
+///     BDOS    = 0005H
+///
+/// Values are read in 6502, i8080, C, binary (%) or decimal notation.
+/// ACME marks unused symbols with '; ?', the comment is ignored.
+/// Values that do not fit into 16 bits (negative constants) are skipped.
+//////////////////////////////////////////////////////////
+use crate::machine::config::parse_number;
+use crate::memory::LoadError;
+use crate::symbols::SymbolTable;
+
+fn parse_value(text: &str) -> Option<i64> {
+    let (negative, text) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest.trim()),
+        None => (false, text),
+    };
+    let value = if let Some(bin) = text.strip_prefix('%') {
+        u32::from_str_radix(bin, 2).ok()?
+    } else {
+        parse_number(text)?
+    } as i64;
+    Some(if negative { -value } else { value })
+}
+
+pub fn parse_symbol_list(text: &str) -> Result<SymbolTable, LoadError> {
+    let mut table = SymbolTable::new();
+    for (index, line) in text.lines().enumerate() {
+        let line = line.split(';').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+        let syntax = |message: String| LoadError::Syntax {
+            line: index + 1,
+            message,
+        };
+        let Some((name, value)) = line.split_once('=') else {
+            return Err(syntax("expected 'name = value'".to_string()));
+        };
+        let name = name.trim().trim_start_matches("!addr").trim();
+        if name.is_empty() || name.contains(char::is_whitespace) {
+            return Err(syntax(format!("invalid symbol name '{}'", name)));
+        }
+        let value = parse_value(value.trim())
+            .ok_or_else(|| syntax(format!("invalid value '{}'", value.trim())))?;
+        if let Ok(addr) = u16::try_from(value) {
+            table.insert(name, addr);
+        }
+    }
+    Ok(table)
+}
+
+///
+/// Symbol list in the layout of acme --symbollist, sorted by address
+///
+pub fn write_symbol_list(table: &SymbolTable) -> String {
+    table
+        .iter()
+        .map(|(addr, name)| format!("{:<15} = ${:04x}\n", name, addr))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    ///
+    /// ACME output, hand written lists and Intel notation
+    ///
+    fn symbol_list() {
+        let text = "\tstart\t= $c000\n\
+                    \tloop\t= $c005\t; ?\n\
+                    \t!addr\tptr\t= $fb\n\
+                    BDOS = 0005H\n\
+                    mask = %1000\n\
+                    offset = -2\n\
+                    \n";
+        let table = parse_symbol_list(text).unwrap();
+        assert_eq!(table.address_of("start"), Some(0xC000));
+        assert_eq!(table.address_of("loop"), Some(0xC005));
+        assert_eq!(table.address_of("ptr"), Some(0x00FB));
+        assert_eq!(table.address_of("BDOS"), Some(0x0005));
+        assert_eq!(table.address_of("mask"), Some(8));
+        assert_eq!(table.address_of("offset"), None);
+        assert!(matches!(
+            parse_symbol_list("start $c000"),
+            Err(LoadError::Syntax { line: 1, .. })
+        ));
+    }
+}
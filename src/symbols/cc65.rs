@@ -0,0 +1,475 @@
+//////////////////////////////////////////////////////////
+/// Support for the output of the cc65 tool chain (ca65/cl65 + ld65):
+/// - raw binaries placed according to the MEMORY areas of the ld65 config
+/// - map file (ld65 -m), exports list is imported as symbols
+/// - VICE label file (ld65 -Ln)
+/// - debug info file (ld65 --dbgfile), symbols and source lines
+///
+/// ```
+/// mod memory;
+/// mod symbols;
+///
+/// fn main() {
+///     let mut memory = memory::Memory::new();
+///     let cfg = std::fs::read_to_string("sbc.cfg").unwrap();
+///     let binary = std::fs::read("program.bin").unwrap();
+///     symbols::cc65::load_binary(&mut memory, &binary, &cfg).unwrap();
+///     let table = symbols::cc65::parse_debug_info(&std::fs::read_to_string("program.dbg").unwrap()).unwrap();
+///     println!("main is at {:04X?}", table.address_of("_main"));
+/// }
+/// ```
+//////////////////////////////////////////////////////////
+use std::collections::HashMap;
+
+use crate::machine::config::parse_number;
+use crate::memory::{Image, LoadError, Memory, Overflow};
+use crate::symbols::SymbolTable;
+
+///
+/// MEMORY area of the ld65 config
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct MemoryArea {
+    pub name: String,
+    pub start: u16,
+    pub size: u32,
+    pub fill: bool,
+    ///
+    /// true if the area goes to the main output file (file = %O, the default)
+    ///
+    pub output: bool,
+}
+
+fn syntax(line: usize, message: &str) -> LoadError {
+    LoadError::Syntax {
+        line,
+        message: message.to_string(),
+    }
+}
+
+///
+/// Number in ca65/ld65 notation: $0800, %1010, 2048 or 0x0800 (debug info)
+///
+fn parse_cc65_number(text: &str) -> Option<u32> {
+    let text = text.trim();
+    if let Some(bin) = text.strip_prefix('%') {
+        u32::from_str_radix(bin, 2).ok()
+    } else {
+        parse_number(text)
+    }
+}
+
+///
+/// Line number (from 1) of a byte position in the text
+///
+fn line_at(text: &str, pos: usize) -> usize {
+    text[..pos].matches('\n').count() + 1
+}
+
+///
+/// Reads MEMORY section of ld65 config. Only constant start and size values
+/// are supported, expressions with symbols are reported as an error.
+///
+pub fn parse_memory_areas(cfg: &str) -> Result<Vec<MemoryArea>, LoadError> {
+    // Remove comments, the config is then a sequence of 'SECTION { ... }' blocks
+    let text: String = cfg
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or(""))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let mut areas = Vec::new();
+    let Some(memory_pos) = text.find("MEMORY") else {
+        return Ok(areas);
+    };
+    let body_start = text[memory_pos..]
+        .find('{')
+        .map(|i| memory_pos + i + 1)
+        .ok_or_else(|| syntax(line_at(&text, memory_pos), "MEMORY section has no '{'"))?;
+    let body_end = text[body_start..]
+        .find('}')
+        .map(|i| body_start + i)
+        .ok_or_else(|| syntax(line_at(&text, body_start), "MEMORY section has no '}'"))?;
+
+    let mut entry_start = body_start;
+    for entry in text[body_start..body_end].split(';') {
+        let entry_pos = entry_start + (entry.len() - entry.trim_start().len());
+        entry_start += entry.len() + 1;
+        let entry = entry.trim();
+        if entry.is_empty() {
+            continue;
+        }
+        let line = line_at(&text, entry_pos);
+        let (name, attributes) = entry
+            .split_once(':')
+            .ok_or_else(|| syntax(line, &format!("invalid memory area '{}'", entry)))?;
+        let mut area = MemoryArea {
+            name: name.trim().to_string(),
+            start: 0,
+            size: 0,
+            fill: false,
+            output: true,
+        };
+        for attribute in attributes.split(',') {
+            let Some((key, value)) = attribute.split_once('=') else {
+                continue;
+            };
+            let value = value.trim();
+            let number = || {
+                parse_cc65_number(value).ok_or_else(|| {
+                    syntax(line, &format!("{}: cannot evaluate '{}'", area.name, value))
+                })
+            };
+            match key.trim() {
+                "start" => {
+                    let start = number()?;
+                    area.start =
+                        u16::try_from(start).map_err(|_| LoadError::AddressOutOfRange {
+                            line,
+                            address: start,
+                        })?;
+                }
+                "size" => area.size = number()?,
+                "fill" => area.fill = value == "yes",
+                "file" => area.output = value == "%O",
+                _ => {}
+            }
+        }
+        areas.push(area);
+    }
+    Ok(areas)
+}
+
+///
+/// Places raw ld65 output into memory. Areas are written to the output file one
+/// after the other, so every area except the last one has to be filled (fill = yes)
+/// to know where the next one starts. Returns the placed blocks.
+///
+pub fn load_binary(memory: &mut Memory, binary: &[u8], cfg: &str) -> Result<Image, LoadError> {
+    let areas: Vec<MemoryArea> = parse_memory_areas(cfg)?
+        .into_iter()
+        .filter(|area| area.output)
+        .collect();
+    let mut image = Image::default();
+    let mut offset = 0usize;
+    for (i, area) in areas.iter().enumerate() {
+        if offset >= binary.len() {
+            break;
+        }
+        let last = i == areas.len() - 1;
+        if !area.fill && !last {
+            return Err(syntax(
+                0,
+                &format!(
+                    "memory area {} is not filled, the following areas cannot be placed",
+                    area.name
+                ),
+            ));
+        }
+        let length = if last {
+            binary.len() - offset
+        } else {
+            (area.size as usize).min(binary.len() - offset)
+        };
+        if length > area.size as usize && area.size != 0 {
+            return Err(LoadError::ImageTooLarge { length });
+        }
+        let data = &binary[offset..offset + length];
+        let loaded = memory.load_program_checked(data, area.start, Overflow::Error)?;
+        image.push(loaded.address, &data[..loaded.length]);
+        offset += length;
+    }
+    Ok(image)
+}
+
+///
+/// Imports "Exports list by name" of the ld65 map file
+///
+pub fn parse_map(text: &str) -> Result<SymbolTable, LoadError> {
+    let mut table = SymbolTable::new();
+    let mut lines = text.lines().enumerate();
+    while let Some((_, line)) = lines.next() {
+        if line.trim() != "Exports list by name:" {
+            continue;
+        }
+        lines.next(); // ------
+        for (index, line) in lines.by_ref() {
+            if line.trim().is_empty() {
+                break;
+            }
+            // Two exports per line: name value flags
+            let tokens: Vec<&str> = line.split_whitespace().collect();
+            for entry in tokens.chunks(3) {
+                if entry.len() < 2 {
+                    return Err(syntax(index + 1, "export has no value"));
+                }
+                let value = u32::from_str_radix(entry[1], 16)
+                    .map_err(|_| syntax(index + 1, &format!("invalid value '{}'", entry[1])))?;
+                // Zero page, absolute labels and equates, values above 64KB are skipped
+                if let Ok(addr) = u16::try_from(value) {
+                    table.insert(entry[0], addr);
+                }
+            }
+        }
+        break;
+    }
+    Ok(table)
+}
+
+///
+/// Reads VICE label file: 'al C:0800 .main' or 'al 000800 .main' as written by ld65 -Ln
+///
+pub fn parse_vice_labels(text: &str) -> Result<SymbolTable, LoadError> {
+    let mut table = SymbolTable::new();
+    for (index, line) in text.lines().enumerate() {
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        if tokens.is_empty() {
+            continue;
+        }
+        if tokens[0] != "al" || tokens.len() < 3 {
+            return Err(syntax(index + 1, "expected 'al <address> .<label>'"));
+        }
+        let address = tokens[1].trim_start_matches("C:");
+        let value = u32::from_str_radix(address, 16)
+            .ok()
+            .and_then(|value| u16::try_from(value).ok())
+            .ok_or_else(|| syntax(index + 1, &format!("invalid address '{}'", tokens[1])))?;
+        table.insert(tokens[2].trim_start_matches('.'), value);
+    }
+    Ok(table)
+}
+
+///
+/// Splits 'key=value,key="quoted, value"' to a map
+///
+fn parse_attributes(text: &str) -> HashMap<&str, &str> {
+    let mut result = HashMap::new();
+    let mut rest = text;
+    while !rest.is_empty() {
+        let Some((key, value)) = rest.split_once('=') else {
+            break;
+        };
+        let (value, next) = if let Some(quoted) = value.strip_prefix('"') {
+            let end = quoted.find('"').unwrap_or(quoted.len());
+            let next = quoted[end..].trim_start_matches('"');
+            (&quoted[..end], next.strip_prefix(',').unwrap_or(next))
+        } else {
+            match value.split_once(',') {
+                Some((value, next)) => (value, next),
+                None => (value, ""),
+            }
+        };
+        result.insert(key.trim(), value);
+        rest = next;
+    }
+    result
+}
+
+///
+/// Reads ld65 debug info file. Label symbols are imported with their values,
+/// line records are resolved through spans and segments to absolute addresses.
+/// Macro expansion lines (type=2) are skipped.
+///
+pub fn parse_debug_info(text: &str) -> Result<SymbolTable, LoadError> {
+    let mut table = SymbolTable::new();
+    let mut files: HashMap<u32, String> = HashMap::new();
+    let mut segments: HashMap<u32, u32> = HashMap::new();
+    let mut spans: HashMap<u32, (u32, u32)> = HashMap::new();
+    let mut lines: Vec<(u32, u32, Vec<u32>)> = Vec::new();
+
+    for (index, line) in text.lines().enumerate() {
+        let Some((kind, rest)) = line.split_once(char::is_whitespace) else {
+            continue;
+        };
+        let attributes = parse_attributes(rest.trim());
+        let number = |key: &str| -> Result<u32, LoadError> {
+            let value = attributes
+                .get(key)
+                .ok_or_else(|| syntax(index + 1, &format!("{} has no {}", kind, key)))?;
+            parse_cc65_number(value)
+                .ok_or_else(|| syntax(index + 1, &format!("invalid {} '{}'", key, value)))
+        };
+        match kind {
+            "file" => {
+                files.insert(
+                    number("id")?,
+                    attributes.get("name").unwrap_or(&"").to_string(),
+                );
+            }
+            "seg" => {
+                segments.insert(number("id")?, number("start")?);
+            }
+            "span" => {
+                spans.insert(number("id")?, (number("seg")?, number("start")?));
+            }
+            "line" => {
+                if attributes.get("type") == Some(&"2") {
+                    continue;
+                }
+                let Some(span_list) = attributes.get("span") else {
+                    continue;
+                };
+                let span_ids = span_list
+                    .split('+')
+                    .filter_map(|id| id.parse().ok())
+                    .collect();
+                lines.push((number("file")?, number("line")?, span_ids));
+            }
+            "sym" => {
+                if attributes.get("type") != Some(&"lab") {
+                    continue;
+                }
+                let name = attributes.get("name").unwrap_or(&"");
+                if let Ok(value) = number("val")
+                    && let Ok(addr) = u16::try_from(value)
+                {
+                    table.insert(name, addr);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    for (file, line, span_ids) in lines {
+        let file_name = files.get(&file).map_or("?", |name| name.as_str());
+        for span in span_ids {
+            let Some(&(seg, start)) = spans.get(&span) else {
+                continue;
+            };
+            let Some(&seg_start) = segments.get(&seg) else {
+                continue;
+            };
+            if let Ok(addr) = u16::try_from(seg_start + start) {
+                table.add_line(addr, file_name, line);
+            }
+        }
+    }
+    Ok(table)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CFG: &str = "
+# Simple SBC
+MEMORY {
+    ZP:   file = \"\", start = $0000, size = $0100;
+    RAM:  start = $0800, size = $0100, fill = yes;   # first part
+    ROM:  start = $E000, size = $2000;
+}
+SEGMENTS {
+    CODE: load = ROM, type = ro;
+}
+";
+
+    #[test]
+    ///
+    /// Reads MEMORY areas of ld65 config
+    ///
+    fn memory_areas() {
+        let areas = parse_memory_areas(CFG).unwrap();
+        assert_eq!(areas.len(), 3);
+        assert!(!areas[0].output);
+        assert_eq!(areas[1].start, 0x0800);
+        assert!(areas[1].fill);
+        assert_eq!(areas[2].size, 0x2000);
+    }
+
+    #[test]
+    ///
+    /// Errors name the line of the memory area or of the MEMORY section
+    ///
+    fn memory_area_errors() {
+        let line_of = |cfg: &str| match parse_memory_areas(cfg) {
+            Err(LoadError::Syntax { line, .. }) => line,
+            other => panic!("unexpected {:?}", other),
+        };
+        assert_eq!(line_of(&CFG.replace("$E000", "__ROM_START__")), 6);
+        assert_eq!(
+            line_of("\nMEMORY {\n    ZP: start = 0;\n    RAM start = $0800;\n}"),
+            4
+        );
+        assert_eq!(line_of("# config\n\nMEMORY\n"), 3);
+        assert_eq!(line_of("\nMEMORY {\n    ZP: start = 0;\n"), 2);
+    }
+
+    #[test]
+    ///
+    /// Filled area is followed by the next area in the binary
+    ///
+    fn load_binary_areas() {
+        let mut binary = vec![0x11; 0x100];
+        binary.extend_from_slice(&[0xA9, 0x01, 0x00]);
+        let mut memory = Memory::new();
+        let image = load_binary(&mut memory, &binary, CFG).unwrap();
+        assert_eq!(image.segments.len(), 2);
+        assert_eq!(image.segments[1].address, 0xE000);
+        assert_eq!(memory.read_byte(0x08FF), 0x11);
+        assert_eq!(memory.read_byte(0xE000), 0xA9);
+        assert_eq!(memory.read_byte(0xE002), 0x00);
+    }
+
+    #[test]
+    ///
+    /// Exports list of map file, two exports on one line
+    ///
+    fn map_exports() {
+        let map = "\
+Segment list:
+-------------
+Name                   Start     End    Size  Align
+----------------------------------------------------
+CODE                  00E000  00E01F  000020  00001
+
+Exports list by name:
+---------------------
+_main                     00E000 RLA    reset                     00E010 RLA
+ptr1                      000002 RLZ
+
+Exports list by value:
+---------------------
+";
+        let table = parse_map(map).unwrap();
+        assert_eq!(table.address_of("_main"), Some(0xE000));
+        assert_eq!(table.address_of("reset"), Some(0xE010));
+        assert_eq!(table.name_of(0x0002), Some("ptr1"));
+        assert_eq!(table.len(), 3);
+    }
+
+    #[test]
+    ///
+    /// Both label formats of VICE files
+    ///
+    fn vice_labels() {
+        let table = parse_vice_labels("al 00E000 .reset\nal C:e010 .loop\n").unwrap();
+        assert_eq!(table.address_of("reset"), Some(0xE000));
+        assert_eq!(table.address_of("loop"), Some(0xE010));
+        assert!(parse_vice_labels("xx 1234 .bad\n").is_err());
+    }
+
+    #[test]
+    ///
+    /// Symbols and source lines of ld65 debug info
+    ///
+    fn debug_info() {
+        let dbg = r#"version	major=2,minor=0
+info	csym=0,file=1,lib=0,line=3,mod=1,scope=1,seg=1,span=2,sym=2,type=1
+file	id=0,name="main, test.s",size=120,mtime=0x5F000000,mod=0
+line	id=0,file=0,line=5,span=0
+line	id=1,file=0,line=6,span=1
+line	id=2,file=0,line=20,type=2,span=1
+seg	id=0,name="CODE",start=0x00E000,size=0x0004,addrsize=absolute,type=ro,oname="a.bin",ooffs=0
+span	id=0,seg=0,start=0,size=2
+span	id=1,seg=0,start=2,size=2
+sym	id=0,name="reset",addrsize=absolute,scope=0,def=0,val=0xE000,seg=0,type=lab
+sym	id=1,name="COUNT",addrsize=zeropage,scope=0,def=1,val=0x10,type=equ
+"#;
+        let table = parse_debug_info(dbg).unwrap();
+        assert_eq!(table.address_of("reset"), Some(0xE000));
+        assert_eq!(table.address_of("COUNT"), None);
+        let line = table.line_at(0xE002).unwrap();
+        assert_eq!(line.file, "main, test.s");
+        assert_eq!(line.line, 6);
+        assert_eq!(table.line_at(0xE000).unwrap().to_string(), "main, test.s:5");
+    }
+}
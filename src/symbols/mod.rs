@@ -0,0 +1,164 @@
+//////////////////////////////////////////////////////////
+/// Symbol table shared by the disassemblers and debugging front ends.
+/// It maps addresses to names (and back) and optionally addresses to source lines.
+/// Tables are filled from the files produced by the assemblers and linkers,
+/// see cc65.rs for ld65 map, VICE label and debug info files and acme.rs for
+/// symbol lists (acme --symbollist, 'name = $addr').
+//////////////////////////////////////////////////////////
+pub mod acme;
+pub mod cc65;
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::path::Path;
+
+use crate::memory::{LoadError, read_text_file};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceLine {
+    pub file: String,
+    pub line: u32,
+}
+
+impl fmt::Display for SourceLine {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.file, self.line)
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct SymbolTable {
+    by_address: BTreeMap<u16, Vec<String>>,
+    by_name: HashMap<String, u16>,
+    lines: BTreeMap<u16, SourceLine>,
+}
+
+impl SymbolTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    ///
+    /// Adds symbol. If the name already exists it is moved to the new address.
+    ///
+    pub fn insert(&mut self, name: &str, addr: u16) {
+        if let Some(old) = self.by_name.insert(name.to_string(), addr)
+            && let Some(names) = self.by_address.get_mut(&old)
+        {
+            names.retain(|n| n != name);
+            if names.is_empty() {
+                self.by_address.remove(&old);
+            }
+        }
+        self.by_address
+            .entry(addr)
+            .or_default()
+            .push(name.to_string());
+    }
+    ///
+    /// Returns the first name defined for the address
+    ///
+    pub fn name_of(&self, addr: u16) -> Option<&str> {
+        self.by_address
+            .get(&addr)
+            .and_then(|names| names.first())
+            .map(|name| name.as_str())
+    }
+    pub fn names_of(&self, addr: u16) -> &[String] {
+        self.by_address.get(&addr).map_or(&[], |names| names)
+    }
+    pub fn address_of(&self, name: &str) -> Option<u16> {
+        self.by_name.get(name).copied()
+    }
+    pub fn add_line(&mut self, addr: u16, file: &str, line: u32) {
+        self.lines.insert(
+            addr,
+            SourceLine {
+                file: file.to_string(),
+                line,
+            },
+        );
+    }
+    pub fn line_at(&self, addr: u16) -> Option<&SourceLine> {
+        self.lines.get(&addr)
+    }
+    pub fn len(&self) -> usize {
+        self.by_name.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.by_name.is_empty()
+    }
+    ///
+    /// Symbols sorted by address
+    ///
+    pub fn iter(&self) -> impl Iterator<Item = (u16, &str)> {
+        self.by_address
+            .iter()
+            .flat_map(|(&addr, names)| names.iter().map(move |name| (addr, name.as_str())))
+    }
+    ///
+    /// Adds all symbols and lines of other table, other table wins on conflicts
+    ///
+    pub fn merge(&mut self, other: &SymbolTable) {
+        for (addr, name) in other.iter() {
+            self.insert(name, addr);
+        }
+        for (&addr, line) in &other.lines {
+            self.lines.insert(addr, line.clone());
+        }
+    }
+}
+
+///
+/// Reads symbol file of any supported format, the format is detected from
+/// the content
+///
+pub fn load<P: AsRef<Path>>(path: P) -> Result<SymbolTable, LoadError> {
+    parse(&read_text_file(path)?)
+}
+
+pub fn parse(text: &str) -> Result<SymbolTable, LoadError> {
+    let first = text.lines().map(str::trim).find(|line| !line.is_empty());
+    if first.is_some_and(|line| line.starts_with("al ")) {
+        cc65::parse_vice_labels(text)
+    } else if first.is_some_and(|line| line.starts_with("version")) {
+        cc65::parse_debug_info(text)
+    } else if text.contains("Exports list by name:") {
+        cc65::parse_map(text)
+    } else {
+        acme::parse_symbol_list(text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    ///
+    /// Symbols can be looked up by address and by name, redefinition moves the name
+    ///
+    fn insert_and_lookup() {
+        let mut table = SymbolTable::new();
+        table.insert("reset", 0xE000);
+        table.insert("start", 0xE000);
+        table.insert("loop", 0xE010);
+        assert_eq!(table.name_of(0xE000), Some("reset"));
+        assert_eq!(table.names_of(0xE000).len(), 2);
+        assert_eq!(table.address_of("loop"), Some(0xE010));
+        table.insert("loop", 0xE020);
+        assert_eq!(table.name_of(0xE010), None);
+        assert_eq!(table.name_of(0xE020), Some("loop"));
+        assert_eq!(table.len(), 3);
+    }
+
+    #[test]
+    ///
+    /// The file format is recognized from the content
+    ///
+    fn detect_format() {
+        let vice = parse("al C:0800 .main\n").unwrap();
+        assert_eq!(vice.address_of("main"), Some(0x0800));
+        let list = parse("main = $0800\n").unwrap();
+        assert_eq!(list.address_of("main"), Some(0x0800));
+    }
+}
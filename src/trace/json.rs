@@ -0,0 +1,89 @@
+//////////////////////////////////////////////////////////
+/// JSON lines trace, one object per instruction:
+///
+/// {"pc":1538,"bytes":[141,0,2],"disassembly":"STA $0200",
+///  "before":{"A":1,...},"after":{"A":1,...},
+///  "accesses":[{"addr":512,"value":1,"kind":"write"}],"cycles":4}
+//////////////////////////////////////////////////////////
+use std::io::Write;
+
+use serde_json::{Map, Value, json};
+
+use crate::memory::AccessKind;
+use crate::trace::{TraceRecord, TraceSink};
+
+pub struct JsonSink<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> JsonSink<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+}
+
+pub fn to_json(record: &TraceRecord) -> Value {
+    let registers = |values: &[u16]| -> Map<String, Value> {
+        record
+            .registers(values)
+            .map(|(name, _, value)| (name.to_string(), json!(value)))
+            .collect()
+    };
+    let accesses: Vec<Value> = record
+        .accesses
+        .iter()
+        .map(|access| {
+            let kind = match access.kind {
+                AccessKind::Read => "read",
+                AccessKind::Write => "write",
+            };
+            json!({"addr": access.addr, "value": access.value, "kind": kind})
+        })
+        .collect();
+    json!({
+        "pc": record.pc,
+        "bytes": record.bytes,
+        "disassembly": record.disassembly,
+        "before": registers(&record.before),
+        "after": registers(&record.after),
+        "accesses": accesses,
+        "cycles": record.cycles,
+    })
+}
+
+impl<W: Write> TraceSink for JsonSink<W> {
+    fn record(&mut self, record: &TraceRecord) {
+        // A trace must not stop the emulation, write errors are ignored
+        let _ = writeln!(self.writer, "{}", to_json(record));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::trace::tests::{SharedBuffer, cpu_with_program};
+
+    #[test]
+    ///
+    /// Every line is a JSON object with the whole record
+    ///
+    fn json_lines() {
+        let mut cpu = cpu_with_program();
+        let buffer = SharedBuffer::default();
+        cpu.set_trace(Some(Box::new(JsonSink::new(buffer.clone()))));
+        cpu.step();
+        cpu.step();
+        let text = buffer.text();
+        let lines: Vec<Value> = text
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[1]["pc"], 0x0602);
+        assert_eq!(lines[1]["disassembly"], "STA $0200");
+        assert_eq!(lines[1]["before"]["A"], 1);
+        assert_eq!(lines[1]["accesses"][0]["kind"], "write");
+        assert_eq!(lines[1]["accesses"][0]["addr"], 0x0200);
+        assert_eq!(lines[1]["cycles"], 4);
+    }
+}
@@ -0,0 +1,170 @@
+//////////////////////////////////////////////////////////
+/// Execution trace. Every traced instruction produces a TraceRecord with
+/// PC, opcode bytes, disassembly, registers before and after, memory
+/// accesses and cycles. Records are delivered to a TraceSink:
+///   text.rs   one line per instruction in the style of the Nestest log
+///   json.rs   JSON lines
+///   ring.rs   compact binary ring buffer of the last records
+///
+/// ```
+/// let ring = RingBuffer::new(64 * 1024);
+/// cpu.set_trace(Some(Box::new(ring.clone())));
+/// ... run ...
+/// for record in ring.records() {
+///     println!("{:04X} {:?}", record.pc, record.bytes);
+/// }
+/// ```
+/// set_debug(true) of the CPUs installs a text sink writing to stdout.
+//////////////////////////////////////////////////////////
+pub mod json;
+pub mod ring;
+pub mod text;
+
+use crate::cpu::{self, Processor};
+use crate::memory::Access;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceRecord {
+    pub pc: u16,
+    pub bytes: Vec<u8>,
+    pub disassembly: String,
+    ///
+    /// Register names and widths, the values below are in this order
+    ///
+    pub layout: &'static [(&'static str, u8)],
+    pub before: Vec<u16>,
+    pub after: Vec<u16>,
+    ///
+    /// Memory reads and writes of the instruction without the instruction fetch
+    ///
+    pub accesses: Vec<Access>,
+    pub cycles: u8,
+}
+
+impl TraceRecord {
+    ///
+    /// Register values with their names and widths
+    ///
+    pub fn registers<'a>(
+        &'a self,
+        values: &'a [u16],
+    ) -> impl Iterator<Item = (&'static str, u8, u16)> + 'a {
+        self.layout
+            .iter()
+            .zip(values)
+            .map(|(&(name, bits), &value)| (name, bits, value))
+    }
+}
+
+pub trait TraceSink {
+    fn record(&mut self, record: &TraceRecord);
+}
+
+fn registers(cpu: &dyn Processor) -> Vec<u16> {
+    cpu.register_layout()
+        .iter()
+        .map(|(name, _)| cpu.register(name).unwrap_or(0))
+        .collect()
+}
+
+///
+/// Executes one instruction and returns its trace record
+///
+pub fn step(cpu: &mut dyn Processor) -> TraceRecord {
+    let pc = cpu.pc();
+    let length = cpu.instruction_length(pc);
+    let bytes = (0..length)
+        .map(|i| cpu.memory().read_byte(pc.wrapping_add(i as u16)))
+        .collect();
+    let disassembly = cpu
+        .disassemble(pc, 1)
+        .first()
+        .map(|instruction| instruction.text())
+        .unwrap_or_default();
+    let cycles = cpu.instruction_cycles(pc);
+    let before = registers(cpu);
+
+    let tracking = cpu.memory().is_tracking();
+    if !tracking {
+        cpu.memory_mut().set_tracking(true);
+    }
+    let accesses = cpu::step_tracked(cpu);
+    if !tracking {
+        cpu.memory_mut().set_tracking(false);
+    }
+
+    TraceRecord {
+        pc,
+        bytes,
+        disassembly,
+        layout: cpu.register_layout(),
+        before,
+        after: registers(cpu),
+        accesses,
+        cycles,
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::cpu::mos6502;
+    use crate::memory::AccessKind;
+    use std::cell::RefCell;
+    use std::io::{self, Write};
+    use std::rc::Rc;
+
+    ///
+    /// Writer shared between a sink and the test which reads the output
+    ///
+    #[derive(Clone, Default)]
+    pub struct SharedBuffer(pub Rc<RefCell<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl SharedBuffer {
+        pub fn text(&self) -> String {
+            String::from_utf8(self.0.borrow().clone()).unwrap()
+        }
+    }
+
+    pub fn cpu_with_program() -> mos6502::Cpu {
+        let mut cpu = mos6502::Cpu::new();
+        let program = vec![
+            0xA9, 0x01, //       LDA #$01
+            0x8D, 0x00, 0x02, // STA $0200
+            0xE8, //             INX
+            0x00, //             BRK
+        ];
+        cpu.load_program(&program, 0x0600);
+        cpu
+    }
+
+    #[test]
+    ///
+    /// Record of STA abs: bytes, disassembly, registers and the write
+    ///
+    fn step_record() {
+        let mut cpu = cpu_with_program();
+        step(&mut cpu);
+        let record = step(&mut cpu);
+        assert_eq!(record.pc, 0x0602);
+        assert_eq!(record.bytes, vec![0x8D, 0x00, 0x02]);
+        assert_eq!(record.disassembly, "STA $0200");
+        assert_eq!(record.cycles, 4);
+        assert_eq!(record.before[0], 0x01);
+        assert_eq!(record.after.last(), Some(&0x0605));
+        assert_eq!(record.accesses.len(), 1);
+        assert_eq!(record.accesses[0].kind, AccessKind::Write);
+        assert_eq!(record.accesses[0].addr, 0x0200);
+        assert!(!cpu.memory.is_tracking());
+    }
+}
@@ -0,0 +1,163 @@
+//////////////////////////////////////////////////////////
+/// Compact binary ring buffer keeping the last records for post-mortem
+/// inspection. When the capacity (in bytes) is exceeded, the oldest records
+/// are dropped. The buffer is a shared handle: give a clone to the CPU and
+/// read the records from the other one.
+///
+/// Record encoding (little endian):
+///   pc:2 cycles:1 length:1 bytes:length
+///   count:1 before:2*count after:2*count
+///   accesses:1 (addr:2 value:1 kind:1)*accesses
+/// The disassembly is not stored, decoded records have it empty.
+//////////////////////////////////////////////////////////
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+use crate::memory::{Access, AccessKind};
+use crate::trace::{TraceRecord, TraceSink};
+
+struct Ring {
+    records: VecDeque<Vec<u8>>,
+    size: usize,
+    capacity: usize,
+    layout: &'static [(&'static str, u8)],
+}
+
+#[derive(Clone)]
+pub struct RingBuffer {
+    ring: Rc<RefCell<Ring>>,
+}
+
+fn encode(record: &TraceRecord) -> Vec<u8> {
+    let mut data = Vec::with_capacity(16 + record.bytes.len() + 4 * record.before.len());
+    data.extend_from_slice(&record.pc.to_le_bytes());
+    data.push(record.cycles);
+    data.push(record.bytes.len() as u8);
+    data.extend_from_slice(&record.bytes);
+    data.push(record.before.len() as u8);
+    for value in record.before.iter().chain(&record.after) {
+        data.extend_from_slice(&value.to_le_bytes());
+    }
+    let accesses = &record.accesses[..record.accesses.len().min(255)];
+    data.push(accesses.len() as u8);
+    for access in accesses {
+        data.extend_from_slice(&access.addr.to_le_bytes());
+        data.push(access.value);
+        data.push((access.kind == AccessKind::Write) as u8);
+    }
+    data
+}
+
+fn decode(data: &[u8], layout: &'static [(&'static str, u8)]) -> TraceRecord {
+    let word = |i: usize| u16::from_le_bytes([data[i], data[i + 1]]);
+    let length = data[3] as usize;
+    let mut i = 4 + length;
+    let count = data[i] as usize;
+    i += 1;
+    let before = (0..count).map(|n| word(i + 2 * n)).collect();
+    i += 2 * count;
+    let after = (0..count).map(|n| word(i + 2 * n)).collect();
+    i += 2 * count;
+    let accesses = data[i + 1..]
+        .chunks(4)
+        .map(|chunk| Access {
+            addr: u16::from_le_bytes([chunk[0], chunk[1]]),
+            value: chunk[2],
+            kind: if chunk[3] == 1 {
+                AccessKind::Write
+            } else {
+                AccessKind::Read
+            },
+        })
+        .collect();
+    TraceRecord {
+        pc: word(0),
+        bytes: data[4..4 + length].to_vec(),
+        disassembly: String::new(),
+        layout,
+        before,
+        after,
+        accesses,
+        cycles: data[2],
+    }
+}
+
+impl RingBuffer {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            ring: Rc::new(RefCell::new(Ring {
+                records: VecDeque::new(),
+                size: 0,
+                capacity,
+                layout: &[],
+            })),
+        }
+    }
+    ///
+    /// Number of records in the buffer
+    ///
+    pub fn len(&self) -> usize {
+        self.ring.borrow().records.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.ring.borrow().records.is_empty()
+    }
+    ///
+    /// Decoded records, the oldest first
+    ///
+    pub fn records(&self) -> Vec<TraceRecord> {
+        let ring = self.ring.borrow();
+        ring.records
+            .iter()
+            .map(|data| decode(data, ring.layout))
+            .collect()
+    }
+}
+
+impl TraceSink for RingBuffer {
+    fn record(&mut self, record: &TraceRecord) {
+        let data = encode(record);
+        let mut ring = self.ring.borrow_mut();
+        ring.layout = record.layout;
+        ring.size += data.len();
+        ring.records.push_back(data);
+        while ring.size > ring.capacity {
+            match ring.records.pop_front() {
+                Some(oldest) => ring.size -= oldest.len(),
+                None => break,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::trace::tests::cpu_with_program;
+
+    #[test]
+    ///
+    /// Records survive the encoding (without disassembly), old ones are dropped
+    ///
+    fn keeps_last_records() {
+        let mut cpu = cpu_with_program();
+        let ring = RingBuffer::new(80);
+        cpu.set_trace(Some(Box::new(ring.clone())));
+        cpu.step();
+        cpu.step();
+        let records = ring.records();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[1].pc, 0x0602);
+        assert_eq!(records[1].bytes, vec![0x8D, 0x00, 0x02]);
+        assert_eq!(records[1].before[0], 0x01);
+        assert_eq!(records[1].accesses[0].addr, 0x0200);
+        assert_eq!(records[1].accesses[0].kind, AccessKind::Write);
+        assert_eq!(records[1].cycles, 4);
+        cpu.step();
+        cpu.step();
+        assert!(ring.ring.borrow().size <= 80);
+        assert_eq!(ring.records().last().unwrap().pc, 0x0606);
+        assert!(ring.len() < 4);
+    }
+}
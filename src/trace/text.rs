@@ -0,0 +1,76 @@
+//////////////////////////////////////////////////////////
+/// Text trace in the style of the Nestest log: address, bytes,
+/// disassembly, registers before the instruction and the cycle counter.
+///
+/// 0600  A9 01     LDA #$01                        A:00 X:00 Y:00 P:00 SP:FD CYC:0
+//////////////////////////////////////////////////////////
+use std::io::Write;
+
+use crate::trace::{TraceRecord, TraceSink};
+
+pub struct TextSink<W: Write> {
+    writer: W,
+    ///
+    /// Cycles executed before the current instruction
+    ///
+    cycles: u64,
+}
+
+impl<W: Write> TextSink<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer, cycles: 0 }
+    }
+}
+
+pub fn format(record: &TraceRecord, cycles: u64) -> String {
+    let bytes: Vec<String> = record.bytes.iter().map(|b| format!("{:02X}", b)).collect();
+    let registers: Vec<String> = record
+        .registers(&record.before)
+        .filter(|(name, _, _)| *name != "PC")
+        .map(|(name, bits, value)| format!("{}:{:02$X}", name, value, bits as usize / 4))
+        .collect();
+    format!(
+        "{:04X}  {:<9} {:<31} {} CYC:{}",
+        record.pc,
+        bytes.join(" "),
+        record.disassembly,
+        registers.join(" "),
+        cycles
+    )
+}
+
+impl<W: Write> TraceSink for TextSink<W> {
+    fn record(&mut self, record: &TraceRecord) {
+        // A trace must not stop the emulation, write errors are ignored
+        let _ = writeln!(self.writer, "{}", format(record, self.cycles));
+        self.cycles += record.cycles as u64;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::trace::tests::{SharedBuffer, cpu_with_program};
+
+    #[test]
+    ///
+    /// Nestest like lines with registers before the instruction
+    ///
+    fn text_lines() {
+        let mut cpu = cpu_with_program();
+        let buffer = SharedBuffer::default();
+        cpu.set_trace(Some(Box::new(TextSink::new(buffer.clone()))));
+        cpu.step();
+        cpu.step();
+        let text = buffer.text();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(
+            lines[0],
+            "0600  A9 01     LDA #$01                        A:00 X:00 Y:00 P:00 SP:FF CYC:0"
+        );
+        assert_eq!(
+            lines[1],
+            "0602  8D 00 02  STA $0200                       A:01 X:00 Y:00 P:00 SP:FF CYC:2"
+        );
+    }
+}